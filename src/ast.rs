@@ -0,0 +1,517 @@
+use crate::lexer::{C1Token, Comment};
+use std::fmt;
+
+/// Return type of a function definition, aka. the `type` production of the grammar.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Type {
+    Bool,
+    Float,
+    Int,
+    Void,
+}
+
+impl Type {
+    /// Map a type keyword to its Type variant
+    pub fn from_token(token: C1Token) -> Option<Type> {
+        match token {
+            C1Token::KwBoolean => Some(Type::Bool),
+            C1Token::KwFloat => Some(Type::Float),
+            C1Token::KwInt => Some(Type::Int),
+            C1Token::KwVoid => Some(Type::Void),
+            _ => None,
+        }
+    }
+
+    /// Return the keyword used for this type in C(-1) source code
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Type::Bool => "bool",
+            Type::Float => "float",
+            Type::Int => "int",
+            Type::Void => "void",
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.keyword())
+    }
+}
+
+/// A complete C(-1) program, i.e. a list of function definitions in source order.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Program {
+    pub functions: Vec<FunctionDefinition>,
+    /// Comments of the source text in source order
+    pub comments: Vec<Comment>,
+}
+
+impl Program {
+    /// Look up a function definition by its name
+    pub fn function(&self, name: &str) -> Option<&FunctionDefinition> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+/// Prints the program as C(-1) source code with one statement per line. Comments are not part of
+/// the syntax tree and are left out.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, function) in self.functions.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl FunctionDefinition {
+    /// Call the visitor for every statement of the body, including nested statements
+    pub fn walk_statements<'a>(&'a self, visitor: &mut impl FnMut(&'a Statement)) {
+        for statement in &self.body {
+            statement.walk(visitor);
+        }
+    }
+
+    /// Call the visitor for every expression of the body, including sub-expressions
+    pub fn walk_expressions<'a>(&'a self, visitor: &mut impl FnMut(&'a Expression)) {
+        self.walk_statements(&mut |statement| {
+            for expression in statement.expressions() {
+                expression.walk(visitor);
+            }
+        });
+    }
+}
+
+/// `type <ID> "(" ")" "{" statementlist "}"`
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionDefinition {
+    pub return_type: Type,
+    pub name: String,
+    pub body: Vec<Statement>,
+    /// Line of the function name
+    pub line: usize,
+}
+
+impl fmt::Display for FunctionDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}() {{", self.return_type, self.name)?;
+        for statement in &self.body {
+            statement.write_source(f, 1)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// A single entry of a `statementlist`. Blocks are kept as nested statements so that the original
+/// structure of the program is preserved.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    /// `"{" statementlist "}"`
+    Block {
+        statements: Vec<Statement>,
+        line: usize,
+    },
+    /// `<KW_IF> "(" assignment ")" block`
+    If {
+        condition: Expression,
+        then_branch: Box<Statement>,
+        line: usize,
+    },
+    /// `<KW_RETURN> ( assignment )? ";"`
+    Return {
+        value: Option<Expression>,
+        line: usize,
+    },
+    /// `<KW_PRINTF> "(" assignment ")" ";"`
+    Printf { value: Expression, line: usize },
+    /// `<ID> "=" assignment ";"`
+    Assign {
+        target: String,
+        value: Expression,
+        line: usize,
+    },
+    /// `<ID> "(" ")" ";"`
+    Call { name: String, line: usize },
+}
+
+impl Statement {
+    /// Write the statement and everything nested in it as source code, indented by four spaces
+    /// per level
+    fn write_source(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let padding = "    ".repeat(indent);
+        match self {
+            Statement::Block { statements, .. } => {
+                writeln!(f, "{}{{", padding)?;
+                for statement in statements {
+                    statement.write_source(f, indent + 1)?;
+                }
+                writeln!(f, "{}}}", padding)
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                write!(f, "{}if ({})", padding, condition)?;
+                Self::write_branch(f, then_branch, indent)
+            }
+            statement => writeln!(f, "{}{}", padding, statement),
+        }
+    }
+
+    /// Write the branch of an if statement after its header, a block starts on the same line
+    fn write_branch(f: &mut fmt::Formatter<'_>, branch: &Statement, indent: usize) -> fmt::Result {
+        match branch {
+            Statement::Block { statements, .. } => {
+                writeln!(f, " {{")?;
+                for statement in statements {
+                    statement.write_source(f, indent + 1)?;
+                }
+                writeln!(f, "{}}}", "    ".repeat(indent))
+            }
+            branch => {
+                writeln!(f)?;
+                branch.write_source(f, indent + 1)
+            }
+        }
+    }
+
+    /// Return the line in which the statement starts
+    pub fn line(&self) -> usize {
+        match self {
+            Statement::Block { line, .. }
+            | Statement::If { line, .. }
+            | Statement::Return { line, .. }
+            | Statement::Printf { line, .. }
+            | Statement::Assign { line, .. }
+            | Statement::Call { line, .. } => *line,
+        }
+    }
+
+    /// Call the visitor for this statement and all nested statements in source order
+    pub fn walk<'a>(&'a self, visitor: &mut impl FnMut(&'a Statement)) {
+        visitor(self);
+        match self {
+            Statement::Block { statements, .. } => {
+                for statement in statements {
+                    statement.walk(visitor);
+                }
+            }
+            Statement::If { then_branch, .. } => then_branch.walk(visitor),
+            _ => {}
+        }
+    }
+
+    /// Return the expressions that are evaluated directly by this statement
+    pub fn expressions(&self) -> Vec<&Expression> {
+        match self {
+            Statement::If { condition, .. } => vec![condition],
+            Statement::Return {
+                value: Some(value), ..
+            }
+            | Statement::Printf { value, .. }
+            | Statement::Assign { value, .. } => vec![value],
+            _ => vec![],
+        }
+    }
+}
+
+/// Prints the statement on a single line. Nested statements of blocks and if statements are not
+/// printed, which makes this representation suitable for labels and diagnostics.
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Block { .. } => write!(f, "{{ ... }}"),
+            Statement::If { condition, .. } => write!(f, "if ({})", condition),
+            Statement::Return { value: None, .. } => write!(f, "return;"),
+            Statement::Return {
+                value: Some(value), ..
+            } => write!(f, "return {};", value),
+            Statement::Printf { value, .. } => write!(f, "printf({});", value),
+            Statement::Assign { target, value, .. } => write!(f, "{} = {};", target, value),
+            Statement::Call { name, .. } => write!(f, "{}();", name),
+        }
+    }
+}
+
+/// The only unary operator of C(-1), the leading minus of a `simpexpr`.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum UnaryOp {
+    Neg,
+}
+
+/// Binary operators of the `expr`, `simpexpr` and `term` productions.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Map an operator token to its BinaryOp variant
+    pub fn from_token(token: C1Token) -> Option<BinaryOp> {
+        match token {
+            C1Token::Plus => Some(BinaryOp::Add),
+            C1Token::Minus => Some(BinaryOp::Sub),
+            C1Token::Asterisk => Some(BinaryOp::Mul),
+            C1Token::Slash => Some(BinaryOp::Div),
+            C1Token::Equal => Some(BinaryOp::Equal),
+            C1Token::NotEqual => Some(BinaryOp::NotEqual),
+            C1Token::Less => Some(BinaryOp::Less),
+            C1Token::Greater => Some(BinaryOp::Greater),
+            C1Token::LessEqual => Some(BinaryOp::LessEqual),
+            C1Token::GreaterEqual => Some(BinaryOp::GreaterEqual),
+            C1Token::And => Some(BinaryOp::And),
+            C1Token::Or => Some(BinaryOp::Or),
+            _ => None,
+        }
+    }
+
+    /// Return the operator as written in C(-1) source code
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::Greater => ">",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    /// Check whether the operator belongs to the `expr` production
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Equal
+                | BinaryOp::NotEqual
+                | BinaryOp::Less
+                | BinaryOp::Greater
+                | BinaryOp::LessEqual
+                | BinaryOp::GreaterEqual
+        )
+    }
+
+    /// Check whether the operator is one of the logical operators `&&` and `||`
+    pub fn is_logical(&self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or)
+    }
+
+    /// Binding strength of the operator: 1 for `expr`, 2 for `simpexpr` and 3 for `term`
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::And => 3,
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// An `assignment` or any of its sub-productions together with the line it starts in.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExpressionKind {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    Variable(String),
+    Call(String),
+    /// `<ID> "=" assignment` used as a value
+    Assign {
+        target: String,
+        value: Box<Expression>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expression>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+    },
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, line: usize) -> Self {
+        Expression { kind, line }
+    }
+
+    /// Call the visitor for this expression and all of its sub-expressions, parents first
+    pub fn walk<'a>(&'a self, visitor: &mut impl FnMut(&'a Expression)) {
+        visitor(self);
+        match &self.kind {
+            ExpressionKind::Assign { value, .. } => value.walk(visitor),
+            ExpressionKind::Unary { operand, .. } => operand.walk(visitor),
+            ExpressionKind::Binary { lhs, rhs, .. } => {
+                lhs.walk(visitor);
+                rhs.walk(visitor);
+            }
+            _ => {}
+        }
+    }
+
+    /// Binding strength of the expression, used to decide where parentheses are required
+    fn precedence(&self) -> u8 {
+        match &self.kind {
+            ExpressionKind::Assign { .. } => 0,
+            ExpressionKind::Binary { op, .. } => op.precedence(),
+            // A leading minus is only allowed in front of the first term of a simpexpr
+            ExpressionKind::Unary { .. } => 2,
+            ExpressionKind::Int(value) if *value < 0 => 2,
+            ExpressionKind::Float(value) if value.is_sign_negative() => 2,
+            _ => 4,
+        }
+    }
+
+    /// Write the expression, wrapping it in parentheses if it binds weaker than `min_precedence`
+    fn fmt_with_precedence(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "(")?;
+            self.fmt_with_precedence(f, 0)?;
+            return write!(f, ")");
+        }
+        match &self.kind {
+            ExpressionKind::Int(value) => write!(f, "{}", value),
+            ExpressionKind::Float(value) => write!(f, "{}", format_float(*value)),
+            ExpressionKind::Bool(value) => write!(f, "{}", value),
+            ExpressionKind::Variable(name) => write!(f, "{}", name),
+            ExpressionKind::Call(name) => write!(f, "{}()", name),
+            ExpressionKind::Assign { target, value } => {
+                write!(f, "{} = ", target)?;
+                value.fmt_with_precedence(f, 0)
+            }
+            ExpressionKind::Unary {
+                op: UnaryOp::Neg,
+                operand,
+            } => {
+                write!(f, "-")?;
+                operand.fmt_with_precedence(f, 3)
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let precedence = op.precedence();
+                if op.is_comparison() {
+                    // Comparisons do not chain, so both sides have to be simple expressions
+                    lhs.fmt_with_precedence(f, 2)?;
+                    write!(f, " {} ", op)?;
+                    rhs.fmt_with_precedence(f, 2)
+                } else {
+                    lhs.fmt_with_precedence(f, precedence)?;
+                    write!(f, " {} ", op)?;
+                    rhs.fmt_with_precedence(f, precedence + 1)
+                }
+            }
+        }
+    }
+}
+
+/// Prints the expression as valid C(-1) source code, inserting parentheses only where needed.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_precedence(f, 0)
+    }
+}
+
+/// Format a float so that the C1Lexer recognizes it as a ConstFloat again. The lexer does not
+/// accept an exponent after a fraction, so the value is always written out in decimal notation.
+pub fn format_float(value: f64) -> String {
+    let text = value.to_string();
+    if text.contains('.') || !value.is_finite() {
+        text
+    } else {
+        format!("{}.0", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::C1Parser;
+
+    #[test]
+    fn expressions_are_printed_with_minimal_parentheses() {
+        let program =
+            C1Parser::parse_program("void main() { x = a * (b + c) - (-d * e); y = -a + b; }")
+                .unwrap();
+        let printed: Vec<String> = program.functions[0]
+            .body
+            .iter()
+            .map(|statement| statement.to_string())
+            .collect();
+        assert_eq!(printed, vec!["x = a * (b + c) - (-d * e);", "y = -a + b;"]);
+    }
+
+    #[test]
+    fn comparisons_are_parenthesized() {
+        let program = C1Parser::parse_program("void main() { x = (a < b) == (c = 1.5); }").unwrap();
+        assert_eq!(
+            program.functions[0].body[0].to_string(),
+            "x = (a < b) == (c = 1.5);"
+        );
+    }
+
+    #[test]
+    fn programs_are_printed_as_source() {
+        let text = "int f() { if (a) { b = 1; } if (c) d(); { { return 2; } } \
+                    if (e) printf(3); }
+                    void main() {}";
+        let printed = C1Parser::parse_program(text).unwrap().to_string();
+        assert_eq!(
+            printed,
+            "int f() {
+    if (a) {
+        b = 1;
+    }
+    if (c)
+        d();
+    {
+        {
+            return 2;
+        }
+    }
+    if (e)
+        printf(3);
+}
+
+void main() {
+}
+"
+        );
+        let reparsed = C1Parser::parse_program(&printed).unwrap();
+        assert_eq!(reparsed.to_string(), printed);
+
+        let example = C1Parser::parse_program(include_str!("../tests/data/beispiel.c-1")).unwrap();
+        let reparsed = C1Parser::parse_program(&example.to_string()).unwrap();
+        assert_eq!(reparsed.to_string(), example.to_string());
+    }
+}
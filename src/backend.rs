@@ -0,0 +1,290 @@
+use crate::ast::{Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type};
+use crate::config::Storage;
+use crate::lint::unassigned_read;
+use crate::types::TypeEnvironment;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A program that a backend cannot translate, since the target language needs static types or
+/// since its variables start as zero where the interpreter fails
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EmitError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+fn error(line: usize, message: String) -> EmitError {
+    EmitError { line, message }
+}
+
+/// A program checked for translation into a statically typed target language.
+///
+/// Every variable must only be assigned values of one type, every called function must be
+/// defined exactly once and calls used as values must not be `void`. Variables live in the
+/// function that assigns them with local storage and in a single shared scope with global
+/// storage, and are listed in the order in which they are found. Backends start variables as
+/// zero, so programs that may read a variable before assigning it are rejected, instead of
+/// printing zero where the interpreter stops with an error.
+pub(crate) struct TypedProgram<'a> {
+    pub program: &'a Program,
+    pub storage: Storage,
+    types: TypeEnvironment,
+    /// Variables per function with local storage, with global storage all are stored under ""
+    variables: HashMap<String, Vec<(String, Type)>>,
+}
+
+impl<'a> TypedProgram<'a> {
+    pub fn check(program: &'a Program, storage: Storage) -> Result<Self, EmitError> {
+        let typed = Self::check_types(program, storage)?;
+        check_assigned(program, storage)?;
+        Ok(typed)
+    }
+
+    /// Check everything but the reads of unassigned variables, for the three-address code,
+    /// which reports them at runtime like the interpreter
+    pub fn check_types(program: &'a Program, storage: Storage) -> Result<Self, EmitError> {
+        let mut functions = HashMap::new();
+        for function in &program.functions {
+            if functions.insert(function.name.as_str(), function).is_some() {
+                return Err(error(
+                    function.line,
+                    format!("function `{}` is defined more than once", function.name),
+                ));
+            }
+        }
+        if !functions.contains_key("main") {
+            return Err(error(1, "the program has no main function".to_string()));
+        }
+        for function in &program.functions {
+            check_calls(function, &functions)?;
+        }
+        let types = TypeEnvironment::infer(program, storage);
+        let mut variables: HashMap<String, Vec<(String, Type)>> = HashMap::new();
+        for function in &program.functions {
+            let scope = match storage {
+                Storage::Local => function.name.as_str(),
+                Storage::Global => "",
+            };
+            let mut result = Ok(());
+            let mut check = |name: &str, line: usize| {
+                let known = variables.entry(scope.to_string()).or_default();
+                if result.is_err() || known.iter().any(|(known, _)| known == name) {
+                    return;
+                }
+                match types.variable_type(&function.name, name) {
+                    Some(variable_type) => known.push((name.to_string(), variable_type)),
+                    None if types.conflicts(&function.name).contains(&name) => {
+                        result = Err(error(
+                            line,
+                            format!("variable `{}` is assigned values of different types", name),
+                        ))
+                    }
+                    None => {
+                        result = Err(error(
+                            line,
+                            format!("variable `{}` is never assigned a value", name),
+                        ))
+                    }
+                }
+            };
+            function.walk_statements(&mut |statement| {
+                if let Statement::Assign { target, line, .. } = statement {
+                    check(target, *line);
+                }
+            });
+            function.walk_expressions(&mut |expression| match &expression.kind {
+                ExpressionKind::Variable(name) | ExpressionKind::Assign { target: name, .. } => {
+                    check(name, expression.line)
+                }
+                _ => {}
+            });
+            result?;
+        }
+        Ok(TypedProgram {
+            program,
+            storage,
+            types,
+            variables,
+        })
+    }
+
+    /// Return the variables that live in the frame of a function, none with global storage
+    pub fn locals(&self, function: &str) -> &[(String, Type)] {
+        match self.storage {
+            Storage::Local => self.scope(function),
+            Storage::Global => &[],
+        }
+    }
+
+    /// Return the variables shared by all functions, none with local storage
+    pub fn globals(&self) -> &[(String, Type)] {
+        match self.storage {
+            Storage::Local => &[],
+            Storage::Global => self.scope(""),
+        }
+    }
+
+    /// Return the static type of an expression of the given function
+    pub fn expression_type(&self, function: &str, expression: &Expression) -> Type {
+        self.types
+            .expression_type(function, expression)
+            .expect("expressions are checked")
+    }
+
+    /// Return the definition of a called function
+    pub fn function(&self, name: &str) -> &'a FunctionDefinition {
+        self.program.function(name).expect("calls are checked")
+    }
+
+    fn scope(&self, scope: &str) -> &[(String, Type)] {
+        self.variables.get(scope).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Check that all calls of a function refer to defined functions that fit how they are used
+fn check_calls(
+    function: &FunctionDefinition,
+    functions: &HashMap<&str, &FunctionDefinition>,
+) -> Result<(), EmitError> {
+    let mut result = Ok(());
+    let mut fail = |line: usize, message: String| {
+        if result.is_ok() {
+            result = Err(error(line, message));
+        }
+    };
+    function.walk_statements(&mut |statement| match statement {
+        Statement::Call { name, line } if !functions.contains_key(name.as_str()) => {
+            fail(*line, format!("call of undefined function `{}`", name))
+        }
+        Statement::Return {
+            value: Some(_),
+            line,
+        } if function.return_type == Type::Void => fail(
+            *line,
+            format!("void function `{}` returns a value", function.name),
+        ),
+        _ => {}
+    });
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Call(name) = &expression.kind {
+            match functions.get(name.as_str()) {
+                None => fail(
+                    expression.line,
+                    format!("call of undefined function `{}`", name),
+                ),
+                Some(callee) if callee.return_type == Type::Void => fail(
+                    expression.line,
+                    format!("void function `{}` is called for its value", name),
+                ),
+                Some(_) => {}
+            }
+        }
+    });
+    result
+}
+
+/// Reject programs that may read a variable before it is assigned, see `lint::unassigned_read`.
+/// The interpreter stops such a read with a runtime error.
+pub(crate) fn check_assigned(program: &Program, storage: Storage) -> Result<(), EmitError> {
+    match unassigned_read(program, storage) {
+        Some((line, name)) => Err(error(
+            line,
+            format!("variable `{}` may be used before it is assigned", name),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether every path through the statements ends with a `return`. Backends end functions for
+/// which this is false with a runtime error if they must return a value.
+pub(crate) fn always_returns(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Return { .. } => true,
+        Statement::Block { statements, .. } => always_returns(statements),
+        _ => false,
+    })
+}
+
+/// Whether a call of the function can end without a result, which is an error in C(-1) if the
+/// result is used
+pub(crate) fn may_miss_result(function: &FunctionDefinition) -> bool {
+    if function.return_type == Type::Void {
+        return false;
+    }
+    let mut bare_return = false;
+    function.walk_statements(&mut |statement| {
+        bare_return |= matches!(statement, Statement::Return { value: None, .. })
+    });
+    bare_return || !always_returns(&function.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Type;
+    use crate::backend::TypedProgram;
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    #[test]
+    fn programs_need_static_types() {
+        let error = |text: &str, storage| {
+            let program = C1Parser::parse_program(text).unwrap();
+            TypedProgram::check(&program, storage)
+                .err()
+                .map(|error| error.to_string())
+        };
+        assert_eq!(
+            error("void main() {\n x = 1;\n x = true;\n}", Storage::Local),
+            Some("Line 2: variable `x` is assigned values of different types".to_string())
+        );
+        assert_eq!(
+            error("void main() {\n printf(x);\n}", Storage::Local),
+            Some("Line 2: variable `x` is never assigned a value".to_string())
+        );
+        assert_eq!(
+            error("void f() {}\nvoid main() {\n x = f();\n}", Storage::Local),
+            Some("Line 3: void function `f` is called for its value".to_string())
+        );
+        assert_eq!(
+            error("void main() {}\nvoid main() {}", Storage::Local),
+            Some("Line 2: function `main` is defined more than once".to_string())
+        );
+        // Variables of different functions only conflict if they are shared
+        let text = "void f() { x = 1.5; }\nvoid main() { x = 1; f(); }";
+        assert_eq!(error(text, Storage::Local), None);
+        assert_eq!(
+            error(text, Storage::Global),
+            Some("Line 1: variable `x` is assigned values of different types".to_string())
+        );
+
+        // Variables start as zero, but the interpreter stops at the read
+        let text = "void main() {\n if (false) a = 1;\n printf(a);\n}";
+        let unassigned = Some("Line 3: variable `a` may be used before it is assigned".to_string());
+        assert_eq!(error(text, Storage::Local), unassigned);
+        assert_eq!(error(text, Storage::Global), unassigned);
+        // Shared variables may be assigned by other functions before every call
+        let text = "void show() { printf(a); }
+                    void set() { a = 1; }
+                    void main() { set(); show(); if (a > 0) b = 1; printf(b); }";
+        assert_eq!(
+            error(text, Storage::Global),
+            Some("Line 3: variable `b` may be used before it is assigned".to_string())
+        );
+        let text = "void show() { printf(a); }\nvoid main() { show(); a = 1; show(); }";
+        assert_eq!(
+            error(text, Storage::Global),
+            Some("Line 1: variable `a` may be used before it is assigned".to_string())
+        );
+
+        let program = C1Parser::parse_program(include_str!("../tests/data/beispiel.c-1")).unwrap();
+        let typed = TypedProgram::check(&program, Storage::Global).unwrap();
+        assert!(typed.locals("main").is_empty());
+        assert_eq!(typed.globals()[0], ("blub1".to_string(), Type::Int));
+    }
+}
@@ -0,0 +1,546 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::config::{LanguageOptions, Storage};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A single instruction of the stack machine. Operands are taken from the top of the stack and
+/// results are pushed back onto it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Instruction {
+    /// Push a value of the constant pool
+    Constant(u32),
+    /// Push a variable of the current call
+    Load(u32),
+    /// Pop a value into a variable of the current call
+    Store(u32),
+    /// Push a variable of the global store
+    LoadGlobal(u32),
+    /// Pop a value into a variable of the global store
+    StoreGlobal(u32),
+    /// Push a copy of the top of the stack
+    Dup,
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    /// Combine two values without short-circuit evaluation, which is done with jumps
+    And,
+    Or,
+    /// Convert the top of the stack to the given type
+    Convert(Type),
+    /// Continue at the given offset of the current function
+    Jump(u32),
+    /// Pop a condition and jump if it is false
+    JumpIfFalse(u32),
+    /// Pop a condition and jump if it is true
+    JumpIfTrue(u32),
+    /// Call a function of the function table and discard its result
+    Call(u32),
+    /// Call a function of the function table and push its result
+    CallValue(u32),
+    /// Pop the result and return it to the caller
+    Return,
+    /// Return without a result
+    ReturnVoid,
+    /// Pop a value and print it
+    Print,
+}
+
+impl Instruction {
+    /// Return the instruction that applies the given binary operator
+    pub fn binary(op: BinaryOp) -> Instruction {
+        match op {
+            BinaryOp::Add => Instruction::Add,
+            BinaryOp::Sub => Instruction::Sub,
+            BinaryOp::Mul => Instruction::Mul,
+            BinaryOp::Div => Instruction::Div,
+            BinaryOp::Equal => Instruction::Equal,
+            BinaryOp::NotEqual => Instruction::NotEqual,
+            BinaryOp::Less => Instruction::Less,
+            BinaryOp::Greater => Instruction::Greater,
+            BinaryOp::LessEqual => Instruction::LessEqual,
+            BinaryOp::GreaterEqual => Instruction::GreaterEqual,
+            BinaryOp::And => Instruction::And,
+            BinaryOp::Or => Instruction::Or,
+        }
+    }
+
+    /// Return the binary operator applied by the instruction, if it is one
+    pub fn binary_op(&self) -> Option<BinaryOp> {
+        Some(match self {
+            Instruction::Add => BinaryOp::Add,
+            Instruction::Sub => BinaryOp::Sub,
+            Instruction::Mul => BinaryOp::Mul,
+            Instruction::Div => BinaryOp::Div,
+            Instruction::Equal => BinaryOp::Equal,
+            Instruction::NotEqual => BinaryOp::NotEqual,
+            Instruction::Less => BinaryOp::Less,
+            Instruction::Greater => BinaryOp::Greater,
+            Instruction::LessEqual => BinaryOp::LessEqual,
+            Instruction::GreaterEqual => BinaryOp::GreaterEqual,
+            Instruction::And => BinaryOp::And,
+            Instruction::Or => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    /// Return how many values the instruction pops from the stack and how many it pushes
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Instruction::Constant(_)
+            | Instruction::Load(_)
+            | Instruction::LoadGlobal(_)
+            | Instruction::CallValue(_) => (0, 1),
+            Instruction::Store(_)
+            | Instruction::StoreGlobal(_)
+            | Instruction::JumpIfFalse(_)
+            | Instruction::JumpIfTrue(_)
+            | Instruction::Return
+            | Instruction::Print => (1, 0),
+            Instruction::Dup => (1, 2),
+            Instruction::Neg | Instruction::Convert(_) => (1, 1),
+            Instruction::Jump(_) | Instruction::Call(_) | Instruction::ReturnVoid => (0, 0),
+            _ => (2, 1),
+        }
+    }
+
+    /// Return the name of the instruction as used by the disassembler
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Constant(_) => "constant",
+            Instruction::Load(_) => "load",
+            Instruction::Store(_) => "store",
+            Instruction::LoadGlobal(_) => "load_global",
+            Instruction::StoreGlobal(_) => "store_global",
+            Instruction::Dup => "dup",
+            Instruction::Neg => "neg",
+            Instruction::Add => "add",
+            Instruction::Sub => "sub",
+            Instruction::Mul => "mul",
+            Instruction::Div => "div",
+            Instruction::Equal => "eq",
+            Instruction::NotEqual => "ne",
+            Instruction::Less => "lt",
+            Instruction::Greater => "gt",
+            Instruction::LessEqual => "le",
+            Instruction::GreaterEqual => "ge",
+            Instruction::And => "and",
+            Instruction::Or => "or",
+            Instruction::Convert(_) => "convert",
+            Instruction::Jump(_) => "jump",
+            Instruction::JumpIfFalse(_) => "jump_if_false",
+            Instruction::JumpIfTrue(_) => "jump_if_true",
+            Instruction::Call(_) => "call",
+            Instruction::CallValue(_) => "call_value",
+            Instruction::Return => "return",
+            Instruction::ReturnVoid => "return_void",
+            Instruction::Print => "print",
+        }
+    }
+}
+
+/// Prints the mnemonic followed by the operand, e.g. `load 3` or `convert bool`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Constant(operand)
+            | Instruction::Load(operand)
+            | Instruction::Store(operand)
+            | Instruction::LoadGlobal(operand)
+            | Instruction::StoreGlobal(operand)
+            | Instruction::Jump(operand)
+            | Instruction::JumpIfFalse(operand)
+            | Instruction::JumpIfTrue(operand)
+            | Instruction::Call(operand)
+            | Instruction::CallValue(operand) => write!(f, "{} {}", self.mnemonic(), operand),
+            Instruction::Convert(target) => write!(f, "{} {}", self.mnemonic(), target),
+            _ => write!(f, "{}", self.mnemonic()),
+        }
+    }
+}
+
+/// A function of a compiled module
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub return_type: Type,
+    /// Names of the variables of a call, indexed by the operand of `Load` and `Store`
+    pub locals: Vec<String>,
+    pub code: Vec<Instruction>,
+    /// Source line of each instruction
+    pub lines: Vec<usize>,
+}
+
+/// A program compiled to bytecode
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Module {
+    pub constants: Vec<Value>,
+    /// Names of the variables of the global store, indexed by the operand of `LoadGlobal` and
+    /// `StoreGlobal`
+    pub globals: Vec<String>,
+    pub functions: Vec<CompiledFunction>,
+}
+
+/// An error that prevents a program from being compiled
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl Module {
+    /// Compile a program. Variables are placed according to the storage of the language options.
+    ///
+    /// Calls of undefined functions and void functions that return a value are rejected, even if
+    /// the tree interpreter would never reach them.
+    pub fn compile(program: &Program, language: &LanguageOptions) -> Result<Module, CompileError> {
+        let mut module = Module::default();
+        // Later definitions win, like in the interpreter
+        let indices: HashMap<&str, u32> = program
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(index, function)| (function.name.as_str(), index as u32))
+            .collect();
+        for function in &program.functions {
+            let compiled =
+                FunctionCompiler::compile(&mut module, &indices, language.storage, function)?;
+            module.functions.push(compiled);
+        }
+        Ok(module)
+    }
+
+    /// Return the index of the function that is called by the given name
+    pub fn function_index(&self, name: &str) -> Option<usize> {
+        self.functions
+            .iter()
+            .rposition(|function| function.name == name)
+    }
+
+    /// Return the index of a constant, adding it to the pool if necessary
+    fn constant(&mut self, value: Value) -> u32 {
+        // Floats are compared by their bits, so that 0.0 and -0.0 stay different
+        let same = |constant: &Value| match (*constant, value) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (constant, value) => constant == value,
+        };
+        match self.constants.iter().position(same) {
+            Some(index) => index as u32,
+            None => {
+                self.constants.push(value);
+                (self.constants.len() - 1) as u32
+            }
+        }
+    }
+}
+
+/// Translates the body of a single function
+struct FunctionCompiler<'m> {
+    module: &'m mut Module,
+    indices: &'m HashMap<&'m str, u32>,
+    storage: Storage,
+    function: CompiledFunction,
+}
+
+impl<'m> FunctionCompiler<'m> {
+    fn compile(
+        module: &'m mut Module,
+        indices: &'m HashMap<&'m str, u32>,
+        storage: Storage,
+        function: &FunctionDefinition,
+    ) -> Result<CompiledFunction, CompileError> {
+        let mut compiler = FunctionCompiler {
+            module,
+            indices,
+            storage,
+            function: CompiledFunction {
+                name: function.name.clone(),
+                return_type: function.return_type,
+                locals: Vec::new(),
+                code: Vec::new(),
+                lines: Vec::new(),
+            },
+        };
+        for statement in &function.body {
+            compiler.statement(statement)?;
+        }
+        // Falling off the end of the body returns without a value
+        let line = function.body.last().map_or(function.line, Statement::line);
+        compiler.emit(Instruction::ReturnVoid, line);
+        Ok(compiler.function)
+    }
+
+    /// Append an instruction and return its offset
+    fn emit(&mut self, instruction: Instruction, line: usize) -> usize {
+        self.function.code.push(instruction);
+        self.function.lines.push(line);
+        self.function.code.len() - 1
+    }
+
+    /// Let the jump at the given offset continue at the next instruction
+    fn patch(&mut self, jump: usize) {
+        let target = self.function.code.len() as u32;
+        match &mut self.function.code[jump] {
+            Instruction::Jump(offset)
+            | Instruction::JumpIfFalse(offset)
+            | Instruction::JumpIfTrue(offset) => *offset = target,
+            instruction => unreachable!("{:?} is not a jump", instruction),
+        }
+    }
+
+    fn function_index(&self, name: &str, line: usize) -> Result<u32, CompileError> {
+        self.indices.get(name).copied().ok_or_else(|| CompileError {
+            line,
+            message: format!("call of undefined function `{}`", name),
+        })
+    }
+
+    /// Return the load and store instructions of a variable
+    fn variable(&mut self, name: &str) -> (Instruction, Instruction) {
+        let slot = |names: &mut Vec<String>| match names.iter().position(|known| known == name) {
+            Some(index) => index as u32,
+            None => {
+                names.push(name.to_string());
+                (names.len() - 1) as u32
+            }
+        };
+        match self.storage {
+            Storage::Local => {
+                let slot = slot(&mut self.function.locals);
+                (Instruction::Load(slot), Instruction::Store(slot))
+            }
+            Storage::Global => {
+                let slot = slot(&mut self.module.globals);
+                (
+                    Instruction::LoadGlobal(slot),
+                    Instruction::StoreGlobal(slot),
+                )
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), CompileError> {
+        match statement {
+            Statement::Block { statements, .. } => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => {
+                self.expression(condition)?;
+                let to_end = self.emit(Instruction::JumpIfFalse(0), *line);
+                self.statement(then_branch)?;
+                self.patch(to_end);
+            }
+            Statement::Return { value, line } => match value {
+                Some(_) if self.function.return_type == Type::Void => {
+                    return Err(CompileError {
+                        line: *line,
+                        message: format!("void function `{}` returns a value", self.function.name),
+                    });
+                }
+                Some(value) => {
+                    self.expression(value)?;
+                    self.emit(Instruction::Return, *line);
+                }
+                None => {
+                    self.emit(Instruction::ReturnVoid, *line);
+                }
+            },
+            Statement::Printf { value, line } => {
+                self.expression(value)?;
+                self.emit(Instruction::Print, *line);
+            }
+            Statement::Assign {
+                target,
+                value,
+                line,
+            } => {
+                self.expression(value)?;
+                let (_, store) = self.variable(target);
+                self.emit(store, *line);
+            }
+            Statement::Call { name, line } => {
+                let index = self.function_index(name, *line)?;
+                self.emit(Instruction::Call(index), *line);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<(), CompileError> {
+        let line = expression.line;
+        match &expression.kind {
+            ExpressionKind::Int(value) => self.push_constant(Value::Int(*value), line),
+            ExpressionKind::Float(value) => self.push_constant(Value::Float(*value), line),
+            ExpressionKind::Bool(value) => self.push_constant(Value::Bool(*value), line),
+            ExpressionKind::Variable(name) => {
+                let (load, _) = self.variable(name);
+                self.emit(load, line);
+            }
+            ExpressionKind::Call(name) => {
+                let index = self.function_index(name, line)?;
+                self.emit(Instruction::CallValue(index), line);
+            }
+            ExpressionKind::Assign { target, value } => {
+                self.expression(value)?;
+                self.emit(Instruction::Dup, line);
+                let (_, store) = self.variable(target);
+                self.emit(store, line);
+            }
+            ExpressionKind::Unary { operand, .. } => {
+                self.expression(operand)?;
+                self.emit(Instruction::Neg, line);
+            }
+            ExpressionKind::Binary { op, lhs, rhs } if op.is_logical() => {
+                // The left operand decides the result if the jump is taken, otherwise it is
+                // combined with the right operand
+                self.expression(lhs)?;
+                self.emit(Instruction::Dup, line);
+                let jump = match op {
+                    BinaryOp::And => Instruction::JumpIfFalse(0),
+                    _ => Instruction::JumpIfTrue(0),
+                };
+                let to_end = self.emit(jump, line);
+                self.expression(rhs)?;
+                self.emit(Instruction::binary(*op), line);
+                self.patch(to_end);
+                self.emit(Instruction::Convert(Type::Bool), line);
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                self.expression(lhs)?;
+                self.expression(rhs)?;
+                self.emit(Instruction::binary(*op), line);
+            }
+        }
+        Ok(())
+    }
+
+    fn push_constant(&mut self, value: Value, line: usize) {
+        let index = self.module.constant(value);
+        self.emit(Instruction::Constant(index), line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Type;
+    use crate::bytecode::{Instruction, Module};
+    use crate::config::{LanguageOptions, Storage};
+    use crate::value::Value;
+    use crate::C1Parser;
+
+    fn compile(text: &str, storage: Storage) -> Module {
+        let program = C1Parser::parse_program(text).unwrap();
+        let language = LanguageOptions { storage };
+        Module::compile(&program, &language).unwrap()
+    }
+
+    #[test]
+    fn statements_are_compiled_to_stack_code() {
+        use Instruction::*;
+        let module = compile(
+            "int main() {
+                 a = 2;
+                 if ((a < 3) && true) printf(a * 1.5);
+                 return a;
+             }",
+            Storage::Local,
+        );
+        assert_eq!(
+            module.constants,
+            vec![
+                Value::Int(2),
+                Value::Int(3),
+                Value::Bool(true),
+                Value::Float(1.5)
+            ]
+        );
+        let main = &module.functions[0];
+        assert_eq!(main.locals, vec!["a"]);
+        assert_eq!(
+            main.code,
+            vec![
+                Constant(0),
+                Store(0),
+                Load(0),
+                Constant(1),
+                Less,
+                Dup,
+                JumpIfFalse(9),
+                Constant(2),
+                And,
+                Convert(Type::Bool),
+                JumpIfFalse(15),
+                Load(0),
+                Constant(3),
+                Mul,
+                Print,
+                Load(0),
+                Return,
+                ReturnVoid,
+            ]
+        );
+        assert_eq!(main.lines[..3], [2, 2, 3]);
+        assert_eq!(main.lines[16..], [4, 4]);
+    }
+
+    #[test]
+    fn global_storage_shares_variables() {
+        let module = compile(
+            "void f() { a = 1; } void main() { b = 2; a = b; f(); }",
+            Storage::Global,
+        );
+        assert_eq!(module.globals, vec!["a", "b"]);
+        assert!(module.functions[1].locals.is_empty());
+        assert_eq!(
+            module.functions[1].code,
+            vec![
+                Instruction::Constant(1),
+                Instruction::StoreGlobal(1),
+                Instruction::LoadGlobal(1),
+                Instruction::StoreGlobal(0),
+                Instruction::Call(0),
+                Instruction::ReturnVoid,
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_programs_are_rejected() {
+        let error = |text| {
+            let program = C1Parser::parse_program(text).unwrap();
+            Module::compile(&program, &LanguageOptions::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("void main() {\n x = g();\n}"),
+            "Line 2: call of undefined function `g`"
+        );
+        assert_eq!(
+            error("void main() {\n return 1;\n}"),
+            "Line 2: void function `main` returns a value"
+        );
+    }
+}
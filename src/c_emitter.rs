@@ -0,0 +1,534 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::backend::{always_returns, may_miss_result, EmitError, TypedProgram};
+use crate::config::Storage;
+use std::fmt::Write;
+
+/// Functions of the generated C code that implement the semantics of C(-1) where they differ
+/// from C: int arithmetic wraps around, float to int conversions saturate and runtime errors
+/// print their line and stop the program.
+const RUNTIME: &str = r#"static void c1_error(int line, const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "Line %d: %s\n", line, message);
+    exit(1);
+}
+
+static inline int c1_add(int lhs, int rhs) { return (int)((unsigned)lhs + (unsigned)rhs); }
+static inline int c1_sub(int lhs, int rhs) { return (int)((unsigned)lhs - (unsigned)rhs); }
+static inline int c1_mul(int lhs, int rhs) { return (int)((unsigned)lhs * (unsigned)rhs); }
+static inline int c1_neg(int value) { return (int)(0u - (unsigned)value); }
+
+static inline int c1_div(int lhs, int rhs, int line) {
+    if (rhs == 0) {
+        c1_error(line, "division by zero");
+    }
+    if (lhs == INT_MIN && rhs == -1) {
+        return INT_MIN;
+    }
+    return lhs / rhs;
+}
+
+/* set by functions that end without a result, which is an error if it is used */
+static const char *c1_missing_result = NULL;
+
+static inline int c1_int_result(int value, int line) {
+    if (c1_missing_result) {
+        c1_error(line, c1_missing_result);
+    }
+    return value;
+}
+
+static inline double c1_float_result(double value, int line) {
+    return c1_int_result(0, line), value;
+}
+
+static inline bool c1_bool_result(bool value, int line) {
+    return c1_int_result(0, line), value;
+}
+
+/* a function, so that C compilers do not warn about dividing by a constant zero */
+static inline double c1_fdiv(double lhs, double rhs) { return lhs / rhs; }
+
+static inline int c1_to_int(double value) {
+    if (isnan(value)) {
+        return 0;
+    }
+    if (value >= 2147483647.0) {
+        return INT_MAX;
+    }
+    if (value <= -2147483648.0) {
+        return INT_MIN;
+    }
+    return (int)value;
+}
+
+/* glibc prints NaN with a sign, C(-1) does not */
+static inline void c1_print_float(double value) {
+    if (isnan(value)) {
+        puts("nan");
+    } else {
+        printf("%.6f\n", value);
+    }
+}
+"#;
+
+impl Program {
+    /// Translate the program into a standalone C99 source file.
+    ///
+    /// Every function gets a forward declaration, so the order of definitions does not matter.
+    /// Variables are declared with the type of the values assigned to them, at the start of each
+    /// function with local storage and at file scope with global storage, and start as zero.
+    /// `printf` uses the format of the value's type, and `#line` directives map every statement
+    /// back to its line in `source_name`. The exit status of the C program is the value returned
+    /// by `main`, runtime errors are printed to stderr and exit with status 1. C leaves the order
+    /// in which operands are evaluated open, so operands with effects are evaluated into
+    /// temporaries before the statement that uses them.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn emit_c(&self, storage: Storage, source_name: &str) -> Result<String, EmitError> {
+        let typed = TypedProgram::check(self, storage)?;
+        let mut emitter = CEmitter {
+            typed: &typed,
+            source_name: source_name.replace('\\', "\\\\").replace('"', "\\\""),
+            output: String::new(),
+            temporaries: 0,
+        };
+        emitter.program();
+        Ok(emitter.output)
+    }
+}
+
+struct CEmitter<'a> {
+    typed: &'a TypedProgram<'a>,
+    /// File name for `#line` directives, escaped for a string literal
+    source_name: String,
+    output: String,
+    /// Number of temporaries declared so far, used to name the next one
+    temporaries: usize,
+}
+
+impl CEmitter<'_> {
+    fn program(&mut self) {
+        let program = self.typed.program;
+        writeln!(self.output, "/* Generated from {} */", self.source_name).unwrap();
+        for header in ["limits.h", "math.h", "stdbool.h", "stdio.h", "stdlib.h"] {
+            writeln!(self.output, "#include <{}>", header).unwrap();
+        }
+        writeln!(self.output, "\n{}", RUNTIME).unwrap();
+        for function in &program.functions {
+            writeln!(self.output, "{};", signature(function)).unwrap();
+        }
+        if !self.typed.globals().is_empty() {
+            self.output.push('\n');
+            for (name, variable_type) in self.typed.globals() {
+                writeln!(
+                    self.output,
+                    "static {} {} = {};",
+                    c_type(*variable_type),
+                    variable(name),
+                    zero(*variable_type)
+                )
+                .unwrap();
+            }
+        }
+        for function in &program.functions {
+            self.function(function);
+        }
+        let main = program.function("main").unwrap();
+        let exit_code = match main.return_type {
+            Type::Void => {
+                writeln!(
+                    self.output,
+                    "\nint main(void) {{\n    f_main();\n    return 0;"
+                )
+                .unwrap();
+                None
+            }
+            Type::Float => Some("c1_to_int(f_main())"),
+            Type::Int | Type::Bool => Some("f_main()"),
+        };
+        if let Some(exit_code) = exit_code {
+            writeln!(
+                self.output,
+                "\nint main(void) {{\n    return {};",
+                exit_code
+            )
+            .unwrap();
+        }
+        writeln!(self.output, "}}").unwrap();
+    }
+
+    fn function(&mut self, function: &FunctionDefinition) {
+        writeln!(self.output, "\n{} {{", signature(function)).unwrap();
+        for (name, variable_type) in self.typed.locals(&function.name) {
+            writeln!(
+                self.output,
+                "    {} {} = {};",
+                c_type(*variable_type),
+                variable(name),
+                zero(*variable_type)
+            )
+            .unwrap();
+        }
+        for statement in &function.body {
+            self.statement(function, statement, 1);
+        }
+        if function.return_type != Type::Void && !always_returns(&function.body) {
+            self.missing_result(function, 1);
+        }
+        writeln!(self.output, "}}").unwrap();
+    }
+
+    /// Return from a function without a result. C would return garbage, C(-1) fails if the
+    /// result is used, so the caller checks `c1_missing_result`.
+    fn missing_result(&mut self, function: &FunctionDefinition, indent: usize) {
+        let padding = "    ".repeat(indent);
+        writeln!(
+            self.output,
+            "{}c1_missing_result = \"function `{}` did not return a value\";\n{}return {};",
+            padding,
+            function.name,
+            padding,
+            zero(function.return_type)
+        )
+        .unwrap();
+    }
+
+    /// Write a `#line` directive for the next line of output
+    fn line(&mut self, line: usize, indent: usize) {
+        writeln!(
+            self.output,
+            "{}#line {} \"{}\"",
+            "    ".repeat(indent),
+            line,
+            self.source_name
+        )
+        .unwrap();
+    }
+
+    fn statement(&mut self, function: &FunctionDefinition, statement: &Statement, indent: usize) {
+        let padding = "    ".repeat(indent);
+        if !matches!(statement, Statement::Block { .. }) {
+            self.line(statement.line(), indent);
+        }
+        match statement {
+            Statement::Block { statements, .. } => {
+                writeln!(self.output, "{}{{", padding).unwrap();
+                for statement in statements {
+                    self.statement(function, statement, indent + 1);
+                }
+                writeln!(self.output, "{}}}", padding).unwrap();
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                let mut prefix = Vec::new();
+                let condition = self.expression(function, condition, &mut prefix).0;
+                self.prefix(prefix, statement.line(), indent);
+                writeln!(self.output, "{}if ({}) {{", padding, condition).unwrap();
+                self.statement(function, then_branch, indent + 1);
+                writeln!(self.output, "{}}}", padding).unwrap();
+            }
+            Statement::Return { value: None, .. } if function.return_type != Type::Void => {
+                self.missing_result(function, indent);
+            }
+            Statement::Return { value: None, .. } => {
+                writeln!(self.output, "{}return;", padding).unwrap();
+            }
+            Statement::Return {
+                value: Some(value), ..
+            } => {
+                let mut prefix = Vec::new();
+                let value = self.expression(function, value, &mut prefix);
+                self.prefix(prefix, statement.line(), indent);
+                let value = convert(value, function.return_type);
+                writeln!(self.output, "{}return {};", padding, value).unwrap();
+            }
+            Statement::Printf { value, .. } => {
+                let mut prefix = Vec::new();
+                let (value, value_type) = self.expression(function, value, &mut prefix);
+                self.prefix(prefix, statement.line(), indent);
+                let call = match value_type {
+                    Type::Int => format!("printf(\"%d\\n\", {})", value),
+                    Type::Float => format!("c1_print_float({})", value),
+                    Type::Bool => format!("printf(\"%s\\n\", {} ? \"true\" : \"false\")", value),
+                    Type::Void => unreachable!("void calls are rejected"),
+                };
+                writeln!(self.output, "{}{};", padding, call).unwrap();
+            }
+            Statement::Assign { target, value, .. } => {
+                let mut prefix = Vec::new();
+                let value = self.expression(function, value, &mut prefix).0;
+                self.prefix(prefix, statement.line(), indent);
+                writeln!(self.output, "{}{} = {};", padding, variable(target), value).unwrap();
+            }
+            Statement::Call { name, .. } => {
+                writeln!(self.output, "{}f_{}();", padding, name).unwrap();
+                // The result is not used, so it may be missing
+                if may_miss_result(self.typed.function(name)) {
+                    writeln!(self.output, "{}c1_missing_result = NULL;", padding).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Write the statements that compute the temporaries of a statement, followed by a new
+    /// `#line` directive for the statement itself
+    fn prefix(&mut self, prefix: Vec<String>, line: usize, indent: usize) {
+        if prefix.is_empty() {
+            return;
+        }
+        let padding = "    ".repeat(indent);
+        for statement in prefix {
+            writeln!(self.output, "{}{}", padding, statement).unwrap();
+        }
+        self.line(line, indent);
+    }
+
+    /// Declare a temporary that holds the value of `code` and return its name. The declaration
+    /// is appended to `prefix`.
+    fn temporary(
+        &mut self,
+        (code, value_type): (String, Type),
+        prefix: &mut Vec<String>,
+    ) -> String {
+        self.temporaries += 1;
+        let name = format!("c1_tmp{}", self.temporaries);
+        prefix.push(format!("{} {} = {};", c_type(value_type), name, code));
+        name
+    }
+
+    /// Translate an expression, returning the C code and the C(-1) type of its value.
+    /// Statements that have to run before the expression are appended to `prefix`.
+    fn expression(
+        &mut self,
+        function: &FunctionDefinition,
+        expression: &Expression,
+        prefix: &mut Vec<String>,
+    ) -> (String, Type) {
+        let value_type = self.typed.expression_type(&function.name, expression);
+        let code = match &expression.kind {
+            ExpressionKind::Int(value) => value.to_string(),
+            ExpressionKind::Float(value) => float_literal(*value),
+            ExpressionKind::Bool(value) => value.to_string(),
+            ExpressionKind::Variable(name) => variable(name),
+            ExpressionKind::Call(name) if may_miss_result(self.typed.function(name)) => format!(
+                "c1_{}_result(f_{}(), {})",
+                value_type.keyword(),
+                name,
+                expression.line
+            ),
+            ExpressionKind::Call(name) => format!("f_{}()", name),
+            ExpressionKind::Assign { target, value } => {
+                format!(
+                    "({} = {})",
+                    variable(target),
+                    self.expression(function, value, prefix).0
+                )
+            }
+            ExpressionKind::Unary { operand, .. } => {
+                match self.expression(function, operand, prefix) {
+                    (operand, Type::Float) => format!("(-{})", operand),
+                    (operand, _) => format!("c1_neg({})", operand),
+                }
+            }
+            ExpressionKind::Binary { op, lhs, rhs } if op.is_logical() => {
+                let lhs = self.expression(function, lhs, prefix);
+                let mut rhs_prefix = Vec::new();
+                let rhs = self.expression(function, rhs, &mut rhs_prefix).0;
+                if rhs_prefix.is_empty() {
+                    // Both sides are truth values in C as well, and C evaluates them in order
+                    format!("({} {} {})", lhs.0, op.symbol(), rhs)
+                } else {
+                    // The temporaries of the right operand are only computed if it is evaluated
+                    let result = self.temporary((lhs.0, Type::Bool), prefix);
+                    let test = match op {
+                        BinaryOp::And => result.clone(),
+                        _ => format!("!{}", result),
+                    };
+                    prefix.push(format!("if ({}) {{", test));
+                    for statement in rhs_prefix {
+                        prefix.push(format!("    {}", statement));
+                    }
+                    prefix.push(format!("    {} = {};", result, rhs));
+                    prefix.push("}".to_string());
+                    result
+                }
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                // C evaluates the operands in any order, C(-1) from left to right
+                let ordered =
+                    !is_literal(lhs) && !is_literal(rhs) && (has_effects(lhs) || has_effects(rhs));
+                let (mut lhs, lhs_type) = self.expression(function, lhs, prefix);
+                if ordered {
+                    lhs = self.temporary((lhs, lhs_type), prefix);
+                }
+                let (rhs, rhs_type) = self.expression(function, rhs, prefix);
+                let float = lhs_type == Type::Float || rhs_type == Type::Float;
+                match op {
+                    BinaryOp::Add if !float => format!("c1_add({}, {})", lhs, rhs),
+                    BinaryOp::Sub if !float => format!("c1_sub({}, {})", lhs, rhs),
+                    BinaryOp::Mul if !float => format!("c1_mul({}, {})", lhs, rhs),
+                    BinaryOp::Div if !float => {
+                        format!("c1_div({}, {}, {})", lhs, rhs, expression.line)
+                    }
+                    BinaryOp::Div => format!("c1_fdiv({}, {})", lhs, rhs),
+                    // Bools and ints are converted like in C(-1)
+                    op => format!("({} {} {})", lhs, op.symbol(), rhs),
+                }
+            }
+        };
+        (code, value_type)
+    }
+}
+
+fn signature(function: &FunctionDefinition) -> String {
+    format!(
+        "static {} f_{}(void)",
+        c_type(function.return_type),
+        function.name
+    )
+}
+
+fn is_literal(expression: &Expression) -> bool {
+    matches!(
+        expression.kind,
+        ExpressionKind::Int(_) | ExpressionKind::Float(_) | ExpressionKind::Bool(_)
+    )
+}
+
+/// Whether evaluating the expression can do more than produce its value. A division can stop
+/// the program.
+fn has_effects(expression: &Expression) -> bool {
+    let mut effects = false;
+    expression.walk(&mut |expression| {
+        effects |= matches!(
+            expression.kind,
+            ExpressionKind::Call(_)
+                | ExpressionKind::Assign { .. }
+                | ExpressionKind::Binary {
+                    op: BinaryOp::Div,
+                    ..
+                }
+        )
+    });
+    effects
+}
+
+/// Variables get a prefix so they cannot clash with C keywords or the functions
+fn variable(name: &str) -> String {
+    format!("v_{}", name)
+}
+
+fn c_type(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Bool => "bool",
+        Type::Float => "double",
+        Type::Int => "int",
+        Type::Void => "void",
+    }
+}
+
+fn zero(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Bool => "false",
+        Type::Float => "0.0",
+        Type::Int | Type::Void => "0",
+    }
+}
+
+/// Convert a value to the return type of its function like C(-1) does
+fn convert((value, value_type): (String, Type), target: Type) -> String {
+    match (value_type, target) {
+        (from, to) if from == to => value,
+        (Type::Float, Type::Int) => format!("c1_to_int({})", value),
+        (_, Type::Bool) => format!("({} != 0)", value),
+        (_, target) => format!("({}){}", c_type(target), value),
+    }
+}
+
+/// Write a float so that C reads exactly the same value
+fn float_literal(value: f64) -> String {
+    if value.is_infinite() {
+        "HUGE_VAL".to_string()
+    } else {
+        // The shortest representation that reads back as the same value, e.g. 1.5 or 1e300
+        format!("{:?}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    #[test]
+    fn functions_and_variables_are_declared() {
+        let program = C1Parser::parse_program(
+            "float blah() {
+                 a = blub() / 2;
+                 if (a > 1.5) printf(a);
+                 return a;
+             }
+             int blub() { return 3; }
+             void main() { b = 1 < 2; printf(b); }",
+        )
+        .unwrap();
+        let c = program.emit_c(Storage::Local, "test.c-1").unwrap();
+        let expected = [
+            "static double f_blah(void);\nstatic int f_blub(void);\nstatic void f_main(void);\n",
+            "static double f_blah(void) {\n    int v_a = 0;\n    #line 2 \"test.c-1\"\n    \
+             v_a = c1_div(f_blub(), 2, 2);\n",
+            "    #line 3 \"test.c-1\"\n    if ((v_a > 1.5)) {\n        #line 3 \"test.c-1\"\n        \
+             printf(\"%d\\n\", v_a);\n    }\n",
+            "    return (double)v_a;\n",
+            "    bool v_b = false;\n",
+            "printf(\"%s\\n\", v_b ? \"true\" : \"false\");",
+            "int main(void) {\n    f_main();\n    return 0;\n}\n",
+        ];
+        for part in expected {
+            assert!(c.contains(part), "{} not found in\n{}", part, c);
+        }
+
+        let c = program.emit_c(Storage::Global, "test.c-1").unwrap();
+        assert!(c.contains("\nstatic int v_a = 0;\nstatic bool v_b = false;\n"));
+    }
+
+    #[test]
+    fn operands_with_effects_are_evaluated_in_order() {
+        let program = C1Parser::parse_program(
+            "int f() { return 1; }
+             void main() { a = f() + f() * 2; b = false || (f() - a < 0); }",
+        )
+        .unwrap();
+        let c = program.emit_c(Storage::Local, "order.c-1").unwrap();
+        let expected = [
+            "    int c1_tmp1 = f_f();\n    #line 2 \"order.c-1\"\n    \
+             v_a = c1_add(c1_tmp1, c1_mul(f_f(), 2));\n",
+            // The right operand of || is only evaluated if the left one is false
+            "    bool c1_tmp3 = false;\n    if (!c1_tmp3) {\n        int c1_tmp2 = f_f();\n        \
+             c1_tmp3 = (c1_sub(c1_tmp2, v_a) < 0);\n    }\n",
+            "    v_b = c1_tmp3;\n",
+        ];
+        for part in expected {
+            assert!(c.contains(part), "{} not found in\n{}", part, c);
+        }
+    }
+
+    #[test]
+    fn missing_results_fail_at_runtime() {
+        let program = C1Parser::parse_program(
+            "int maybe() {\n if (false) return 1;\n}\nint main() { return maybe(); }",
+        )
+        .unwrap();
+        let c = program.emit_c(Storage::Local, "maybe.c-1").unwrap();
+        assert!(c.contains(
+            "    c1_missing_result = \"function `maybe` did not return a value\";\n    return 0;\n}"
+        ));
+        assert!(c.contains("return c1_int_result(f_maybe(), 4);"));
+        assert!(c.ends_with("int main(void) {\n    return f_main();\n}\n"));
+    }
+}
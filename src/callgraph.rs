@@ -0,0 +1,578 @@
+use crate::ast::{BinaryOp, Expression, ExpressionKind, Program, Statement};
+use crate::cfg::{ControlFlowGraph, Terminator};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// A single call of a function, either as a statement or inside an expression
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CallSite {
+    pub caller: String,
+    pub callee: String,
+    pub line: usize,
+}
+
+/// A set of functions that call each other recursively, i.e. a strongly connected component of
+/// the call graph that contains at least one edge
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RecursionCycle {
+    /// Members of the cycle in source order
+    pub functions: Vec<String>,
+    /// True if the cycle consists of a single function that calls itself
+    pub direct: bool,
+    /// False if every path through every member of the cycle calls back into the cycle before it
+    /// can return, which means that the recursion can never terminate
+    pub may_terminate: bool,
+}
+
+/// Call graph of a program. Nodes are the function definitions in source order, edges are the
+/// call sites found in their bodies.
+#[derive(Debug, Clone)]
+pub struct CallGraph<'a> {
+    program: &'a Program,
+    /// Call sites per function, indexed like `program.functions`
+    calls: Vec<Vec<CallSite>>,
+    index: HashMap<&'a str, usize>,
+}
+
+impl<'a> CallGraph<'a> {
+    /// Name of the function that every program starts in
+    pub const ENTRY_POINT: &'static str = "main";
+
+    pub fn build(program: &'a Program) -> Self {
+        let index = program
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, function)| (function.name.as_str(), i))
+            .collect();
+        let calls = program
+            .functions
+            .iter()
+            .map(|function| {
+                let mut calls = Vec::new();
+                for statement in &function.body {
+                    statement_calls(&function.name, statement, &mut calls);
+                }
+                calls
+            })
+            .collect();
+        CallGraph {
+            program,
+            calls,
+            index,
+        }
+    }
+
+    /// Return all call sites inside the given function
+    pub fn calls(&self, function: &str) -> &[CallSite] {
+        match self.index.get(function) {
+            Some(&i) => &self.calls[i],
+            None => &[],
+        }
+    }
+
+    /// Return the names of the defined functions that the given function calls directly, without
+    /// duplicates and in the order of the first call
+    pub fn callees(&self, function: &str) -> Vec<&str> {
+        let mut callees = Vec::new();
+        for call in self.calls(function) {
+            if self.index.contains_key(call.callee.as_str()) && !callees.contains(&&*call.callee) {
+                callees.push(call.callee.as_str());
+            }
+        }
+        callees
+    }
+
+    /// Return all call sites whose callee is not defined in the program
+    pub fn undefined_calls(&self) -> Vec<&CallSite> {
+        self.calls
+            .iter()
+            .flatten()
+            .filter(|call| !self.index.contains_key(call.callee.as_str()))
+            .collect()
+    }
+
+    /// Compute the strongly connected components of the call graph with Tarjan's algorithm. Each
+    /// component lists the function indices in source order, the components are ordered by their
+    /// first member.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            next_index: 0,
+            indices: vec![None; self.calls.len()],
+            low_links: vec![0; self.calls.len()],
+            stack: Vec::new(),
+            on_stack: vec![false; self.calls.len()],
+            components: Vec::new(),
+        };
+        for node in 0..self.calls.len() {
+            if tarjan.indices[node].is_none() {
+                tarjan.visit(node);
+            }
+        }
+        let mut components = tarjan.components;
+        for component in components.iter_mut() {
+            component.sort_unstable();
+        }
+        components.sort_unstable();
+        components
+    }
+
+    /// Return all direct and mutual recursions of the program
+    pub fn recursion_cycles(&self) -> Vec<RecursionCycle> {
+        self.strongly_connected_components()
+            .into_iter()
+            .filter(|component| {
+                component.len() > 1 || self.successors(component[0]).contains(&component[0])
+            })
+            .map(|component| RecursionCycle {
+                functions: component
+                    .iter()
+                    .map(|&i| self.program.functions[i].name.clone())
+                    .collect(),
+                direct: component.len() == 1,
+                may_terminate: component.iter().any(|&i| self.can_escape(i, &component)),
+            })
+            .collect()
+    }
+
+    /// Return the names of all functions that can be reached from `main`, including `main`
+    pub fn reachable_from_main(&self) -> HashSet<&'a str> {
+        let mut reachable = HashSet::new();
+        let mut worklist: Vec<usize> = self
+            .index
+            .get(Self::ENTRY_POINT)
+            .copied()
+            .into_iter()
+            .collect();
+        while let Some(node) = worklist.pop() {
+            if reachable.insert(self.program.functions[node].name.as_str()) {
+                worklist.extend(self.successors(node));
+            }
+        }
+        reachable
+    }
+
+    /// Return the names of all functions that are never executed because `main` cannot reach
+    /// them, in source order
+    pub fn unreachable_functions(&self) -> Vec<&'a str> {
+        let reachable = self.reachable_from_main();
+        self.program
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .filter(|name| !reachable.contains(name))
+            .collect()
+    }
+
+    /// Export the call graph in the DOT format of Graphviz. Edges are labelled with the lines of
+    /// the call sites, members of recursion cycles are filled and unreachable functions dashed.
+    pub fn to_dot(&self) -> String {
+        let unreachable = self.unreachable_functions();
+        let cycles = self.recursion_cycles();
+        let mut dot = String::from("digraph calls {\n    node [shape=ellipse];\n");
+        for function in &self.program.functions {
+            let mut styles = Vec::new();
+            let mut attributes = vec![format!(
+                "label=\"{} {}()\"",
+                function.return_type, function.name
+            )];
+            if unreachable.contains(&function.name.as_str()) {
+                styles.push("dashed");
+            }
+            if let Some(cycle) = cycles
+                .iter()
+                .find(|cycle| cycle.functions.contains(&function.name))
+            {
+                styles.push("filled");
+                let color = if cycle.may_terminate {
+                    "lightyellow"
+                } else {
+                    "salmon"
+                };
+                attributes.push(format!("fillcolor={}", color));
+            }
+            if !styles.is_empty() {
+                attributes.push(format!("style=\"{}\"", styles.join(",")));
+            }
+            writeln!(
+                dot,
+                "    \"{}\" [{}];",
+                function.name,
+                attributes.join(", ")
+            )
+            .unwrap();
+        }
+        for (i, function) in self.program.functions.iter().enumerate() {
+            for callee in self.callees(&function.name) {
+                let lines: Vec<String> = self.calls[i]
+                    .iter()
+                    .filter(|call| call.callee == callee)
+                    .map(|call| call.line.to_string())
+                    .collect();
+                writeln!(
+                    dot,
+                    "    \"{}\" -> \"{}\" [label=\"line {}\"];",
+                    function.name,
+                    callee,
+                    lines.join(", ")
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Export the call graph, the recursion cycles and the unreachable functions as JSON
+    pub fn to_json(&self) -> String {
+        let reachable = self.reachable_from_main();
+        let functions: Vec<String> = self
+            .program
+            .functions
+            .iter()
+            .zip(&self.calls)
+            .map(|(function, calls)| {
+                let calls: Vec<String> = calls.iter().map(call_to_json).collect();
+                format!(
+                    "{{\"name\":{},\"return_type\":{},\"line\":{},\"reachable\":{},\"calls\":[{}]}}",
+                    json_string(&function.name),
+                    json_string(function.return_type.keyword()),
+                    function.line,
+                    reachable.contains(function.name.as_str()),
+                    calls.join(",")
+                )
+            })
+            .collect();
+        let recursion: Vec<String> = self
+            .recursion_cycles()
+            .iter()
+            .map(|cycle| {
+                format!(
+                    "{{\"functions\":{},\"direct\":{},\"may_terminate\":{}}}",
+                    json_string_array(&cycle.functions),
+                    cycle.direct,
+                    cycle.may_terminate
+                )
+            })
+            .collect();
+        let undefined: Vec<String> = self
+            .undefined_calls()
+            .into_iter()
+            .map(call_to_json)
+            .collect();
+        format!(
+            "{{\"functions\":[{}],\"recursion\":[{}],\"unreachable\":{},\"undefined_calls\":[{}]}}",
+            functions.join(","),
+            recursion.join(","),
+            json_string_array(&self.unreachable_functions()),
+            undefined.join(",")
+        )
+    }
+
+    /// Indices of the defined functions called by the given function
+    fn successors(&self, node: usize) -> Vec<usize> {
+        self.callees(&self.program.functions[node].name)
+            .into_iter()
+            .map(|callee| self.index[callee])
+            .collect()
+    }
+
+    /// Check whether the function can return without definitely calling a member of its
+    /// recursion cycle first, i.e. whether its exit block can be reached on a path without such
+    /// a call.
+    fn can_escape(&self, node: usize, cycle: &[usize]) -> bool {
+        let members: HashSet<&str> = cycle
+            .iter()
+            .map(|&i| self.program.functions[i].name.as_str())
+            .collect();
+        let graph = ControlFlowGraph::build(&self.program.functions[node]);
+        let calls_cycle = |block: usize| {
+            let block = &graph.blocks[block];
+            let mut callees = Vec::new();
+            for statement in &block.statements {
+                definite_statement_calls(statement, &mut callees);
+            }
+            match &block.terminator {
+                Terminator::Branch { condition, .. } => definite_calls(condition, &mut callees),
+                Terminator::Return {
+                    value: Some(value), ..
+                } => definite_calls(value, &mut callees),
+                _ => {}
+            }
+            callees.iter().any(|callee| members.contains(callee))
+        };
+        let mut visited = vec![false; graph.blocks.len()];
+        let mut worklist = vec![ControlFlowGraph::ENTRY];
+        while let Some(block) = worklist.pop() {
+            if visited[block] || calls_cycle(block) {
+                continue;
+            }
+            visited[block] = true;
+            if block == ControlFlowGraph::EXIT {
+                return true;
+            }
+            worklist.extend(graph.successors(block).into_iter().map(|(to, _)| to));
+        }
+        false
+    }
+}
+
+/// State of Tarjan's strongly connected components algorithm
+struct Tarjan<'g, 'a> {
+    graph: &'g CallGraph<'a>,
+    next_index: usize,
+    indices: Vec<Option<usize>>,
+    low_links: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_, '_> {
+    fn visit(&mut self, node: usize) {
+        self.indices[node] = Some(self.next_index);
+        self.low_links[node] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for successor in self.graph.successors(node) {
+            match self.indices[successor] {
+                None => {
+                    self.visit(successor);
+                    self.low_links[node] = self.low_links[node].min(self.low_links[successor]);
+                }
+                Some(index) if self.on_stack[successor] => {
+                    self.low_links[node] = self.low_links[node].min(index);
+                }
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.low_links[node]) == self.indices[node] {
+            let mut component = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+/// Collect all call sites of a statement and its nested statements
+fn statement_calls(caller: &str, statement: &Statement, calls: &mut Vec<CallSite>) {
+    let add_expression = |expression: &Expression, calls: &mut Vec<CallSite>| {
+        expression_calls(caller, expression, calls)
+    };
+    match statement {
+        Statement::Block { statements, .. } => {
+            for statement in statements {
+                statement_calls(caller, statement, calls);
+            }
+        }
+        Statement::If {
+            condition,
+            then_branch,
+            ..
+        } => {
+            add_expression(condition, calls);
+            statement_calls(caller, then_branch, calls);
+        }
+        Statement::Return { value, .. } => {
+            if let Some(value) = value {
+                add_expression(value, calls);
+            }
+        }
+        Statement::Printf { value, .. } | Statement::Assign { value, .. } => {
+            add_expression(value, calls)
+        }
+        Statement::Call { name, line } => calls.push(CallSite {
+            caller: caller.to_string(),
+            callee: name.clone(),
+            line: *line,
+        }),
+    }
+}
+
+fn expression_calls(caller: &str, expression: &Expression, calls: &mut Vec<CallSite>) {
+    match &expression.kind {
+        ExpressionKind::Call(name) => calls.push(CallSite {
+            caller: caller.to_string(),
+            callee: name.clone(),
+            line: expression.line,
+        }),
+        ExpressionKind::Assign { value, .. } => expression_calls(caller, value, calls),
+        ExpressionKind::Unary { operand, .. } => expression_calls(caller, operand, calls),
+        ExpressionKind::Binary { lhs, rhs, .. } => {
+            expression_calls(caller, lhs, calls);
+            expression_calls(caller, rhs, calls);
+        }
+        ExpressionKind::Int(_)
+        | ExpressionKind::Float(_)
+        | ExpressionKind::Bool(_)
+        | ExpressionKind::Variable(_) => {}
+    }
+}
+
+/// Collect the callees of a simple statement that are called whenever the statement executes
+fn definite_statement_calls<'a>(statement: &'a Statement, callees: &mut Vec<&'a str>) {
+    match statement {
+        Statement::Call { name, .. } => callees.push(name),
+        Statement::Printf { value, .. } | Statement::Assign { value, .. } => {
+            definite_calls(value, callees)
+        }
+        // Nested statements are split into separate basic blocks
+        Statement::Block { .. } | Statement::If { .. } | Statement::Return { .. } => {}
+    }
+}
+
+/// Collect the callees of an expression that are called whenever the expression is evaluated. The
+/// right operand of `&&` and `||` is skipped because of short-circuit evaluation.
+fn definite_calls<'a>(expression: &'a Expression, callees: &mut Vec<&'a str>) {
+    match &expression.kind {
+        ExpressionKind::Call(name) => callees.push(name),
+        ExpressionKind::Assign { value, .. } => definite_calls(value, callees),
+        ExpressionKind::Unary { operand, .. } => definite_calls(operand, callees),
+        ExpressionKind::Binary {
+            op: BinaryOp::And | BinaryOp::Or,
+            lhs,
+            ..
+        } => definite_calls(lhs, callees),
+        ExpressionKind::Binary { lhs, rhs, .. } => {
+            definite_calls(lhs, callees);
+            definite_calls(rhs, callees);
+        }
+        ExpressionKind::Int(_)
+        | ExpressionKind::Float(_)
+        | ExpressionKind::Bool(_)
+        | ExpressionKind::Variable(_) => {}
+    }
+}
+
+fn call_to_json(call: &CallSite) -> String {
+    format!(
+        "{{\"caller\":{},\"callee\":{},\"line\":{}}}",
+        json_string(&call.caller),
+        json_string(&call.callee),
+        call.line
+    )
+}
+
+fn json_string_array<S: AsRef<str>>(values: &[S]) -> String {
+    let values: Vec<String> = values
+        .iter()
+        .map(|value| json_string(value.as_ref()))
+        .collect();
+    format!("[{}]", values.join(","))
+}
+
+/// Quote and escape a string for JSON
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::callgraph::{CallGraph, RecursionCycle};
+    use crate::C1Parser;
+
+    #[test]
+    fn example_program() {
+        let program = C1Parser::parse_program(include_str!("../tests/data/beispiel.c-1")).unwrap();
+        let graph = CallGraph::build(&program);
+        assert_eq!(graph.callees("main"), vec!["blub", "blah"]);
+        assert_eq!(graph.callees("blah"), vec!["blub"]);
+        assert_eq!(graph.calls("blah").len(), 4);
+        assert!(graph.recursion_cycles().is_empty());
+        assert!(graph.unreachable_functions().is_empty());
+    }
+
+    #[test]
+    fn direct_and_mutual_recursion() {
+        let program = C1Parser::parse_program(
+            "int fac() { if (n <= 1) return 1; n = n - 1; return fac() * 2; }
+             void ping() { pong(); }
+             void pong() { if (x) return; ping(); }
+             void main() { printf(fac()); ping(); }",
+        )
+        .unwrap();
+        let graph = CallGraph::build(&program);
+        assert_eq!(
+            graph.recursion_cycles(),
+            vec![
+                RecursionCycle {
+                    functions: vec!["fac".to_string()],
+                    direct: true,
+                    may_terminate: true,
+                },
+                RecursionCycle {
+                    functions: vec!["ping".to_string(), "pong".to_string()],
+                    direct: false,
+                    may_terminate: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn recursion_without_conditional_return_is_flagged() {
+        let program = C1Parser::parse_program(
+            "int loop() { printf(1); return loop(); }
+             void a() { if (x) { printf(x); } b(); }
+             void b() { a(); return; }
+             void c() { if (x || c()) return; }
+             void main() { loop(); a(); c(); }",
+        )
+        .unwrap();
+        let cycles = CallGraph::build(&program).recursion_cycles();
+        assert_eq!(cycles.len(), 3);
+        assert!(!cycles[0].may_terminate);
+        assert!(!cycles[1].may_terminate);
+        // The recursive call is only evaluated if x is false
+        assert!(cycles[2].may_terminate);
+    }
+
+    #[test]
+    fn dead_functions_and_exports() {
+        let program = C1Parser::parse_program(
+            "void main() { used(); }\nvoid used() { missing(); }\nint dead() { return dead(); }",
+        )
+        .unwrap();
+        let graph = CallGraph::build(&program);
+        assert_eq!(graph.unreachable_functions(), vec!["dead"]);
+        assert_eq!(graph.undefined_calls()[0].callee, "missing");
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("\"main\" -> \"used\" [label=\"line 1\"];"));
+        assert!(dot.contains(
+            "\"dead\" [label=\"int dead()\", fillcolor=salmon, style=\"dashed,filled\"];"
+        ));
+
+        let json = graph.to_json();
+        assert!(json.starts_with(
+            "{\"functions\":[{\"name\":\"main\",\"return_type\":\"void\",\"line\":1,\"reachable\":true,\
+             \"calls\":[{\"caller\":\"main\",\"callee\":\"used\",\"line\":1}]}"
+        ));
+        assert!(json.ends_with(
+            "\"recursion\":[{\"functions\":[\"dead\"],\"direct\":true,\"may_terminate\":false}],\
+             \"unreachable\":[\"dead\"],\
+             \"undefined_calls\":[{\"caller\":\"used\",\"callee\":\"missing\",\"line\":2}]}"
+        ));
+    }
+}
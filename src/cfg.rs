@@ -0,0 +1,415 @@
+use crate::ast::{Expression, FunctionDefinition, Program, Statement};
+use std::fmt::Write;
+
+/// Index of a basic block in `ControlFlowGraph::blocks`
+pub type BlockId = usize;
+
+/// Label of an edge between two basic blocks
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum EdgeKind {
+    /// Taken if the condition of an `if` evaluates to true
+    True,
+    /// Taken if the condition of an `if` evaluates to false
+    False,
+    /// Unconditional control flow, including the flow from a `return` to the exit block
+    Fallthrough,
+}
+
+impl EdgeKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            EdgeKind::True => "true",
+            EdgeKind::False => "false",
+            EdgeKind::Fallthrough => "fallthrough",
+        }
+    }
+}
+
+/// The way control leaves a basic block
+#[derive(Debug, PartialEq, Clone)]
+pub enum Terminator<'a> {
+    /// Continue with the given block
+    Jump(BlockId),
+    /// Evaluate the condition of an `if` statement and continue with one of the two blocks
+    Branch {
+        condition: &'a Expression,
+        line: usize,
+        on_true: BlockId,
+        on_false: BlockId,
+    },
+    /// Leave the function, optionally with a value
+    Return {
+        value: Option<&'a Expression>,
+        line: usize,
+    },
+    /// Terminator of the synthetic exit block
+    Exit,
+}
+
+/// A maximal sequence of statements without control flow. Only assignments, `printf` and function
+/// call statements are stored in `statements`, conditions and returns end up in the terminator.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock<'a> {
+    pub id: BlockId,
+    pub statements: Vec<&'a Statement>,
+    pub terminator: Terminator<'a>,
+}
+
+/// Control-flow graph of a single function definition. Block 0 is the entry block and block 1 is
+/// a synthetic, empty exit block that every `return` and the end of the function body lead to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ControlFlowGraph<'a> {
+    pub function: &'a FunctionDefinition,
+    pub blocks: Vec<BasicBlock<'a>>,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    pub const ENTRY: BlockId = 0;
+    pub const EXIT: BlockId = 1;
+
+    /// Build the control-flow graph for the given function
+    pub fn build(function: &'a FunctionDefinition) -> Self {
+        let mut builder = CfgBuilder {
+            blocks: Vec::new(),
+            current: None,
+        };
+        let entry = builder.new_block();
+        let exit = builder.new_block();
+        builder.blocks[exit].terminator = Terminator::Exit;
+        builder.current = Some(entry);
+        builder.statement_list(&function.body);
+        ControlFlowGraph {
+            function,
+            blocks: builder.blocks,
+        }
+    }
+
+    /// Build the control-flow graphs of all functions of a program in source order
+    pub fn build_all(program: &'a Program) -> Vec<Self> {
+        program
+            .functions
+            .iter()
+            .map(ControlFlowGraph::build)
+            .collect()
+    }
+
+    /// Return the outgoing edges of a block
+    pub fn successors(&self, block: BlockId) -> Vec<(BlockId, EdgeKind)> {
+        match self.blocks[block].terminator {
+            Terminator::Jump(target) => vec![(target, EdgeKind::Fallthrough)],
+            Terminator::Branch {
+                on_true, on_false, ..
+            } => vec![(on_true, EdgeKind::True), (on_false, EdgeKind::False)],
+            Terminator::Return { .. } => vec![(Self::EXIT, EdgeKind::Fallthrough)],
+            Terminator::Exit => vec![],
+        }
+    }
+
+    /// Return the incoming edges of a block
+    pub fn predecessors(&self, block: BlockId) -> Vec<(BlockId, EdgeKind)> {
+        self.edges()
+            .into_iter()
+            .filter(|(_, to, _)| *to == block)
+            .map(|(from, _, kind)| (from, kind))
+            .collect()
+    }
+
+    /// Return all edges of the graph as (from, to, kind) triples
+    pub fn edges(&self) -> Vec<(BlockId, BlockId, EdgeKind)> {
+        self.blocks
+            .iter()
+            .flat_map(|block| {
+                self.successors(block.id)
+                    .into_iter()
+                    .map(move |(to, kind)| (block.id, to, kind))
+            })
+            .collect()
+    }
+
+    /// Return the blocks reachable from the entry block in reverse postorder, which is the usual
+    /// iteration order for forward data-flow analyses
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // Iterative depth-first search, the bool marks whether the children were already pushed
+        let mut stack = vec![(Self::ENTRY, false)];
+        while let Some((block, children_done)) = stack.pop() {
+            if children_done {
+                postorder.push(block);
+                continue;
+            }
+            if visited[block] {
+                continue;
+            }
+            visited[block] = true;
+            stack.push((block, true));
+            for (successor, _) in self.successors(block).into_iter().rev() {
+                if !visited[successor] {
+                    stack.push((successor, false));
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Return the ids of all blocks that cannot be reached from the entry block and contain code
+    pub fn unreachable_blocks(&self) -> Vec<BlockId> {
+        let reachable = self.reverse_postorder();
+        self.blocks
+            .iter()
+            .filter(|block| block.id != Self::EXIT && !reachable.contains(&block.id))
+            .map(|block| block.id)
+            .collect()
+    }
+
+    /// Export the graph in the DOT format of Graphviz
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph {} {{", quote(&self.function.name)).unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        self.write_dot_body(&mut dot, "    ", "");
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write the nodes and edges of the graph. Node names are prefixed so that the graphs of
+    /// several functions can share one DOT file.
+    fn write_dot_body(&self, dot: &mut String, indent: &str, prefix: &str) {
+        for block in &self.blocks {
+            writeln!(
+                dot,
+                "{}{}{} [label={}];",
+                indent,
+                prefix,
+                block_name(block.id),
+                quote(&self.block_label(block))
+            )
+            .unwrap();
+        }
+        for (from, to, kind) in self.edges() {
+            writeln!(
+                dot,
+                "{}{}{} -> {}{} [label=\"{}\"];",
+                indent,
+                prefix,
+                block_name(from),
+                prefix,
+                block_name(to),
+                kind.label()
+            )
+            .unwrap();
+        }
+    }
+
+    /// Text shown for a block: its name followed by one left-aligned line per statement
+    fn block_label(&self, block: &BasicBlock) -> String {
+        let title = match block.id {
+            Self::ENTRY => format!("{} (entry)", block_name(block.id)),
+            Self::EXIT => format!("{} (exit)", block_name(block.id)),
+            _ => block_name(block.id),
+        };
+        let mut lines = vec![title];
+        for statement in &block.statements {
+            lines.push(format!("{}: {}", statement.line(), statement));
+        }
+        match &block.terminator {
+            Terminator::Branch {
+                condition, line, ..
+            } => lines.push(format!("{}: if ({})", line, condition)),
+            Terminator::Return { value: None, line } => lines.push(format!("{}: return;", line)),
+            Terminator::Return {
+                value: Some(value),
+                line,
+            } => lines.push(format!("{}: return {};", line, value)),
+            Terminator::Jump(_) | Terminator::Exit => {}
+        }
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+/// Export the control-flow graphs of several functions as one DOT graph with a cluster per
+/// function
+pub fn cfgs_to_dot(graphs: &[ControlFlowGraph]) -> String {
+    let mut dot =
+        String::from("digraph program {\n    node [shape=box, fontname=\"monospace\"];\n");
+    for graph in graphs {
+        let name = &graph.function.name;
+        writeln!(
+            dot,
+            "    subgraph {} {{",
+            quote(&format!("cluster_{}", name))
+        )
+        .unwrap();
+        writeln!(dot, "        label={};", quote(name)).unwrap();
+        graph.write_dot_body(&mut dot, "        ", &format!("{}_", name));
+        dot.push_str("    }\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn block_name(block: BlockId) -> String {
+    format!("B{}", block)
+}
+
+/// Quote a string for use as a DOT identifier. Newlines become left-aligned line breaks.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\l"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Helper for building a graph from the nested statements of a function body
+struct CfgBuilder<'a> {
+    blocks: Vec<BasicBlock<'a>>,
+    /// Block that receives the next statement. None after a return, in which case the next
+    /// statement starts a new, unreachable block.
+    current: Option<BlockId>,
+}
+
+impl<'a> CfgBuilder<'a> {
+    /// Create a new block that falls through to the exit block until its terminator is set
+    fn new_block(&mut self) -> BlockId {
+        let id = self.blocks.len();
+        self.blocks.push(BasicBlock {
+            id,
+            statements: Vec::new(),
+            terminator: Terminator::Jump(ControlFlowGraph::EXIT),
+        });
+        id
+    }
+
+    /// Return the block that receives the next statement, creating one if necessary
+    fn current_block(&mut self) -> BlockId {
+        match self.current {
+            Some(block) => block,
+            None => {
+                let block = self.new_block();
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    fn statement_list(&mut self, statements: &'a [Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Block { statements, .. } => self.statement_list(statements),
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => {
+                let condition_block = self.current_block();
+                let then_block = self.new_block();
+                let join_block = self.new_block();
+                self.blocks[condition_block].terminator = Terminator::Branch {
+                    condition,
+                    line: *line,
+                    on_true: then_block,
+                    on_false: join_block,
+                };
+                self.current = Some(then_block);
+                self.statement(then_branch);
+                if let Some(end) = self.current {
+                    self.blocks[end].terminator = Terminator::Jump(join_block);
+                }
+                self.current = Some(join_block);
+            }
+            Statement::Return { value, line } => {
+                let block = self.current_block();
+                self.blocks[block].terminator = Terminator::Return {
+                    value: value.as_ref(),
+                    line: *line,
+                };
+                self.current = None;
+            }
+            Statement::Printf { .. } | Statement::Assign { .. } | Statement::Call { .. } => {
+                let block = self.current_block();
+                self.blocks[block].statements.push(statement);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg::{ControlFlowGraph, EdgeKind, Terminator};
+    use crate::C1Parser;
+
+    #[test]
+    fn straight_line_code_is_one_block() {
+        let program = C1Parser::parse_program("void main() { a = 1; b = 2; printf(a); }").unwrap();
+        let graph = ControlFlowGraph::build(&program.functions[0]);
+        assert_eq!(graph.blocks.len(), 2);
+        assert_eq!(graph.blocks[0].statements.len(), 3);
+        assert_eq!(
+            graph.successors(ControlFlowGraph::ENTRY),
+            vec![(ControlFlowGraph::EXIT, EdgeKind::Fallthrough)]
+        );
+    }
+
+    #[test]
+    fn blocks_are_split_at_conditions_and_returns() {
+        let program = C1Parser::parse_program(
+            "int blub() {\n a = 1;\n if (a < 2) return a;\n printf(a);\n return 0;\n}",
+        )
+        .unwrap();
+        let graph = ControlFlowGraph::build(&program.functions[0]);
+        // entry, exit, then, join
+        assert_eq!(graph.blocks.len(), 4);
+        assert!(matches!(
+            graph.blocks[0].terminator,
+            Terminator::Branch { line: 3, .. }
+        ));
+        assert_eq!(
+            graph.successors(0),
+            vec![(2, EdgeKind::True), (3, EdgeKind::False)]
+        );
+        assert_eq!(graph.successors(2), vec![(1, EdgeKind::Fallthrough)]);
+        assert_eq!(graph.blocks[3].statements.len(), 1);
+        assert!(matches!(
+            graph.blocks[3].terminator,
+            Terminator::Return { line: 5, .. }
+        ));
+        assert_eq!(graph.predecessors(ControlFlowGraph::EXIT).len(), 2);
+    }
+
+    #[test]
+    fn code_after_return_is_unreachable() {
+        let program =
+            C1Parser::parse_program("void main() { return; printf(1); { printf(2); } }").unwrap();
+        let graph = ControlFlowGraph::build(&program.functions[0]);
+        assert_eq!(graph.unreachable_blocks(), vec![2]);
+        assert_eq!(graph.blocks[2].statements.len(), 2);
+        assert_eq!(graph.reverse_postorder(), vec![0, 1]);
+    }
+
+    #[test]
+    fn dot_export_contains_statements_and_labels() {
+        let program =
+            C1Parser::parse_program("void main() {\n a = 1;\n if (a <= 2) {\n  printf(a);\n }\n}")
+                .unwrap();
+        let dot = ControlFlowGraph::build(&program.functions[0]).to_dot();
+        assert!(dot.starts_with("digraph \"main\" {"));
+        assert!(dot.contains("B0 [label=\"B0 (entry)\\l2: a = 1;\\l3: if (a <= 2)\\l\"];"));
+        assert!(dot.contains("B0 -> B2 [label=\"true\"];"));
+        assert!(dot.contains("B0 -> B3 [label=\"false\"];"));
+        assert!(dot.contains("B2 [label=\"B2\\l4: printf(a);\\l\"];"));
+        assert!(dot.contains("B2 -> B3 [label=\"fallthrough\"];"));
+    }
+}
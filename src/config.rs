@@ -0,0 +1,601 @@
+use crate::diagnostic::{Diagnostic, Level};
+use crate::lint::{find_rule, Linter};
+use crate::parser::{C1Parser, ParseOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where the variables of a program live. The grammar of C(-1) has no declarations, so it does
+/// not say whether `a` in two different functions is the same variable.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
+pub enum Storage {
+    /// Every function call gets a fresh frame. A variable exists from its first assignment until
+    /// the call returns and is invisible to callers and callees, also to recursive calls of the
+    /// same function.
+    #[default]
+    Local,
+    /// All functions share one store that lives as long as the program. A call can read and
+    /// overwrite every variable of its caller, so analyses have to assume that any call changes
+    /// any variable.
+    Global,
+}
+
+impl Storage {
+    pub const ALL: &'static [Storage] = &[Storage::Local, Storage::Global];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Storage::Local => "local",
+            Storage::Global => "global",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Storage> {
+        Storage::ALL
+            .iter()
+            .copied()
+            .find(|storage| storage.name() == name)
+    }
+}
+
+/// Options that define the dialect of C(-1) a program is written in. There are no grammar
+/// extensions yet, so the storage of variables is the only option.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LanguageOptions {
+    pub storage: Storage,
+}
+
+/// Project configuration, usually read from a `c1.toml` file:
+///
+/// ```toml
+/// warnings-as-errors = false
+/// max-errors = 20
+///
+/// [parser]
+/// max-depth = 64
+///
+/// [language]
+/// storage = "local"
+///
+/// [lints]
+/// self-assignment = "deny"
+/// empty-block = "allow"
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Config {
+    /// Levels of lint rules that differ from their default level
+    pub lint_levels: Vec<(&'static str, Level)>,
+    pub max_depth: usize,
+    /// Report all warnings as errors
+    pub warnings_as_errors: bool,
+    /// Maximum number of errors that are reported, None for no limit
+    pub max_errors: Option<usize>,
+    pub language: LanguageOptions,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            lint_levels: Vec::new(),
+            max_depth: ParseOptions::DEFAULT_MAX_DEPTH,
+            warnings_as_errors: false,
+            max_errors: None,
+            language: LanguageOptions::default(),
+        }
+    }
+}
+
+impl Config {
+    pub const FILE_NAME: &'static str = "c1.toml";
+
+    /// Find the configuration file for the given input file by looking into the directory of the
+    /// input and then into each of its parent directories
+    pub fn discover(input: &Path) -> Option<PathBuf> {
+        // The parent of a bare file name is empty, so the walk starts from the absolute path
+        let input = std::env::current_dir().ok()?.join(input);
+        let start = if input.is_dir() {
+            input.as_path()
+        } else {
+            input.parent()?
+        };
+        start
+            .ancestors()
+            .map(|directory| directory.join(Self::FILE_NAME))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Load the configuration for the given input file. Returns the default configuration if no
+    /// configuration file exists.
+    pub fn for_input(input: &Path) -> Result<(Config, Vec<Diagnostic>), String> {
+        match Self::discover(input) {
+            Some(path) => Self::load(&path),
+            None => Ok((Config::default(), Vec::new())),
+        }
+    }
+
+    /// Read a configuration file. Invalid entries are skipped and reported as diagnostics.
+    pub fn load(path: &Path) -> Result<(Config, Vec<Diagnostic>), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        Ok(Self::from_toml(&text, &path.display().to_string()))
+    }
+
+    /// Parse a configuration from its text. `file` is used to point diagnostics to the
+    /// configuration file.
+    pub fn from_toml(text: &str, file: &str) -> (Config, Vec<Diagnostic>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        for entry in parse_toml(text, &mut errors) {
+            let key = match entry.table.as_str() {
+                "" => entry.key.clone(),
+                table => format!("{}.{}", table, entry.key),
+            };
+            let result = match (entry.table.as_str(), entry.key.as_str()) {
+                ("", "warnings-as-errors") => entry.value.as_bool().map(|value| {
+                    config.warnings_as_errors = value;
+                }),
+                ("", "max-errors") => entry.value.as_positive_integer().map(|value| {
+                    config.max_errors = Some(value);
+                }),
+                ("parser", "max-depth") => entry.value.as_positive_integer().map(|value| {
+                    config.max_depth = value;
+                }),
+                ("language", "storage") => entry.value.as_str().and_then(|name| {
+                    config.language.storage = Storage::from_name(name).ok_or_else(|| {
+                        format!("invalid storage `{}`, expected local or global", name)
+                    })?;
+                    Ok(())
+                }),
+                ("lints", rule) => match find_rule(rule) {
+                    Some(rule) => entry.value.as_str().and_then(|name| {
+                        let level = Level::from_name(name).ok_or_else(|| {
+                            format!("invalid level `{}`, expected allow, warn or deny", name)
+                        })?;
+                        config.lint_levels.retain(|(id, _)| *id != rule.id);
+                        config.lint_levels.push((rule.id, level));
+                        Ok(())
+                    }),
+                    None => Err(format!("unknown lint rule `{}`", rule)),
+                },
+                ("" | "parser" | "language", _) => Err("unknown key".to_string()),
+                _ => Err(format!("unknown table `{}`", entry.table)),
+            };
+            if let Err(message) = result {
+                errors.push((entry.line, format!("{}: {}", key, message)));
+            }
+        }
+        errors.sort_by_key(|(line, _)| *line);
+        let diagnostics = errors
+            .into_iter()
+            .map(|(line, message)| Diagnostic {
+                code: "invalid-config",
+                level: Level::Warn,
+                line,
+                message,
+                help: Some(format!("this entry of {} is ignored", Self::FILE_NAME)),
+                file: Some(file.to_string()),
+            })
+            .collect();
+        (config, diagnostics)
+    }
+
+    /// Return the parser options defined by this configuration
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            max_depth: self.max_depth,
+            language: self.language.clone(),
+        }
+    }
+
+    /// Return a linter with the rule levels and language options of this configuration
+    pub fn linter(&self) -> Linter {
+        let mut linter = Linter::new().with_language(self.language.clone());
+        for (rule, level) in &self.lint_levels {
+            // Rules are validated while the configuration is read
+            linter.set_level(rule, *level).unwrap();
+        }
+        linter
+    }
+
+    /// Apply `warnings-as-errors` and `max-errors` to the given diagnostics. If errors are left
+    /// out, a note that says how many is appended.
+    pub fn report(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        let mut reported = Vec::new();
+        let mut omitted = 0;
+        let mut errors = 0;
+        for mut diagnostic in diagnostics {
+            if self.warnings_as_errors && diagnostic.level == Level::Warn {
+                diagnostic.level = Level::Deny;
+            }
+            if diagnostic.is_error() {
+                if self
+                    .max_errors
+                    .is_some_and(|max_errors| errors >= max_errors)
+                {
+                    omitted += 1;
+                    continue;
+                }
+                errors += 1;
+            }
+            reported.push(diagnostic);
+        }
+        if omitted > 0 {
+            reported.push(Diagnostic {
+                code: "too-many-errors",
+                level: Level::Allow,
+                line: reported.last().map_or(1, |diagnostic| diagnostic.line),
+                message: format!("{} more errors were not reported", omitted),
+                help: Some("increase max-errors to see them".to_string()),
+                file: None,
+            });
+        }
+        reported
+    }
+}
+
+/// Parse and lint the given text with the settings of the configuration. Syntax errors are
+/// reported with the code `syntax-error`.
+pub fn check_source(text: &str, config: &Config) -> Vec<Diagnostic> {
+    let diagnostics = match C1Parser::parse_program_with(text, &config.parse_options()) {
+        Ok(program) => config.linter().check(&program),
+        Err(message) => vec![syntax_error(&message)],
+    };
+    config.report(diagnostics)
+}
+
+/// Turn an error message of the parser into a diagnostic
+pub fn syntax_error(message: &str) -> Diagnostic {
+    // Parser messages start with "Line <number>: "
+    let (line, message) = message
+        .strip_prefix("Line ")
+        .and_then(|rest| rest.split_once(": "))
+        .and_then(|(line, message)| Some((line.parse().ok()?, message)))
+        .unwrap_or((1, message));
+    Diagnostic {
+        code: "syntax-error",
+        level: Level::Deny,
+        line,
+        message: message.to_string(),
+        help: None,
+        file: None,
+    }
+}
+
+/// A `key = value` pair of a TOML file together with the table it belongs to
+struct TomlEntry {
+    table: String,
+    key: String,
+    value: TomlValue,
+    line: usize,
+}
+
+/// The subset of TOML values that is used by the configuration file
+#[derive(Debug, PartialEq)]
+enum TomlValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<TomlValue>),
+}
+
+impl TomlValue {
+    fn as_bool(&self) -> Result<bool, String> {
+        match self {
+            TomlValue::Boolean(value) => Ok(*value),
+            _ => Err("expected true or false".to_string()),
+        }
+    }
+
+    fn as_positive_integer(&self) -> Result<usize, String> {
+        match self {
+            TomlValue::Integer(value) if *value > 0 => Ok(*value as usize),
+            _ => Err("expected a positive integer".to_string()),
+        }
+    }
+
+    fn as_str(&self) -> Result<&str, String> {
+        match self {
+            TomlValue::String(value) => Ok(value),
+            _ => Err("expected a string".to_string()),
+        }
+    }
+}
+
+/// Parse the subset of TOML used by configuration files: tables, bare or quoted keys, and
+/// single-line strings, integers, booleans and arrays. Lines that cannot be parsed are reported
+/// as (line, message) pairs.
+fn parse_toml(text: &str, errors: &mut Vec<(usize, String)>) -> Vec<TomlEntry> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let mut scanner = TomlScanner::new(raw_line);
+        scanner.skip_whitespace();
+        if scanner.at_end() {
+            continue;
+        }
+        let result = if scanner.eat('[') {
+            scanner.key().and_then(|name| {
+                scanner.expect(']')?;
+                scanner.expect_end()?;
+                table = name;
+                Ok(None)
+            })
+        } else {
+            scanner.key().and_then(|key| {
+                scanner.expect('=')?;
+                let value = scanner.value()?;
+                scanner.expect_end()?;
+                Ok(Some(TomlEntry {
+                    table: table.clone(),
+                    key,
+                    value,
+                    line,
+                }))
+            })
+        };
+        match result {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None) => {}
+            Err(message) => errors.push((line, message)),
+        }
+    }
+    entries
+}
+
+/// Character based scanner over a single line of a TOML file
+struct TomlScanner<'a> {
+    rest: &'a str,
+}
+
+impl<'a> TomlScanner<'a> {
+    fn new(line: &'a str) -> Self {
+        TomlScanner { rest: line }
+    }
+
+    /// Skip whitespace and a trailing comment
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+        if self.rest.starts_with('#') {
+            self.rest = "";
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// Consume the given character if it comes next
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", c))
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(format!("unexpected `{}`", self.rest))
+        }
+    }
+
+    /// A bare key made of letters, digits, `-` and `_`, or a quoted key
+    fn key(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.rest.starts_with('"') {
+            return self.string();
+        }
+        let length = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(self.rest.len());
+        if length == 0 {
+            return Err("expected a key".to_string());
+        }
+        let (key, rest) = self.rest.split_at(length);
+        self.rest = rest;
+        Ok(key.to_string())
+    }
+
+    fn value(&mut self) -> Result<TomlValue, String> {
+        self.skip_whitespace();
+        if self.rest.starts_with('"') {
+            return self.string().map(TomlValue::String);
+        }
+        if self.eat('[') {
+            let mut values = Vec::new();
+            loop {
+                if self.eat(']') {
+                    break;
+                }
+                if self.at_end() {
+                    return Err("arrays have to end in the same line".to_string());
+                }
+                values.push(self.value()?);
+                if !self.eat(',') && !self.at_end() {
+                    self.expect(']')?;
+                    break;
+                }
+            }
+            return Ok(TomlValue::Array(values));
+        }
+        let length = self
+            .rest
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(length);
+        let value = match word {
+            "true" => TomlValue::Boolean(true),
+            "false" => TomlValue::Boolean(false),
+            _ => TomlValue::Integer(
+                word.replace('_', "")
+                    .parse()
+                    .map_err(|_| format!("invalid value `{}`", word))?,
+            ),
+        };
+        self.rest = rest;
+        Ok(value)
+    }
+
+    /// A basic string in double quotes with the escapes `\"`, `\\`, `\n` and `\t`
+    fn string(&mut self) -> Result<String, String> {
+        let mut chars = self.rest.char_indices().skip(1);
+        let mut value = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[index + 1..];
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    _ => return Err("invalid escape sequence".to_string()),
+                },
+                c => value.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{check_source, Config, Storage};
+    use crate::diagnostic::Level;
+    use std::fs;
+
+    #[test]
+    fn configuration_is_read() {
+        let (config, diagnostics) = Config::from_toml(
+            "# Settings for the exercises
+            warnings-as-errors = true
+            max-errors = 5
+
+            [parser]
+            max-depth = 32 # enough for everyone
+
+            [language]
+            storage = \"global\"
+
+            [lints]
+            \"self-assignment\" = \"deny\"
+            empty-block = \"allow\"
+            ",
+            "c1.toml",
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert!(config.warnings_as_errors);
+        assert_eq!(config.max_errors, Some(5));
+        assert_eq!(config.max_depth, 32);
+        assert_eq!(config.language.storage, Storage::Global);
+        let linter = config.linter();
+        assert_eq!(linter.level("self-assignment"), Some(Level::Deny));
+        assert_eq!(linter.level("empty-block"), Some(Level::Allow));
+        assert_eq!(linter.level("float-equality"), Some(Level::Warn));
+    }
+
+    #[test]
+    fn invalid_entries_are_reported() {
+        let (config, diagnostics) = Config::from_toml(
+            "colour = true
+            max-errors = -1
+            [lints]
+            self-assignment = \"sometimes\"
+            no-such-rule = \"deny\"
+            [language]
+            extensions = [\"goto\"]
+            storage = \"static\"
+            [unknown]
+            key = 1
+            broken = [1, 2
+            ",
+            "c1.toml",
+        );
+        let messages: Vec<String> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .map(|text| text.lines().next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "warning[invalid-config]: c1.toml:1: colour: unknown key",
+                "warning[invalid-config]: c1.toml:2: max-errors: expected a positive integer",
+                "warning[invalid-config]: c1.toml:4: lints.self-assignment: invalid level \
+                 `sometimes`, expected allow, warn or deny",
+                "warning[invalid-config]: c1.toml:5: lints.no-such-rule: unknown lint rule \
+                 `no-such-rule`",
+                "warning[invalid-config]: c1.toml:7: language.extensions: unknown key",
+                "warning[invalid-config]: c1.toml:8: language.storage: invalid storage `static`, \
+                 expected local or global",
+                "warning[invalid-config]: c1.toml:10: unknown.key: unknown table `unknown`",
+                "warning[invalid-config]: c1.toml:11: arrays have to end in the same line",
+            ]
+        );
+        assert_eq!(config.max_errors, None);
+    }
+
+    #[test]
+    fn configuration_is_applied_to_diagnostics() {
+        let text = "void main() { a = a; b = b; c = c; {} }";
+        let config = Config {
+            warnings_as_errors: true,
+            max_errors: Some(2),
+            ..Config::default()
+        };
+        let codes: Vec<(&str, Level)> = check_source(text, &config)
+            .iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.level))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("self-assignment", Level::Deny),
+                ("self-assignment", Level::Deny),
+                ("too-many-errors", Level::Allow),
+            ]
+        );
+
+        let config = Config {
+            max_depth: 1,
+            ..Config::default()
+        };
+        let diagnostics = check_source("void main() {\n { { } }\n}", &config);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "syntax-error");
+        assert_eq!(diagnostics[0].line, 2);
+    }
+
+    #[test]
+    fn configuration_file_is_discovered_in_parent_directories() {
+        let root = std::env::temp_dir().join(format!("c1-config-{}", std::process::id()));
+        let nested = root.join("exercises").join("week3");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(Config::FILE_NAME), "max-errors = 3\n").unwrap();
+        let input = nested.join("main.c-1");
+        fs::write(&input, "void main() {}").unwrap();
+
+        assert_eq!(Config::discover(&input), Some(root.join(Config::FILE_NAME)));
+        let (config, diagnostics) = Config::for_input(&input).unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(config.max_errors, Some(3));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
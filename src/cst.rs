@@ -0,0 +1,499 @@
+use crate::ast::BinaryOp;
+use crate::lexer::{C1Lexer, C1Token, Trivia};
+use crate::parser::{C1Parser, ParseOptions};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Grammar production of a node of the concrete syntax tree
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum SyntaxKind {
+    Program,
+    FunctionDefinition,
+    FunctionCall,
+    StatementList,
+    Block,
+    Statement,
+    IfStatement,
+    ReturnStatement,
+    Printf,
+    Type,
+    StatAssignment,
+    Assignment,
+    Expr,
+    Simpexpr,
+    Term,
+    Factor,
+}
+
+/// A token of the concrete syntax tree with the trivia around it. The trailing trivia reach up to
+/// and including the end of the line of the token, all other trivia lead the next token.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxToken {
+    pub kind: C1Token,
+    pub text: String,
+    pub line: usize,
+    pub leading_trivia: Vec<Trivia>,
+    pub trailing_trivia: Vec<Trivia>,
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in &self.leading_trivia {
+            f.write_str(&trivia.text)?;
+        }
+        f.write_str(&self.text)?;
+        for trivia in &self.trailing_trivia {
+            f.write_str(&trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl fmt::Display for SyntaxElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxElement::Node(node) => write!(f, "{}", node),
+            SyntaxElement::Token(token) => write!(f, "{}", token),
+        }
+    }
+}
+
+/// Node of a grammar production with the nodes and tokens it consists of in source order
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    /// Call the visitor for this node and every node nested in it, parents before children
+    pub fn walk<'a>(&'a self, visitor: &mut impl FnMut(&'a SyntaxNode)) {
+        visitor(self);
+        for child in &self.children {
+            if let SyntaxElement::Node(node) = child {
+                node.walk(visitor);
+            }
+        }
+    }
+
+    /// Return the tokens of the node and its descendants in source order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.walk_tokens(&mut tokens);
+        tokens
+    }
+
+    fn walk_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.walk_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lossless concrete syntax tree of a program. Every token, whitespace and comment of the source
+/// text is part of the tree, so printing the tree gives exactly the source text.
+/// ```
+/// use cb_3::{ParseOptions, SyntaxKind, SyntaxTree};
+/// let text = "void main() {\n\tx = 1 ; // one\n}\n";
+/// let tree = SyntaxTree::parse(text, &ParseOptions::default()).unwrap();
+///
+/// assert_eq!(tree.root.kind, SyntaxKind::Program);
+/// assert_eq!(tree.to_string(), text);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+    /// Trivia after the line of the last token
+    pub end_trivia: Vec<Trivia>,
+}
+
+impl SyntaxTree {
+    /// Parse the text into a concrete syntax tree. The text is checked by the `C1Parser` first,
+    /// so the same programs are accepted with the same errors.
+    pub fn parse(text: &str, options: &ParseOptions) -> Result<SyntaxTree, String> {
+        C1Parser::parse_program_with(text, options)?;
+        let (tokens, end_trivia) = tokens(text);
+        let mut builder = Builder { tokens };
+        Ok(SyntaxTree {
+            root: builder.program(),
+            end_trivia,
+        })
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for trivia in &self.end_trivia {
+            f.write_str(&trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lex the text losslessly and distribute the trivia between the tokens. Returns the tokens and
+/// the trivia after the line of the last token.
+fn tokens(text: &str) -> (VecDeque<SyntaxToken>, Vec<Trivia>) {
+    let mut lexer = C1Lexer::lossless(text);
+    let mut tokens: Vec<SyntaxToken> = Vec::new();
+    while let (Some(kind), Some(text), Some(line)) = (
+        lexer.current_token(),
+        lexer.current_text(),
+        lexer.current_line_number(),
+    ) {
+        let mut leading_trivia = lexer.current_trivia().to_vec();
+        if let Some(previous) = tokens.last_mut() {
+            previous.trailing_trivia = split_line(&mut leading_trivia);
+        }
+        tokens.push(SyntaxToken {
+            kind,
+            text: text.to_string(),
+            line,
+            leading_trivia,
+            trailing_trivia: Vec::new(),
+        });
+        lexer.eat();
+    }
+    let mut end_trivia = lexer.end_trivia().to_vec();
+    if let Some(last) = tokens.last_mut() {
+        last.trailing_trivia = split_line(&mut end_trivia);
+    }
+    (tokens.into(), end_trivia)
+}
+
+/// Remove and return the trivia up to and including the first line break
+fn split_line(trivia: &mut Vec<Trivia>) -> Vec<Trivia> {
+    let end = trivia
+        .iter()
+        .position(|trivia| trivia.kind == C1Token::Linebreak || trivia.text.ends_with('\n'))
+        .map_or(trivia.len(), |index| index + 1);
+    trivia.drain(..end).collect()
+}
+
+/// Recursive descent over the tokens of a program that the `C1Parser` accepted. Every grammar
+/// production is implemented by a method of the same name which returns its node.
+struct Builder {
+    tokens: VecDeque<SyntaxToken>,
+}
+
+impl Builder {
+    /// program ::= ( functiondefinition )* <EOF>
+    fn program(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        while !self.tokens.is_empty() {
+            children.push(SyntaxElement::Node(self.function_definition()));
+        }
+        node(SyntaxKind::Program, children)
+    }
+
+    /// functiondefinition ::= type <ID> "(" ")" "{" statementlist "}"
+    fn function_definition(&mut self) -> SyntaxNode {
+        let mut children = vec![SyntaxElement::Node(self.return_type())];
+        self.eat_tokens(&mut children, 4);
+        children.push(SyntaxElement::Node(self.statement_list()));
+        self.eat(&mut children);
+        node(SyntaxKind::FunctionDefinition, children)
+    }
+
+    /// functioncall ::= <ID> "(" ")"
+    fn function_call(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        self.eat_tokens(&mut children, 3);
+        node(SyntaxKind::FunctionCall, children)
+    }
+
+    /// statementlist ::= ( block )*
+    fn statement_list(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        while !self.tokens.is_empty() && !self.current_matches(C1Token::RightBrace) {
+            children.push(SyntaxElement::Node(self.block()));
+        }
+        node(SyntaxKind::StatementList, children)
+    }
+
+    /// block ::= "{" statementlist "}" | statement
+    fn block(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        if self.current_matches(C1Token::LeftBrace) {
+            self.eat(&mut children);
+            children.push(SyntaxElement::Node(self.statement_list()));
+            self.eat(&mut children);
+        } else {
+            children.push(SyntaxElement::Node(self.statement()));
+        }
+        node(SyntaxKind::Block, children)
+    }
+
+    /// statement ::= ifstatement | returnstatement ";" | printf ";" | statassignment ";"
+    ///             | functioncall ";"
+    fn statement(&mut self) -> SyntaxNode {
+        let inner = match self.current() {
+            Some(C1Token::KwIf) => {
+                let children = vec![SyntaxElement::Node(self.if_statement())];
+                return node(SyntaxKind::Statement, children);
+            }
+            Some(C1Token::KwReturn) => self.return_statement(),
+            Some(C1Token::KwPrintf) => self.printf(),
+            _ if self.next_matches(C1Token::Assign) => self.stat_assignment(),
+            _ => self.function_call(),
+        };
+        let mut children = vec![SyntaxElement::Node(inner)];
+        self.eat(&mut children);
+        node(SyntaxKind::Statement, children)
+    }
+
+    /// ifstatement ::= <KW_IF> "(" assignment ")" block
+    fn if_statement(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        self.eat_tokens(&mut children, 2);
+        children.push(SyntaxElement::Node(self.assignment()));
+        self.eat(&mut children);
+        children.push(SyntaxElement::Node(self.block()));
+        node(SyntaxKind::IfStatement, children)
+    }
+
+    /// returnstatement ::= <KW_RETURN> ( assignment )?
+    fn return_statement(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        self.eat(&mut children);
+        if !self.current_matches(C1Token::Semicolon) {
+            children.push(SyntaxElement::Node(self.assignment()));
+        }
+        node(SyntaxKind::ReturnStatement, children)
+    }
+
+    /// printf ::= <KW_PRINTF> "(" assignment ")"
+    fn printf(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        self.eat_tokens(&mut children, 2);
+        children.push(SyntaxElement::Node(self.assignment()));
+        self.eat(&mut children);
+        node(SyntaxKind::Printf, children)
+    }
+
+    /// type ::= <KW_BOOLEAN> | <KW_FLOAT> | <KW_INT> | <KW_VOID>
+    fn return_type(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        self.eat(&mut children);
+        node(SyntaxKind::Type, children)
+    }
+
+    /// statassignment ::= <ID> "=" assignment
+    fn stat_assignment(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        self.eat_tokens(&mut children, 2);
+        children.push(SyntaxElement::Node(self.assignment()));
+        node(SyntaxKind::StatAssignment, children)
+    }
+
+    /// assignment ::= ( ( <ID> "=" assignment ) | expr )
+    fn assignment(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        if self.current_matches(C1Token::Identifier) && self.next_matches(C1Token::Assign) {
+            self.eat_tokens(&mut children, 2);
+            children.push(SyntaxElement::Node(self.assignment()));
+        } else {
+            children.push(SyntaxElement::Node(self.expr()));
+        }
+        node(SyntaxKind::Assignment, children)
+    }
+
+    /// expr ::= simpexpr ( ( "==" | "!=" | "<=" | ">=" | "<" | ">" ) simpexpr )?
+    fn expr(&mut self) -> SyntaxNode {
+        let mut children = vec![SyntaxElement::Node(self.simpexpr())];
+        if self.current_binary_op(BinaryOp::is_comparison) {
+            self.eat(&mut children);
+            children.push(SyntaxElement::Node(self.simpexpr()));
+        }
+        node(SyntaxKind::Expr, children)
+    }
+
+    /// simpexpr ::= ( "-" )? term ( ( "+" | "-" | "||" ) term )*
+    fn simpexpr(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        if self.current_matches(C1Token::Minus) {
+            self.eat(&mut children);
+        }
+        children.push(SyntaxElement::Node(self.term()));
+        while self
+            .current_binary_op(|op| matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or))
+        {
+            self.eat(&mut children);
+            children.push(SyntaxElement::Node(self.term()));
+        }
+        node(SyntaxKind::Simpexpr, children)
+    }
+
+    /// term ::= factor ( ( "*" | "/" | "&&" ) factor )*
+    fn term(&mut self) -> SyntaxNode {
+        let mut children = vec![SyntaxElement::Node(self.factor())];
+        while self
+            .current_binary_op(|op| matches!(op, BinaryOp::Mul | BinaryOp::Div | BinaryOp::And))
+        {
+            self.eat(&mut children);
+            children.push(SyntaxElement::Node(self.factor()));
+        }
+        node(SyntaxKind::Term, children)
+    }
+
+    /// factor ::= <CONST_INT> | <CONST_FLOAT> | <CONST_BOOLEAN> | functioncall | <ID>
+    ///          | "(" assignment ")"
+    fn factor(&mut self) -> SyntaxNode {
+        let mut children = Vec::new();
+        match self.current() {
+            Some(C1Token::Identifier) if self.next_matches(C1Token::LeftParenthesis) => {
+                children.push(SyntaxElement::Node(self.function_call()));
+            }
+            Some(C1Token::LeftParenthesis) => {
+                self.eat(&mut children);
+                children.push(SyntaxElement::Node(self.assignment()));
+                self.eat(&mut children);
+            }
+            _ => self.eat(&mut children),
+        }
+        node(SyntaxKind::Factor, children)
+    }
+
+    // Helper methods
+
+    fn current(&self) -> Option<C1Token> {
+        self.tokens.front().map(|token| token.kind)
+    }
+
+    fn current_matches(&self, token: C1Token) -> bool {
+        self.current() == Some(token)
+    }
+
+    fn next_matches(&self, token: C1Token) -> bool {
+        self.tokens.get(1).map(|token| token.kind) == Some(token)
+    }
+
+    /// Check whether the current token is a binary operator that satisfies the given filter
+    fn current_binary_op(&self, filter: impl Fn(&BinaryOp) -> bool) -> bool {
+        self.current()
+            .and_then(BinaryOp::from_token)
+            .is_some_and(|op| filter(&op))
+    }
+
+    /// Move the current token into the children
+    fn eat(&mut self, children: &mut Vec<SyntaxElement>) {
+        let token = self.tokens.pop_front().expect("programs are checked");
+        children.push(SyntaxElement::Token(token));
+    }
+
+    fn eat_tokens(&mut self, children: &mut Vec<SyntaxElement>, count: usize) {
+        for _ in 0..count {
+            self.eat(children);
+        }
+    }
+}
+
+fn node(kind: SyntaxKind, children: Vec<SyntaxElement>) -> SyntaxNode {
+    SyntaxNode { kind, children }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cst::{SyntaxElement, SyntaxKind, SyntaxTree};
+    use crate::{C1Token, ParseOptions};
+
+    #[test]
+    fn trees_reproduce_the_input() {
+        let options = ParseOptions::default();
+        let texts = [
+            "",
+            "// only a comment",
+            "\n\n/* header */\nint  main ( ) {\n\treturn -a+ ( b=2 )*f();  // tail\n\n}  \n\n// end\n",
+            "void main() { if (a<=b) { printf(1.5); } if (c) x(); { } return; }",
+        ];
+        for text in texts {
+            let tree = SyntaxTree::parse(text, &options).unwrap();
+            assert_eq!(tree.to_string(), text);
+        }
+        assert!(SyntaxTree::parse("void main() { if (a) }", &options).is_err());
+    }
+
+    #[test]
+    fn nodes_follow_the_grammar() {
+        let tree = SyntaxTree::parse("int f() { x = -1; }", &ParseOptions::default()).unwrap();
+        let mut kinds = Vec::new();
+        tree.root.walk(&mut |node| kinds.push(node.kind));
+        use SyntaxKind::*;
+        assert_eq!(
+            kinds,
+            [
+                Program,
+                FunctionDefinition,
+                Type,
+                StatementList,
+                Block,
+                Statement,
+                StatAssignment,
+                Assignment,
+                Expr,
+                Simpexpr,
+                Term,
+                Factor
+            ]
+        );
+        let SyntaxElement::Node(function) = &tree.root.children[0] else {
+            panic!("expected a function definition");
+        };
+        let texts: Vec<&str> = function
+            .tokens()
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect();
+        assert_eq!(
+            texts,
+            ["int", "f", "(", ")", "{", "x", "=", "-", "1", ";", "}"]
+        );
+    }
+
+    #[test]
+    fn trivia_up_to_the_end_of_the_line_trail_the_token() {
+        let text = "void main() {\n  f(); // call\n  // next\n  g();\n}\n\n// end\n";
+        let tree = SyntaxTree::parse(text, &ParseOptions::default()).unwrap();
+        let tokens = tree.root.tokens();
+        let semicolon = tokens[8];
+        assert_eq!(semicolon.kind, C1Token::Semicolon);
+        let trailing: Vec<&str> = semicolon
+            .trailing_trivia
+            .iter()
+            .map(|trivia| trivia.text.as_str())
+            .collect();
+        assert_eq!(trailing, [" ", "// call\n"]);
+        let g = tokens[9];
+        let leading: Vec<&str> = g
+            .leading_trivia
+            .iter()
+            .map(|trivia| trivia.text.as_str())
+            .collect();
+        assert_eq!(leading, ["  ", "// next\n", "  "]);
+        assert_eq!(g.line, 4);
+        let end: Vec<&str> = tree
+            .end_trivia
+            .iter()
+            .map(|trivia| trivia.text.as_str())
+            .collect();
+        assert_eq!(end, ["\n", "// end\n"]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// Severity of a diagnostic. The order of the variants goes from least to most severe.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub enum Level {
+    /// The diagnostic is suppressed
+    Allow,
+    /// The diagnostic is reported, but does not fail the check
+    Warn,
+    /// The diagnostic is reported as an error
+    Deny,
+}
+
+impl Level {
+    /// Parse a level from its name as used in configuration files and directives
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        }
+    }
+}
+
+/// A message about a specific line of the checked program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    /// Identifier of the rule or check that produced the diagnostic, e.g. `self-assignment`
+    pub code: &'static str,
+    pub level: Level,
+    pub line: usize,
+    pub message: String,
+    pub help: Option<String>,
+    /// File the line belongs to if it is not the checked program itself, e.g. a configuration file
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Deny
+    }
+}
+
+/// Prints the diagnostic in the form `warning[code]: line 3: message`, or
+/// `warning[code]: file:3: message` for other files, followed by the help text on a separate line.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.level {
+            Level::Allow => "note",
+            Level::Warn => "warning",
+            Level::Deny => "error",
+        };
+        match &self.file {
+            Some(file) => write!(f, "{}[{}]: {}:{}: ", severity, self.code, file, self.line)?,
+            None => write!(f, "{}[{}]: line {}: ", severity, self.code, self.line)?,
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(help) = &self.help {
+            write!(f, "\n  = help: {}", help)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::diagnostic::{Diagnostic, Level};
+use crate::lexer::Comment;
+use crate::lint::{find_rule, RULES};
+use std::collections::{HashMap, HashSet};
+
+/// Prefix of a directive that allows rules for the statement in the following line
+const ALLOW_LINE: &str = "c1-allow";
+/// Prefix of a directive that allows rules for the whole file
+const ALLOW_FILE: &str = "c1-allow-file";
+
+/// Lint rules that were allowed by `c1-allow(...)` and `c1-allow-file(...)` comments
+#[derive(Debug, Default, Clone)]
+pub struct Suppressions {
+    file: HashSet<&'static str>,
+    lines: HashMap<usize, HashSet<&'static str>>,
+}
+
+impl Suppressions {
+    /// Collect the directives of the given comments. Malformed directives and unknown rule names
+    /// are returned as diagnostics.
+    ///
+    /// `// c1-allow(rule, ...)` allows the rules for the line following the comment,
+    /// `/* c1-allow-file(rule, ...) */` allows them for the whole file, but only if the comment is
+    /// placed in front of the first token.
+    pub fn from_comments(comments: &[Comment]) -> (Suppressions, Vec<Diagnostic>) {
+        let mut suppressions = Suppressions::default();
+        let mut diagnostics = Vec::new();
+        for comment in comments {
+            let content = comment.content().trim();
+            // The file prefix has to be checked first, since it starts with the line prefix
+            let (is_file, arguments) = if let Some(arguments) = content.strip_prefix(ALLOW_FILE) {
+                (true, arguments)
+            } else if let Some(arguments) = content.strip_prefix(ALLOW_LINE) {
+                (false, arguments)
+            } else {
+                continue;
+            };
+            let names = match arguments
+                .trim()
+                .strip_prefix('(')
+                .and_then(|arguments| arguments.strip_suffix(')'))
+            {
+                Some(names) => names,
+                None => {
+                    diagnostics.push(directive_error(
+                        comment.line,
+                        format!("malformed directive `{}`", content),
+                        "write the directive as `c1-allow(rule, ...)`",
+                    ));
+                    continue;
+                }
+            };
+            if is_file && !comment.before_first_token {
+                diagnostics.push(directive_error(
+                    comment.line,
+                    format!("`{}` is ignored after the first token", ALLOW_FILE),
+                    "move the directive to the top of the file",
+                ));
+                continue;
+            }
+            for name in names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                let rule = match find_rule(name) {
+                    Some(rule) => rule,
+                    None => {
+                        diagnostics.push(Diagnostic {
+                            code: "unknown-rule",
+                            level: Level::Warn,
+                            line: comment.line,
+                            message: format!("unknown lint rule `{}`", name),
+                            help: Some(format!(
+                                "known rules are {}",
+                                RULES
+                                    .iter()
+                                    .map(|rule| rule.id)
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            )),
+                            file: None,
+                        });
+                        continue;
+                    }
+                };
+                if is_file {
+                    suppressions.file.insert(rule.id);
+                } else {
+                    suppressions
+                        .lines
+                        .entry(comment.end_line() + 1)
+                        .or_default()
+                        .insert(rule.id);
+                }
+            }
+        }
+        (suppressions, diagnostics)
+    }
+
+    /// Check whether the diagnostic was allowed by a directive
+    pub fn is_suppressed(&self, diagnostic: &Diagnostic) -> bool {
+        self.file.contains(diagnostic.code)
+            || self
+                .lines
+                .get(&diagnostic.line)
+                .is_some_and(|rules| rules.contains(diagnostic.code))
+    }
+}
+
+fn directive_error(line: usize, message: String, help: &str) -> Diagnostic {
+    Diagnostic {
+        code: "invalid-directive",
+        level: Level::Warn,
+        line,
+        message,
+        help: Some(help.to_string()),
+        file: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{C1Parser, Linter};
+
+    fn lint(text: &str) -> Vec<(&'static str, usize)> {
+        let program = C1Parser::parse_program(text).unwrap();
+        Linter::new()
+            .check(&program)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.line))
+            .collect()
+    }
+
+    #[test]
+    fn line_directives_allow_the_next_line() {
+        let findings = lint(
+            "void main() {
+                // c1-allow(self-assignment)
+                a = a;
+                b = b;
+                /* c1-allow(empty-block, self-assignment) */
+                { c = c; }
+            }",
+        );
+        assert_eq!(findings, vec![("self-assignment", 4)]);
+    }
+
+    #[test]
+    fn file_directives_allow_the_whole_file() {
+        let findings = lint(
+            "/* c1-allow-file(self-assignment) */
+            void main() {
+                a = a;
+                {}
+            }",
+        );
+        assert_eq!(findings, vec![("empty-block", 4)]);
+    }
+
+    #[test]
+    fn invalid_directives_are_reported() {
+        let findings = lint(
+            "void main() {
+                // c1-allow(no-such-rule, self-assignment)
+                a = a;
+                // c1-allow self-assignment
+                /* c1-allow-file(empty-block) */
+                {}
+            }",
+        );
+        assert_eq!(
+            findings,
+            vec![
+                ("unknown-rule", 2),
+                ("invalid-directive", 4),
+                ("invalid-directive", 5),
+                ("empty-block", 6),
+            ]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use crate::ast::format_float;
+use crate::bytecode::{Instruction, Module};
+use crate::value::Value;
+use std::fmt::Write;
+
+impl Module {
+    /// Return a human readable listing of the module. Every instruction is printed with its
+    /// offset and the source line it was compiled from, operands that refer to the constant pool,
+    /// variables or functions are followed by what they refer to.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        writeln!(
+            listing,
+            "; C(-1) module, format version {}",
+            Self::FORMAT_VERSION
+        )
+        .unwrap();
+        if !self.constants.is_empty() {
+            writeln!(listing, "\nconstants:").unwrap();
+            for (index, constant) in self.constants.iter().enumerate() {
+                writeln!(listing, "    #{:<4} {}", index, describe(constant)).unwrap();
+            }
+        }
+        if !self.globals.is_empty() {
+            writeln!(listing, "\nglobals: {}", self.globals.join(", ")).unwrap();
+        }
+        for (index, function) in self.functions.iter().enumerate() {
+            writeln!(
+                listing,
+                "\nfunction {}: {} {}()",
+                index, function.return_type, function.name
+            )
+            .unwrap();
+            if !function.locals.is_empty() {
+                writeln!(listing, "    locals: {}", function.locals.join(", ")).unwrap();
+            }
+            writeln!(listing, "    {:>6}  {:>6}  instruction", "line", "offset").unwrap();
+            for (offset, instruction) in function.code.iter().enumerate() {
+                let line = function.lines.get(offset).copied().unwrap_or(0);
+                let referenced = match *instruction {
+                    Instruction::Constant(index) => {
+                        self.constants.get(index as usize).map(describe)
+                    }
+                    Instruction::Load(slot) | Instruction::Store(slot) => {
+                        function.locals.get(slot as usize).cloned()
+                    }
+                    Instruction::LoadGlobal(slot) | Instruction::StoreGlobal(slot) => {
+                        self.globals.get(slot as usize).cloned()
+                    }
+                    Instruction::Call(index) | Instruction::CallValue(index) => self
+                        .functions
+                        .get(index as usize)
+                        .map(|callee| format!("{}()", callee.name)),
+                    _ => None,
+                };
+                let text = match referenced {
+                    Some(referenced) => format!("{:<20} ; {}", instruction.to_string(), referenced),
+                    None => instruction.to_string(),
+                };
+                writeln!(listing, "    {:>6}  {:>6}  {}", line, offset, text).unwrap();
+            }
+        }
+        listing
+    }
+}
+
+/// Describe a constant with its type, e.g. `float 1.5`
+fn describe(constant: &Value) -> String {
+    match *constant {
+        Value::Float(value) => format!("float {}", format_float(value)),
+        value => format!("{} {}", value.value_type(), value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::Module;
+    use crate::config::LanguageOptions;
+    use crate::C1Parser;
+
+    #[test]
+    fn instructions_are_listed_with_their_lines() {
+        let program = C1Parser::parse_program(
+            "float half() {
+                 return 1 / 2.0;
+             }
+
+             void main() {
+                 x = half();
+                 if (x > 0) printf(x);
+             }",
+        )
+        .unwrap();
+        let module = Module::compile(&program, &LanguageOptions::default()).unwrap();
+        let listing = module.disassemble();
+        assert_eq!(
+            listing,
+            "; C(-1) module, format version 1
+
+constants:
+    #0    int 1
+    #1    float 2.0
+    #2    int 0
+
+function 0: float half()
+      line  offset  instruction
+         2       0  constant 0           ; int 1
+         2       1  constant 1           ; float 2.0
+         2       2  div
+         2       3  return
+         2       4  return_void
+
+function 1: void main()
+    locals: x
+      line  offset  instruction
+         6       0  call_value 0         ; half()
+         6       1  store 0              ; x
+         7       2  load 0               ; x
+         7       3  constant 2           ; int 0
+         7       4  gt
+         7       5  jump_if_false 8
+         7       6  load 0               ; x
+         7       7  print
+         7       8  return_void
+"
+        );
+        // The listing can also be produced from a loaded module
+        let loaded = Module::from_bytes(&module.to_bytes()).unwrap();
+        assert_eq!(loaded.disassemble(), listing);
+    }
+}
@@ -0,0 +1,409 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type, UnaryOp,
+};
+use crate::config::Storage;
+use crate::types::TypeEnvironment;
+use crate::value::Value;
+
+impl Program {
+    /// Return a copy of the program with constant operations evaluated and algebraic identities
+    /// removed, e.g. `2 * 3 + 1` becomes `7` and `x * 1` becomes `x` if `x` is an int.
+    ///
+    /// The folded program behaves exactly like the original one: operations that fail at runtime,
+    /// like an integer division by zero, are kept, and operands are only dropped if they would
+    /// not have been evaluated anyway. The storage decides which assignments are taken into
+    /// account when the types of variables are inferred.
+    pub fn fold_constants(&self, storage: Storage) -> Program {
+        let types = TypeEnvironment::infer(self, storage);
+        Program {
+            functions: self
+                .functions
+                .iter()
+                .map(|function| {
+                    let folder = Folder::new(&types, &function.name);
+                    FunctionDefinition {
+                        body: function
+                            .body
+                            .iter()
+                            .map(|statement| folder.fold_statement(statement))
+                            .collect(),
+                        ..function.clone()
+                    }
+                })
+                .collect(),
+            comments: self.comments.clone(),
+        }
+    }
+}
+
+/// Folds the statements and expressions of a single function
+pub(crate) struct Folder<'a> {
+    types: &'a TypeEnvironment,
+    function: &'a str,
+}
+
+impl<'a> Folder<'a> {
+    pub(crate) fn new(types: &'a TypeEnvironment, function: &'a str) -> Self {
+        Folder { types, function }
+    }
+
+    fn fold_statement(&self, statement: &Statement) -> Statement {
+        match statement {
+            Statement::Block { statements, line } => Statement::Block {
+                statements: statements
+                    .iter()
+                    .map(|statement| self.fold_statement(statement))
+                    .collect(),
+                line: *line,
+            },
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => Statement::If {
+                condition: self.fold(condition),
+                then_branch: Box::new(self.fold_statement(then_branch)),
+                line: *line,
+            },
+            Statement::Return { value, line } => Statement::Return {
+                value: value.as_ref().map(|value| self.fold(value)),
+                line: *line,
+            },
+            Statement::Printf { value, line } => Statement::Printf {
+                value: self.fold(value),
+                line: *line,
+            },
+            Statement::Assign {
+                target,
+                value,
+                line,
+            } => Statement::Assign {
+                target: target.clone(),
+                value: self.fold(value),
+                line: *line,
+            },
+            Statement::Call { .. } => statement.clone(),
+        }
+    }
+
+    /// Fold an expression bottom-up
+    pub(crate) fn fold(&self, expression: &Expression) -> Expression {
+        let line = expression.line;
+        match &expression.kind {
+            ExpressionKind::Assign { target, value } => Expression::new(
+                ExpressionKind::Assign {
+                    target: target.clone(),
+                    value: Box::new(self.fold(value)),
+                },
+                line,
+            ),
+            ExpressionKind::Unary { op, operand } => self.fold_unary(*op, self.fold(operand), line),
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                self.fold_binary(*op, self.fold(lhs), self.fold(rhs), line)
+            }
+            _ => expression.clone(),
+        }
+    }
+
+    fn fold_unary(&self, op: UnaryOp, operand: Expression, line: usize) -> Expression {
+        let UnaryOp::Neg = op;
+        if let Some(value) = constant(&operand) {
+            if let Some(folded) = literal(value.negate(), line) {
+                return folded;
+            }
+        }
+        // `-(-x)` is `x` unless the inner negation converts a bool to an int
+        if let ExpressionKind::Unary { operand: inner, .. } = &operand.kind {
+            if matches!(self.type_of(inner), Some(Type::Int | Type::Float)) {
+                return *inner.clone();
+            }
+        }
+        unary(op, operand, line)
+    }
+
+    fn fold_binary(
+        &self,
+        op: BinaryOp,
+        lhs: Expression,
+        rhs: Expression,
+        line: usize,
+    ) -> Expression {
+        if op.is_logical() {
+            return self.fold_logical(op, lhs, rhs, line);
+        }
+        if let (Some(left), Some(right)) = (constant(&lhs), constant(&rhs)) {
+            // An error like a division by zero has to happen at runtime
+            if let Some(folded) = Value::binary(op, left, right)
+                .ok()
+                .and_then(|value| literal(value, line))
+            {
+                return folded;
+            }
+        }
+        let left = constant(&lhs);
+        let right = constant(&rhs);
+        let lhs_type = self.type_of(&lhs);
+        let rhs_type = self.type_of(&rhs);
+        // Which operand the operation can be replaced with. Adding an int zero to a float is not
+        // an identity, `-0.0 + 0` is `0.0`.
+        let keep_lhs = match op {
+            BinaryOp::Mul if is_one(right, lhs_type) => Some(true),
+            BinaryOp::Mul if is_one(left, rhs_type) => Some(false),
+            BinaryOp::Add if right == Some(Value::Int(0)) && lhs_type == Some(Type::Int) => {
+                Some(true)
+            }
+            BinaryOp::Add if left == Some(Value::Int(0)) && rhs_type == Some(Type::Int) => {
+                Some(false)
+            }
+            BinaryOp::Sub if is_zero(right, lhs_type) => Some(true),
+            BinaryOp::Div if is_one(right, lhs_type) => Some(true),
+            _ => None,
+        };
+        match keep_lhs {
+            Some(true) => lhs,
+            Some(false) => rhs,
+            None => binary(op, lhs, rhs, line),
+        }
+    }
+
+    /// Fold `&&` and `||`. The right operand is only dropped if short-circuit evaluation would
+    /// skip it, so `x && false` is kept because `x` may have side effects.
+    fn fold_logical(
+        &self,
+        op: BinaryOp,
+        lhs: Expression,
+        rhs: Expression,
+        line: usize,
+    ) -> Expression {
+        // The value for which the left operand decides the result on its own
+        let deciding = op == BinaryOp::Or;
+        if let Some(left) = constant(&lhs) {
+            if left.is_truthy() == deciding {
+                return Expression::new(ExpressionKind::Bool(deciding), line);
+            }
+            // The result is the truth value of the right operand
+            return match constant(&rhs) {
+                Some(right) => Expression::new(ExpressionKind::Bool(right.is_truthy()), line),
+                None => self
+                    .truth_value(&rhs)
+                    .unwrap_or_else(|| binary(op, lhs, rhs, line)),
+            };
+        }
+        match constant(&rhs) {
+            Some(right) if right.is_truthy() != deciding => self
+                .truth_value(&lhs)
+                .unwrap_or_else(|| binary(op, lhs, rhs, line)),
+            _ => binary(op, lhs, rhs, line),
+        }
+    }
+
+    /// Return an expression that evaluates `expression` and converts it to a bool, None if its
+    /// type is not known
+    fn truth_value(&self, expression: &Expression) -> Option<Expression> {
+        match self.type_of(expression)? {
+            Type::Bool => Some(expression.clone()),
+            Type::Int | Type::Float => {
+                let zero = Expression::new(ExpressionKind::Int(0), expression.line);
+                Some(binary(
+                    BinaryOp::NotEqual,
+                    expression.clone(),
+                    zero,
+                    expression.line,
+                ))
+            }
+            Type::Void => None,
+        }
+    }
+
+    fn type_of(&self, expression: &Expression) -> Option<Type> {
+        self.types.expression_type(self.function, expression)
+    }
+}
+
+/// Return the value of a literal
+pub(crate) fn constant(expression: &Expression) -> Option<Value> {
+    match expression.kind {
+        ExpressionKind::Int(value) => Some(Value::Int(value)),
+        ExpressionKind::Float(value) => Some(Value::Float(value)),
+        ExpressionKind::Bool(value) => Some(Value::Bool(value)),
+        _ => None,
+    }
+}
+
+/// Turn a value into a literal, None for floats that are not finite because C(-1) has no way to
+/// write them. `-2147483648` would be parsed as the negation of an int that does not fit, so the
+/// smallest int is written as `-2147483647 - 1`.
+pub(crate) fn literal(value: Value, line: usize) -> Option<Expression> {
+    let kind = match value {
+        Value::Int(i32::MIN) => {
+            let max = Expression::new(ExpressionKind::Int(-i32::MAX), line);
+            let one = Expression::new(ExpressionKind::Int(1), line);
+            return Some(binary(BinaryOp::Sub, max, one, line));
+        }
+        Value::Int(value) => ExpressionKind::Int(value),
+        Value::Float(value) if !value.is_finite() => return None,
+        Value::Float(value) => ExpressionKind::Float(value),
+        Value::Bool(value) => ExpressionKind::Bool(value),
+    };
+    Some(Expression::new(kind, line))
+}
+
+/// Whether multiplying or dividing an operand of the given type by `constant` leaves it unchanged
+fn is_one(constant: Option<Value>, operand: Option<Type>) -> bool {
+    match (constant, operand) {
+        (Some(Value::Int(1)), Some(Type::Int | Type::Float)) => true,
+        (Some(Value::Float(value)), Some(Type::Float)) => value == 1.0,
+        _ => false,
+    }
+}
+
+/// Whether subtracting `constant` from an operand of the given type leaves it unchanged
+fn is_zero(constant: Option<Value>, operand: Option<Type>) -> bool {
+    match (constant, operand) {
+        (Some(Value::Int(0)), Some(Type::Int | Type::Float)) => true,
+        (Some(Value::Float(value)), Some(Type::Float)) => value == 0.0 && value.is_sign_positive(),
+        _ => false,
+    }
+}
+
+fn unary(op: UnaryOp, operand: Expression, line: usize) -> Expression {
+    Expression::new(
+        ExpressionKind::Unary {
+            op,
+            operand: Box::new(operand),
+        },
+        line,
+    )
+}
+
+fn binary(op: BinaryOp, lhs: Expression, rhs: Expression, line: usize) -> Expression {
+    Expression::new(
+        ExpressionKind::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        line,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::{C1Parser, Interpreter};
+
+    /// Fold a program and return the printed statements of its last function
+    fn fold(text: &str) -> Vec<String> {
+        let program = C1Parser::parse_program(text).unwrap();
+        let folded = program.fold_constants(Storage::Local);
+        folded
+            .functions
+            .last()
+            .unwrap()
+            .body
+            .iter()
+            .map(|statement| statement.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn constant_operations_are_evaluated() {
+        assert_eq!(
+            fold(
+                "void main() {
+                     a = 2 * 3 + 1;
+                     b = 1 + 2.5;
+                     c = (1 < 2) == true;
+                     d = -(4 - 1) * b;
+                     d = (0 - 3) * b;
+                     e = 2147483647 + 1;
+                     f = -7 / 2;
+                     g = 2.0 / 0;
+                 }"
+            ),
+            vec![
+                "a = 7;",
+                "b = 3.5;",
+                "c = true;",
+                "d = -3 * b;",
+                "d = (-3) * b;",
+                "e = -2147483647 - 1;",
+                "f = -3;",
+                "g = 2.0 / 0;",
+            ]
+        );
+        // Failing operations are left for the runtime
+        assert_eq!(
+            fold("void main() { printf(1 / 0); printf(1 / (2 - 2)); }"),
+            vec!["printf(1 / 0);", "printf(1 / 0);"]
+        );
+    }
+
+    #[test]
+    fn identities_depend_on_the_operand_type() {
+        assert_eq!(
+            fold(
+                "int g() { return 2; }
+                 void main() {
+                     i = 3; f = 1.5; b = true;
+                     x = i * 1 + 0; x = 1 * f / 1.0; x = f - 0.0; x = f + 0; x = b * 1;
+                     x = -(-i); x = -(-b); x = g() * 1;
+                 }"
+            ),
+            vec![
+                "i = 3;",
+                "f = 1.5;",
+                "b = true;",
+                "x = i;",
+                "x = f;",
+                "x = f;",
+                "x = f + 0;",
+                "x = b * 1;",
+                "x = i;",
+                "x = -(-b);",
+                "x = g();",
+            ]
+        );
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(
+            fold(
+                "void main() {
+                     b = true; i = 1;
+                     x = false && g(); x = 1 || g(); x = b && true; x = true && i;
+                     x = i || false; x = g() && false; x = true && (2 > 1);
+                 }"
+            ),
+            vec![
+                "b = true;",
+                "i = 1;",
+                "x = false;",
+                "x = true;",
+                "x = b;",
+                "x = i != 0;",
+                "x = i != 0;",
+                "x = g() && false;",
+                "x = true;",
+            ]
+        );
+    }
+
+    #[test]
+    fn folded_programs_print_as_equivalent_source() {
+        let text = "int f() { return 2147483647 * 2 - 0; }
+                    void main() {
+                        a = 1;
+                        if (a <= 2 * 2 && true) printf(f() + 0 * a);
+                        printf(-(1.5 * 2) / 0.5);
+                    }";
+        let program = C1Parser::parse_program(text).unwrap();
+        let folded = program.fold_constants(Storage::Local);
+        let reparsed = C1Parser::parse_program(&folded.to_string()).unwrap();
+        assert_eq!(reparsed.to_string(), folded.to_string());
+        let expected = Interpreter::new(&program).run().unwrap();
+        assert_eq!(expected.output, "-2\n-6.000000\n");
+        assert_eq!(Interpreter::new(&reparsed).run(), Ok(expected));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::ast::{FunctionDefinition, Program, Statement};
+use crate::lexer::{C1Lexer, C1Token, Comment};
+use crate::parser::{C1Parser, ParseOptions};
+use std::iter::{Enumerate, Peekable};
+use std::slice;
+use std::vec;
+
+/// Placement of the opening brace of function bodies and blocks
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum BraceStyle {
+    /// At the end of the function header or if statement
+    #[default]
+    SameLine,
+    /// On a line of its own, indented like the header
+    NextLine,
+}
+
+/// Source formatter that prints a program from its syntax tree with one statement per line,
+/// spaces around binary operators and the minimal parentheses.
+///
+/// Comments are not part of the syntax tree. They are put back by their position: a comment
+/// inside a statement or in the line of a statement follows the statement, a comment inside a
+/// block stays before its closing brace, and all others are printed on lines of their own before
+/// the next statement or closing brace. A single empty line is kept where the source has empty
+/// lines between statements, and functions are separated by exactly one empty line. Formatting
+/// formatted text does not change it.
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    /// Number of spaces per nesting level
+    pub indent_width: usize,
+    pub brace_style: BraceStyle,
+    pub parse_options: ParseOptions,
+}
+
+impl Formatter {
+    pub const DEFAULT_INDENT_WIDTH: usize = 4;
+
+    pub fn new() -> Self {
+        Formatter {
+            indent_width: Self::DEFAULT_INDENT_WIDTH,
+            brace_style: BraceStyle::default(),
+            parse_options: ParseOptions::default(),
+        }
+    }
+
+    /// Return the formatted text, or the syntax error of the text
+    pub fn format(&self, text: &str) -> Result<String, String> {
+        let program = C1Parser::parse_program_with(text, &self.parse_options)?;
+        let (headers, braces, semicolons) = layout(text);
+        let mut printer = Printer {
+            formatter: self,
+            comments: program.comments.iter().enumerate().peekable(),
+            headers: headers.into_iter(),
+            braces: braces.into_iter(),
+            semicolons: semicolons.into_iter(),
+            lines: Vec::new(),
+            depth: 0,
+            last_line: 0,
+            commented: false,
+            opened: true,
+        };
+        printer.program(&program);
+        Ok(printer.finish())
+    }
+
+    /// Check whether the text is formatted. Returns the first line that the formatter would
+    /// change, or `None` if the text is already formatted.
+    pub fn check(&self, text: &str) -> Result<Option<usize>, String> {
+        let formatted = self.format(text)?;
+        if formatted == text {
+            return Ok(None);
+        }
+        let common = text
+            .lines()
+            .zip(formatted.lines())
+            .take_while(|(original, formatted)| original == formatted)
+            .count();
+        let last = text.lines().count().max(formatted.lines().count()).max(1);
+        Ok(Some((common + 1).min(last)))
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Position of a token in the source text
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    line: usize,
+    /// Number of comments before the token
+    comments: usize,
+}
+
+/// Find the positions of the first token of every function, of the opening and closing brace of
+/// every block in the order of the opening braces, and of the semicolons. This is the order in
+/// which the printer visits functions, blocks and simple statements.
+fn layout(text: &str) -> (Vec<Position>, Vec<(Position, Position)>, Vec<Position>) {
+    let mut lexer = C1Lexer::new(text);
+    let mut headers = Vec::new();
+    let mut braces = Vec::new();
+    let mut open = Vec::new();
+    let mut semicolons = Vec::new();
+    let mut comments = lexer
+        .comments()
+        .iter()
+        .filter(|comment| comment.before_first_token)
+        .count();
+    // Functions start at the beginning and after a closing brace outside of all blocks
+    let mut starts_function = true;
+    while let (Some(token), Some(line)) = (lexer.current_token(), lexer.current_line_number()) {
+        let position = Position { line, comments };
+        if starts_function {
+            headers.push(position);
+        }
+        match token {
+            C1Token::LeftBrace => {
+                open.push(braces.len());
+                braces.push((position, position));
+            }
+            C1Token::RightBrace => {
+                if let Some(index) = open.pop() {
+                    braces[index].1 = position;
+                }
+            }
+            C1Token::Semicolon => semicolons.push(position),
+            _ => {}
+        }
+        starts_function = token == C1Token::RightBrace && open.is_empty();
+        // The lexer has read the next token, so the comments before it are collected
+        comments = lexer.comments().len();
+        lexer.eat();
+    }
+    (headers, braces, semicolons)
+}
+
+struct Printer<'a> {
+    formatter: &'a Formatter,
+    comments: Peekable<Enumerate<slice::Iter<'a, Comment>>>,
+    headers: vec::IntoIter<Position>,
+    braces: vec::IntoIter<(Position, Position)>,
+    semicolons: vec::IntoIter<Position>,
+    lines: Vec<String>,
+    depth: usize,
+    /// Last source line of what was printed last. Comments up to this line are appended to the
+    /// last output line.
+    last_line: usize,
+    /// Whether the last output line ends with a line comment, after which nothing is appended
+    commented: bool,
+    /// Whether nothing was printed since the last opening brace, where no empty line is kept
+    opened: bool,
+}
+
+impl Printer<'_> {
+    fn program(&mut self, program: &Program) {
+        for (index, function) in program.functions.iter().enumerate() {
+            if index > 0 {
+                self.flush(self.last_line + 1);
+                self.lines.push(String::new());
+                self.opened = true;
+            }
+            self.function(function);
+        }
+        self.flush(usize::MAX);
+    }
+
+    fn function(&mut self, function: &FunctionDefinition) {
+        // Comments within the header are printed after its opening brace
+        match self.headers.next() {
+            Some(start) => self.flush_before(start),
+            None => self.flush(function.line),
+        }
+        let header = format!("{} {}()", function.return_type, function.name);
+        self.line(function.line, header);
+        self.block(&function.body, true);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.flush(statement.line());
+        match statement {
+            Statement::Block { statements, .. } => self.block(statements, false),
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => {
+                self.line(*line, format!("if ({})", condition));
+                self.branch(then_branch);
+            }
+            statement => {
+                self.line(statement.line(), statement.to_string());
+                if let Some(semicolon) = self.semicolons.next() {
+                    self.last_line = semicolon.line;
+                    self.flush_before(semicolon);
+                }
+            }
+        }
+    }
+
+    /// Print the branch of an if statement after its header
+    fn branch(&mut self, branch: &Statement) {
+        match branch {
+            Statement::Block { statements, .. } => self.block(statements, true),
+            branch => {
+                self.depth += 1;
+                self.opened = true;
+                self.statement(branch);
+                self.depth -= 1;
+            }
+        }
+    }
+
+    /// Print a block with its braces. The opening brace of the block of a function or if
+    /// statement is placed according to the brace style.
+    fn block(&mut self, statements: &[Statement], after_header: bool) {
+        let fallback = Position {
+            line: self.last_line,
+            comments: 0,
+        };
+        let (opening, closing) = self.braces.next().unwrap_or((fallback, fallback));
+        match after_header {
+            true if self.formatter.brace_style == BraceStyle::SameLine => self.append(" {"),
+            true => self.push("{".to_string()),
+            false => self.line(opening.line, "{".to_string()),
+        }
+        self.last_line = opening.line;
+        self.flush_before(opening);
+        self.depth += 1;
+        self.opened = true;
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.flush_before(closing);
+        self.depth -= 1;
+        self.push("}".to_string());
+        self.last_line = closing.line;
+    }
+
+    /// Print the comments that start before the given line
+    fn flush(&mut self, line: usize) {
+        while let Some((_, comment)) = self.comments.next_if(|(_, comment)| comment.line < line) {
+            self.comment(comment);
+        }
+    }
+
+    /// Print the comments that precede the token at the given position
+    fn flush_before(&mut self, position: Position) {
+        while let Some((_, comment)) = self
+            .comments
+            .next_if(|(index, _)| *index < position.comments)
+        {
+            self.comment(comment);
+        }
+    }
+
+    /// Print a comment after the last output line if it starts in the last printed source line,
+    /// otherwise on a line of its own
+    fn comment(&mut self, comment: &Comment) {
+        if comment.line <= self.last_line && !self.commented {
+            self.append(&format!(" {}", comment.text));
+        } else {
+            self.line(comment.line.max(self.last_line), comment.text.clone());
+        }
+        self.commented = comment.text.starts_with("//");
+        self.last_line = self.last_line.max(comment.end_line());
+    }
+
+    /// Start a new output line for text from the given source line, after an empty line if the
+    /// source has one
+    fn line(&mut self, line: usize, text: String) {
+        if !self.opened && line > self.last_line + 1 {
+            self.lines.push(String::new());
+        }
+        self.push(text);
+        self.last_line = line;
+    }
+
+    fn push(&mut self, text: String) {
+        let indent = " ".repeat(self.depth * self.formatter.indent_width);
+        self.lines.push(indent + &text);
+        self.commented = false;
+        self.opened = false;
+    }
+
+    fn append(&mut self, text: &str) {
+        if let Some(last) = self.lines.last_mut() {
+            last.push_str(text);
+        }
+        self.commented = false;
+    }
+
+    fn finish(self) -> String {
+        let mut text = self.lines.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formatter::{BraceStyle, Formatter};
+
+    #[test]
+    fn statements_are_printed_one_per_line() {
+        let formatted = Formatter::new()
+            .format("int f(){a=1;if(a<=2)return a*(a+1);\n\n\n  printf( -a ) ;return 0;}\nvoid main(){f();}")
+            .unwrap();
+        assert_eq!(
+            formatted,
+            "int f() {\n    a = 1;\n    if (a <= 2)\n        return a * (a + 1);\n\n    printf(-a);\n    return 0;\n}\n\nvoid main() {\n    f();\n}\n"
+        );
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let text = "// header\nint f() { // body\n  x = 1; y = 2; // after y\n  /* own */\n  return x;\n  // before brace\n} // end\n";
+        let formatted = Formatter::new().format(text).unwrap();
+        assert_eq!(
+            formatted,
+            "// header\nint f() { // body\n    x = 1;\n    y = 2; // after y\n    /* own */\n    return x;\n    // before brace\n} // end\n"
+        );
+        assert_eq!(Formatter::new().format(&formatted).unwrap(), formatted);
+
+        let text = "void main() {\n  a = 1 + /* inline */ 2; b = 3;\n  c = // line\n    4; // after c\n  if (a) { /* empty */ }\n  if (b) {\n  }\n}\n";
+        let formatted = Formatter::new().format(text).unwrap();
+        assert_eq!(
+            formatted,
+            "void main() {\n    a = 1 + 2; /* inline */\n    b = 3;\n    c = 4; // line\n    // after c\n    if (a) { /* empty */\n    }\n    if (b) {\n    }\n}\n"
+        );
+        assert_eq!(Formatter::new().format(&formatted).unwrap(), formatted);
+
+        // Comments before and within a function header stay out of the body
+        for (text, expected) in [
+            (
+                "/* a */ int f() { return 1; }",
+                "/* a */\nint f() {\n    return 1;\n}\n",
+            ),
+            ("/*a*/void main(){}", "/*a*/\nvoid main() {\n}\n"),
+            (
+                "void /* c */ main() { x = 1; }",
+                "void main() { /* c */\n    x = 1;\n}\n",
+            ),
+            (
+                "void f() {}\nint // c\n g() { return 1; }",
+                "void f() {\n}\n\nint g() { // c\n    return 1;\n}\n",
+            ),
+        ] {
+            let formatted = Formatter::new().format(text).unwrap();
+            assert_eq!(formatted, expected);
+            assert_eq!(Formatter::new().format(&formatted).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn braces_follow_the_style() {
+        let mut formatter = Formatter::new();
+        let text = "void main() { if (a) { b(); } if (c) d(); { e(); } }";
+        assert_eq!(
+            formatter.format(text).unwrap(),
+            "void main() {\n    if (a) {\n        b();\n    }\n    if (c)\n        d();\n    {\n        e();\n    }\n}\n"
+        );
+
+        formatter.brace_style = BraceStyle::NextLine;
+        formatter.indent_width = 2;
+        assert_eq!(
+            formatter.format(text).unwrap(),
+            "void main()\n{\n  if (a)\n  {\n    b();\n  }\n  if (c)\n    d();\n  {\n    e();\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn check_reports_the_first_changed_line() {
+        let formatter = Formatter::new();
+        assert_eq!(formatter.check("void main() {\n    x = 1;\n}\n"), Ok(None));
+        assert_eq!(
+            formatter.check("void main() {\n    x = 1;\n  y = 2;\n}\n"),
+            Ok(Some(3))
+        );
+        assert_eq!(formatter.check("void main() {\n}"), Ok(Some(2)));
+        assert!(formatter.check("void main() {").is_err());
+    }
+}
@@ -0,0 +1,717 @@
+use crate::ast::{Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type};
+use crate::callgraph::CallGraph;
+use crate::config::Storage;
+use crate::lint::may_read_unassigned;
+use crate::types::TypeEnvironment;
+use std::collections::{HashMap, HashSet};
+
+/// Replaces calls of small functions by their bodies.
+///
+/// A function is inlined if it is not part of a recursion cycle, its body is not larger than the
+/// budget, the result needs no conversion, i.e. every `return` has a value of the declared
+/// return type, and no `return` is inside an if statement. Such a `return` would need an else
+/// branch for the statements after the if statement, which C(-1) does not have. The returned
+/// value is assigned to a new variable, which takes the place of the call. Parts of the surrounding expression that are evaluated before the
+/// call are assigned to new variables first, so the order of evaluation does not change. Calls
+/// in the right operand of `&&` and `||` are not inlined, since they are evaluated conditionally.
+///
+/// With local storage the variables of an inlined body are renamed, because every call has its
+/// own variables, and functions that may read a variable before assigning it are not inlined.
+#[derive(Debug, Clone)]
+pub struct Inliner {
+    /// Maximum size of an inlined body, counted in statements and expression nodes
+    pub budget: usize,
+    pub storage: Storage,
+}
+
+impl Inliner {
+    pub const DEFAULT_BUDGET: usize = 40;
+
+    pub fn new(storage: Storage) -> Self {
+        Inliner {
+            budget: Self::DEFAULT_BUDGET,
+            storage,
+        }
+    }
+
+    /// Return the program with all calls of suitable functions inlined and the functions that
+    /// `main` no longer reaches removed. Callees are processed before their callers, so a body
+    /// is inlined together with the calls already inlined into it.
+    pub fn inline(&self, program: &Program) -> Program {
+        let graph = CallGraph::build(program);
+        let recursive: HashSet<String> = graph
+            .recursion_cycles()
+            .into_iter()
+            .flat_map(|cycle| cycle.functions)
+            .collect();
+        let mut names = identifiers(program);
+        let mut result = program.clone();
+        for index in bottom_up_order(program) {
+            let types = TypeEnvironment::infer(&result, self.storage);
+            let mut rewriter = Rewriter {
+                inliner: self,
+                program: &result,
+                types: &types,
+                recursive: &recursive,
+                names: &mut names,
+                temporaries: HashSet::new(),
+                templates: HashMap::new(),
+            };
+            let body = rewriter.statements(&result.functions[index].body);
+            result.functions[index].body = body;
+        }
+
+        let graph = CallGraph::build(&result);
+        if result.function(CallGraph::ENTRY_POINT).is_some() {
+            let reachable: HashSet<String> = graph
+                .reachable_from_main()
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            result
+                .functions
+                .retain(|function| reachable.contains(&function.name));
+        }
+        result
+    }
+}
+
+/// The body of a function prepared for inlining, with every `return` at the end of a path
+#[derive(Debug, Clone)]
+struct Template {
+    body: Vec<Statement>,
+    /// Every path ends with a `return` of a value, so the call can be used as a value
+    returns_value: bool,
+}
+
+struct Rewriter<'a> {
+    inliner: &'a Inliner,
+    program: &'a Program,
+    types: &'a TypeEnvironment,
+    recursive: &'a HashSet<String>,
+    /// All names used in the program, including the generated ones
+    names: &'a mut HashSet<String>,
+    /// Generated variables, which are assigned exactly once
+    temporaries: HashSet<String>,
+    /// Templates of the callees, None for functions that are not inlined
+    templates: HashMap<String, Option<Template>>,
+}
+
+impl Rewriter<'_> {
+    fn statements(&mut self, statements: &[Statement]) -> Vec<Statement> {
+        statements
+            .iter()
+            .flat_map(|statement| self.statement(statement))
+            .collect()
+    }
+
+    /// Rewrite a statement, preceded by the inlined bodies of the calls it contains
+    fn statement(&mut self, statement: &Statement) -> Vec<Statement> {
+        let mut rewritten = Vec::new();
+        let last = match statement {
+            Statement::Block { statements, line } => Statement::Block {
+                statements: self.statements(statements),
+                line: *line,
+            },
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => Statement::If {
+                condition: self.expression(condition, &mut rewritten),
+                then_branch: Box::new(self.branch(then_branch)),
+                line: *line,
+            },
+            Statement::Return { value, line } => Statement::Return {
+                value: value
+                    .as_ref()
+                    .map(|value| self.expression(value, &mut rewritten)),
+                line: *line,
+            },
+            Statement::Printf { value, line } => Statement::Printf {
+                value: self.expression(value, &mut rewritten),
+                line: *line,
+            },
+            Statement::Assign {
+                target,
+                value,
+                line,
+            } => Statement::Assign {
+                target: target.clone(),
+                value: self.expression(value, &mut rewritten),
+                line: *line,
+            },
+            Statement::Call { name, .. } => match self.template(name) {
+                Some(template) => {
+                    self.instantiate(name, &template, &mut rewritten);
+                    return rewritten;
+                }
+                None => statement.clone(),
+            },
+        };
+        rewritten.push(last);
+        rewritten
+    }
+
+    /// Rewrite the branch of an `if` statement, which has to stay a single statement
+    fn branch(&mut self, branch: &Statement) -> Statement {
+        let mut statements = self.statement(branch);
+        if statements.len() == 1 {
+            statements.remove(0)
+        } else {
+            Statement::Block {
+                statements,
+                line: branch.line(),
+            }
+        }
+    }
+
+    /// Inline all calls of an expression that can be inlined, the bodies are appended to
+    /// `prefix`
+    fn expression(&mut self, expression: &Expression, prefix: &mut Vec<Statement>) -> Expression {
+        let mut expression = expression.clone();
+        while let Some(rewritten) = self.hoist(&expression, prefix) {
+            expression = rewritten;
+        }
+        expression
+    }
+
+    /// Inline the first call of the expression in evaluation order, None if there is none
+    fn hoist(
+        &mut self,
+        expression: &Expression,
+        prefix: &mut Vec<Statement>,
+    ) -> Option<Expression> {
+        let line = expression.line;
+        let kind = match &expression.kind {
+            ExpressionKind::Call(name) => {
+                let template = self
+                    .template(name)
+                    .filter(|template| template.returns_value)?;
+                let result = self.instantiate(name, &template, prefix)?;
+                ExpressionKind::Variable(result)
+            }
+            ExpressionKind::Assign { target, value } => ExpressionKind::Assign {
+                target: target.clone(),
+                value: Box::new(self.hoist(value, prefix)?),
+            },
+            ExpressionKind::Unary { op, operand } => ExpressionKind::Unary {
+                op: *op,
+                operand: Box::new(self.hoist(operand, prefix)?),
+            },
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                if let Some(lhs) = self.hoist(lhs, prefix) {
+                    ExpressionKind::Binary {
+                        op: *op,
+                        lhs: Box::new(lhs),
+                        rhs: rhs.clone(),
+                    }
+                } else if !op.is_logical() && self.contains_inlinable_call(rhs) {
+                    // The left operand is evaluated before the inlined body
+                    let lhs = self.temporary(lhs, prefix);
+                    ExpressionKind::Binary {
+                        op: *op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(self.hoist(rhs, prefix)?),
+                    }
+                } else {
+                    return None;
+                }
+            }
+            _ => return None,
+        };
+        Some(Expression::new(kind, line))
+    }
+
+    /// Whether `hoist` would find a call to inline
+    fn contains_inlinable_call(&mut self, expression: &Expression) -> bool {
+        match &expression.kind {
+            ExpressionKind::Call(name) => self
+                .template(name)
+                .is_some_and(|template| template.returns_value),
+            ExpressionKind::Assign { value, .. } => self.contains_inlinable_call(value),
+            ExpressionKind::Unary { operand, .. } => self.contains_inlinable_call(operand),
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                self.contains_inlinable_call(lhs)
+                    || (!op.is_logical() && self.contains_inlinable_call(rhs))
+            }
+            _ => false,
+        }
+    }
+
+    /// Assign the value of an expression to a new variable, unless it cannot change anyway
+    fn temporary(&mut self, expression: &Expression, prefix: &mut Vec<Statement>) -> Expression {
+        match &expression.kind {
+            ExpressionKind::Int(_) | ExpressionKind::Float(_) | ExpressionKind::Bool(_) => {
+                return expression.clone();
+            }
+            ExpressionKind::Variable(name) if self.temporaries.contains(name) => {
+                return expression.clone();
+            }
+            _ => {}
+        }
+        let name = self.fresh("tmp");
+        prefix.push(Statement::Assign {
+            target: name.clone(),
+            value: expression.clone(),
+            line: expression.line,
+        });
+        Expression::new(ExpressionKind::Variable(name), expression.line)
+    }
+
+    /// Append the body of a function to `statements` and return the variable that holds its
+    /// result, if it returns one
+    fn instantiate(
+        &mut self,
+        name: &str,
+        template: &Template,
+        statements: &mut Vec<Statement>,
+    ) -> Option<String> {
+        let mut copy = Instance {
+            callee: name,
+            storage: self.inliner.storage,
+            renamed: HashMap::new(),
+            result: None,
+        };
+        for statement in &template.body {
+            copy.statement(statement, self, statements);
+        }
+        copy.result
+    }
+
+    /// Return the template of a function if calls of it can be inlined
+    fn template(&mut self, name: &str) -> Option<Template> {
+        if let Some(template) = self.templates.get(name) {
+            return template.clone();
+        }
+        let template = self.prepare(name);
+        self.templates.insert(name.to_string(), template.clone());
+        template
+    }
+
+    fn prepare(&self, name: &str) -> Option<Template> {
+        // Like the interpreter, use the last definition of a name
+        let function = self
+            .program
+            .functions
+            .iter()
+            .rev()
+            .find(|function| function.name == name)?;
+        if self.recursive.contains(name)
+            || (self.inliner.storage == Storage::Local && may_read_unassigned(function))
+            || !self.returns_without_conversion(function)
+        {
+            return None;
+        }
+        let body = normalize(&function.body);
+        let returns_in_branch = body.iter().any(|statement| {
+            matches!(statement, Statement::If { .. }) && contains_return(statement)
+        });
+        if returns_in_branch || size(&body) > self.inliner.budget {
+            return None;
+        }
+        Some(Template {
+            returns_value: function.return_type != Type::Void && always_returns(&body),
+            body,
+        })
+    }
+
+    /// Whether every `return` of the function has a value of exactly the declared type, or no
+    /// value in a `void` function
+    fn returns_without_conversion(&self, function: &FunctionDefinition) -> bool {
+        let mut valid = true;
+        function.walk_statements(&mut |statement| {
+            if let Statement::Return {
+                value: Some(value), ..
+            } = statement
+            {
+                let value_type = self.types.expression_type(&function.name, value);
+                valid &=
+                    function.return_type != Type::Void && value_type == Some(function.return_type);
+            }
+        });
+        valid
+    }
+
+    /// Return a name that is not used anywhere in the program, made from `base` and a number
+    fn fresh(&mut self, base: &str) -> String {
+        let name = (1..)
+            .map(|number| format!("{}{}", base, number))
+            .find(|name| !self.names.contains(name))
+            .unwrap();
+        self.names.insert(name.clone());
+        self.temporaries.insert(name.clone());
+        name
+    }
+}
+
+/// Copies the template of a function into a caller
+struct Instance<'a> {
+    callee: &'a str,
+    storage: Storage,
+    /// New names of the variables of the callee with local storage
+    renamed: HashMap<String, String>,
+    result: Option<String>,
+}
+
+impl Instance<'_> {
+    fn statement(
+        &mut self,
+        statement: &Statement,
+        rewriter: &mut Rewriter,
+        out: &mut Vec<Statement>,
+    ) {
+        let copied = match statement {
+            Statement::Block { statements, line } => {
+                let mut copied = Vec::new();
+                for statement in statements {
+                    self.statement(statement, rewriter, &mut copied);
+                }
+                Statement::Block {
+                    statements: copied,
+                    line: *line,
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => {
+                let condition = self.expression(condition, rewriter);
+                let mut copied = Vec::new();
+                for statement in flatten(then_branch) {
+                    self.statement(statement, rewriter, &mut copied);
+                }
+                Statement::If {
+                    condition,
+                    then_branch: Box::new(Statement::Block {
+                        statements: copied,
+                        line: then_branch.line(),
+                    }),
+                    line: *line,
+                }
+            }
+            // Returns are only at the end of a path, a `return` without a value ends the body
+            Statement::Return { value: None, .. } => return,
+            Statement::Return {
+                value: Some(value),
+                line,
+            } => {
+                let value = self.expression(value, rewriter);
+                let result = match &self.result {
+                    Some(result) => result.clone(),
+                    None => {
+                        let result = rewriter.fresh(&format!("{}Result", self.callee));
+                        self.result = Some(result.clone());
+                        result
+                    }
+                };
+                Statement::Assign {
+                    target: result,
+                    value,
+                    line: *line,
+                }
+            }
+            Statement::Printf { value, line } => Statement::Printf {
+                value: self.expression(value, rewriter),
+                line: *line,
+            },
+            Statement::Assign {
+                target,
+                value,
+                line,
+            } => Statement::Assign {
+                value: self.expression(value, rewriter),
+                target: self.variable(target, rewriter),
+                line: *line,
+            },
+            Statement::Call { .. } => statement.clone(),
+        };
+        out.push(copied);
+    }
+
+    fn expression(&mut self, expression: &Expression, rewriter: &mut Rewriter) -> Expression {
+        let kind = match &expression.kind {
+            ExpressionKind::Variable(name) => {
+                ExpressionKind::Variable(self.variable(name, rewriter))
+            }
+            ExpressionKind::Assign { target, value } => ExpressionKind::Assign {
+                value: Box::new(self.expression(value, rewriter)),
+                target: self.variable(target, rewriter),
+            },
+            ExpressionKind::Unary { op, operand } => ExpressionKind::Unary {
+                op: *op,
+                operand: Box::new(self.expression(operand, rewriter)),
+            },
+            ExpressionKind::Binary { op, lhs, rhs } => ExpressionKind::Binary {
+                op: *op,
+                lhs: Box::new(self.expression(lhs, rewriter)),
+                rhs: Box::new(self.expression(rhs, rewriter)),
+            },
+            kind => kind.clone(),
+        };
+        Expression::new(kind, expression.line)
+    }
+
+    /// Return the name of a variable of the callee inside the caller
+    fn variable(&mut self, name: &str, rewriter: &mut Rewriter) -> String {
+        if self.storage == Storage::Global {
+            return name.to_string();
+        }
+        if let Some(renamed) = self.renamed.get(name) {
+            return renamed.clone();
+        }
+        let mut base = self.callee.to_string();
+        base.extend(name.chars().next().map(|first| first.to_ascii_uppercase()));
+        base.push_str(&name[1..]);
+        let renamed = rewriter.fresh(&base);
+        // Renamed variables are assigned like the original ones and are no temporaries
+        rewriter.temporaries.remove(&renamed);
+        self.renamed.insert(name.to_string(), renamed.clone());
+        renamed
+    }
+}
+
+/// Flatten the blocks of a body and drop the statements after a `return`
+fn normalize(statements: &[Statement]) -> Vec<Statement> {
+    let mut normalized = Vec::new();
+    for (i, statement) in statements.iter().enumerate() {
+        let rest = &statements[i + 1..];
+        match statement {
+            Statement::Block {
+                statements: inner, ..
+            } => {
+                normalized.extend(normalize(&[inner.as_slice(), rest].concat()));
+                return normalized;
+            }
+            Statement::Return { .. } => {
+                normalized.push(statement.clone());
+                return normalized;
+            }
+            _ => normalized.push(statement.clone()),
+        }
+    }
+    normalized
+}
+
+/// Return the statements of a branch
+fn flatten(branch: &Statement) -> &[Statement] {
+    match branch {
+        Statement::Block { statements, .. } => statements,
+        statement => std::slice::from_ref(statement),
+    }
+}
+
+fn contains_return(statement: &Statement) -> bool {
+    let mut found = false;
+    statement.walk(&mut |statement| found |= matches!(statement, Statement::Return { .. }));
+    found
+}
+
+/// Whether normalized statements end with a `return` of a value
+fn always_returns(statements: &[Statement]) -> bool {
+    match statements.last() {
+        Some(Statement::Return { value, .. }) => value.is_some(),
+        Some(Statement::Block { statements, .. }) => always_returns(statements),
+        _ => false,
+    }
+}
+
+/// Number of statements and expression nodes
+fn size(statements: &[Statement]) -> usize {
+    let mut size = 0;
+    for statement in statements {
+        statement.walk(&mut |statement| {
+            size += 1;
+            for expression in statement.expressions() {
+                expression.walk(&mut |_| size += 1);
+            }
+        });
+    }
+    size
+}
+
+/// Collect the names of all functions and variables of a program
+fn identifiers(program: &Program) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for function in &program.functions {
+        names.insert(function.name.clone());
+        function.walk_statements(&mut |statement| match statement {
+            Statement::Assign { target, .. } => {
+                names.insert(target.clone());
+            }
+            Statement::Call { name, .. } => {
+                names.insert(name.clone());
+            }
+            _ => {}
+        });
+        function.walk_expressions(&mut |expression| match &expression.kind {
+            ExpressionKind::Variable(name)
+            | ExpressionKind::Call(name)
+            | ExpressionKind::Assign { target: name, .. } => {
+                names.insert(name.clone());
+            }
+            _ => {}
+        });
+    }
+    names
+}
+
+/// Order the functions so that callees come before their callers, except inside recursion cycles
+fn bottom_up_order(program: &Program) -> Vec<usize> {
+    let graph = CallGraph::build(program);
+    let index: HashMap<&str, usize> = program
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, function)| (function.name.as_str(), i))
+        .collect();
+    let mut visited = vec![false; program.functions.len()];
+    let mut order = Vec::new();
+    // Depth-first search with an explicit stack of (function, callees pushed)
+    for start in 0..program.functions.len() {
+        let mut stack = vec![(start, false)];
+        while let Some((node, expanded)) = stack.pop() {
+            if expanded {
+                order.push(node);
+                continue;
+            }
+            if visited[node] {
+                continue;
+            }
+            visited[node] = true;
+            stack.push((node, true));
+            for callee in graph
+                .callees(&program.functions[node].name)
+                .into_iter()
+                .rev()
+            {
+                if let Some(&callee) = index.get(callee) {
+                    if !visited[callee] {
+                        stack.push((callee, false));
+                    }
+                }
+            }
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::inline::Inliner;
+    use crate::{C1Parser, Interpreter, Program};
+
+    fn parse(text: &str) -> Program {
+        C1Parser::parse_program(text).unwrap()
+    }
+
+    /// Inline a program and check that it still behaves the same
+    fn inline(text: &str, inliner: &Inliner) -> String {
+        let program = parse(text);
+        let inlined = inliner.inline(&program);
+        let mut original = Interpreter::new(&program);
+        original.storage = inliner.storage;
+        let mut optimized = Interpreter::new(&inlined);
+        optimized.storage = inliner.storage;
+        assert_eq!(optimized.run(), original.run());
+        // The result is valid source code
+        let printed = inlined.to_string();
+        assert_eq!(parse(&printed).to_string(), printed);
+        printed
+    }
+
+    #[test]
+    fn results_replace_calls_inside_expressions() {
+        let text = "int two() { return 2; }
+                    int sign() {
+                        s = 1;
+                        if (x < 0) s = -1;
+                        printf(x);
+                        return s;
+                    }
+                    void main() {
+                        x = 3;
+                        y = x + two() * sign();
+                        printf(y);
+                    }";
+        assert_eq!(
+            inline(text, &Inliner::new(Storage::Global)),
+            "void main() {
+    x = 3;
+    tmp1 = x;
+    twoResult1 = 2;
+    s = 1;
+    if (x < 0) {
+        s = -1;
+    }
+    printf(x);
+    signResult1 = s;
+    y = tmp1 + twoResult1 * signResult1;
+    printf(y);
+}
+"
+        );
+        // Within the budget only `two` is small enough
+        let inliner = Inliner {
+            budget: 3,
+            ..Inliner::new(Storage::Global)
+        };
+        let inlined = inline(text, &inliner);
+        assert!(
+            inlined.contains("y = tmp1 + twoResult1 * sign();"),
+            "{}",
+            inlined
+        );
+        assert!(inlined.starts_with("int sign() {"), "{}", inlined);
+    }
+
+    #[test]
+    fn recursive_and_unsafe_functions_are_kept() {
+        let text = "int down() { if (n > 0) { n = n - 1; return down(); } return 0; }
+                    int unknown() { return value; }
+                    int early() { if (n > 5) return 1; return 0; }
+                    bool truth() { return 1; }
+                    void log() { printf(n); }
+                    void main() {
+                        n = 3; value = 1;
+                        log();
+                        printf(false && down() == 0);
+                        printf(true || truth());
+                        printf(down() + unknown());
+                        printf(early());
+                    }";
+        let inlined = inline(text, &Inliner::new(Storage::Global));
+        // `unknown` reads a variable of main, `log` is inlined as a statement
+        assert!(inlined.contains("    printf(n);\n    printf(false && down() == 0);"));
+        assert!(inlined.contains("printf(true || truth());"), "{}", inlined);
+        assert!(inlined.contains("tmp1 = down();\n    unknownResult1 = value;\n"));
+        assert!(!inlined.contains("void log()"), "{}", inlined);
+        // A `return` inside an if statement would need an else branch
+        assert!(inlined.contains("printf(early());"), "{}", inlined);
+        // With local storage `unknown` fails to read `value`
+        let inlined = inline(text, &Inliner::new(Storage::Local));
+        assert!(inlined.contains("down() + unknown()"), "{}", inlined);
+    }
+
+    #[test]
+    fn variables_are_renamed_with_local_storage() {
+        let text = "float half() { h = 1.0; h = h / 2; return h; }
+                    void main() { h = half() + half(); printf(h); }";
+        assert_eq!(
+            inline(text, &Inliner::new(Storage::Local)),
+            "void main() {
+    halfH1 = 1.0;
+    halfH1 = halfH1 / 2;
+    halfResult1 = halfH1;
+    halfH2 = 1.0;
+    halfH2 = halfH2 / 2;
+    halfResult2 = halfH2;
+    h = halfResult1 + halfResult2;
+    printf(h);
+}
+"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::config::Storage;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An error that stops the execution of a program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RuntimeError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+/// Result of running a program to completion
+#[derive(Debug, PartialEq, Clone)]
+pub struct Execution {
+    /// Everything printed by `printf`, one value per line
+    pub output: String,
+    /// Value returned by `main`, None for `void main()`
+    pub exit_value: Option<Value>,
+}
+
+impl Execution {
+    /// Return the exit value as a process exit code, 0 if `main` returned nothing
+    pub fn exit_code(&self) -> i32 {
+        match self
+            .exit_value
+            .and_then(|value| value.convert_to(Type::Int))
+        {
+            Some(Value::Int(code)) => code,
+            _ => 0,
+        }
+    }
+}
+
+/// How control leaves a statement
+enum Flow {
+    Normal,
+    Return(Option<Value>, usize),
+}
+
+/// Variables of a single function call, or of the whole program with global storage
+type Frame = HashMap<String, Value>;
+
+/// Executes a program by walking its syntax tree, starting at `main`.
+///
+/// Variables are created by their first assignment. Where they live is decided by `storage`: by
+/// default every call gets its own frame, with `Storage::Global` all calls share one store.
+/// `printf` prints its argument followed by a line break, see `Value` for the
+/// semantics of the operators.
+pub struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a FunctionDefinition>,
+    output: String,
+    depth: usize,
+    steps: usize,
+    /// Maximum number of nested function calls before the execution is aborted
+    pub max_call_depth: usize,
+    /// Maximum number of executed statements before the execution is aborted, unlimited if None
+    pub max_steps: Option<usize>,
+    pub storage: Storage,
+}
+
+impl<'a> Interpreter<'a> {
+    pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+    pub fn new(program: &'a Program) -> Self {
+        Interpreter {
+            functions: program
+                .functions
+                .iter()
+                .map(|function| (function.name.as_str(), function))
+                .collect(),
+            output: String::new(),
+            depth: 0,
+            steps: 0,
+            max_call_depth: Self::DEFAULT_MAX_CALL_DEPTH,
+            max_steps: None,
+            storage: Storage::default(),
+        }
+    }
+
+    /// Run the program by calling `main`
+    pub fn run(&mut self) -> Result<Execution, RuntimeError> {
+        if !self.functions.contains_key("main") {
+            return Err(RuntimeError {
+                line: 1,
+                message: "the program has no main function".to_string(),
+            });
+        }
+        let exit_value = self.call("main", 1, &mut Frame::new())?;
+        Ok(Execution {
+            output: std::mem::take(&mut self.output),
+            exit_value,
+        })
+    }
+
+    /// Call a single function with fresh variables and return its result converted to the
+    /// declared return type, None if it returns nothing
+    pub fn call_function(&mut self, name: &str) -> Result<Option<Value>, RuntimeError> {
+        self.call(name, 1, &mut Frame::new())
+    }
+
+    /// Return the output printed so far, which is useful after a runtime error
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Call a function and return its result converted to the declared return type. `caller` is
+    /// the frame of the calling function, which the callee shares with global storage.
+    fn call(
+        &mut self,
+        name: &str,
+        line: usize,
+        caller: &mut Frame,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let function = *self.functions.get(name).ok_or_else(|| RuntimeError {
+            line,
+            message: format!("call of undefined function `{}`", name),
+        })?;
+        if self.depth >= self.max_call_depth {
+            return Err(RuntimeError {
+                line,
+                message: format!(
+                    "maximum call depth of {} exceeded in call of `{}`",
+                    self.max_call_depth, name
+                ),
+            });
+        }
+        self.depth += 1;
+        let flow = match self.storage {
+            Storage::Local => self.execute_all(&function.body, &mut Frame::new()),
+            Storage::Global => self.execute_all(&function.body, caller),
+        };
+        self.depth -= 1;
+        match flow? {
+            Flow::Return(Some(_), line) if function.return_type == Type::Void => {
+                Err(RuntimeError {
+                    line,
+                    message: format!("void function `{}` returns a value", name),
+                })
+            }
+            Flow::Return(Some(value), _) => Ok(value.convert_to(function.return_type)),
+            Flow::Return(None, _) | Flow::Normal => Ok(None),
+        }
+    }
+
+    /// Call a function whose result is used as a value
+    fn call_for_value(
+        &mut self,
+        name: &str,
+        line: usize,
+        caller: &mut Frame,
+    ) -> Result<Value, RuntimeError> {
+        match self.call(name, line, caller)? {
+            Some(value) => Ok(value),
+            None => {
+                let message = if self.functions[name].return_type == Type::Void {
+                    format!("void function `{}` is called for its value", name)
+                } else {
+                    format!("function `{}` did not return a value", name)
+                };
+                Err(RuntimeError { line, message })
+            }
+        }
+    }
+
+    fn execute_all(
+        &mut self,
+        statements: &'a [Statement],
+        frame: &mut Frame,
+    ) -> Result<Flow, RuntimeError> {
+        for statement in statements {
+            if let Flow::Return(value, line) = self.execute(statement, frame)? {
+                return Ok(Flow::Return(value, line));
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute(
+        &mut self,
+        statement: &'a Statement,
+        frame: &mut Frame,
+    ) -> Result<Flow, RuntimeError> {
+        self.steps += 1;
+        if let Some(max_steps) = self.max_steps.filter(|&max_steps| self.steps > max_steps) {
+            return Err(RuntimeError {
+                line: statement.line(),
+                message: format!("step limit of {} exceeded", max_steps),
+            });
+        }
+        match statement {
+            Statement::Block { statements, .. } => self.execute_all(statements, frame),
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                if self.evaluate(condition, frame)?.is_truthy() {
+                    self.execute(then_branch, frame)
+                } else {
+                    Ok(Flow::Normal)
+                }
+            }
+            Statement::Return { value, line } => {
+                let value = match value {
+                    Some(value) => Some(self.evaluate(value, frame)?),
+                    None => None,
+                };
+                Ok(Flow::Return(value, *line))
+            }
+            Statement::Printf { value, .. } => {
+                let value = self.evaluate(value, frame)?;
+                self.output.push_str(&format!("{}\n", value));
+                Ok(Flow::Normal)
+            }
+            Statement::Assign { target, value, .. } => {
+                let value = self.evaluate(value, frame)?;
+                frame.insert(target.clone(), value);
+                Ok(Flow::Normal)
+            }
+            Statement::Call { name, line } => {
+                self.call(name, *line, frame)?;
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    fn evaluate(
+        &mut self,
+        expression: &Expression,
+        frame: &mut Frame,
+    ) -> Result<Value, RuntimeError> {
+        let line = expression.line;
+        match &expression.kind {
+            ExpressionKind::Int(value) => Ok(Value::Int(*value)),
+            ExpressionKind::Float(value) => Ok(Value::Float(*value)),
+            ExpressionKind::Bool(value) => Ok(Value::Bool(*value)),
+            ExpressionKind::Variable(name) => {
+                frame.get(name).copied().ok_or_else(|| RuntimeError {
+                    line,
+                    message: format!("variable `{}` is used before it is assigned", name),
+                })
+            }
+            ExpressionKind::Call(name) => self.call_for_value(name, line, frame),
+            ExpressionKind::Assign { target, value } => {
+                let value = self.evaluate(value, frame)?;
+                frame.insert(target.clone(), value);
+                Ok(value)
+            }
+            ExpressionKind::Unary { operand, .. } => Ok(self.evaluate(operand, frame)?.negate()),
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let lhs = self.evaluate(lhs, frame)?;
+                // Short-circuit evaluation of && and ||
+                if op.is_logical() && lhs.is_truthy() == (*op == BinaryOp::Or) {
+                    return Ok(Value::Bool(lhs.is_truthy()));
+                }
+                let rhs = self.evaluate(rhs, frame)?;
+                Value::binary(*op, lhs, rhs).map_err(|message| RuntimeError { line, message })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::interpreter::{Interpreter, RuntimeError};
+    use crate::value::Value;
+    use crate::C1Parser;
+
+    fn run(text: &str) -> Result<(String, Option<Value>), RuntimeError> {
+        run_with(text, Storage::Local)
+    }
+
+    fn run_with(text: &str, storage: Storage) -> Result<(String, Option<Value>), RuntimeError> {
+        let program = C1Parser::parse_program(text).unwrap();
+        let mut interpreter = Interpreter::new(&program);
+        interpreter.storage = storage;
+        let execution = interpreter.run()?;
+        Ok((execution.output, execution.exit_value))
+    }
+
+    #[test]
+    fn example_program() {
+        assert_eq!(
+            run(include_str!("../tests/data/beispiel.c-1")),
+            Ok(("3\n17\n3.141590\n".to_string(), None))
+        );
+    }
+
+    #[test]
+    fn values_and_return_types() {
+        let (output, exit_value) = run("int half() { return 7.9 / 2; }
+             bool yes() { return 2; }
+             float third() { return 1 / 3; }
+             int main() {
+                 printf(half());
+                 printf(yes());
+                 printf(third());
+                 printf(-2 * 3 + 1 < 0 && true);
+                 x = y = 4;
+                 printf(x + y);
+                 return x * 10;
+             }")
+        .unwrap();
+        assert_eq!(output, "3\ntrue\n0.000000\ntrue\n8\n");
+        assert_eq!(exit_value, Some(Value::Int(40)));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let (output, _) = run("bool noisy() { printf(1); return true; }
+             void main() { a = false && noisy(); b = true || noisy(); c = true && noisy(); }")
+        .unwrap();
+        assert_eq!(output, "1\n");
+    }
+
+    #[test]
+    fn variables_are_local_to_each_call() {
+        let (output, _) = run("int f() { a = 2; return a; }
+             void main() { a = 1; b = f(); printf(a); printf(b); }")
+        .unwrap();
+        assert_eq!(output, "1\n2\n");
+    }
+
+    #[test]
+    fn callees_overwrite_variables_only_with_global_storage() {
+        let text = "int blub() { blub1 = 42; return 1; }
+             void main() { blub1 = 1; x = blub(); printf(blub1); }";
+        assert_eq!(run_with(text, Storage::Local).unwrap().0, "1\n");
+        assert_eq!(run_with(text, Storage::Global).unwrap().0, "42\n");
+    }
+
+    #[test]
+    fn recursive_calls_follow_the_storage() {
+        // Each recursive call of blub() reassigns blub1. With global storage the callers see the
+        // new value after the call returns, with local storage every call starts without blub1.
+        let text = "int blub() {
+                 blub1 = blub1 + 1;
+                 if (blub1 < 3) { blub2 = blub(); }
+                 printf(blub1);
+                 return blub1;
+             }
+             int main() { blub1 = 0; return blub(); }";
+        assert_eq!(
+            run_with(text, Storage::Global),
+            Ok(("3\n3\n3\n".to_string(), Some(Value::Int(3))))
+        );
+        assert_eq!(
+            run_with(text, Storage::Local).unwrap_err().to_string(),
+            "Line 2: variable `blub1` is used before it is assigned"
+        );
+    }
+
+    #[test]
+    fn single_functions_can_be_called_with_a_step_limit() {
+        let program = C1Parser::parse_program(
+            "int tree() { d = 0; return f(); }
+             int f() { d = d + 1; if (d < 20) { a = f(); b = f(); } d = d - 1; return 0; }
+             int three() { a = 1; return a + 2; }",
+        )
+        .unwrap();
+        let mut interpreter = Interpreter::new(&program);
+        interpreter.max_steps = Some(1000);
+        assert_eq!(interpreter.call_function("three"), Ok(Some(Value::Int(3))));
+        interpreter.storage = Storage::Global;
+        assert_eq!(
+            interpreter.call_function("tree").unwrap_err().to_string(),
+            "Line 2: step limit of 1000 exceeded"
+        );
+    }
+
+    #[test]
+    fn runtime_errors_contain_line_numbers() {
+        let error = |text| run(text).unwrap_err().to_string();
+        assert_eq!(
+            error("void main() {\n a = 0;\n printf(1 / a);\n}"),
+            "Line 3: division by zero"
+        );
+        assert_eq!(
+            error("void f() {}\nvoid main() {\n x = f();\n}"),
+            "Line 3: void function `f` is called for its value"
+        );
+        assert_eq!(
+            error("int f() { if (false) return 1; }\nvoid main() {\n x = f();\n}"),
+            "Line 3: function `f` did not return a value"
+        );
+        assert_eq!(
+            error("void main() {\n printf(x);\n}"),
+            "Line 2: variable `x` is used before it is assigned"
+        );
+        assert_eq!(
+            error("void main() {\n g();\n}"),
+            "Line 2: call of undefined function `g`"
+        );
+        assert_eq!(
+            error("void main() {\n return 1;\n}"),
+            "Line 2: void function `main` returns a value"
+        );
+        assert_eq!(
+            error("int f() {\n return f();\n}\nvoid main() { f(); }"),
+            "Line 2: maximum call depth of 256 exceeded in call of `f`"
+        );
+        assert_eq!(
+            error("void f() {}"),
+            "Line 1: the program has no main function"
+        );
+    }
+}
@@ -0,0 +1,720 @@
+use crate::ast::{
+    format_float, BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement,
+    Type, UnaryOp,
+};
+use crate::backend::{EmitError, TypedProgram};
+use crate::config::Storage;
+use crate::value::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Where an instruction stores its result
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
+pub enum Place {
+    /// A value computed by the lowering, written as `%t1` so it differs from any variable.
+    /// Temporaries belong to a single call of their function even with global storage.
+    Temp(usize),
+    /// A variable of the program
+    Variable(String),
+    /// A value of a place in static single assignment form, written as `x.1`. Version 0 is the
+    /// value before the first assignment, which is never defined.
+    Version(Box<Place>, usize),
+}
+
+impl Place {
+    /// Return the place of the lowered program, without the version of SSA form
+    pub fn base(&self) -> &Place {
+        match self {
+            Place::Version(place, _) => place.base(),
+            place => place,
+        }
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Place::Temp(index) => write!(f, "%t{}", index),
+            Place::Variable(name) => f.write_str(name),
+            Place::Version(place, version) => write!(f, "{}.{}", place, version),
+        }
+    }
+}
+
+/// An argument of an instruction
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Constant(Value),
+    Place(Place),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Constant(Value::Float(value)) => f.write_str(&format_float(*value)),
+            Operand::Constant(value) => write!(f, "{}", value),
+            Operand::Place(place) => write!(f, "{}", place),
+        }
+    }
+}
+
+/// A jump target within a function, written as `L1`
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub struct Label(pub usize);
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{}", self.0)
+    }
+}
+
+/// What an instruction does. Operands of binary operators have the same type, `&&` and `||`
+/// are lowered to branches.
+#[derive(Debug, PartialEq, Clone)]
+pub enum InstructionKind {
+    Copy {
+        target: Place,
+        value: Operand,
+    },
+    Unary {
+        target: Place,
+        op: UnaryOp,
+        operand: Operand,
+    },
+    Binary {
+        target: Place,
+        op: BinaryOp,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    /// Convert a value to another type like an assignment in C would
+    Convert {
+        target: Place,
+        to: Type,
+        value: Operand,
+    },
+    /// Call a function, the call fails if the result is stored but the function returns none
+    Call {
+        target: Option<Place>,
+        function: String,
+    },
+    Print(Operand),
+    Label(Label),
+    Jump(Label),
+    /// Continue at `then_label` if the bool condition is true, otherwise at `else_label`
+    Branch {
+        condition: Operand,
+        then_label: Label,
+        else_label: Label,
+    },
+    Return(Option<Operand>),
+    /// Choose the operand of the block control came from, with one operand per predecessor of
+    /// the block. Only found at the start of a block in SSA form.
+    Phi {
+        target: Place,
+        sources: Vec<(Label, Operand)>,
+    },
+}
+
+/// A three-address instruction together with the source line it was lowered from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Instruction {
+    pub kind: InstructionKind,
+    pub line: usize,
+}
+
+impl Instruction {
+    pub fn new(kind: InstructionKind, line: usize) -> Self {
+        Instruction { kind, line }
+    }
+
+    /// Return the place the instruction assigns, if any
+    pub fn target(&self) -> Option<&Place> {
+        match &self.kind {
+            InstructionKind::Copy { target, .. }
+            | InstructionKind::Unary { target, .. }
+            | InstructionKind::Binary { target, .. }
+            | InstructionKind::Convert { target, .. }
+            | InstructionKind::Phi { target, .. } => Some(target),
+            InstructionKind::Call { target, .. } => target.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn target_mut(&mut self) -> Option<&mut Place> {
+        match &mut self.kind {
+            InstructionKind::Copy { target, .. }
+            | InstructionKind::Unary { target, .. }
+            | InstructionKind::Binary { target, .. }
+            | InstructionKind::Convert { target, .. }
+            | InstructionKind::Phi { target, .. } => Some(target),
+            InstructionKind::Call { target, .. } => target.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Return the operands the instruction reads, in evaluation order
+    pub fn operands(&self) -> Vec<&Operand> {
+        match &self.kind {
+            InstructionKind::Copy { value, .. } | InstructionKind::Convert { value, .. } => {
+                vec![value]
+            }
+            InstructionKind::Unary { operand, .. } => vec![operand],
+            InstructionKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            InstructionKind::Print(value) => vec![value],
+            InstructionKind::Branch { condition, .. } => vec![condition],
+            InstructionKind::Return(Some(value)) => vec![value],
+            InstructionKind::Phi { sources, .. } => {
+                sources.iter().map(|(_, operand)| operand).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match &mut self.kind {
+            InstructionKind::Copy { value, .. } | InstructionKind::Convert { value, .. } => {
+                vec![value]
+            }
+            InstructionKind::Unary { operand, .. } => vec![operand],
+            InstructionKind::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            InstructionKind::Print(value) => vec![value],
+            InstructionKind::Branch { condition, .. } => vec![condition],
+            InstructionKind::Return(Some(value)) => vec![value],
+            InstructionKind::Phi { sources, .. } => {
+                sources.iter_mut().map(|(_, operand)| operand).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether control never continues with the next instruction
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self.kind,
+            InstructionKind::Jump(_) | InstructionKind::Branch { .. } | InstructionKind::Return(_)
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            InstructionKind::Copy { target, value } => write!(f, "{} = {}", target, value),
+            InstructionKind::Unary {
+                target,
+                op: UnaryOp::Neg,
+                operand,
+            } => write!(f, "{} = -{}", target, operand),
+            InstructionKind::Binary {
+                target,
+                op,
+                lhs,
+                rhs,
+            } => write!(f, "{} = {} {} {}", target, lhs, op, rhs),
+            InstructionKind::Convert { target, to, value } => {
+                write!(f, "{} = ({}) {}", target, to, value)
+            }
+            InstructionKind::Call {
+                target: Some(target),
+                function,
+            } => write!(f, "{} = call {}", target, function),
+            InstructionKind::Call {
+                target: None,
+                function,
+            } => write!(f, "call {}", function),
+            InstructionKind::Print(value) => write!(f, "print {}", value),
+            InstructionKind::Label(label) => write!(f, "{}:", label),
+            InstructionKind::Jump(label) => write!(f, "goto {}", label),
+            InstructionKind::Branch {
+                condition,
+                then_label,
+                else_label,
+            } => write!(
+                f,
+                "if {} goto {} else {}",
+                condition, then_label, else_label
+            ),
+            InstructionKind::Return(Some(value)) => write!(f, "return {}", value),
+            InstructionKind::Return(None) => f.write_str("return"),
+            InstructionKind::Phi { target, sources } => {
+                let sources: Vec<String> = sources
+                    .iter()
+                    .map(|(label, operand)| format!("{}: {}", label, operand))
+                    .collect();
+                write!(f, "{} = phi({})", target, sources.join(", "))
+            }
+        }
+    }
+}
+
+/// A function lowered to a list of three-address instructions
+#[derive(Debug, PartialEq, Clone)]
+pub struct IrFunction {
+    pub name: String,
+    pub return_type: Type,
+    pub line: usize,
+    /// Type of every place the function uses
+    pub types: BTreeMap<Place, Type>,
+    pub instructions: Vec<Instruction>,
+    /// Number of temporaries and labels created so far, so that transforms can add new ones
+    pub temps: usize,
+    pub labels: usize,
+}
+
+impl IrFunction {
+    pub fn new(name: &str, return_type: Type, line: usize) -> Self {
+        IrFunction {
+            name: name.to_string(),
+            return_type,
+            line,
+            types: BTreeMap::new(),
+            instructions: Vec::new(),
+            temps: 0,
+            labels: 0,
+        }
+    }
+
+    /// Create a temporary of the given type
+    pub fn new_temp(&mut self, value_type: Type) -> Place {
+        self.temps += 1;
+        let temp = Place::Temp(self.temps);
+        self.types.insert(temp.clone(), value_type);
+        temp
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels += 1;
+        Label(self.labels)
+    }
+
+    /// Return the type of an operand
+    pub fn operand_type(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Constant(value) => value.value_type(),
+            Operand::Place(place) => self.types[place],
+        }
+    }
+}
+
+/// Dump of the function, one instruction per line followed by its source line
+impl fmt::Display for IrFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "function {} {}()", self.return_type, self.name)?;
+        let variables: Vec<String> = self
+            .types
+            .iter()
+            .filter(|(place, _)| matches!(place.base(), Place::Variable(_)))
+            .map(|(place, value_type)| format!("{}: {}", place, value_type))
+            .collect();
+        if !variables.is_empty() {
+            writeln!(f, "    ; variables {}", variables.join(", "))?;
+        }
+        for instruction in &self.instructions {
+            let text = match instruction.kind {
+                InstructionKind::Label(_) => format!("  {}", instruction),
+                _ => format!("    {}", instruction),
+            };
+            writeln!(f, "{:<36} ; line {}", text, instruction.line)?;
+        }
+        Ok(())
+    }
+}
+
+/// A program in three-address code, shared by the optimizations and backends that need a
+/// linear representation with explicit control flow and conversions
+#[derive(Debug, PartialEq, Clone)]
+pub struct IrProgram {
+    pub functions: Vec<IrFunction>,
+    pub storage: Storage,
+}
+
+impl IrProgram {
+    pub fn function(&self, name: &str) -> Option<&IrFunction> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+impl fmt::Display for IrProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "; {} storage", self.storage.name())?;
+        for function in &self.functions {
+            write!(f, "\n{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl Program {
+    /// Lower the program into three-address code. Conversions between types become explicit
+    /// instructions, `if` statements and the short-circuit operators become branches, and
+    /// every function ends with a `return`.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn lower(&self, storage: Storage) -> Result<IrProgram, EmitError> {
+        let typed = TypedProgram::check_types(self, storage)?;
+        let functions = self
+            .functions
+            .iter()
+            .map(|function| {
+                let mut lowering = Lowering {
+                    typed: &typed,
+                    source: function,
+                    function: IrFunction::new(&function.name, function.return_type, function.line),
+                };
+                lowering.function();
+                lowering.function
+            })
+            .collect();
+        Ok(IrProgram { functions, storage })
+    }
+}
+
+struct Lowering<'a> {
+    typed: &'a TypedProgram<'a>,
+    source: &'a FunctionDefinition,
+    function: IrFunction,
+}
+
+impl Lowering<'_> {
+    fn function(&mut self) {
+        let variables = match self.typed.storage {
+            Storage::Local => self.typed.locals(&self.source.name),
+            Storage::Global => self.typed.globals(),
+        };
+        for (name, variable_type) in variables {
+            self.function
+                .types
+                .insert(Place::Variable(name.clone()), *variable_type);
+        }
+        for statement in &self.source.body {
+            self.statement(statement);
+        }
+        if !self.ends_with_terminator() {
+            let line = self
+                .source
+                .body
+                .last()
+                .map_or(self.source.line, Statement::line);
+            self.emit(InstructionKind::Return(None), line);
+        }
+    }
+
+    fn emit(&mut self, kind: InstructionKind, line: usize) {
+        self.function
+            .instructions
+            .push(Instruction::new(kind, line));
+    }
+
+    fn ends_with_terminator(&self) -> bool {
+        self.function
+            .instructions
+            .last()
+            .is_some_and(Instruction::is_terminator)
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        let line = statement.line();
+        match statement {
+            Statement::Block { statements, .. } => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                let condition = self.expression(condition);
+                let condition = self.truth(condition, line);
+                let then_label = self.function.new_label();
+                let end_label = self.function.new_label();
+                self.emit(
+                    InstructionKind::Branch {
+                        condition,
+                        then_label,
+                        else_label: end_label,
+                    },
+                    line,
+                );
+                self.emit(InstructionKind::Label(then_label), line);
+                self.statement(then_branch);
+                self.emit(InstructionKind::Label(end_label), line);
+            }
+            Statement::Return { value, .. } => {
+                let value = value.as_ref().map(|value| {
+                    let operand = self.expression(value);
+                    self.convert(operand, self.source.return_type, line)
+                });
+                self.emit(InstructionKind::Return(value), line);
+            }
+            Statement::Printf { value, .. } => {
+                let value = self.expression(value);
+                self.emit(InstructionKind::Print(value), line);
+            }
+            Statement::Assign { target, value, .. } => {
+                let value = self.expression(value);
+                self.assign(Place::Variable(target.clone()), value, line);
+            }
+            Statement::Call { name, .. } => self.emit(
+                InstructionKind::Call {
+                    target: None,
+                    function: name.clone(),
+                },
+                line,
+            ),
+        }
+    }
+
+    /// Assign a value to a variable. A temporary computed by the last instruction is replaced by
+    /// the variable instead of being copied.
+    fn assign(&mut self, target: Place, value: Operand, line: usize) {
+        if let Operand::Place(temp @ Place::Temp(_)) = &value {
+            let last = self.function.instructions.last_mut();
+            if let Some(last) = last.filter(|last| last.target() == Some(temp)) {
+                *last.target_mut().expect("the instruction has a target") = target;
+                self.function.types.remove(temp);
+                return;
+            }
+        }
+        self.emit(InstructionKind::Copy { target, value }, line);
+    }
+
+    /// Lower an expression and return the operand that holds its value
+    fn expression(&mut self, expression: &Expression) -> Operand {
+        let line = expression.line;
+        let value_type = self.typed.expression_type(&self.source.name, expression);
+        match &expression.kind {
+            ExpressionKind::Int(value) => Operand::Constant(Value::Int(*value)),
+            ExpressionKind::Float(value) => Operand::Constant(Value::Float(*value)),
+            ExpressionKind::Bool(value) => Operand::Constant(Value::Bool(*value)),
+            ExpressionKind::Variable(name) => Operand::Place(Place::Variable(name.clone())),
+            ExpressionKind::Call(name) => {
+                let target = self.function.new_temp(value_type);
+                self.emit(
+                    InstructionKind::Call {
+                        target: Some(target.clone()),
+                        function: name.clone(),
+                    },
+                    line,
+                );
+                Operand::Place(target)
+            }
+            ExpressionKind::Assign { target, value } => {
+                let value = self.expression(value);
+                let target = Place::Variable(target.clone());
+                self.assign(target.clone(), value, line);
+                Operand::Place(target)
+            }
+            ExpressionKind::Unary { op, operand } => {
+                let operand = self.expression(operand);
+                let operand = self.convert(operand, value_type, line);
+                if let Operand::Constant(value) = operand {
+                    return Operand::Constant(value.negate());
+                }
+                let target = self.function.new_temp(value_type);
+                self.emit(
+                    InstructionKind::Unary {
+                        target: target.clone(),
+                        op: *op,
+                        operand,
+                    },
+                    line,
+                );
+                Operand::Place(target)
+            }
+            ExpressionKind::Binary { op, lhs, rhs } if op.is_logical() => {
+                // The result is assigned on both paths, see `Place::Temp`
+                let target = self.function.new_temp(Type::Bool);
+                let short_circuit = *op == BinaryOp::Or;
+                self.emit(
+                    InstructionKind::Copy {
+                        target: target.clone(),
+                        value: Operand::Constant(Value::Bool(short_circuit)),
+                    },
+                    line,
+                );
+                let lhs = self.expression(lhs);
+                let condition = self.truth(lhs, line);
+                let rhs_label = self.function.new_label();
+                let end_label = self.function.new_label();
+                let (then_label, else_label) = match op {
+                    BinaryOp::And => (rhs_label, end_label),
+                    _ => (end_label, rhs_label),
+                };
+                self.emit(
+                    InstructionKind::Branch {
+                        condition,
+                        then_label,
+                        else_label,
+                    },
+                    line,
+                );
+                self.emit(InstructionKind::Label(rhs_label), line);
+                let rhs = self.expression(rhs);
+                let rhs = self.truth(rhs, line);
+                self.emit(
+                    InstructionKind::Copy {
+                        target: target.clone(),
+                        value: rhs,
+                    },
+                    line,
+                );
+                self.emit(InstructionKind::Label(end_label), line);
+                Operand::Place(target)
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let lhs_type = self.typed.expression_type(&self.source.name, lhs);
+                let rhs_type = self.typed.expression_type(&self.source.name, rhs);
+                let operand_type = if lhs_type == Type::Float || rhs_type == Type::Float {
+                    Type::Float
+                } else {
+                    Type::Int
+                };
+                let lhs_value = self.expression(lhs);
+                let mut lhs_value = self.convert(lhs_value, operand_type, line);
+                // The right operand must not change the value of the left one
+                if matches!(lhs_value, Operand::Place(Place::Variable(_))) && has_effects(rhs) {
+                    let copy = self.function.new_temp(operand_type);
+                    self.emit(
+                        InstructionKind::Copy {
+                            target: copy.clone(),
+                            value: lhs_value,
+                        },
+                        line,
+                    );
+                    lhs_value = Operand::Place(copy);
+                }
+                let rhs_value = self.expression(rhs);
+                let rhs_value = self.convert(rhs_value, operand_type, line);
+                let target = self.function.new_temp(value_type);
+                self.emit(
+                    InstructionKind::Binary {
+                        target: target.clone(),
+                        op: *op,
+                        lhs: lhs_value,
+                        rhs: rhs_value,
+                    },
+                    line,
+                );
+                Operand::Place(target)
+            }
+        }
+    }
+
+    /// Convert an operand to the given type, constants are converted right away
+    fn convert(&mut self, operand: Operand, to: Type, line: usize) -> Operand {
+        if self.function.operand_type(&operand) == to {
+            return operand;
+        }
+        if let Operand::Constant(value) = &operand {
+            return Operand::Constant(value.convert_to(to).expect("values are not void"));
+        }
+        let target = self.function.new_temp(to);
+        self.emit(
+            InstructionKind::Convert {
+                target: target.clone(),
+                to,
+                value: operand,
+            },
+            line,
+        );
+        Operand::Place(target)
+    }
+
+    /// Convert an operand to a bool, like conditions and operands of `&&` and `||` are
+    fn truth(&mut self, operand: Operand, line: usize) -> Operand {
+        self.convert(operand, Type::Bool, line)
+    }
+}
+
+/// Whether evaluating the expression can assign variables
+fn has_effects(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Call(_) | ExpressionKind::Assign { .. } => true,
+        ExpressionKind::Unary { operand, .. } => has_effects(operand),
+        ExpressionKind::Binary { lhs, rhs, .. } => has_effects(lhs) || has_effects(rhs),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    fn lowered(text: &str) -> Vec<String> {
+        let program = C1Parser::parse_program(text).unwrap();
+        let ir = program.lower(Storage::Local).unwrap();
+        ir.functions[0]
+            .instructions
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn expressions_become_three_address_code() {
+        assert_eq!(
+            lowered("float main() { a = 2; b = a * 3 + 0.5; return b > a; }"),
+            vec![
+                "a = 2",
+                "%t1 = a * 3",
+                "%t2 = (float) %t1",
+                "b = %t2 + 0.5",
+                "%t4 = (float) a",
+                "%t5 = b > %t4",
+                "%t6 = (float) %t5",
+                "return %t6",
+            ]
+        );
+        // Assignments and calls on the right could change the left operand
+        assert_eq!(
+            lowered("int main() { x = 1; return x + (x = 2); }"),
+            vec!["x = 1", "%t1 = x", "x = 2", "%t2 = %t1 + x", "return %t2"]
+        );
+        assert_eq!(
+            lowered(
+                "void main() { x = 0; if (x || g()) printf(-1.5); }\nbool g() { return true; }"
+            ),
+            vec![
+                "x = 0",
+                "%t1 = true",
+                "%t2 = (bool) x",
+                "if %t2 goto L2 else L1",
+                "L1:",
+                "%t3 = call g",
+                "%t1 = %t3",
+                "L2:",
+                "if %t1 goto L3 else L4",
+                "L3:",
+                "print -1.5",
+                "L4:",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn dumps_show_source_lines() {
+        let program =
+            C1Parser::parse_program("int main() {\n a = 1;\n if (a) return 2;\n}").unwrap();
+        let ir = program.lower(Storage::Global).unwrap();
+        assert_eq!(
+            ir.to_string(),
+            "; global storage
+
+function int main()
+    ; variables a: int
+    a = 1                            ; line 2
+    %t1 = (bool) a                   ; line 3
+    if %t1 goto L1 else L2           ; line 3
+  L1:                                ; line 3
+    return 2                         ; line 3
+  L2:                                ; line 3
+    return                           ; line 3
+"
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::Storage;
+use crate::interpreter::{Execution, Interpreter, RuntimeError};
+use crate::ir::{InstructionKind, IrFunction, IrProgram, Label, Operand, Place};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Places of a single function call. With global storage variables live in the shared store
+/// instead.
+type Frame = HashMap<Place, Value>;
+
+/// Position of every label in the instructions of a function
+type Labels = Rc<HashMap<Label, usize>>;
+
+/// Executes a program in three-address code, starting at `main`. Behaves like `Interpreter`
+/// for the program that was lowered, which makes it possible to test the lowering and the
+/// transforms of the IR against the semantics of the source.
+pub struct IrInterpreter<'a> {
+    storage: Storage,
+    functions: HashMap<&'a str, (&'a IrFunction, Labels)>,
+    globals: HashMap<String, Value>,
+    output: String,
+    depth: usize,
+    /// Maximum number of nested function calls before the execution is aborted
+    pub max_call_depth: usize,
+}
+
+impl<'a> IrInterpreter<'a> {
+    pub fn new(program: &'a IrProgram) -> Self {
+        let functions = program
+            .functions
+            .iter()
+            .map(|function| {
+                let labels = function
+                    .instructions
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(position, instruction)| match instruction.kind {
+                        InstructionKind::Label(label) => Some((label, position)),
+                        _ => None,
+                    })
+                    .collect();
+                (function.name.as_str(), (function, Rc::new(labels)))
+            })
+            .collect();
+        IrInterpreter {
+            storage: program.storage,
+            functions,
+            globals: HashMap::new(),
+            output: String::new(),
+            depth: 0,
+            max_call_depth: Interpreter::DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    /// Run the program by calling `main`
+    pub fn run(&mut self) -> Result<Execution, RuntimeError> {
+        if !self.functions.contains_key("main") {
+            return Err(RuntimeError {
+                line: 1,
+                message: "the program has no main function".to_string(),
+            });
+        }
+        let exit_value = self.call("main", 1)?;
+        Ok(Execution {
+            output: std::mem::take(&mut self.output),
+            exit_value,
+        })
+    }
+
+    /// Return the output printed so far, which is useful after a runtime error
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    fn call(&mut self, name: &str, line: usize) -> Result<Option<Value>, RuntimeError> {
+        let (function, labels) = match self.functions.get(name) {
+            Some((function, labels)) => (*function, Rc::clone(labels)),
+            None => {
+                return Err(RuntimeError {
+                    line,
+                    message: format!("call of undefined function `{}`", name),
+                })
+            }
+        };
+        if self.depth >= self.max_call_depth {
+            return Err(RuntimeError {
+                line,
+                message: format!(
+                    "maximum call depth of {} exceeded in call of `{}`",
+                    self.max_call_depth, name
+                ),
+            });
+        }
+        self.depth += 1;
+        let mut frame = Frame::new();
+        let mut position = 0;
+        // Labels of the current block and of the block control came from, which select the
+        // operands of phi instructions
+        let mut current = None;
+        let mut previous = None;
+        let result = loop {
+            let instruction = &function.instructions[position];
+            let line = instruction.line;
+            position += 1;
+            match &instruction.kind {
+                InstructionKind::Copy { target, value } => {
+                    let value = self.read(&frame, value, line)?;
+                    self.write(&mut frame, target, value);
+                }
+                InstructionKind::Unary {
+                    target, operand, ..
+                } => {
+                    let value = self.read(&frame, operand, line)?.negate();
+                    self.write(&mut frame, target, value);
+                }
+                InstructionKind::Binary {
+                    target,
+                    op,
+                    lhs,
+                    rhs,
+                } => {
+                    let lhs = self.read(&frame, lhs, line)?;
+                    let rhs = self.read(&frame, rhs, line)?;
+                    let value = Value::binary(*op, lhs, rhs)
+                        .map_err(|message| RuntimeError { line, message })?;
+                    self.write(&mut frame, target, value);
+                }
+                InstructionKind::Convert { target, to, value } => {
+                    let value = self.read(&frame, value, line)?;
+                    let value = value.convert_to(*to).expect("conversions are not to void");
+                    self.write(&mut frame, target, value);
+                }
+                InstructionKind::Call { target, function } => {
+                    let result = self.call(function, line)?;
+                    if let Some(target) = target {
+                        let value = result.ok_or_else(|| RuntimeError {
+                            line,
+                            message: format!("function `{}` did not return a value", function),
+                        })?;
+                        self.write(&mut frame, target, value);
+                    }
+                }
+                InstructionKind::Print(value) => {
+                    let value = self.read(&frame, value, line)?;
+                    self.output.push_str(&format!("{}\n", value));
+                }
+                InstructionKind::Label(label) => {
+                    previous = current;
+                    current = Some(*label);
+                }
+                InstructionKind::Jump(label) => position = labels[label],
+                InstructionKind::Branch {
+                    condition,
+                    then_label,
+                    else_label,
+                } => {
+                    let condition = self.read(&frame, condition, line)?;
+                    let label = match condition.is_truthy() {
+                        true => then_label,
+                        false => else_label,
+                    };
+                    position = labels[label];
+                }
+                InstructionKind::Return(value) => {
+                    break match value {
+                        Some(value) => Some(self.read(&frame, value, line)?),
+                        None => None,
+                    };
+                }
+                InstructionKind::Phi { .. } => {
+                    // All phis of the block read their operands before any of them is assigned.
+                    // A value that is not assigned on the incoming path stays unassigned, so the
+                    // error is reported where the value is used.
+                    let mut values = Vec::new();
+                    position -= 1;
+                    while let InstructionKind::Phi { target, sources } =
+                        &function.instructions[position].kind
+                    {
+                        let (_, source) = sources
+                            .iter()
+                            .find(|(label, _)| Some(*label) == previous)
+                            .expect("phis have an operand for every predecessor");
+                        let value = match source {
+                            Operand::Constant(value) => Some(*value),
+                            Operand::Place(place) => self.lookup(&frame, place),
+                        };
+                        values.push((target, value));
+                        position += 1;
+                    }
+                    for (target, value) in values {
+                        match value {
+                            Some(value) => self.write(&mut frame, target, value),
+                            None => {
+                                frame.remove(target);
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        self.depth -= 1;
+        Ok(result)
+    }
+
+    fn read(&self, frame: &Frame, operand: &Operand, line: usize) -> Result<Value, RuntimeError> {
+        let place = match operand {
+            Operand::Constant(value) => return Ok(*value),
+            Operand::Place(place) => place,
+        };
+        self.lookup(frame, place).ok_or_else(|| RuntimeError {
+            line,
+            message: format!("variable `{}` is used before it is assigned", place.base()),
+        })
+    }
+
+    fn lookup(&self, frame: &Frame, place: &Place) -> Option<Value> {
+        let value = match (self.storage, place) {
+            (Storage::Global, Place::Variable(name)) => self.globals.get(name),
+            _ => frame.get(place),
+        };
+        value.copied()
+    }
+
+    fn write(&mut self, frame: &mut Frame, place: &Place, value: Value) {
+        match (self.storage, place) {
+            (Storage::Global, Place::Variable(name)) => {
+                self.globals.insert(name.clone(), value);
+            }
+            _ => {
+                frame.insert(place.clone(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::ir_interpreter::IrInterpreter;
+    use crate::value::Value;
+    use crate::C1Parser;
+
+    #[test]
+    fn lowered_programs_run_like_the_source() {
+        let program = C1Parser::parse_program(
+            "int fib() { if (n < 2) return n; n = n - 1; a = fib(); n = n - 1; return a + fib() + 0 * (n = n + 2); }
+             int main() { n = 10; printf(fib()); printf(n); printf(1 / (n - 10)); }",
+        )
+        .unwrap();
+        let ir = program.lower(Storage::Global).unwrap();
+        let mut interpreter = IrInterpreter::new(&ir);
+        let error = interpreter.run().unwrap_err();
+        assert_eq!(error.to_string(), "Line 2: division by zero");
+        assert_eq!(interpreter.output(), "55\n10\n");
+
+        let program = C1Parser::parse_program("bool main() { x = 2.5; return x; }").unwrap();
+        let ir = program.lower(Storage::Local).unwrap();
+        let execution = IrInterpreter::new(&ir).run().unwrap();
+        assert_eq!(execution.exit_value, Some(Value::Bool(true)));
+    }
+}
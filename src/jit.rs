@@ -0,0 +1,876 @@
+use crate::ast::{BinaryOp, Program, Type};
+use crate::backend::{check_assigned, EmitError};
+use crate::config::Storage;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::ir::{InstructionKind, IrFunction, IrProgram, Label, Operand, Place};
+use crate::value::Value;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{self, types, AbiParam, Block, FuncRef, InstBuilder, MemFlags};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use std::collections::{HashMap, HashSet};
+use std::mem::offset_of;
+
+/// State of a run that the compiled code shares with the host. Every compiled function gets a
+/// pointer to it as its only argument, the fields it accesses are found with `offset_of!`.
+#[repr(C)]
+struct Runtime<'a> {
+    /// Number of active calls, including the one of `main`
+    depth: u32,
+    max_call_depth: u32,
+    /// Set when a runtime error happened, every function then returns right after its calls
+    failed: u8,
+    /// Set by a function that returns without a result, checked by callers that use it
+    missing_result: u8,
+    /// One slot of 8 bytes for every variable with global storage
+    globals: *mut u64,
+    print: &'a mut dyn FnMut(Value),
+    error: Option<RuntimeError>,
+    functions: &'a [String],
+}
+
+/// Runtime errors the compiled code reports through `c1_fail`
+const DIVISION_BY_ZERO: i64 = 0;
+const MISSING_RESULT: i64 = 1;
+const CALL_DEPTH: i64 = 2;
+
+extern "C" fn print_int(runtime: &mut Runtime, value: i32) {
+    (runtime.print)(Value::Int(value));
+}
+
+extern "C" fn print_float(runtime: &mut Runtime, value: f64) {
+    (runtime.print)(Value::Float(value));
+}
+
+extern "C" fn print_bool(runtime: &mut Runtime, value: u8) {
+    (runtime.print)(Value::Bool(value != 0));
+}
+
+/// Record a runtime error, `function` is the index of the called function for errors of calls
+extern "C" fn fail(runtime: &mut Runtime, kind: i32, line: i32, function: i32) {
+    let name = &runtime.functions[function as usize];
+    let message = match kind as i64 {
+        DIVISION_BY_ZERO => "division by zero".to_string(),
+        MISSING_RESULT => format!("function `{}` did not return a value", name),
+        _ => format!(
+            "maximum call depth of {} exceeded in call of `{}`",
+            runtime.max_call_depth, name
+        ),
+    };
+    runtime.error = Some(RuntimeError {
+        line: line as usize,
+        message,
+    });
+    runtime.failed = 1;
+}
+
+/// A program compiled to native code with Cranelift, which runs in the current process.
+///
+/// Each function is translated from the three-address code of `Program::lower`, with its
+/// variables and temporaries in Cranelift variables and variables with global storage in
+/// memory. Values follow the semantics of the interpreter: int arithmetic wraps around, float
+/// to int conversions saturate and runtime errors are reported with their line. Like the other
+/// native backends, it rejects programs that may read a variable before assigning it.
+pub struct Jit {
+    /// Always present, taken when the code is freed
+    module: Option<JITModule>,
+    main: *const u8,
+    main_type: Type,
+    functions: Vec<String>,
+    globals: usize,
+    /// Maximum number of nested function calls before the execution is aborted
+    pub max_call_depth: usize,
+}
+
+impl Jit {
+    /// Compile all functions of the program for the host.
+    ///
+    /// Fails if the program has no static types, see `EmitError`, or if Cranelift does not
+    /// support the host.
+    pub fn compile(program: &Program, storage: Storage) -> Result<Self, EmitError> {
+        check_assigned(program, storage)?;
+        let ir = program.lower(storage)?;
+        let host_error = |message: String| EmitError {
+            line: 1,
+            message: format!("Cranelift does not support the host: {}", message),
+        };
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").unwrap();
+        flags.set("is_pic", "false").unwrap();
+        let isa = cranelift_native::builder()
+            .map_err(|message| host_error(message.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|error| host_error(error.to_string()))?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("c1_print_int", print_int as *const u8);
+        builder.symbol("c1_print_float", print_float as *const u8);
+        builder.symbol("c1_print_bool", print_bool as *const u8);
+        builder.symbol("c1_fail", fail as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let mut compiler = Compiler::new(&mut module, &ir);
+        let result = compiler.declare().and_then(|()| {
+            for function in &ir.functions {
+                compiler.define(function)?;
+            }
+            Ok(())
+        });
+        let main_id = compiler.ids.get("main").copied();
+        let globals = compiler.globals.len();
+        if let Err(error) = result {
+            // SAFETY: none of the code was run
+            unsafe { module.free_memory() };
+            return Err(error);
+        }
+        let main = ir
+            .function("main")
+            .expect("programs are checked for a main function");
+        let module_error = |error: cranelift_module::ModuleError| EmitError {
+            line: main.line,
+            message: error.to_string(),
+        };
+        module.finalize_definitions().map_err(module_error)?;
+        Ok(Jit {
+            main: module.get_finalized_function(main_id.expect("main is declared")),
+            module: Some(module),
+            main_type: main.return_type,
+            functions: ir
+                .functions
+                .iter()
+                .map(|function| function.name.clone())
+                .collect(),
+            globals,
+            max_call_depth: Interpreter::DEFAULT_MAX_CALL_DEPTH,
+        })
+    }
+
+    /// Run the program by calling `main`, passing every value that `printf` prints to `print`.
+    /// Variables with global storage start as zero in every run. Returns the value returned by
+    /// `main` like `Interpreter::run`.
+    pub fn run(&self, mut print: impl FnMut(Value)) -> Result<Option<Value>, RuntimeError> {
+        let mut globals = vec![0u64; self.globals];
+        let mut runtime = Runtime {
+            depth: 1,
+            max_call_depth: self.max_call_depth.min(u32::MAX as usize) as u32,
+            failed: 0,
+            missing_result: 0,
+            globals: globals.as_mut_ptr(),
+            print: &mut print,
+            error: None,
+            functions: &self.functions,
+        };
+        if runtime.max_call_depth == 0 {
+            return Err(RuntimeError {
+                line: 1,
+                message: "maximum call depth of 0 exceeded in call of `main`".to_string(),
+            });
+        }
+        let pointer = &mut runtime as *mut Runtime;
+        // SAFETY: main was compiled with this signature, and the runtime and globals outlive
+        // the call
+        let result = unsafe {
+            match self.main_type {
+                Type::Void => {
+                    let main: extern "C" fn(*mut Runtime) = std::mem::transmute(self.main);
+                    main(pointer);
+                    None
+                }
+                Type::Int => {
+                    let main: extern "C" fn(*mut Runtime) -> i32 = std::mem::transmute(self.main);
+                    Some(Value::Int(main(pointer)))
+                }
+                Type::Float => {
+                    let main: extern "C" fn(*mut Runtime) -> f64 = std::mem::transmute(self.main);
+                    Some(Value::Float(main(pointer)))
+                }
+                Type::Bool => {
+                    let main: extern "C" fn(*mut Runtime) -> u8 = std::mem::transmute(self.main);
+                    Some(Value::Bool(main(pointer) != 0))
+                }
+            }
+        };
+        if let Some(error) = runtime.error {
+            return Err(error);
+        }
+        match runtime.missing_result {
+            0 => Ok(result),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the code can only be called through `run`, which borrows the Jit
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Functions of the host that the compiled code calls
+struct HostFunctions {
+    print_int: FuncId,
+    print_float: FuncId,
+    print_bool: FuncId,
+    fail: FuncId,
+}
+
+struct Compiler<'a> {
+    module: &'a mut JITModule,
+    ir: &'a IrProgram,
+    ids: HashMap<&'a str, FuncId>,
+    host: Option<HostFunctions>,
+    /// Offset of every variable with global storage in the globals
+    globals: HashMap<&'a str, i32>,
+    /// Functions that can return without a result
+    may_miss_result: HashSet<&'a str>,
+    builder_context: FunctionBuilderContext,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(module: &'a mut JITModule, ir: &'a IrProgram) -> Self {
+        let mut globals = HashMap::new();
+        if ir.storage == Storage::Global {
+            for function in &ir.functions {
+                for place in function.types.keys() {
+                    if let Place::Variable(name) = place {
+                        let offset = 8 * globals.len() as i32;
+                        globals.entry(name.as_str()).or_insert(offset);
+                    }
+                }
+            }
+        }
+        let may_miss_result = ir
+            .functions
+            .iter()
+            .filter(|function| {
+                function.return_type != Type::Void
+                    && function
+                        .instructions
+                        .iter()
+                        .any(|instruction| instruction.kind == InstructionKind::Return(None))
+            })
+            .map(|function| function.name.as_str())
+            .collect();
+        Compiler {
+            module,
+            ir,
+            ids: HashMap::new(),
+            host: None,
+            globals,
+            may_miss_result,
+            builder_context: FunctionBuilderContext::new(),
+        }
+    }
+
+    fn declare(&mut self) -> Result<(), EmitError> {
+        let pointer = self.module.target_config().pointer_type();
+        let declare = |module: &mut JITModule, name: &str, linkage, parameters: &[AbiParam]| {
+            let mut signature = module.make_signature();
+            signature.params.push(AbiParam::new(pointer));
+            signature.params.extend_from_slice(parameters);
+            module
+                .declare_function(name, linkage, &signature)
+                .map_err(|error| EmitError {
+                    line: 1,
+                    message: error.to_string(),
+                })
+        };
+        self.host = Some(HostFunctions {
+            print_int: declare(
+                self.module,
+                "c1_print_int",
+                Linkage::Import,
+                &[AbiParam::new(types::I32)],
+            )?,
+            print_float: declare(
+                self.module,
+                "c1_print_float",
+                Linkage::Import,
+                &[AbiParam::new(types::F64)],
+            )?,
+            print_bool: declare(
+                self.module,
+                "c1_print_bool",
+                Linkage::Import,
+                &[AbiParam::new(types::I8).uext()],
+            )?,
+            fail: declare(
+                self.module,
+                "c1_fail",
+                Linkage::Import,
+                &[AbiParam::new(types::I32); 3],
+            )?,
+        });
+        for function in &self.ir.functions {
+            let signature = self.signature(function);
+            let id = self
+                .module
+                .declare_function(&format!("f_{}", function.name), Linkage::Local, &signature)
+                .map_err(|error| EmitError {
+                    line: function.line,
+                    message: error.to_string(),
+                })?;
+            self.ids.insert(&function.name, id);
+        }
+        Ok(())
+    }
+
+    fn signature(&self, function: &IrFunction) -> ir::Signature {
+        let mut signature = self.module.make_signature();
+        signature
+            .params
+            .push(AbiParam::new(self.module.target_config().pointer_type()));
+        if function.return_type != Type::Void {
+            signature
+                .returns
+                .push(abi_param(clif_type(function.return_type)));
+        }
+        signature
+    }
+
+    fn define(&mut self, function: &'a IrFunction) -> Result<(), EmitError> {
+        let mut context = self.module.make_context();
+        context.func.signature = self.signature(function);
+        let builder = FunctionBuilder::new(&mut context.func, &mut self.builder_context);
+        let host = self.host.as_ref().expect("functions are declared first");
+        let mut callee = |id: FuncId| self.module.declare_func_in_func(id, builder.func);
+        let host_functions = [
+            callee(host.print_int),
+            callee(host.print_float),
+            callee(host.print_bool),
+            callee(host.fail),
+        ];
+        let functions: HashMap<&str, FuncRef> = self
+            .ids
+            .iter()
+            .map(|(name, id)| (*name, callee(*id)))
+            .collect();
+        let mut translator = Translator {
+            builder,
+            function,
+            functions,
+            indices: self
+                .ir
+                .functions
+                .iter()
+                .enumerate()
+                .map(|(index, function)| (function.name.as_str(), index as i64))
+                .collect(),
+            print_int: host_functions[0],
+            print_float: host_functions[1],
+            print_bool: host_functions[2],
+            fail: host_functions[3],
+            global_offsets: &self.globals,
+            may_miss_result: &self.may_miss_result,
+            runtime: None,
+            globals: None,
+            variables: HashMap::new(),
+            blocks: HashMap::new(),
+            bail: None,
+        };
+        translator.translate();
+        translator.builder.finalize();
+
+        let id = self.ids[function.name.as_str()];
+        self.module
+            .define_function(id, &mut context)
+            .map_err(|error| EmitError {
+                line: function.line,
+                message: format!("{:?}", error),
+            })
+    }
+}
+
+/// Translates the instructions of one function into Cranelift IR
+struct Translator<'a, 'b> {
+    builder: FunctionBuilder<'b>,
+    function: &'a IrFunction,
+    functions: HashMap<&'a str, FuncRef>,
+    /// Index of every function in the names of the runtime
+    indices: HashMap<&'a str, i64>,
+    print_int: FuncRef,
+    print_float: FuncRef,
+    print_bool: FuncRef,
+    fail: FuncRef,
+    global_offsets: &'a HashMap<&'a str, i32>,
+    may_miss_result: &'a HashSet<&'a str>,
+    /// Pointer to the runtime and to its globals
+    runtime: Option<ir::Value>,
+    globals: Option<ir::Value>,
+    variables: HashMap<&'a Place, Variable>,
+    blocks: HashMap<Label, Block>,
+    /// Block that returns right away after a runtime error
+    bail: Option<Block>,
+}
+
+impl<'a> Translator<'a, '_> {
+    fn translate(&mut self) {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let runtime = self.builder.block_params(entry)[0];
+        self.runtime = Some(runtime);
+        if !self.global_offsets.is_empty() {
+            let pointer = self.builder.func.dfg.value_type(runtime);
+            let globals = self.builder.ins().load(
+                pointer,
+                MemFlags::trusted(),
+                runtime,
+                offset_of!(Runtime, globals) as i32,
+            );
+            self.globals = Some(globals);
+        }
+        for (place, value_type) in &self.function.types {
+            if self.is_global(place) {
+                continue;
+            }
+            let variable = Variable::from_u32(self.variables.len() as u32);
+            self.builder.declare_var(variable, clif_type(*value_type));
+            let zero = self.zero(*value_type);
+            self.builder.def_var(variable, zero);
+            self.variables.insert(place, variable);
+        }
+        for instruction in &self.function.instructions {
+            if let InstructionKind::Label(label) = instruction.kind {
+                self.blocks.insert(label, self.builder.create_block());
+            }
+        }
+
+        let mut terminated = false;
+        for instruction in &self.function.instructions {
+            match instruction.kind {
+                InstructionKind::Label(label) => {
+                    let block = self.blocks[&label];
+                    if !terminated {
+                        self.builder.ins().jump(block, &[]);
+                    }
+                    self.builder.switch_to_block(block);
+                    terminated = false;
+                    continue;
+                }
+                // Code after a return that no jump reaches
+                _ if terminated => {
+                    let block = self.builder.create_block();
+                    self.builder.switch_to_block(block);
+                }
+                _ => {}
+            }
+            self.instruction(&instruction.kind, instruction.line as i64);
+            terminated = instruction.is_terminator();
+        }
+
+        if let Some(bail) = self.bail {
+            self.builder.switch_to_block(bail);
+            self.zero_return();
+        }
+        self.builder.seal_all_blocks();
+    }
+
+    fn instruction(&mut self, kind: &'a InstructionKind, line: i64) {
+        match kind {
+            InstructionKind::Copy { target, value } => {
+                let value = self.operand(value);
+                self.store(target, value);
+            }
+            InstructionKind::Unary {
+                target, operand, ..
+            } => {
+                let value = self.operand(operand);
+                let value = match self.function.operand_type(operand) {
+                    Type::Float => self.builder.ins().fneg(value),
+                    _ => self.builder.ins().ineg(value),
+                };
+                self.store(target, value);
+            }
+            InstructionKind::Binary {
+                target,
+                op,
+                lhs,
+                rhs,
+            } => {
+                let operand_type = self.function.operand_type(lhs);
+                let lhs = self.operand(lhs);
+                let rhs = self.operand(rhs);
+                let value = self.binary(*op, operand_type, lhs, rhs, line);
+                self.store(target, value);
+            }
+            InstructionKind::Convert { target, to, value } => {
+                let from = self.function.operand_type(value);
+                let value = self.operand(value);
+                let value = self.convert(value, from, *to);
+                self.store(target, value);
+            }
+            InstructionKind::Call { target, function } => {
+                self.call(target.as_ref(), function, line)
+            }
+            InstructionKind::Print(value) => {
+                let print = match self.function.operand_type(value) {
+                    Type::Float => self.print_float,
+                    Type::Bool => self.print_bool,
+                    _ => self.print_int,
+                };
+                let value = self.operand(value);
+                let runtime = self.runtime();
+                self.builder.ins().call(print, &[runtime, value]);
+            }
+            InstructionKind::Label(_) => unreachable!("labels start blocks"),
+            InstructionKind::Jump(label) => {
+                self.builder.ins().jump(self.blocks[label], &[]);
+            }
+            InstructionKind::Branch {
+                condition,
+                then_label,
+                else_label,
+            } => {
+                let condition = self.operand(condition);
+                let (then_block, else_block) = (self.blocks[then_label], self.blocks[else_label]);
+                self.builder
+                    .ins()
+                    .brif(condition, then_block, &[], else_block, &[]);
+            }
+            InstructionKind::Return(Some(value)) => {
+                let value = self.operand(value);
+                self.builder.ins().return_(&[value]);
+            }
+            InstructionKind::Return(None) => {
+                if self.function.return_type != Type::Void {
+                    let one = self.builder.ins().iconst(types::I8, 1);
+                    self.store_runtime(one, offset_of!(Runtime, missing_result));
+                }
+                self.zero_return();
+            }
+            InstructionKind::Phi { .. } => unreachable!("the code is not in SSA form"),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        operand_type: Type,
+        lhs: ir::Value,
+        rhs: ir::Value,
+        line: i64,
+    ) -> ir::Value {
+        if op.is_logical() {
+            let lhs = self.convert(lhs, operand_type, Type::Bool);
+            let rhs = self.convert(rhs, operand_type, Type::Bool);
+            return match op {
+                BinaryOp::And => self.builder.ins().band(lhs, rhs),
+                _ => self.builder.ins().bor(lhs, rhs),
+            };
+        }
+        let ins = self.builder.ins();
+        if operand_type == Type::Float {
+            return match op {
+                BinaryOp::Add => ins.fadd(lhs, rhs),
+                BinaryOp::Sub => ins.fsub(lhs, rhs),
+                BinaryOp::Mul => ins.fmul(lhs, rhs),
+                BinaryOp::Div => ins.fdiv(lhs, rhs),
+                op => ins.fcmp(float_condition(op), lhs, rhs),
+            };
+        }
+        match op {
+            BinaryOp::Add => ins.iadd(lhs, rhs),
+            BinaryOp::Sub => ins.isub(lhs, rhs),
+            BinaryOp::Mul => ins.imul(lhs, rhs),
+            BinaryOp::Div => {
+                let zero = ins.icmp_imm(IntCC::Equal, rhs, 0);
+                self.fail_if(zero, DIVISION_BY_ZERO, line, 0);
+                // sdiv traps on the overflow of `i32::MIN / -1`, which wraps around in C(-1)
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                let one = self.builder.ins().iconst(types::I32, 1);
+                let divisor = self.builder.ins().select(minus_one, one, rhs);
+                let quotient = self.builder.ins().sdiv(lhs, divisor);
+                let negated = self.builder.ins().ineg(lhs);
+                self.builder.ins().select(minus_one, negated, quotient)
+            }
+            op => ins.icmp(int_condition(op), lhs, rhs),
+        }
+    }
+
+    /// Convert a value to another type like `Value::convert_to`
+    fn convert(&mut self, value: ir::Value, from: Type, to: Type) -> ir::Value {
+        let ins = self.builder.ins();
+        match (from, to) {
+            (from, to) if from == to => value,
+            (Type::Int, Type::Float) => ins.fcvt_from_sint(types::F64, value),
+            (Type::Bool, Type::Int) => ins.uextend(types::I32, value),
+            (Type::Bool, Type::Float) => {
+                let value = ins.uextend(types::I32, value);
+                self.builder.ins().fcvt_from_sint(types::F64, value)
+            }
+            // Saturates, NaN becomes zero
+            (Type::Float, Type::Int) => ins.fcvt_to_sint_sat(types::I32, value),
+            (Type::Int, Type::Bool) => ins.icmp_imm(IntCC::NotEqual, value, 0),
+            (Type::Float, Type::Bool) => {
+                let zero = ins.f64const(0.0);
+                // NaN is true
+                self.builder.ins().fcmp(FloatCC::NotEqual, value, zero)
+            }
+            (from, to) => unreachable!("no conversion from {} to {}", from, to),
+        }
+    }
+
+    /// Call a function. The call depth is checked before, errors of the callee and a missing
+    /// result that is used are checked after the call.
+    fn call(&mut self, target: Option<&'a Place>, name: &'a str, line: i64) {
+        let index = self.indices[name];
+        let runtime = self.runtime();
+        let flags = MemFlags::trusted();
+        let depth_offset = offset_of!(Runtime, depth) as i32;
+        let depth = self
+            .builder
+            .ins()
+            .load(types::I32, flags, runtime, depth_offset);
+        let maximum = self.builder.ins().load(
+            types::I32,
+            flags,
+            runtime,
+            offset_of!(Runtime, max_call_depth) as i32,
+        );
+        let exceeded = self
+            .builder
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, depth, maximum);
+        self.fail_if(exceeded, CALL_DEPTH, line, index);
+
+        let deeper = self.builder.ins().iadd_imm(depth, 1);
+        self.builder
+            .ins()
+            .store(flags, deeper, runtime, depth_offset);
+        let call = self.builder.ins().call(self.functions[name], &[runtime]);
+        let result = self.builder.inst_results(call).first().copied();
+        self.builder
+            .ins()
+            .store(flags, depth, runtime, depth_offset);
+        let failed = self.load_runtime(offset_of!(Runtime, failed));
+        let bail = self.bail();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(failed, bail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+
+        if !self.may_miss_result.contains(name) {
+            if let (Some(target), Some(result)) = (target, result) {
+                self.store(target, result);
+            }
+            return;
+        }
+        let missing_result = offset_of!(Runtime, missing_result);
+        match (target, result) {
+            (Some(target), Some(result)) => {
+                let missing = self.load_runtime(missing_result);
+                self.fail_if(missing, MISSING_RESULT, line, index);
+                self.store(target, result);
+            }
+            // The result is not used, so it may be missing
+            _ => {
+                let zero = self.builder.ins().iconst(types::I8, 0);
+                self.store_runtime(zero, missing_result);
+            }
+        }
+    }
+
+    /// Report a runtime error and return if the condition is true
+    fn fail_if(&mut self, condition: ir::Value, kind: i64, line: i64, function: i64) {
+        let failure = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, failure, &[], next, &[]);
+        self.builder.switch_to_block(failure);
+        let runtime = self.runtime();
+        let arguments =
+            [kind, line, function].map(|value| self.builder.ins().iconst(types::I32, value));
+        self.builder.ins().call(
+            self.fail,
+            &[runtime, arguments[0], arguments[1], arguments[2]],
+        );
+        let bail = self.bail();
+        self.builder.ins().jump(bail, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn bail(&mut self) -> Block {
+        *self.bail.get_or_insert_with(|| self.builder.create_block())
+    }
+
+    /// Return zero, or nothing from a void function
+    fn zero_return(&mut self) {
+        match self.function.return_type {
+            Type::Void => self.builder.ins().return_(&[]),
+            return_type => {
+                let zero = self.zero(return_type);
+                self.builder.ins().return_(&[zero])
+            }
+        };
+    }
+
+    fn operand(&mut self, operand: &'a Operand) -> ir::Value {
+        let place = match operand {
+            Operand::Constant(Value::Int(value)) => {
+                return self.builder.ins().iconst(types::I32, *value as i64)
+            }
+            Operand::Constant(Value::Float(value)) => return self.builder.ins().f64const(*value),
+            Operand::Constant(Value::Bool(value)) => {
+                return self.builder.ins().iconst(types::I8, *value as i64)
+            }
+            Operand::Place(place) => place,
+        };
+        match self.global_offset(place) {
+            Some(offset) => {
+                let value_type = clif_type(self.function.types[place]);
+                let globals = self.globals.expect("the function has globals");
+                self.builder
+                    .ins()
+                    .load(value_type, MemFlags::trusted(), globals, offset)
+            }
+            None => self.builder.use_var(self.variables[place]),
+        }
+    }
+
+    fn store(&mut self, place: &'a Place, value: ir::Value) {
+        match self.global_offset(place) {
+            Some(offset) => {
+                let globals = self.globals.expect("the function has globals");
+                self.builder
+                    .ins()
+                    .store(MemFlags::trusted(), value, globals, offset);
+            }
+            None => self.builder.def_var(self.variables[place], value),
+        }
+    }
+
+    fn is_global(&self, place: &Place) -> bool {
+        self.global_offset(place).is_some()
+    }
+
+    fn global_offset(&self, place: &Place) -> Option<i32> {
+        match place {
+            Place::Variable(name) => self.global_offsets.get(name.as_str()).copied(),
+            _ => None,
+        }
+    }
+
+    fn runtime(&self) -> ir::Value {
+        self.runtime.expect("the entry block is translated first")
+    }
+
+    /// Load a flag of the runtime
+    fn load_runtime(&mut self, offset: usize) -> ir::Value {
+        let runtime = self.runtime();
+        self.builder
+            .ins()
+            .load(types::I8, MemFlags::trusted(), runtime, offset as i32)
+    }
+
+    fn store_runtime(&mut self, value: ir::Value, offset: usize) {
+        let runtime = self.runtime();
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), value, runtime, offset as i32);
+    }
+
+    fn zero(&mut self, value_type: Type) -> ir::Value {
+        match value_type {
+            Type::Float => self.builder.ins().f64const(0.0),
+            value_type => self.builder.ins().iconst(clif_type(value_type), 0),
+        }
+    }
+}
+
+/// Cranelift has no bool type, bools are bytes that are 0 or 1
+fn clif_type(value_type: Type) -> ir::Type {
+    match value_type {
+        Type::Bool => types::I8,
+        Type::Float => types::F64,
+        Type::Int | Type::Void => types::I32,
+    }
+}
+
+fn abi_param(value_type: ir::Type) -> AbiParam {
+    match value_type {
+        types::I8 => AbiParam::new(value_type).uext(),
+        value_type => AbiParam::new(value_type),
+    }
+}
+
+fn int_condition(op: BinaryOp) -> IntCC {
+    match op {
+        BinaryOp::Equal => IntCC::Equal,
+        BinaryOp::NotEqual => IntCC::NotEqual,
+        BinaryOp::Less => IntCC::SignedLessThan,
+        BinaryOp::Greater => IntCC::SignedGreaterThan,
+        BinaryOp::LessEqual => IntCC::SignedLessThanOrEqual,
+        BinaryOp::GreaterEqual => IntCC::SignedGreaterThanOrEqual,
+        op => unreachable!("{} is not a comparison", op),
+    }
+}
+
+/// Comparisons with NaN are false, except for `!=`
+fn float_condition(op: BinaryOp) -> FloatCC {
+    match op {
+        BinaryOp::Equal => FloatCC::Equal,
+        BinaryOp::NotEqual => FloatCC::NotEqual,
+        BinaryOp::Less => FloatCC::LessThan,
+        BinaryOp::Greater => FloatCC::GreaterThan,
+        BinaryOp::LessEqual => FloatCC::LessThanOrEqual,
+        BinaryOp::GreaterEqual => FloatCC::GreaterThanOrEqual,
+        op => unreachable!("{} is not a comparison", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::jit::Jit;
+    use crate::value::Value;
+    use crate::C1Parser;
+
+    fn run(text: &str, storage: Storage) -> (String, Result<Option<Value>, String>) {
+        let program = C1Parser::parse_program(text).unwrap();
+        let jit = Jit::compile(&program, storage).unwrap();
+        let mut output = String::new();
+        let result = jit
+            .run(|value| output.push_str(&format!("{}\n", value)))
+            .map_err(|error| error.to_string());
+        (output, result)
+    }
+
+    #[test]
+    fn programs_run_as_native_code() {
+        let (output, result) = run(
+            "int fib() { if (n < 2) return n; n = n - 1; a = fib(); n = n - 1; return a + fib() + 0 * (n = n + 2); }
+             float main() { n = 10; printf(fib()); printf(2147483647 + 1); printf(0.0 / 0.0); printf(n == 10); return 2.5; }",
+            Storage::Global,
+        );
+        assert_eq!(output, "55\n-2147483648\nnan\ntrue\n");
+        assert_eq!(result, Ok(Some(Value::Float(2.5))));
+    }
+
+    #[test]
+    fn runtime_errors_stop_the_program() {
+        let (output, result) = run(
+            "int f() {\n x = 0;\n printf(1);\n return 1 / x;\n}\nvoid main() { printf(f()); printf(2); }",
+            Storage::Local,
+        );
+        assert_eq!(output, "1\n");
+        assert_eq!(result, Err("Line 4: division by zero".to_string()));
+
+        let (_, result) = run(
+            "int maybe() {\n if (false) return 1;\n}\nint main() { maybe(); return maybe(); }",
+            Storage::Local,
+        );
+        assert_eq!(
+            result,
+            Err("Line 4: function `maybe` did not return a value".to_string())
+        );
+
+        let (_, result) = run("void main() {\n main();\n}", Storage::Local);
+        assert_eq!(
+            result,
+            Err("Line 2: maximum call depth of 256 exceeded in call of `main`".to_string())
+        );
+    }
+}
@@ -120,15 +120,15 @@ pub enum C1Token {
     #[regex("[a-zA-Z]+[0-9a-zA-Z]*")]
     Identifier,
 
-    #[regex(r"/\*[^\*/]*\*/", logos::skip)]
+    #[regex(r"/\*[^\*/]*\*/")]
     CComment,
 
-    #[regex("//[^\n]*(\n)?", logos::skip)]
+    #[regex("//[^\n]*(\n)?")]
     CPPComment,
 
-    // We can also use this variant to define whitespace,
-    // or any other matches we wish to skip.
-    #[regex(r"[ \t\f]+", logos::skip)]
+    // Whitespace, line breaks and comments are skipped by the C1Lexer, or kept as trivia in
+    // lossless mode
+    #[regex(r"[ \t\f]+")]
     Whitespace,
 
     #[regex(r"[\n]")]
@@ -138,7 +138,6 @@ pub enum C1Token {
     // it can be named anything you wish.
     #[error]
     Error,
-    
 }
 
 /// # Overview
@@ -180,16 +179,88 @@ pub struct C1Lexer<'a> {
     logos_line_number: usize,
     current_token: Option<TokenData<'a>>,
     peek_token: Option<TokenData<'a>>,
+    comments: Vec<Comment>,
+    tokens_lexed: usize,
+    /// Whether skipped text is kept as trivia of the following token
+    lossless: bool,
+    /// Trivia lexed since the last token
+    trivia: Vec<Trivia>,
+}
+
+/// A comment that was skipped by the lexer. Comments are not part of the token stream, but are
+/// collected so that later passes can evaluate directives in them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Comment {
+    /// Text of the comment including the comment delimiters, without a trailing line break
+    pub text: String,
+    /// Line in which the comment starts
+    pub line: usize,
+    /// True if no token precedes the comment in the lexed text
+    pub before_first_token: bool,
+}
+
+impl Comment {
+    /// Return the line in which the comment ends
+    pub fn end_line(&self) -> usize {
+        self.line + self.text.matches('\n').count()
+    }
+
+    /// Return the text of the comment without the comment delimiters
+    pub fn content(&self) -> &str {
+        if let Some(content) = self.text.strip_prefix("//") {
+            content
+        } else {
+            self.text
+                .strip_prefix("/*")
+                .and_then(|text| text.strip_suffix("*/"))
+                .unwrap_or(&self.text)
+        }
+    }
+}
+
+/// Whitespace, a line break or a comment that a lossless lexer keeps in front of the next token
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    /// One of `Whitespace`, `Linebreak`, `CComment` and `CPPComment`
+    pub kind: C1Token,
+    /// Text of the trivia, a line comment includes its line break
+    pub text: String,
+    /// Line in which the trivia starts
+    pub line: usize,
 }
 
 impl<'a> C1Lexer<'a> {
     /// Initialize a new C1Lexer for the given string slice
-    pub fn new(text: &'a str) -> C1Lexer {
+    pub fn new(text: &'a str) -> C1Lexer<'a> {
+        Self::with_mode(text, false)
+    }
+
+    /// Initialize a C1Lexer that keeps whitespace, line breaks and comments as trivia, so that
+    /// the trivia and the tokens together make up the whole text.
+    /// ```
+    /// use cb_3::{C1Lexer, C1Token};
+    /// let mut lexer = C1Lexer::lossless("  x // comment\n");
+    ///
+    /// assert_eq!(lexer.current_trivia()[0].text, "  ");
+    /// lexer.eat();
+    /// assert_eq!(lexer.current_token(), None);
+    /// let kinds: Vec<C1Token> = lexer.end_trivia().iter().map(|trivia| trivia.kind).collect();
+    /// assert_eq!(kinds, [C1Token::Whitespace, C1Token::CPPComment]);
+    /// ```
+    pub fn lossless(text: &'a str) -> C1Lexer<'a> {
+        Self::with_mode(text, true)
+    }
+
+    fn with_mode(text: &'a str, lossless: bool) -> C1Lexer<'a> {
         let mut lexer = C1Lexer {
             logos_lexer: C1Token::lexer(text),
             logos_line_number: 1,
             current_token: None,
             peek_token: None,
+            comments: Vec::new(),
+            tokens_lexed: 0,
+            lossless,
+            trivia: Vec::new(),
         };
         lexer.current_token = lexer.next_token();
         lexer.peek_token = lexer.next_token();
@@ -236,6 +307,19 @@ impl<'a> C1Lexer<'a> {
         self.peek_token.text()
     }
 
+    /// Return the trivia in front of the current token. Only a lossless lexer keeps trivia.
+    pub fn current_trivia(&self) -> &[Trivia] {
+        self.current_token
+            .as_ref()
+            .map_or(&[], |data| &data.leading_trivia)
+    }
+
+    /// Return the trivia after the last token. They are complete once the last token is the
+    /// current token.
+    pub fn end_trivia(&self) -> &[Trivia] {
+        &self.trivia
+    }
+
     /// Return the line number where the current token is located
     pub fn current_line_number(&self) -> Option<usize> {
         self.current_token.line_number()
@@ -271,27 +355,66 @@ impl<'a> C1Lexer<'a> {
         self.peek_token = self.next_token();
     }
 
+    /// Return the comments that were skipped so far
+    /// ```
+    /// use cb_3::C1Lexer;
+    /// let mut lexer = C1Lexer::new("// first\nx /* second */ y");
+    /// lexer.eat();
+    ///
+    /// let comments = lexer.comments();
+    /// assert_eq!(comments[0].text, "// first");
+    /// assert_eq!(comments[0].line, 1);
+    /// assert_eq!(comments[1].content(), " second ");
+    /// assert_eq!(comments[1].line, 2);
+    /// ```
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    /// Take the comments that were skipped so far out of the lexer
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        std::mem::take(&mut self.comments)
+    }
+
     /// Private method for reading the next token from the logos::Lexer and extracting the required data
     /// from it
     fn next_token(&mut self) -> Option<TokenData<'a>> {
-        // Retrieve the next token from the internal lexer
-        if let Some(c1_token) = self.logos_lexer.next() {
+        // Retrieve tokens from the internal lexer until one is not skipped
+        while let Some(c1_token) = self.logos_lexer.next() {
+            let text = self.logos_lexer.slice();
+            let line = self.logos_line_number;
             match c1_token {
-                C1Token::Linebreak => {
-                    // If the token is a linebreak, increase the line number and get the next token
-                    self.logos_line_number += 1;
-                    self.next_token()
+                // If the token is a linebreak, increase the line number
+                C1Token::Linebreak => self.logos_line_number += 1,
+                C1Token::Whitespace => {}
+                C1Token::CComment | C1Token::CPPComment => {
+                    // Comments are stored separately, the line breaks inside them are counted
+                    self.comments.push(Comment {
+                        text: text.trim_end_matches('\n').to_string(),
+                        line,
+                        before_first_token: self.tokens_lexed == 0,
+                    });
+                    self.logos_line_number += text.matches('\n').count();
+                }
+                _ => {
+                    self.tokens_lexed += 1;
+                    return Some(TokenData {
+                        token_type: c1_token,
+                        token_text: text,
+                        token_line: line,
+                        leading_trivia: std::mem::take(&mut self.trivia),
+                    });
                 }
-                _ => Some(TokenData {
-                    // If the token is not a linebreak, initialize and return a TokenData instance
-                    token_type: c1_token,
-                    token_text: self.logos_lexer.slice(),
-                    token_line: self.logos_line_number,
-                }),
             }
-        } else {
-            None
+            if self.lossless {
+                self.trivia.push(Trivia {
+                    kind: c1_token,
+                    text: text.to_string(),
+                    line,
+                });
+            }
         }
+        None
     }
 }
 
@@ -300,6 +423,8 @@ struct TokenData<'a> {
     token_type: C1Token,
     token_text: &'a str,
     token_line: usize,
+    /// Trivia between the previous token and this one, only kept by a lossless lexer
+    leading_trivia: Vec<Trivia>,
 }
 
 /// Hidden trait that makes it possible to implemented the required getter functionality directly for
@@ -359,6 +484,46 @@ mod tests {
         assert_eq!(lexer2.peek_line_number(), Some(1));
     }
 
+    #[test]
+    fn lines_in_comments_are_counted() {
+        let mut lexer = C1Lexer::new("a // line comment\nb /* block\ncomment */ c\nd");
+        assert_eq!(lexer.current_line_number(), Some(1));
+        assert_eq!(lexer.peek_line_number(), Some(2));
+        lexer.eat();
+        lexer.eat();
+        assert_eq!(lexer.current_line_number(), Some(3));
+        assert_eq!(lexer.peek_line_number(), Some(4));
+
+        let comments = lexer.comments();
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].text, "// line comment");
+        assert_eq!(comments[1].end_line(), 3);
+        assert!(!comments[0].before_first_token);
+    }
+
+    #[test]
+    fn lossless_lexing_keeps_the_tokens() {
+        let text = "int f() {\n\treturn 1; /* one */\n}";
+        let mut plain = C1Lexer::new(text);
+        let mut lossless = C1Lexer::lossless(text);
+        let mut restored = String::new();
+        while plain.current_token().is_some() {
+            assert_eq!(lossless.current_token(), plain.current_token());
+            assert_eq!(lossless.current_line_number(), plain.current_line_number());
+            assert!(plain.current_trivia().is_empty());
+            for trivia in lossless.current_trivia() {
+                restored.push_str(&trivia.text);
+            }
+            restored.push_str(lossless.current_text().unwrap());
+            plain.eat();
+            lossless.eat();
+        }
+        assert_eq!(lossless.current_token(), None);
+        assert_eq!(lossless.comments(), plain.comments());
+        assert_eq!(restored, text);
+        assert!(lossless.end_trivia().is_empty());
+    }
+
     #[test]
     fn float_recognition() {
         let lexer = C1Lexer::new("1.2");
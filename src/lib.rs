@@ -1,10 +1,75 @@
+mod ast;
+mod backend;
+mod bytecode;
+mod c_emitter;
+mod callgraph;
+mod cfg;
+mod config;
+mod cst;
+mod diagnostic;
+mod directives;
+mod disassembler;
+mod fold;
+mod formatter;
+mod inline;
+mod interpreter;
+mod ir;
+mod ir_interpreter;
+#[cfg(feature = "jit")]
+mod jit;
 mod lexer;
+mod lint;
+mod llvm_emitter;
+mod module_file;
 mod parser;
+mod propagate;
+mod purity;
+mod regalloc;
+mod rust_emitter;
+mod ssa;
+mod types;
+mod value;
+mod verifier;
+mod vm;
+mod wat_emitter;
+mod x86_64_emitter;
+mod x86_64_ir_emitter;
 
 // Type definition for the Result that is being used by the parser. You may change it to anything
 // you want
 pub type ParseResult = Result<(), String>;
 
-pub use lexer::C1Lexer;
+pub use ast::{
+    format_float, BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement,
+    Type, UnaryOp,
+};
+pub use backend::EmitError;
+pub use bytecode::{CompileError, CompiledFunction, Instruction, Module};
+pub use callgraph::{CallGraph, CallSite, RecursionCycle};
+pub use cfg::{cfgs_to_dot, BasicBlock, BlockId, ControlFlowGraph, EdgeKind, Terminator};
+pub use config::{check_source, syntax_error, Config, LanguageOptions, Storage};
+pub use cst::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken, SyntaxTree};
+pub use diagnostic::{Diagnostic, Level};
+pub use directives::Suppressions;
+pub use formatter::{BraceStyle, Formatter};
+pub use inline::Inliner;
+pub use interpreter::{Execution, Interpreter, RuntimeError};
+pub use ir::{
+    Instruction as IrInstruction, InstructionKind, IrFunction, IrProgram, Label, Operand, Place,
+};
+pub use ir_interpreter::IrInterpreter;
+#[cfg(feature = "jit")]
+pub use jit::Jit;
 pub use lexer::C1Token;
-pub use parser::C1Parser;
+pub use lexer::{C1Lexer, Comment, Trivia};
+pub use lint::{find_rule, Linter, Rule, RULES};
+pub use module_file::FormatError;
+pub use parser::{C1Parser, ParseOptions};
+pub use propagate::{EliminatedBranch, Elimination};
+pub use purity::{Purity, PurityAnalysis};
+pub use regalloc::{Allocation, LiveInterval, Liveness, Location, RegisterClass, RegisterSet};
+pub use ssa::{Block, DominatorTree, FlowGraph, SsaError, SsaErrorKind};
+pub use types::TypeEnvironment;
+pub use value::Value;
+pub use verifier::{VerifyError, VerifyErrorKind};
+pub use vm::Vm;
@@ -0,0 +1,749 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::config::{LanguageOptions, Storage};
+use crate::diagnostic::{Diagnostic, Level};
+use crate::directives::Suppressions;
+use std::collections::{HashMap, HashSet};
+
+/// Findings of a rule inside one function as (line, message) pairs
+type Findings = Vec<(usize, String)>;
+
+/// A named check over the syntax tree of a program
+pub struct Rule {
+    pub id: &'static str,
+    pub default_level: Level,
+    /// Explanation that is attached to every diagnostic of the rule
+    pub help: &'static str,
+    check: fn(&Program, &FunctionDefinition, &LanguageOptions, &mut Findings),
+}
+
+/// All rules known to the linter
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: "self-assignment",
+        default_level: Level::Warn,
+        help: "assigning a variable to itself has no effect, remove the assignment",
+        check: self_assignment,
+    },
+    Rule {
+        id: "constant-condition",
+        default_level: Level::Warn,
+        help: "the condition does not depend on the program state, remove the if or the block",
+        check: constant_condition,
+    },
+    Rule {
+        id: "comparison-assignment",
+        default_level: Level::Warn,
+        help: "a comparison result is stored in a variable, check whether `==` was meant \
+               instead of `=`",
+        check: comparison_assignment,
+    },
+    Rule {
+        id: "float-equality",
+        default_level: Level::Warn,
+        help: "floats are subject to rounding errors, compare the difference against a \
+               tolerance instead",
+        check: float_equality,
+    },
+    Rule {
+        id: "empty-block",
+        default_level: Level::Warn,
+        help: "the block contains no statements, remove it",
+        check: empty_block,
+    },
+    Rule {
+        id: "chained-assignment",
+        default_level: Level::Warn,
+        help: "split the chained assignment into one statement per variable",
+        check: chained_assignment,
+    },
+    Rule {
+        id: "reserved-identifier",
+        default_level: Level::Deny,
+        help: "the name is a reserved word in C, which breaks compiling the program as C code; \
+               rename it",
+        check: reserved_identifier,
+    },
+    Rule {
+        id: "uninitialized-variable",
+        default_level: Level::Allow,
+        help: "assign the variable before reading it; with local storage a function cannot see \
+               the variables of its caller",
+        check: uninitialized_variable,
+    },
+];
+
+/// Look up a rule by its id
+pub fn find_rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.id == id)
+}
+
+/// Runs a configurable set of rules over a parsed program. Every rule starts at its default level
+/// and can be switched off by setting its level to `Level::Allow`.
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    levels: HashMap<&'static str, Level>,
+    language: LanguageOptions,
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Linter::default()
+    }
+
+    /// Check programs written in the given dialect, which decides e.g. whether variables are
+    /// shared between functions
+    pub fn with_language(mut self, language: LanguageOptions) -> Self {
+        self.language = language;
+        self
+    }
+
+    /// Return the level at which the given rule is reported
+    pub fn level(&self, rule: &str) -> Option<Level> {
+        let rule = find_rule(rule)?;
+        Some(*self.levels.get(rule.id).unwrap_or(&rule.default_level))
+    }
+
+    /// Change the level of a rule. Fails if no rule with the given id exists.
+    pub fn set_level(&mut self, rule: &str, level: Level) -> Result<(), String> {
+        match find_rule(rule) {
+            Some(rule) => {
+                self.levels.insert(rule.id, level);
+                Ok(())
+            }
+            None => Err(format!("unknown lint rule `{}`", rule)),
+        }
+    }
+
+    /// Run all enabled rules and return their diagnostics ordered by line. Diagnostics that are
+    /// allowed by `c1-allow` directives in the comments of the program are left out, problems
+    /// with the directives themselves are reported instead.
+    pub fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        let (suppressions, mut diagnostics) = Suppressions::from_comments(&program.comments);
+        for rule in RULES {
+            let level = self.level(rule.id).unwrap_or(rule.default_level);
+            if level == Level::Allow {
+                continue;
+            }
+            for function in &program.functions {
+                let mut findings = Vec::new();
+                (rule.check)(program, function, &self.language, &mut findings);
+                diagnostics.extend(
+                    findings
+                        .into_iter()
+                        .map(|(line, message)| Diagnostic {
+                            code: rule.id,
+                            level,
+                            line,
+                            message,
+                            help: Some(rule.help.to_string()),
+                            file: None,
+                        })
+                        .filter(|diagnostic| !suppressions.is_suppressed(diagnostic)),
+                );
+            }
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+        diagnostics
+    }
+}
+
+fn self_assignment(
+    _: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    let mut check = |target: &str, value: &Expression, line: usize| {
+        if matches!(&value.kind, ExpressionKind::Variable(name) if name == target) {
+            findings.push((line, format!("`{}` is assigned to itself", target)));
+        }
+    };
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign {
+            target,
+            value,
+            line,
+        } = statement
+        {
+            check(target, value, *line);
+        }
+    });
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Assign { target, value } = &expression.kind {
+            check(target, value, expression.line);
+        }
+    });
+}
+
+fn constant_condition(
+    _: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    function.walk_statements(&mut |statement| {
+        if let Statement::If {
+            condition, line, ..
+        } = statement
+        {
+            let value = match &condition.kind {
+                ExpressionKind::Bool(value) => Some(*value),
+                ExpressionKind::Int(value) => Some(*value != 0),
+                ExpressionKind::Float(value) => Some(*value != 0.0),
+                _ => None,
+            };
+            if let Some(value) = value {
+                findings.push((*line, format!("the condition is always {}", value)));
+            }
+        }
+    });
+}
+
+fn comparison_assignment(
+    _: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign {
+            target,
+            value,
+            line,
+        } = statement
+        {
+            if let ExpressionKind::Binary { op, .. } = &value.kind {
+                if op.is_comparison() {
+                    findings.push((
+                        *line,
+                        format!("the result of `{}` is assigned to `{}`", value, target),
+                    ));
+                }
+            }
+        }
+    });
+}
+
+fn float_equality(
+    program: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    let float_variables = float_variables(program, function);
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Binary {
+            op: op @ (BinaryOp::Equal | BinaryOp::NotEqual),
+            lhs,
+            rhs,
+        } = &expression.kind
+        {
+            if is_float(program, &float_variables, lhs) || is_float(program, &float_variables, rhs)
+            {
+                findings.push((
+                    expression.line,
+                    format!("floating point values are compared with `{}`", op),
+                ));
+            }
+        }
+    });
+}
+
+fn empty_block(
+    _: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    function.walk_statements(&mut |statement| {
+        if let Statement::Block { statements, line } = statement {
+            if statements.is_empty() {
+                findings.push((*line, "empty block".to_string()));
+            }
+        }
+    });
+}
+
+fn chained_assignment(
+    _: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign {
+            target,
+            value,
+            line,
+        } = statement
+        {
+            if let ExpressionKind::Assign { target: inner, .. } = &value.kind {
+                findings.push((
+                    *line,
+                    format!("`{}` and `{}` are assigned in one statement", target, inner),
+                ));
+            }
+        }
+    });
+}
+
+/// Keywords of C99 that are not keywords of C(-1) and can therefore be used as identifiers
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "double", "enum", "extern",
+    "goto", "inline", "long", "register", "restrict", "short", "signed", "sizeof", "static",
+    "struct", "switch", "typedef", "union", "unsigned", "volatile",
+];
+
+fn reserved_identifier(
+    _: &Program,
+    function: &FunctionDefinition,
+    _: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    if C_KEYWORDS.contains(&function.name.as_str()) {
+        findings.push((
+            function.line,
+            format!(
+                "function `{}` is named after a reserved word",
+                function.name
+            ),
+        ));
+    }
+    let mut reported = HashSet::new();
+    let mut check = |name: &str, line: usize| {
+        if C_KEYWORDS.contains(&name) && reported.insert(name.to_string()) {
+            findings.push((
+                line,
+                format!("variable `{}` is named after a reserved word", name),
+            ));
+        }
+    };
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign { target, line, .. } = statement {
+            check(target, *line);
+        }
+    });
+    function.walk_expressions(&mut |expression| match &expression.kind {
+        ExpressionKind::Variable(name) | ExpressionKind::Assign { target: name, .. } => {
+            check(name, expression.line)
+        }
+        _ => {}
+    });
+}
+
+fn uninitialized_variable(
+    program: &Program,
+    function: &FunctionDefinition,
+    language: &LanguageOptions,
+    findings: &mut Findings,
+) {
+    match language.storage {
+        Storage::Local => {
+            let mut analysis = DefiniteAssignment::new(None);
+            analysis.function(function, HashSet::new());
+            findings.extend(analysis.unassigned.into_iter().map(|(line, name)| {
+                (
+                    line,
+                    format!("`{}` may be used before it is assigned", name),
+                )
+            }));
+        }
+        Storage::Global => {
+            // Any function may assign a shared variable before the read, so only variables that
+            // are assigned nowhere in the program are certainly uninitialized
+            let mut assigned = HashSet::new();
+            for function in &program.functions {
+                assigned.extend(assigned_variables(function));
+            }
+            let mut reported = HashSet::new();
+            function.walk_expressions(&mut |expression| {
+                if let ExpressionKind::Variable(name) = &expression.kind {
+                    if !assigned.contains(name.as_str()) && reported.insert(name.as_str()) {
+                        findings.push((
+                            expression.line,
+                            format!("`{}` is never assigned in any function", name),
+                        ));
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Whether a function may read one of its variables before assigning it when every call has its
+/// own variables
+pub(crate) fn may_read_unassigned(function: &FunctionDefinition) -> bool {
+    let mut analysis = DefiniteAssignment::new(None);
+    analysis.function(function, HashSet::new());
+    !analysis.unassigned.is_empty()
+}
+
+/// Find a read of a variable that may happen before the variable is assigned and return its
+/// line and the name of the variable. With local storage every function is checked on its own.
+/// With global storage, a call assigns the variables that the called function assigns on every
+/// path, and a function may read the variables that are assigned before every call of it.
+/// Functions that `main` never calls are not checked.
+pub(crate) fn unassigned_read(program: &Program, storage: Storage) -> Option<(usize, String)> {
+    let first = |analysis: DefiniteAssignment| {
+        let (line, name) = analysis.unassigned.first()?;
+        Some((*line, name.to_string()))
+    };
+    if storage == Storage::Local {
+        return program.functions.iter().find_map(|function| {
+            let mut analysis = DefiniteAssignment::new(None);
+            analysis.function(function, HashSet::new());
+            first(analysis)
+        });
+    }
+    // The variables that each function assigns, grown until they no longer change
+    let mut summaries: HashMap<&str, HashSet<&str>> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for function in &program.functions {
+            let assigns =
+                DefiniteAssignment::new(Some(&summaries)).function(function, HashSet::new());
+            if summaries.get(function.name.as_str()) != Some(&assigns) {
+                summaries.insert(&function.name, assigns);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    // The variables that are assigned before every call of each function, shrunk until they no
+    // longer change
+    let mut entries: HashMap<&str, HashSet<&str>> = HashMap::from([("main", HashSet::new())]);
+    loop {
+        let mut changed = false;
+        for function in &program.functions {
+            let Some(entry) = entries.get(function.name.as_str()) else {
+                continue;
+            };
+            let mut analysis = DefiniteAssignment::new(Some(&summaries));
+            analysis.function(function, entry.clone());
+            for (callee, assigned) in analysis.calls {
+                let entry = match entries.get(callee) {
+                    Some(entry) => entry.intersection(&assigned).copied().collect(),
+                    None => assigned,
+                };
+                if entries.get(callee) != Some(&entry) {
+                    entries.insert(callee, entry);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    program.functions.iter().find_map(|function| {
+        let entry = entries.get(function.name.as_str())?;
+        let mut analysis = DefiniteAssignment::new(Some(&summaries));
+        analysis.function(function, entry.clone());
+        first(analysis)
+    })
+}
+
+/// Forward analysis of the variables that are assigned on every path to a statement. The set is
+/// None after a return, since the following statements are unreachable.
+struct DefiniteAssignment<'a, 's> {
+    reported: HashSet<&'a str>,
+    /// Reads of variables that may not be assigned yet, with their line
+    unassigned: Vec<(usize, &'a str)>,
+    /// With global storage, the variables that a call of each function assigns
+    summaries: Option<&'s HashMap<&'a str, HashSet<&'a str>>>,
+    /// With global storage, the called functions and the variables assigned before each call
+    calls: Vec<(&'a str, HashSet<&'a str>)>,
+    /// The variables assigned on every path that returned so far
+    returned: Option<HashSet<&'a str>>,
+}
+
+impl<'a, 's> DefiniteAssignment<'a, 's> {
+    fn new(summaries: Option<&'s HashMap<&'a str, HashSet<&'a str>>>) -> Self {
+        DefiniteAssignment {
+            reported: HashSet::new(),
+            unassigned: Vec::new(),
+            summaries,
+            calls: Vec::new(),
+            returned: None,
+        }
+    }
+
+    /// Analyse the body of a function that starts with the given variables assigned and return
+    /// the variables that are assigned when it returns
+    fn function(
+        &mut self,
+        function: &'a FunctionDefinition,
+        entry: HashSet<&'a str>,
+    ) -> HashSet<&'a str> {
+        let mut assigned = Some(entry);
+        self.statements(&function.body, &mut assigned);
+        self.returns(assigned);
+        self.returned.take().unwrap_or_default()
+    }
+
+    fn returns(&mut self, assigned: Option<HashSet<&'a str>>) {
+        if let Some(assigned) = assigned {
+            self.returned = Some(match self.returned.take() {
+                Some(returned) => returned.intersection(&assigned).copied().collect(),
+                None => assigned,
+            });
+        }
+    }
+
+    fn call(&mut self, name: &'a str, assigned: &mut Option<HashSet<&'a str>>) {
+        if let (Some(summaries), Some(assigned)) = (self.summaries, assigned) {
+            self.calls.push((name, assigned.clone()));
+            if let Some(assigns) = summaries.get(name) {
+                assigned.extend(assigns);
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Statement], assigned: &mut Option<HashSet<&'a str>>) {
+        for statement in statements {
+            self.statement(statement, assigned);
+        }
+    }
+
+    fn statement(&mut self, statement: &'a Statement, assigned: &mut Option<HashSet<&'a str>>) {
+        match statement {
+            Statement::Block { statements, .. } => self.statements(statements, assigned),
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                self.expression(condition, assigned);
+                // The then branch may be skipped, so its assignments are not kept
+                self.statement(then_branch, &mut assigned.clone());
+            }
+            Statement::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value, assigned);
+                }
+                self.returns(assigned.take());
+            }
+            Statement::Printf { value, .. } => self.expression(value, assigned),
+            Statement::Assign { target, value, .. } => {
+                self.expression(value, assigned);
+                if let Some(assigned) = assigned {
+                    assigned.insert(target);
+                }
+            }
+            Statement::Call { name, .. } => self.call(name, assigned),
+        }
+    }
+
+    fn expression(&mut self, expression: &'a Expression, assigned: &mut Option<HashSet<&'a str>>) {
+        let Some(variables) = assigned else {
+            return;
+        };
+        match &expression.kind {
+            ExpressionKind::Variable(name) => {
+                if !variables.contains(name.as_str()) && self.reported.insert(name) {
+                    self.unassigned.push((expression.line, name));
+                }
+            }
+            ExpressionKind::Assign { target, value } => {
+                self.expression(value, assigned);
+                if let Some(assigned) = assigned {
+                    assigned.insert(target);
+                }
+            }
+            ExpressionKind::Unary { operand, .. } => self.expression(operand, assigned),
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                self.expression(lhs, assigned);
+                if op.is_logical() {
+                    // Assignments in the right operand only happen if it is evaluated
+                    self.expression(rhs, &mut assigned.clone());
+                } else {
+                    self.expression(rhs, assigned);
+                }
+            }
+            ExpressionKind::Call(name) => self.call(name, assigned),
+            ExpressionKind::Int(_) | ExpressionKind::Float(_) | ExpressionKind::Bool(_) => {}
+        }
+    }
+}
+
+/// Collect the variables that are assigned somewhere in the body of a function
+fn assigned_variables(function: &FunctionDefinition) -> HashSet<&str> {
+    let mut assigned = HashSet::new();
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign { target, .. } = statement {
+            assigned.insert(target.as_str());
+        }
+    });
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Assign { target, .. } = &expression.kind {
+            assigned.insert(target.as_str());
+        }
+    });
+    assigned
+}
+
+/// Collect the variables of a function that are assigned a float value somewhere in its body
+fn float_variables<'a>(program: &Program, function: &'a FunctionDefinition) -> HashSet<&'a str> {
+    let mut assignments = Vec::new();
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign { target, value, .. } = statement {
+            assignments.push((target.as_str(), value));
+        }
+    });
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Assign { target, value } = &expression.kind {
+            assignments.push((target.as_str(), value));
+        }
+    });
+    // Iterate until no more variables are found, since floats propagate through variables
+    let mut floats = HashSet::new();
+    loop {
+        let before = floats.len();
+        for (target, value) in &assignments {
+            if is_float(program, &floats, value) {
+                floats.insert(*target);
+            }
+        }
+        if floats.len() == before {
+            return floats;
+        }
+    }
+}
+
+/// Check whether an expression is known to produce a float
+fn is_float(program: &Program, float_variables: &HashSet<&str>, expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Float(_) => true,
+        ExpressionKind::Variable(name) => float_variables.contains(name.as_str()),
+        ExpressionKind::Call(name) => program
+            .function(name)
+            .is_some_and(|function| function.return_type == Type::Float),
+        ExpressionKind::Assign { value, .. } => is_float(program, float_variables, value),
+        ExpressionKind::Unary { operand, .. } => is_float(program, float_variables, operand),
+        ExpressionKind::Binary { op, lhs, rhs } => {
+            !op.is_comparison()
+                && !op.is_logical()
+                && (is_float(program, float_variables, lhs)
+                    || is_float(program, float_variables, rhs))
+        }
+        ExpressionKind::Int(_) | ExpressionKind::Bool(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::Level;
+    use crate::lint::{Linter, RULES};
+    use crate::{C1Parser, LanguageOptions, Storage};
+
+    fn lint(text: &str) -> Vec<(&'static str, usize)> {
+        let program = C1Parser::parse_program(text).unwrap();
+        Linter::new()
+            .check(&program)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.line))
+            .collect()
+    }
+
+    #[test]
+    fn example_program_is_clean() {
+        assert!(lint(include_str!("../tests/data/beispiel.c-1")).is_empty());
+    }
+
+    #[test]
+    fn rules_report_their_findings() {
+        let findings = lint(
+            "void main() {
+                a = a;
+                if (true) printf(1);
+                b = a < 2;
+                x = 1.5;
+                if (x == 1) {}
+                a = b = 3;
+                double = 2;
+            }",
+        );
+        assert_eq!(
+            findings,
+            vec![
+                ("self-assignment", 2),
+                ("constant-condition", 3),
+                ("comparison-assignment", 4),
+                ("float-equality", 6),
+                ("empty-block", 6),
+                ("chained-assignment", 7),
+                ("reserved-identifier", 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn float_equality_follows_variables_and_calls() {
+        let findings = lint(
+            "float f() { return 1.0; }
+             void main() { a = f(); b = a * 2; if (b != 2) return; if (1 == 2) return; }",
+        );
+        assert_eq!(findings, vec![("float-equality", 2)]);
+    }
+
+    #[test]
+    fn uninitialized_variables_depend_on_the_storage() {
+        let text = "int blub() { if (blub1 > 0) return blub1; return 0; }
+             void main() {
+                 blub1 = 1;
+                 a = 2; if (blub() > 0) { a = 1; b = 3; }
+                 printf(a + b);
+                 if (false || (c = 1)) printf(c);
+                 return;
+                 printf(d);
+             }";
+        let program = C1Parser::parse_program(text).unwrap();
+        let findings = |storage| -> Vec<(usize, String)> {
+            let mut linter = Linter::new().with_language(LanguageOptions { storage });
+            linter
+                .set_level("uninitialized-variable", Level::Warn)
+                .unwrap();
+            linter
+                .check(&program)
+                .into_iter()
+                .filter(|diagnostic| diagnostic.code == "uninitialized-variable")
+                .map(|diagnostic| (diagnostic.line, diagnostic.message))
+                .collect()
+        };
+        assert_eq!(
+            findings(Storage::Local),
+            vec![
+                (1, "`blub1` may be used before it is assigned".to_string()),
+                (5, "`b` may be used before it is assigned".to_string()),
+                (6, "`c` may be used before it is assigned".to_string()),
+            ]
+        );
+        assert_eq!(
+            findings(Storage::Global),
+            vec![(8, "`d` is never assigned in any function".to_string())]
+        );
+    }
+
+    #[test]
+    fn rules_can_be_configured() {
+        let program = C1Parser::parse_program("void main() { a = a; {} }").unwrap();
+        let mut linter = Linter::new();
+        linter.set_level("empty-block", Level::Allow).unwrap();
+        linter.set_level("self-assignment", Level::Deny).unwrap();
+        let diagnostics = linter.check(&program);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(
+            diagnostics[0].to_string(),
+            format!(
+                "error[self-assignment]: line 1: `a` is assigned to itself\n  = help: {}",
+                RULES[0].help
+            )
+        );
+        assert!(linter.set_level("no-such-rule", Level::Warn).is_err());
+    }
+}
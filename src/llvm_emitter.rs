@@ -0,0 +1,645 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::backend::{always_returns, may_miss_result, EmitError, TypedProgram};
+use crate::config::Storage;
+use std::fmt::Write;
+
+/// Functions of the generated module that implement the semantics of C(-1) where they differ
+/// from LLVM: int division fails with an error instead of being undefined and wraps around for
+/// `-2147483648 / -1`, and runtime errors print their line and stop the program.
+const RUNTIME: &str = r#"define internal void @c1_error(i32 %line, i8* %message) noreturn {
+entry:
+  %flushed = call i32 @fflush(i8* null)
+  %printed = call i32 (i32, i8*, ...) @dprintf(i32 2, i8* getelementptr inbounds ([13 x i8], [13 x i8]* @.error_format, i64 0, i64 0), i32 %line, i8* %message)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal i32 @c1_div(i32 %lhs, i32 %rhs, i32 %line) {
+entry:
+  %zero = icmp eq i32 %rhs, 0
+  br i1 %zero, label %fail, label %nonzero
+fail:
+  call void @c1_error(i32 %line, i8* getelementptr inbounds ([17 x i8], [17 x i8]* @.division_by_zero, i64 0, i64 0))
+  unreachable
+nonzero:
+  %minus_one = icmp eq i32 %rhs, -1
+  br i1 %minus_one, label %negate, label %divide
+negate:
+  %negated = sub i32 0, %lhs
+  ret i32 %negated
+divide:
+  %quotient = sdiv i32 %lhs, %rhs
+  ret i32 %quotient
+}
+
+declare i32 @printf(i8*, ...)
+declare i32 @dprintf(i32, i8*, ...)
+declare i32 @fflush(i8*)
+declare void @exit(i32) noreturn
+declare i32 @llvm.fptosi.sat.i32.f64(double)
+declare double @llvm.fabs.f64(double)
+
+@.error_format = private unnamed_addr constant [13 x i8] c"Line %d: %s\0A\00"
+@.division_by_zero = private unnamed_addr constant [17 x i8] c"division by zero\00"
+@.int_format = private unnamed_addr constant [4 x i8] c"%d\0A\00"
+@.float_format = private unnamed_addr constant [6 x i8] c"%.6f\0A\00"
+@.string_format = private unnamed_addr constant [4 x i8] c"%s\0A\00"
+@.true = private unnamed_addr constant [5 x i8] c"true\00"
+@.false = private unnamed_addr constant [6 x i8] c"false\00"
+@c1_missing_result = internal global i8* null
+"#;
+
+impl Program {
+    /// Translate the program into a textual LLVM IR module, which `llvm-as` turns into bitcode.
+    ///
+    /// Every function becomes an LLVM function with the type of its `type` keyword, `int` is
+    /// `i32`, `float` is `double` and `bool` is `i1`. Variables are `alloca`s in the entry block
+    /// of their function with local storage and internal globals with global storage, and
+    /// start as zero. `if` statements and `&&` and `||` become basic blocks with branches, and
+    /// comparisons become `icmp` or `fcmp` depending on the type of the operands. `printf` calls
+    /// the variadic `printf` of libc with the format of the value's type, and `main` returns
+    /// the value of the C(-1) `main` as exit status. Runtime errors are printed to stderr and
+    /// exit with status 1.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn emit_llvm(&self, storage: Storage, source_name: &str) -> Result<String, EmitError> {
+        let typed = TypedProgram::check(self, storage)?;
+        let mut emitter = LlvmEmitter {
+            typed: &typed,
+            output: String::new(),
+            strings: String::new(),
+            block: String::new(),
+            terminated: false,
+            temporaries: 0,
+            labels: 0,
+        };
+        writeln!(
+            emitter.output,
+            "; Generated from {}\nsource_filename = \"{}\"\n",
+            source_name,
+            escape(source_name)
+        )
+        .unwrap();
+        emitter.program();
+        Ok(emitter.output)
+    }
+}
+
+struct LlvmEmitter<'a> {
+    typed: &'a TypedProgram<'a>,
+    output: String,
+    /// Definitions of the string constants used by the functions
+    strings: String,
+    /// Label of the basic block that instructions are added to
+    block: String,
+    /// Whether the current block ends with a terminator, so that further instructions need a
+    /// new block
+    terminated: bool,
+    /// Number of temporaries created in the current function
+    temporaries: usize,
+    /// Number of labels created in the current function
+    labels: usize,
+}
+
+impl LlvmEmitter<'_> {
+    fn program(&mut self) {
+        let program = self.typed.program;
+        for (name, variable_type) in self.typed.globals() {
+            writeln!(
+                self.output,
+                "@{} = internal global {} {}",
+                variable(name),
+                llvm_type(*variable_type),
+                zero(*variable_type)
+            )
+            .unwrap();
+        }
+        if !self.typed.globals().is_empty() {
+            self.output.push('\n');
+        }
+        for function in &program.functions {
+            self.function(function);
+        }
+        let main = program.function("main").unwrap();
+        self.output.push_str("define i32 @main() {\nentry:\n");
+        let exit_code = match main.return_type {
+            Type::Void => {
+                self.output.push_str("  call void @f_main()\n");
+                "0".to_string()
+            }
+            main_type => {
+                writeln!(
+                    self.output,
+                    "  %result = call {} @f_main()",
+                    llvm_type(main_type)
+                )
+                .unwrap();
+                self.temporaries = 0;
+                self.convert("%result".to_string(), main_type, Type::Int)
+            }
+        };
+        writeln!(self.output, "  ret i32 {}\n}}\n", exit_code).unwrap();
+        self.output.push_str(RUNTIME);
+        let strings = std::mem::take(&mut self.strings);
+        self.output.push_str(&strings);
+    }
+
+    fn function(&mut self, function: &FunctionDefinition) {
+        self.temporaries = 0;
+        self.labels = 0;
+        writeln!(
+            self.output,
+            "define internal {} @f_{}() {{\nentry:",
+            llvm_type(function.return_type),
+            function.name
+        )
+        .unwrap();
+        self.block = "entry".to_string();
+        self.terminated = false;
+        for (name, variable_type) in self.typed.locals(&function.name) {
+            let llvm_type = llvm_type(*variable_type);
+            writeln!(
+                self.output,
+                "  %{} = alloca {}\n  store {} {}, {}* %{}",
+                variable(name),
+                llvm_type,
+                llvm_type,
+                zero(*variable_type),
+                llvm_type,
+                variable(name)
+            )
+            .unwrap();
+        }
+        for statement in &function.body {
+            self.statement(function, statement);
+        }
+        if !self.terminated {
+            match function.return_type {
+                Type::Void => self.terminate("ret void".to_string()),
+                _ if always_returns(&function.body) => self.terminate("unreachable".to_string()),
+                _ => self.missing_result(function),
+            }
+        }
+        self.output.push_str("}\n\n");
+    }
+
+    /// Return from a function without a result, which is only an error if the caller uses it
+    fn missing_result(&mut self, function: &FunctionDefinition) {
+        let message = self.string(&format!(
+            "function `{}` did not return a value",
+            function.name
+        ));
+        self.instruction(format!("store i8* {}, i8** @c1_missing_result", message));
+        self.terminate(format!(
+            "ret {} {}",
+            llvm_type(function.return_type),
+            zero(function.return_type)
+        ));
+    }
+
+    fn statement(&mut self, function: &FunctionDefinition, statement: &Statement) {
+        if !matches!(statement, Statement::Block { .. }) {
+            self.instruction(format!("; line {}", statement.line()));
+        }
+        match statement {
+            Statement::Block { statements, .. } => {
+                for statement in statements {
+                    self.statement(function, statement);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                let (condition, condition_type) = self.expression(function, condition);
+                let condition = self.truth(condition, condition_type);
+                let then_label = self.label();
+                let end_label = self.label();
+                self.terminate(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    condition, then_label, end_label
+                ));
+                self.start_block(&then_label);
+                self.statement(function, then_branch);
+                self.branch(&end_label);
+                self.start_block(&end_label);
+            }
+            Statement::Return { value: None, .. } if function.return_type != Type::Void => {
+                self.missing_result(function);
+            }
+            Statement::Return { value: None, .. } => self.terminate("ret void".to_string()),
+            Statement::Return {
+                value: Some(value), ..
+            } => {
+                let (value, value_type) = self.expression(function, value);
+                let value = self.convert(value, value_type, function.return_type);
+                self.terminate(format!("ret {} {}", llvm_type(function.return_type), value));
+            }
+            Statement::Printf { value, .. } => {
+                let (value, value_type) = self.expression(function, value);
+                let argument = match value_type {
+                    Type::Float => {
+                        // glibc prints NaN with a sign, C(-1) does not
+                        let is_nan = self.temporary();
+                        let absolute = self.temporary();
+                        let unsigned = self.temporary();
+                        self.instruction(format!(
+                            "{} = fcmp uno double {}, {}",
+                            is_nan, value, value
+                        ));
+                        self.instruction(format!(
+                            "{} = call double @llvm.fabs.f64(double {})",
+                            absolute, value
+                        ));
+                        self.instruction(format!(
+                            "{} = select i1 {}, double {}, double {}",
+                            unsigned, is_nan, absolute, value
+                        ));
+                        format!("double {}", unsigned)
+                    }
+                    Type::Bool => {
+                        let text = self.temporary();
+                        self.instruction(format!(
+                            "{} = select i1 {}, i8* {}, i8* {}",
+                            text,
+                            value,
+                            constant_string(".true", 5),
+                            constant_string(".false", 6)
+                        ));
+                        format!("i8* {}", text)
+                    }
+                    _ => format!("i32 {}", value),
+                };
+                let format = match value_type {
+                    Type::Float => constant_string(".float_format", 6),
+                    Type::Bool => constant_string(".string_format", 4),
+                    _ => constant_string(".int_format", 4),
+                };
+                let printed = self.temporary();
+                self.instruction(format!(
+                    "{} = call i32 (i8*, ...) @printf(i8* {}, {})",
+                    printed, format, argument
+                ));
+            }
+            Statement::Assign { target, value, .. } => {
+                let (value, value_type) = self.expression(function, value);
+                self.store(target, &value, value_type);
+            }
+            Statement::Call { name, .. } => {
+                let callee = self.typed.function(name);
+                match callee.return_type {
+                    Type::Void => self.instruction(format!("call void @f_{}()", name)),
+                    return_type => {
+                        let result = self.temporary();
+                        self.instruction(format!(
+                            "{} = call {} @f_{}()",
+                            result,
+                            llvm_type(return_type),
+                            name
+                        ));
+                    }
+                }
+                // The result is not used, so it may be missing
+                if may_miss_result(callee) {
+                    self.instruction("store i8* null, i8** @c1_missing_result".to_string());
+                }
+            }
+        }
+    }
+
+    /// Translate an expression, returning the value and its C(-1) type
+    fn expression(
+        &mut self,
+        function: &FunctionDefinition,
+        expression: &Expression,
+    ) -> (String, Type) {
+        let value_type = self.typed.expression_type(&function.name, expression);
+        let value = match &expression.kind {
+            ExpressionKind::Int(value) => value.to_string(),
+            ExpressionKind::Float(value) => format!("0x{:016X}", value.to_bits()),
+            ExpressionKind::Bool(value) => value.to_string(),
+            ExpressionKind::Variable(name) => {
+                let value = self.temporary();
+                let llvm_type = llvm_type(value_type);
+                self.instruction(format!(
+                    "{} = load {}, {}* {}",
+                    value,
+                    llvm_type,
+                    llvm_type,
+                    self.location(name)
+                ));
+                value
+            }
+            ExpressionKind::Call(name) => {
+                let value = self.temporary();
+                self.instruction(format!(
+                    "{} = call {} @f_{}()",
+                    value,
+                    llvm_type(value_type),
+                    name
+                ));
+                if may_miss_result(self.typed.function(name)) {
+                    self.check_result(expression.line);
+                }
+                value
+            }
+            ExpressionKind::Assign { target, value } => {
+                let (value, value_type) = self.expression(function, value);
+                self.store(target, &value, value_type);
+                value
+            }
+            ExpressionKind::Unary { operand, .. } => {
+                let (operand, operand_type) = self.expression(function, operand);
+                let operand = self.convert(operand, operand_type, value_type);
+                let value = self.temporary();
+                match value_type {
+                    Type::Float => self.instruction(format!("{} = fneg double {}", value, operand)),
+                    _ => self.instruction(format!("{} = sub i32 0, {}", value, operand)),
+                }
+                value
+            }
+            ExpressionKind::Binary { op, lhs, rhs } if op.is_logical() => {
+                let (lhs, lhs_type) = self.expression(function, lhs);
+                let lhs = self.truth(lhs, lhs_type);
+                let lhs_block = self.block.clone();
+                let rhs_label = self.label();
+                let end_label = self.label();
+                let (short_circuit, branches) = match op {
+                    BinaryOp::And => ("false", (&rhs_label, &end_label)),
+                    _ => ("true", (&end_label, &rhs_label)),
+                };
+                self.terminate(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    lhs, branches.0, branches.1
+                ));
+                self.start_block(&rhs_label);
+                let (rhs, rhs_type) = self.expression(function, rhs);
+                let rhs = self.truth(rhs, rhs_type);
+                let rhs_block = self.block.clone();
+                self.branch(&end_label);
+                self.start_block(&end_label);
+                let value = self.temporary();
+                self.instruction(format!(
+                    "{} = phi i1 [ {}, %{} ], [ {}, %{} ]",
+                    value, short_circuit, lhs_block, rhs, rhs_block
+                ));
+                value
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let (lhs, lhs_type) = self.expression(function, lhs);
+                let (rhs, rhs_type) = self.expression(function, rhs);
+                let operand_type = if lhs_type == Type::Float || rhs_type == Type::Float {
+                    Type::Float
+                } else {
+                    Type::Int
+                };
+                let lhs = self.convert(lhs, lhs_type, operand_type);
+                let rhs = self.convert(rhs, rhs_type, operand_type);
+                let value = self.temporary();
+                let instruction = match (operand_type, op) {
+                    (Type::Int, BinaryOp::Div) => format!(
+                        "call i32 @c1_div(i32 {}, i32 {}, i32 {})",
+                        lhs, rhs, expression.line
+                    ),
+                    (Type::Int, op) => format!("{} i32 {}, {}", int_instruction(*op), lhs, rhs),
+                    (_, op) => format!("{} double {}, {}", float_instruction(*op), lhs, rhs),
+                };
+                self.instruction(format!("{} = {}", value, instruction));
+                value
+            }
+        };
+        (value, value_type)
+    }
+
+    /// Fail with the message in `@c1_missing_result` if the last call had no result
+    fn check_result(&mut self, line: usize) {
+        let message = self.temporary();
+        let missing = self.temporary();
+        let fail_label = self.label();
+        let ok_label = self.label();
+        self.instruction(format!("{} = load i8*, i8** @c1_missing_result", message));
+        self.instruction(format!("{} = icmp ne i8* {}, null", missing, message));
+        self.terminate(format!(
+            "br i1 {}, label %{}, label %{}",
+            missing, fail_label, ok_label
+        ));
+        self.start_block(&fail_label);
+        self.instruction(format!(
+            "call void @c1_error(i32 {}, i8* {})",
+            line, message
+        ));
+        self.terminate("unreachable".to_string());
+        self.start_block(&ok_label);
+    }
+
+    /// Convert a value like C(-1) does
+    fn convert(&mut self, value: String, from: Type, to: Type) -> String {
+        let instruction = match (from, to) {
+            (from, to) if from == to => return value,
+            (_, Type::Bool) => return self.truth(value, from),
+            (Type::Float, Type::Int) => {
+                format!("call i32 @llvm.fptosi.sat.i32.f64(double {})", value)
+            }
+            (Type::Int, Type::Float) => format!("sitofp i32 {} to double", value),
+            (Type::Bool, Type::Float) => format!("uitofp i1 {} to double", value),
+            (Type::Bool, Type::Int) => format!("zext i1 {} to i32", value),
+            (from, to) => unreachable!("no conversion from {} to {}", from, to),
+        };
+        let result = self.temporary();
+        self.instruction(format!("{} = {}", result, instruction));
+        result
+    }
+
+    /// Convert a value to a bool, like conditions and operands of `&&` and `||` are
+    fn truth(&mut self, value: String, value_type: Type) -> String {
+        let instruction = match value_type {
+            Type::Bool => return value,
+            Type::Float => format!("fcmp une double {}, 0.0", value),
+            _ => format!("icmp ne i32 {}, 0", value),
+        };
+        let result = self.temporary();
+        self.instruction(format!("{} = {}", result, instruction));
+        result
+    }
+
+    fn store(&mut self, name: &str, value: &str, value_type: Type) {
+        let llvm_type = llvm_type(value_type);
+        let location = self.location(name);
+        self.instruction(format!(
+            "store {} {}, {}* {}",
+            llvm_type, value, llvm_type, location
+        ));
+    }
+
+    /// Return the pointer to a variable
+    fn location(&self, name: &str) -> String {
+        match self.typed.storage {
+            Storage::Local => format!("%{}", variable(name)),
+            Storage::Global => format!("@{}", variable(name)),
+        }
+    }
+
+    fn instruction(&mut self, instruction: String) {
+        if self.terminated {
+            // Code after a `return` is unreachable but still needs a block
+            let label = self.label();
+            self.start_block(&label);
+        }
+        writeln!(self.output, "  {}", instruction).unwrap();
+    }
+
+    /// Add the last instruction of the current block
+    fn terminate(&mut self, instruction: String) {
+        self.instruction(instruction);
+        self.terminated = true;
+    }
+
+    /// Jump to a label unless the current block already ends
+    fn branch(&mut self, label: &str) {
+        if !self.terminated {
+            self.terminate(format!("br label %{}", label));
+        }
+    }
+
+    fn start_block(&mut self, label: &str) {
+        writeln!(self.output, "{}:", label).unwrap();
+        self.block = label.to_string();
+        self.terminated = false;
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporaries += 1;
+        format!("%t{}", self.temporaries)
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    /// Add a string constant to the module and return a pointer to its first character
+    fn string(&mut self, text: &str) -> String {
+        let name = format!(".str.{}", self.strings.lines().count());
+        writeln!(
+            self.strings,
+            "@{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
+            name,
+            text.len() + 1,
+            escape(text)
+        )
+        .unwrap();
+        constant_string(&name, text.len() + 1)
+    }
+}
+
+/// Pointer to the first character of a string constant with the given length
+fn constant_string(name: &str, length: usize) -> String {
+    format!(
+        "getelementptr inbounds ([{} x i8], [{} x i8]* @{}, i64 0, i64 0)",
+        length, length, name
+    )
+}
+
+/// Escape text for a string literal of LLVM IR
+fn escape(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'"' | b'\\' | 0..=31 | 127.. => format!("\\{:02X}", byte),
+            _ => (byte as char).to_string(),
+        })
+        .collect()
+}
+
+/// Variables get a prefix so they cannot clash with the temporaries
+fn variable(name: &str) -> String {
+    format!("v_{}", name)
+}
+
+fn llvm_type(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Bool => "i1",
+        Type::Float => "double",
+        Type::Int => "i32",
+        Type::Void => "void",
+    }
+}
+
+fn zero(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Bool => "false",
+        Type::Float => "0.0",
+        _ => "0",
+    }
+}
+
+fn int_instruction(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Equal => "icmp eq",
+        BinaryOp::NotEqual => "icmp ne",
+        BinaryOp::Less => "icmp slt",
+        BinaryOp::Greater => "icmp sgt",
+        BinaryOp::LessEqual => "icmp sle",
+        BinaryOp::GreaterEqual => "icmp sge",
+        BinaryOp::Div | BinaryOp::And | BinaryOp::Or => unreachable!("{} has no instruction", op),
+    }
+}
+
+/// Floats compare like C: `!=` is true and all other comparisons are false for NaN
+fn float_instruction(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "fadd",
+        BinaryOp::Sub => "fsub",
+        BinaryOp::Mul => "fmul",
+        BinaryOp::Div => "fdiv",
+        BinaryOp::Equal => "fcmp oeq",
+        BinaryOp::NotEqual => "fcmp une",
+        BinaryOp::Less => "fcmp olt",
+        BinaryOp::Greater => "fcmp ogt",
+        BinaryOp::LessEqual => "fcmp ole",
+        BinaryOp::GreaterEqual => "fcmp oge",
+        BinaryOp::And | BinaryOp::Or => unreachable!("{} has no instruction", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    #[test]
+    fn statements_become_basic_blocks() {
+        let program = C1Parser::parse_program(
+            "bool check() { a = 2.5; if (a < 3) return true; return (a == 1) || (a > 2); }
+             void main() { printf(check()); }",
+        )
+        .unwrap();
+        let llvm = program.emit_llvm(Storage::Local, "test.c-1").unwrap();
+        let expected = [
+            "source_filename = \"test.c-1\"\n",
+            "define internal i1 @f_check() {\nentry:\n  %v_a = alloca double\n  \
+             store double 0.0, double* %v_a\n  ; line 1\n  \
+             store double 0x4004000000000000, double* %v_a\n",
+            "  %t2 = sitofp i32 3 to double\n  %t3 = fcmp olt double %t1, %t2\n  \
+             br i1 %t3, label %L1, label %L2\nL1:\n  ; line 1\n  ret i1 true\nL2:\n",
+            "  br i1 %t6, label %L4, label %L3\nL3:\n",
+            "  %t10 = phi i1 [ true, %L2 ], [ %t9, %L3 ]\n  ret i1 %t10\n}\n",
+            "  %t2 = select i1 %t1, i8* getelementptr inbounds ([5 x i8], [5 x i8]* @.true, \
+             i64 0, i64 0), i8* getelementptr inbounds ([6 x i8], [6 x i8]* @.false, i64 0, i64 0)\n",
+            "declare i32 @printf(i8*, ...)\n",
+        ];
+        for part in expected {
+            assert!(llvm.contains(part), "{} not found in\n{}", part, llvm);
+        }
+        let llvm = program.emit_llvm(Storage::Global, "test.c-1").unwrap();
+        assert!(llvm.starts_with(
+            "; Generated from test.c-1\nsource_filename = \"test.c-1\"\n\n\
+             @v_a = internal global double 0.0\n"
+        ));
+    }
+}
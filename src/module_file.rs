@@ -0,0 +1,491 @@
+use crate::ast::Type;
+use crate::bytecode::{CompiledFunction, Instruction, Module};
+use crate::value::Value;
+use std::fmt;
+
+/// Binary container of a compiled module. All numbers are little endian, strings are stored as
+/// their length (u32) followed by UTF-8 bytes.
+///
+/// ```text
+/// header      "C1BC" magic, version (u16)
+/// constants   count (u32), then per constant a tag (0 int, 1 float, 2 bool) and the value
+/// globals     count (u32), then the variable names
+/// functions   count (u32), then per function its name, return type, local variable names and
+///             number of instructions
+/// code        the instructions of all functions in table order: an opcode (u8) followed by
+///             its operand, a u32 or a type
+/// lines       per function the number of runs (u32) and (instruction count, line) pairs
+/// ```
+impl Module {
+    pub const MAGIC: &'static [u8; 4] = b"C1BC";
+    pub const FORMAT_VERSION: u16 = 1;
+
+    /// Encode the module in the binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(Self::MAGIC);
+        writer.u16(Self::FORMAT_VERSION);
+
+        writer.u32(self.constants.len() as u32);
+        for constant in &self.constants {
+            match *constant {
+                Value::Int(value) => {
+                    writer.u8(0);
+                    writer.u32(value as u32);
+                }
+                Value::Float(value) => {
+                    writer.u8(1);
+                    writer
+                        .bytes
+                        .extend_from_slice(&value.to_bits().to_le_bytes());
+                }
+                Value::Bool(value) => {
+                    writer.u8(2);
+                    writer.u8(value as u8);
+                }
+            }
+        }
+        writer.strings(&self.globals);
+
+        writer.u32(self.functions.len() as u32);
+        for function in &self.functions {
+            writer.string(&function.name);
+            writer.u8(type_tag(function.return_type));
+            writer.strings(&function.locals);
+            writer.u32(function.code.len() as u32);
+        }
+        for function in &self.functions {
+            for instruction in &function.code {
+                writer.instruction(instruction);
+            }
+        }
+        for function in &self.functions {
+            let mut runs: Vec<(u32, usize)> = Vec::new();
+            for &line in &function.lines {
+                match runs.last_mut() {
+                    Some((count, last)) if *last == line => *count += 1,
+                    _ => runs.push((1, line)),
+                }
+            }
+            writer.u32(runs.len() as u32);
+            for (count, line) in runs {
+                writer.u32(count);
+                writer.u32(line as u32);
+            }
+        }
+        writer.bytes
+    }
+
+    /// Decode a module from the binary format. Besides the structure of the file, the loader
+    /// checks that constants and variables referenced by instructions exist and that every
+    /// instruction has a line. Jumps and calls are checked by the verifier.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module, FormatError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4).ok() != Some(&Self::MAGIC[..]) {
+            return Err(reader.error_at(0, "not a C(-1) module, the magic number is missing"));
+        }
+        let version = reader.u16()?;
+        if version != Self::FORMAT_VERSION {
+            return Err(reader.error_at(
+                4,
+                &format!(
+                    "unsupported format version {}, expected {}",
+                    version,
+                    Self::FORMAT_VERSION
+                ),
+            ));
+        }
+
+        let mut module = Module::default();
+        for _ in 0..reader.count()? {
+            let start = reader.offset;
+            let constant = match reader.u8()? {
+                0 => Value::Int(reader.u32()? as i32),
+                1 => Value::Float(f64::from_bits(u64::from_le_bytes(
+                    reader.take(8)?.try_into().unwrap(),
+                ))),
+                2 => match reader.u8()? {
+                    0 => Value::Bool(false),
+                    1 => Value::Bool(true),
+                    value => {
+                        return Err(reader.error_at(start + 1, &format!("invalid bool {}", value)))
+                    }
+                },
+                tag => return Err(reader.error_at(start, &format!("invalid constant tag {}", tag))),
+            };
+            module.constants.push(constant);
+        }
+        module.globals = reader.strings()?;
+
+        let mut code_lengths = Vec::new();
+        for _ in 0..reader.count()? {
+            let name = reader.string()?;
+            let return_type = reader.return_type()?;
+            let locals = reader.strings()?;
+            code_lengths.push(reader.count()?);
+            module.functions.push(CompiledFunction {
+                name,
+                return_type,
+                locals,
+                code: Vec::new(),
+                lines: Vec::new(),
+            });
+        }
+        for (function, &length) in module.functions.iter_mut().zip(&code_lengths) {
+            for _ in 0..length {
+                let start = reader.offset;
+                let instruction = reader.instruction()?;
+                let operand_exists = match instruction {
+                    Instruction::Constant(index) => (index as usize) < module.constants.len(),
+                    Instruction::Load(slot) | Instruction::Store(slot) => {
+                        (slot as usize) < function.locals.len()
+                    }
+                    Instruction::LoadGlobal(slot) | Instruction::StoreGlobal(slot) => {
+                        (slot as usize) < module.globals.len()
+                    }
+                    _ => true,
+                };
+                if !operand_exists {
+                    return Err(reader.error_at(
+                        start,
+                        &format!(
+                            "operand of `{}` in function `{}` is out of range",
+                            instruction, function.name
+                        ),
+                    ));
+                }
+                function.code.push(instruction);
+            }
+        }
+        for function in &mut module.functions {
+            let start = reader.offset;
+            for _ in 0..reader.count()? {
+                let run = reader.offset;
+                let count = reader.u32()? as usize;
+                let line = reader.u32()? as usize;
+                if function.lines.len() + count > function.code.len() {
+                    return Err(reader.error_at(
+                        run,
+                        &format!(
+                            "line table of `{}` covers more than {} instructions",
+                            function.name,
+                            function.code.len()
+                        ),
+                    ));
+                }
+                function.lines.extend(std::iter::repeat_n(line, count));
+            }
+            if function.lines.len() != function.code.len() {
+                return Err(reader.error_at(
+                    start,
+                    &format!(
+                        "the line table of function `{}` does not match its {} instructions",
+                        function.name,
+                        function.code.len()
+                    ),
+                ));
+            }
+        }
+        if reader.offset != bytes.len() {
+            return Err(reader.error_at(
+                reader.offset,
+                &format!(
+                    "{} unexpected bytes at the end",
+                    bytes.len() - reader.offset
+                ),
+            ));
+        }
+        Ok(module)
+    }
+}
+
+/// An error in the binary representation of a module
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FormatError {
+    /// Position of the problem in bytes from the start of the file
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.message)
+    }
+}
+
+fn type_tag(return_type: Type) -> u8 {
+    match return_type {
+        Type::Bool => 0,
+        Type::Float => 1,
+        Type::Int => 2,
+        Type::Void => 3,
+    }
+}
+
+fn tag_type(tag: u8) -> Option<Type> {
+    [Type::Bool, Type::Float, Type::Int, Type::Void]
+        .get(tag as usize)
+        .copied()
+}
+
+/// Instructions without an operand in the order of their opcodes, which start after the
+/// opcodes of the instructions with an operand
+const SIMPLE_INSTRUCTIONS: &[Instruction] = &[
+    Instruction::Dup,
+    Instruction::Neg,
+    Instruction::Add,
+    Instruction::Sub,
+    Instruction::Mul,
+    Instruction::Div,
+    Instruction::Equal,
+    Instruction::NotEqual,
+    Instruction::Less,
+    Instruction::Greater,
+    Instruction::LessEqual,
+    Instruction::GreaterEqual,
+    Instruction::And,
+    Instruction::Or,
+    Instruction::Return,
+    Instruction::ReturnVoid,
+    Instruction::Print,
+];
+const FIRST_SIMPLE_OPCODE: u8 = 0x20;
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn strings(&mut self, values: &[String]) {
+        self.u32(values.len() as u32);
+        for value in values {
+            self.string(value);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        let (opcode, operand) = match *instruction {
+            Instruction::Constant(operand) => (0x01, operand),
+            Instruction::Load(operand) => (0x02, operand),
+            Instruction::Store(operand) => (0x03, operand),
+            Instruction::LoadGlobal(operand) => (0x04, operand),
+            Instruction::StoreGlobal(operand) => (0x05, operand),
+            Instruction::Jump(operand) => (0x06, operand),
+            Instruction::JumpIfFalse(operand) => (0x07, operand),
+            Instruction::JumpIfTrue(operand) => (0x08, operand),
+            Instruction::Call(operand) => (0x09, operand),
+            Instruction::CallValue(operand) => (0x0a, operand),
+            Instruction::Convert(target) => {
+                self.u8(0x0b);
+                self.u8(type_tag(target));
+                return;
+            }
+            simple => {
+                let index = SIMPLE_INSTRUCTIONS
+                    .iter()
+                    .position(|candidate| *candidate == simple)
+                    .unwrap();
+                self.u8(FIRST_SIMPLE_OPCODE + index as u8);
+                return;
+            }
+        };
+        self.u8(opcode);
+        self.u32(operand);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error_at(&self, offset: usize, message: &str) -> FormatError {
+        FormatError {
+            offset,
+            message: message.to_string(),
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], FormatError> {
+        match self.bytes.get(self.offset..self.offset + length) {
+            Some(bytes) => {
+                self.offset += length;
+                Ok(bytes)
+            }
+            None => Err(self.error_at(self.bytes.len(), "unexpected end of the module")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A number of following items, which cannot exceed the number of remaining bytes
+    fn count(&mut self) -> Result<usize, FormatError> {
+        let start = self.offset;
+        let count = self.u32()? as usize;
+        if count > self.bytes.len() - self.offset {
+            return Err(self.error_at(start, &format!("count {} exceeds the module", count)));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let start = self.offset;
+        let length = self.count()?;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| self.error_at(start, "string is not valid UTF-8"))
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, FormatError> {
+        (0..self.count()?).map(|_| self.string()).collect()
+    }
+
+    fn return_type(&mut self) -> Result<Type, FormatError> {
+        let start = self.offset;
+        let tag = self.u8()?;
+        tag_type(tag).ok_or_else(|| self.error_at(start, &format!("invalid type {}", tag)))
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, FormatError> {
+        let start = self.offset;
+        let opcode = self.u8()?;
+        let instruction = match opcode {
+            0x01..=0x0a => {
+                let operand = self.u32()?;
+                match opcode {
+                    0x01 => Instruction::Constant(operand),
+                    0x02 => Instruction::Load(operand),
+                    0x03 => Instruction::Store(operand),
+                    0x04 => Instruction::LoadGlobal(operand),
+                    0x05 => Instruction::StoreGlobal(operand),
+                    0x06 => Instruction::Jump(operand),
+                    0x07 => Instruction::JumpIfFalse(operand),
+                    0x08 => Instruction::JumpIfTrue(operand),
+                    0x09 => Instruction::Call(operand),
+                    _ => Instruction::CallValue(operand),
+                }
+            }
+            0x0b => match self.return_type()? {
+                Type::Void => return Err(self.error_at(start, "conversion to void")),
+                target => Instruction::Convert(target),
+            },
+            _ => *opcode
+                .checked_sub(FIRST_SIMPLE_OPCODE)
+                .and_then(|index| SIMPLE_INSTRUCTIONS.get(index as usize))
+                .ok_or_else(|| self.error_at(start, &format!("invalid opcode {:#04x}", opcode)))?,
+        };
+        Ok(instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::Module;
+    use crate::config::{LanguageOptions, Storage};
+    use crate::C1Parser;
+
+    fn compile(text: &str, storage: Storage) -> Module {
+        let program = C1Parser::parse_program(text).unwrap();
+        let language = LanguageOptions { storage };
+        Module::compile(&program, &language).unwrap()
+    }
+
+    #[test]
+    fn modules_survive_a_round_trip() {
+        for storage in [Storage::Local, Storage::Global] {
+            let module = compile(include_str!("../tests/data/beispiel.c-1"), storage);
+            let bytes = module.to_bytes();
+            assert_eq!(&bytes[..6], b"C1BC\x01\x00");
+            assert_eq!(Module::from_bytes(&bytes), Ok(module));
+        }
+        let module = compile(
+            "float f() { return -0.0 * 2.5; } bool main() { x = f() < 1 || false; return x; }",
+            Storage::Local,
+        );
+        assert_eq!(Module::from_bytes(&module.to_bytes()), Ok(module));
+    }
+
+    #[test]
+    fn invalid_modules_are_rejected() {
+        let module = compile("int main() { a = 1; return a; }", Storage::Local);
+        let bytes = module.to_bytes();
+        let error = |bytes: &[u8]| Module::from_bytes(bytes).unwrap_err().to_string();
+
+        assert_eq!(
+            error(b"\x7fELF"),
+            "offset 0: not a C(-1) module, the magic number is missing"
+        );
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(
+            error(&newer),
+            "offset 4: unsupported format version 2, expected 1"
+        );
+        assert_eq!(
+            error(&bytes[..bytes.len() - 1]),
+            format!("offset {}: unexpected end of the module", bytes.len() - 1)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            error(&trailing),
+            format!("offset {}: 1 unexpected bytes at the end", bytes.len())
+        );
+        // The module ends with the only run of the line table of `main`
+        let run = bytes.len() - 8;
+        let mut lines = bytes.clone();
+        lines[run] += 1;
+        assert_eq!(
+            error(&lines),
+            format!(
+                "offset {}: line table of `main` covers more than {} instructions",
+                run,
+                module.functions[0].code.len()
+            )
+        );
+
+        // The first instruction is `constant #0`, point it to a constant that does not exist
+        let code = bytes
+            .windows(6)
+            .position(|window| window == [0x01, 0, 0, 0, 0, 0x03])
+            .unwrap();
+        let mut constant = bytes.clone();
+        constant[code + 1] = 7;
+        assert!(error(&constant)
+            .ends_with("operand of `constant 7` in function `main` is out of range"));
+        let mut opcode = bytes.clone();
+        opcode[code] = 0xff;
+        assert_eq!(
+            error(&opcode),
+            format!("offset {}: invalid opcode 0xff", code)
+        );
+    }
+}
@@ -1,585 +1,732 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type, UnaryOp,
+};
+use crate::config::LanguageOptions;
 use crate::lexer::{C1Lexer, C1Token};
-use crate::ParseResult;
 use std::ops::{Deref, DerefMut};
 
-pub struct C1Parser<'a>(C1Lexer<'a>);
-
+/// Result type of the individual parse methods. Errors are reported as a message that contains
+/// the line number of the offending token.
 type ParseResult<T> = Result<T, String>;
 
-impl<'a> C1Parser<'a> {
-     pub fn new(lexer: C1Lexer<'a>) -> Self {
-        C1Parser(lexer)
-    }
-    
-    pub fn parse(&mut self) -> ParseResult<()> {
-        while let Some(token) = self.current_token() {
-            match token {
-                C1Token::KwPrintf => self.parse_printf_statement()?,
-                _ => {
-                    return Err(format!(
-                        "Unexpected token {:?} at position {}",
-                        token,
-                        token.position()
-                    ));
-                }
-            }
-        }
-        Ok(())
-    }
+/// Options that change which programs the parser accepts
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseOptions {
+    /// Maximum nesting depth of blocks, if statements and parenthesized expressions
+    pub max_depth: usize,
+    pub language: LanguageOptions,
+}
 
-    fn initialize_parser(text: &str) -> C1Parser {
-        C1Parser(C1Lexer::new(text))
-    }
+impl ParseOptions {
+    pub const DEFAULT_MAX_DEPTH: usize = 256;
+}
 
-    fn type_(&mut self) -> ParseResult<u32> {
-        match self.current_token() {
-            Some(token) => match token.token_type {
-                C1Token::KwBoolean
-                | C1Token::KwFloat
-                | C1Token::KwInt
-                | C1Token::KwVoid => {
-                    self.eat();
-                    Ok(())
-                }
-                _ => Err(format!("Expected type, found {:?}", token)),
-            },
-            None => Err("Unexpected end of input".to_string()),
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            language: LanguageOptions::default(),
         }
     }
+}
 
-    /// Check whether the current token is equal to the given token. If yes, consume it, otherwise
-    /// return an error with the given error message
-    
-    fn expect_token(&mut self, expected_token: C1Token) -> ParseResult<()> {
-        let token = self.next_token()?;
-        if token == expected_token {
-            Ok(())
-        } else {
-            Err(format!(
-                "Expected {:?}, found {:?}",
-                expected_token, token
-            ))
-        }
-    }
+/// Recursive descent parser for C(-1). Every grammar production is implemented by a method of the
+/// same name which returns the corresponding node of the syntax tree.
+pub struct C1Parser<'a> {
+    lexer: C1Lexer<'a>,
+    options: ParseOptions,
+    /// Line of the most recently consumed token, used for errors at the end of the input
+    last_line: usize,
+    /// Current nesting depth, limited by `ParseOptions::max_depth`
+    depth: usize,
+}
 
-    fn next_token(&mut self) -> ParseResult {
-        let next_token = self.tokens.next();
-        Ok(next_token)
+impl<'a> C1Parser<'a> {
+    /// Check the given text for syntax errors
+    pub fn parse(text: &str) -> crate::ParseResult {
+        Self::parse_program(text).map(|_| ())
     }
 
-    fn assignment(&mut self) -> ParseResult {
-        if self.current_matches(&C1Token::Identifier) {
-            self.expect_token(&C1Token::Identifier)?;
-            self.expect_token(&C1Token::Assign)?;
-            self.assignment()?;
-        } else {
-            self.expr()?;
-        }
-        Ok(())
+    /// Parse the given text into a syntax tree
+    pub fn parse_program(text: &str) -> ParseResult<Program> {
+        Self::parse_program_with(text, &ParseOptions::default())
     }
 
-    fn expr(&mut self) -> ParseResult {
-        self.simpexpr()?;
-        if let Some(token) = self.current_token() {
-            if token == &C1Token::Equal
-                || token == &C1Token::NotEqual
-                || token == &C1Token::LessEqual
-                || token == &C1Token::GreaterEqual
-                || token == &C1Token::Less
-                || token == &C1Token::Greater
-            {
-                self.eat();
-                self.simpexpr()?;
-            }
-        }
-        Ok(())
+    /// Parse the given text into a syntax tree, using the given options
+    pub fn parse_program_with(text: &str, options: &ParseOptions) -> ParseResult<Program> {
+        let mut parser = C1Parser::initialize_parser(text);
+        parser.options = options.clone();
+        parser.program()
     }
 
-    fn simpexpr(&mut self) -> ParseResult {
-        if self.current_token().is_some() && self.current_token() == &C1Token::Minus {
-            self.eat();
-        }
-        self.term()?;
-        while let Some(token) = self.current_token() {
-            if token == &C1Token::Plus || token == &C1Token::Minus || token == &C1Token::Or {
-                self.eat();
-                self.term()?;
-            } else {
-                break;
-            }
+    fn initialize_parser(text: &str) -> C1Parser<'_> {
+        C1Parser {
+            lexer: C1Lexer::new(text),
+            options: ParseOptions::default(),
+            last_line: 1,
+            depth: 0,
         }
-        Ok(())
     }
 
-    fn term(&mut self) -> ParseResult {
-        self.factor()?;
-        while let Some(token) = self.current_token() {
-            if token == &C1Token::Asterisk || token == &C1Token::Slash || token == &C1Token::And {
-                self.eat();
-                self.factor()?;
-            } else {
-                break;
-            }
+    /// program ::= ( functiondefinition )* <EOF>
+    fn program(&mut self) -> ParseResult<Program> {
+        let mut functions = Vec::new();
+        while self.current_token().is_some() {
+            functions.push(self.function_definition()?);
         }
-        Ok(())
+        Ok(Program {
+            functions,
+            comments: self.lexer.take_comments(),
+        })
+    }
+
+    /// functiondefinition ::= type <ID> "(" ")" "{" statementlist "}"
+    fn function_definition(&mut self) -> ParseResult<FunctionDefinition> {
+        let return_type = self.return_type()?;
+        let line = self.line();
+        let name = self.identifier()?;
+        self.check_and_eat_tokens(&[C1Token::LeftParenthesis, C1Token::RightParenthesis])?;
+        self.check_and_eat_token(C1Token::LeftBrace)?;
+        let body = self.statement_list()?;
+        self.check_and_eat_token(C1Token::RightBrace)?;
+        Ok(FunctionDefinition {
+            return_type,
+            name,
+            body,
+            line,
+        })
+    }
+
+    /// functioncall ::= <ID> "(" ")"
+    fn function_call(&mut self) -> ParseResult<String> {
+        let name = self.identifier()?;
+        self.check_and_eat_tokens(&[C1Token::LeftParenthesis, C1Token::RightParenthesis])?;
+        Ok(name)
+    }
+
+    /// statementlist ::= ( block )*
+    fn statement_list(&mut self) -> ParseResult<Vec<Statement>> {
+        let mut statements = Vec::new();
+        while self.current_token().is_some() && !self.current_matches(C1Token::RightBrace) {
+            statements.push(self.block()?);
+        }
+        Ok(statements)
     }
 
-    fn factor(&mut self) -> ParseResult {
-        if self.current_matches(&C1Token::ConstInt)
-            || self.current_matches(&C1Token::ConstFloat)
-            || self.current_matches(&C1Token::ConstBoolean)
-        {
+    /// block ::= "{" statementlist "}" | statement
+    fn block(&mut self) -> ParseResult<Statement> {
+        if self.current_matches(C1Token::LeftBrace) {
+            let line = self.line();
             self.eat();
-        } else if self.current_matches(&C1Token::Identifier) {
-            self.eat();
-            if self.current_matches(&C1Token::LeftParenthesis) {
-                self.expect_token(&C1Token::LeftParenthesis)?;
-                self.expect_token(&C1Token::RightParenthesis)?;
-            }
-        } else if self.current_matches(&C1Token::LeftParenthesis) {
-            self.expect_token(&C1Token::LeftParenthesis)?;
-            self.assignment()?;
-            self.expect_token(&C1Token::RightParenthesis)?;
+            let statements = self.nested(Self::statement_list)?;
+            self.check_and_eat_token(C1Token::RightBrace)?;
+            Ok(Statement::Block { statements, line })
         } else {
-            return Err(format!("Unexpected token: {:?}", self.current_token()));
+            self.statement()
         }
-        Ok(())
     }
 
-    fn program(&mut self) -> ParseResult {
-        while self.current_token().is_some() {
-            self.function_definition()?;
+    /// statement ::= ifstatement | returnstatement ";" | printf ";" | statassignment ";"
+    ///             | functioncall ";"
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let line = self.line();
+        let statement = match self.current_token() {
+            Some(C1Token::KwIf) => return self.nested(Self::if_statement),
+            Some(C1Token::KwReturn) => Statement::Return {
+                value: self.return_statement()?,
+                line,
+            },
+            Some(C1Token::KwPrintf) => Statement::Printf {
+                value: self.printf()?,
+                line,
+            },
+            Some(C1Token::Identifier) if self.next_matches(C1Token::Assign) => {
+                let (target, value) = self.stat_assignment()?;
+                Statement::Assign {
+                    target,
+                    value,
+                    line,
+                }
+            }
+            Some(C1Token::Identifier) => Statement::Call {
+                name: self.function_call()?,
+                line,
+            },
+            _ => return Err(self.unexpected("statement")),
+        };
+        self.check_and_eat_token(C1Token::Semicolon)?;
+        Ok(statement)
+    }
+
+    /// ifstatement ::= <KW_IF> "(" assignment ")" block
+    fn if_statement(&mut self) -> ParseResult<Statement> {
+        let line = self.line();
+        self.check_and_eat_tokens(&[C1Token::KwIf, C1Token::LeftParenthesis])?;
+        let condition = self.assignment()?;
+        self.check_and_eat_token(C1Token::RightParenthesis)?;
+        let then_branch = Box::new(self.block()?);
+        Ok(Statement::If {
+            condition,
+            then_branch,
+            line,
+        })
+    }
+
+    /// returnstatement ::= <KW_RETURN> ( assignment )?
+    fn return_statement(&mut self) -> ParseResult<Option<Expression>> {
+        self.check_and_eat_token(C1Token::KwReturn)?;
+        if self.current_matches(C1Token::Semicolon) || self.current_token().is_none() {
+            Ok(None)
+        } else {
+            self.assignment().map(Some)
         }
-        self.expect_token(&C1Token::EOF)?;
-        Ok(())
-    }
-
-    fn function_definition(&mut self) -> ParseResult {
-        self.type_()?;
-        self.expect_token(&C1Token::Identifier)?;
-        self.expect_token(&C1Token::LeftParenthesis)?;
-        self.expect_token(&C1Token::RightParenthesis)?;
-        self.expect_token(&C1Token::LeftBrace)?;
-        self.statement_list()?;
-        self.expect_token(&C1Token::RightBrace)?;
-        Ok(())
     }
 
-    fn statement_assignment(&mut self) -> ParseResult {
-        self.expect_token(&C1Token::Identifier)?;
-        self.expect_token(&C1Token::Assign)?;
-        self.assignment()?;
-        Ok(())
+    /// printf ::= <KW_PRINTF> "(" assignment ")"
+    fn printf(&mut self) -> ParseResult<Expression> {
+        self.check_and_eat_tokens(&[C1Token::KwPrintf, C1Token::LeftParenthesis])?;
+        let value = self.assignment()?;
+        self.check_and_eat_token(C1Token::RightParenthesis)?;
+        Ok(value)
     }
 
-    fn function_call(&mut self) -> ParseResult {
-        self.expect_token(&C1Token::Identifier)?;
-        self.expect_token(&C1Token::LeftParenthesis)?;
-        self.expect_token(&C1Token::RightParenthesis)?;
-        Ok(())
-    }
-
-    fn block(&mut self) -> ParseResult {
-        self.expect_token(&C1Token::LeftBrace)?;
-        self.statement_list()?;
-        self.expect_token(&C1Token::RightBrace)?;
-        Ok(())
-    }
-
-    fn statement_list(&mut self) -> ParseResult {
-        self.statement()?;
-        if self.current_token().is_some() {
-            self.statement_list()?;
+    /// type ::= <KW_BOOLEAN> | <KW_FLOAT> | <KW_INT> | <KW_VOID>
+    fn return_type(&mut self) -> ParseResult<Type> {
+        match self.current_token().and_then(Type::from_token) {
+            Some(return_type) => {
+                self.eat();
+                Ok(return_type)
+            }
+            None => Err(self.unexpected("type")),
         }
-        Ok(())
     }
 
-    fn statement(&mut self) -> ParseResult {
-        if self.current_matches(&C1Token::KwIf) {
-            self.if_statement()?;
-        } else if self.current_matches(&C1Token::KwReturn) {
-            self.return_statement()?;
-        } else if self.current_matches(&C1Token::KwPrintf) {
-            self.printf()?;
-        } else if self.current_matches(&C1Token::Identifier) {
-            if self.next_matches(&C1Token::Assign) {
-                self.statement_assignment()?;
-            } else {
-                self.function_call()?;
-            }
-        } else if self.current_matches(&C1Token::LeftBrace) {
-            self.block()?;
+    /// statassignment ::= <ID> "=" assignment
+    fn stat_assignment(&mut self) -> ParseResult<(String, Expression)> {
+        let target = self.identifier()?;
+        self.check_and_eat_token(C1Token::Assign)?;
+        let value = self.assignment()?;
+        Ok((target, value))
+    }
+
+    /// assignment ::= ( ( <ID> "=" assignment ) | expr )
+    fn assignment(&mut self) -> ParseResult<Expression> {
+        if self.current_matches(C1Token::Identifier) && self.next_matches(C1Token::Assign) {
+            let line = self.line();
+            let (target, value) = self.nested(Self::stat_assignment)?;
+            Ok(Expression::new(
+                ExpressionKind::Assign {
+                    target,
+                    value: Box::new(value),
+                },
+                line,
+            ))
         } else {
-            return Err(format!("Unexpected token: {:?}", self.current_token()));
+            self.expr()
         }
-        self.expect_token(&C1Token::Semicolon)?;
-        Ok(())
     }
 
-    fn if_statement(&mut self) -> ParseResult {
-        self.expect_token(&C1Token::KwIf)?;
-        self.expect_token(&C1Token::LeftParenthesis)?;
-        self.assignment()?;
-        self.expect_token(&C1Token::RightParenthesis)?;
-        self.block()?;
-        Ok(())
+    /// expr ::= simpexpr ( ( "==" | "!=" | "<=" | ">=" | "<" | ">" ) simpexpr )?
+    fn expr(&mut self) -> ParseResult<Expression> {
+        let lhs = self.simpexpr()?;
+        match self.current_binary_op(BinaryOp::is_comparison) {
+            Some(op) => {
+                self.eat();
+                let rhs = self.simpexpr()?;
+                Ok(binary(op, lhs, rhs))
+            }
+            None => Ok(lhs),
+        }
     }
 
-    fn return_statement(&mut self) -> ParseResult {
-        self.expect_token(&C1Token::KwReturn)?;
-        if !self.current_matches(&C1Token::Semicolon) {
-            self.assignment()?;
+    /// simpexpr ::= ( "-" )? term ( ( "+" | "-" | "||" ) term )*
+    fn simpexpr(&mut self) -> ParseResult<Expression> {
+        let mut lhs = if self.current_matches(C1Token::Minus) {
+            let line = self.line();
+            self.eat();
+            let operand = Box::new(self.term()?);
+            Expression::new(
+                ExpressionKind::Unary {
+                    op: UnaryOp::Neg,
+                    operand,
+                },
+                line,
+            )
+        } else {
+            self.term()?
+        };
+        while let Some(op) =
+            self.current_binary_op(|op| matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or))
+        {
+            self.eat();
+            let rhs = self.term()?;
+            lhs = binary(op, lhs, rhs);
         }
-        Ok(())
-    }
-    
-    fn parse_printf_statement(&mut self) -> ParseResult<()> {
-        self.expect_token(C1Token::KwPrintf)?;
-        self.expect_token(C1Token::LeftParenthesis)?;
-        self.assignment()?;
-        self.expect_token(C1Token::RightParenthesis)?;
-        Ok(())
+        Ok(lhs)
     }
 
-    fn printf(&mut self) -> ParseResult {
-        self.expect_token(&C1Token::KwPrintf)?;
-        self.expect_token(&C1Token::LeftParenthesis)?;
-        self.assignment()?;
-        self.expect_token(&C1Token::RightParenthesis)?;
-        Ok(())
+    /// term ::= factor ( ( "*" | "/" | "&&" ) factor )*
+    fn term(&mut self) -> ParseResult<Expression> {
+        let mut lhs = self.factor()?;
+        while let Some(op) =
+            self.current_binary_op(|op| matches!(op, BinaryOp::Mul | BinaryOp::Div | BinaryOp::And))
+        {
+            self.eat();
+            let rhs = self.factor()?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    /// factor ::= <CONST_INT> | <CONST_FLOAT> | <CONST_BOOLEAN> | functioncall | <ID>
+    ///          | "(" assignment ")"
+    fn factor(&mut self) -> ParseResult<Expression> {
+        let line = self.line();
+        let kind = match self.current_token() {
+            Some(C1Token::ConstInt) => {
+                let text = self.current_text().unwrap_or_default();
+                let value = text.parse().map_err(|_| {
+                    format!("Line {}: integer constant {} is out of range", line, text)
+                })?;
+                self.eat();
+                ExpressionKind::Int(value)
+            }
+            Some(C1Token::ConstFloat) => {
+                let text = self.current_text().unwrap_or_default();
+                let value = text
+                    .parse()
+                    .map_err(|_| format!("Line {}: invalid float constant {}", line, text))?;
+                self.eat();
+                ExpressionKind::Float(value)
+            }
+            Some(C1Token::ConstBoolean) => {
+                let value = self.current_text() == Some("true");
+                self.eat();
+                ExpressionKind::Bool(value)
+            }
+            Some(C1Token::Identifier) if self.next_matches(C1Token::LeftParenthesis) => {
+                ExpressionKind::Call(self.function_call()?)
+            }
+            Some(C1Token::Identifier) => ExpressionKind::Variable(self.identifier()?),
+            Some(C1Token::LeftParenthesis) => {
+                self.eat();
+                let inner = self.nested(Self::assignment)?;
+                self.check_and_eat_token(C1Token::RightParenthesis)?;
+                return Ok(inner);
+            }
+            _ => return Err(self.unexpected("factor")),
+        };
+        Ok(Expression::new(kind, line))
     }
 
     // Helper methods
 
-    /// For each token in the given slice, check whether the token is equal to the current token,
-    /// consume the current token, and check the next token in the slice against the next token
-    /// provided by the lexer.
-    fn expect_tokens(&mut self, tokens: &[C1Token]) -> ParseResult {
-        for token in tokens {
-            self.expect_token(token)?;
+    /// Call the given parse method one nesting level deeper, failing if the maximum depth is
+    /// exceeded
+    fn nested<T>(&mut self, parse_method: fn(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        if self.depth >= self.options.max_depth {
+            return Err(format!(
+                "Line {}: nesting depth exceeds the limit of {}",
+                self.line(),
+                self.options.max_depth
+            ));
         }
-        Ok(())
+        self.depth += 1;
+        let result = parse_method(self);
+        self.depth -= 1;
+        result
     }
 
-    /// Check whether the given token matches the current token
-    fn current_matches(&self, token: &C1Token) -> bool {
-        match self.current_token() {
-            None => false,
-            Some(current) => current == token,
-        }
-    }
-    
-    /// Check whether the given token matches the next token
-    fn next_matches(&self, token: &C1Token) -> bool {
-        match self.peek_token() {
-            None => false,
-            Some(next) => next == token,
+    /// Consume an identifier and return its text
+    fn identifier(&mut self) -> ParseResult<String> {
+        match (self.current_token(), self.current_text()) {
+            (Some(C1Token::Identifier), Some(text)) => {
+                let name = text.to_string();
+                self.eat();
+                Ok(name)
+            }
+            _ => Err(self.unexpected("identifier")),
         }
     }
 
-    /// Check whether any of the tokens matches the current token.
-    fn any_match_current(&self, tokens: &[C1Token]) -> bool {
-        tokens.iter().any(|token| self.current_matches(token))
-    }
-
-    /// Check whether any of the tokens matches the current token, then consume it
-    fn any_match_and_eat(&mut self, tokens: &[C1Token], error_message: &str) -> ParseResult {
-        if tokens.iter().any(|token| self.check_and_eat_token(token, "").is_ok()) {
-            Ok(())
-        } else {
-            Err(String::from(error_message))
-        }
+    /// Return the binary operator of the current token if it satisfies the given filter
+    fn current_binary_op(&self, filter: impl Fn(&BinaryOp) -> bool) -> Option<BinaryOp> {
+        self.current_token()
+            .and_then(BinaryOp::from_token)
+            .filter(filter)
     }
 
     /// Check whether the current token is equal to the given token. If yes, consume it, otherwise
-    /// return an error with the given error message
-    fn check_and_eat_token(&mut self, token: &C1Token, error_message: &str) -> ParseResult {
+    /// return an error
+    fn check_and_eat_token(&mut self, token: C1Token) -> ParseResult<()> {
         if self.current_matches(token) {
             self.eat();
             Ok(())
         } else {
-            Err(String::from(error_message))
+            Err(self.unexpected(&format!("{:?}", token)))
         }
     }
 
-    /// Consume the current token and move to the next token
-    fn eat(&mut self) {
-        self.0.next();
+    /// For each token in the given slice, check whether the token is equal to the current token
+    /// and consume it
+    fn check_and_eat_tokens(&mut self, tokens: &[C1Token]) -> ParseResult<()> {
+        for token in tokens {
+            self.check_and_eat_token(*token)?;
+        }
+        Ok(())
     }
 
-    /// Get the current token
-    fn current_token(&self) -> Option<&C1Token> {
-        self.0.current_token()
+    /// Check whether the given token matches the current token
+    fn current_matches(&self, token: C1Token) -> bool {
+        self.current_token() == Some(token)
     }
 
-    /// Get the next token without consuming it
-    fn peek_token(&self) -> Option<&C1Token> {
-        self.tokens.peek_token()
-    }
-    
-    fn next_token(&mut self) -> ParseResult<C1Token> {
-        self.0.next().ok_or("Unexpected end of input".to_string())
+    /// Check whether the given token matches the next token
+    fn next_matches(&self, token: C1Token) -> bool {
+        self.peek_token() == Some(token)
     }
 
-    fn peek_token(&self) -> Option<&C1Token> {
-        self.0.peek_token()
+    /// Consume the current token and move to the next token
+    fn eat(&mut self) {
+        if let Some(line) = self.current_line_number() {
+            self.last_line = line;
+        }
+        self.lexer.eat();
+    }
+
+    /// Line of the current token, or of the last token if the input is exhausted
+    fn line(&self) -> usize {
+        self.current_line_number().unwrap_or(self.last_line)
+    }
+
+    /// Build the error message for an unexpected token
+    fn unexpected(&self, expected: &str) -> String {
+        match (self.current_token(), self.current_text()) {
+            (Some(token), Some(text)) => format!(
+                "Line {}: expected {}, found {:?} '{}'",
+                self.line(),
+                expected,
+                token,
+                text
+            ),
+            _ => format!(
+                "Line {}: expected {}, found end of input",
+                self.line(),
+                expected
+            ),
+        }
     }
 }
 
+/// Combine two operands into a binary expression located at the line of the left operand
+fn binary(op: BinaryOp, lhs: Expression, rhs: Expression) -> Expression {
+    let line = lhs.line;
+    Expression::new(
+        ExpressionKind::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        line,
+    )
+}
+
 impl<'a> Deref for C1Parser<'a> {
     type Target = C1Lexer<'a>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.lexer
     }
 }
 
 impl<'a> DerefMut for C1Parser<'a> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.lexer
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use crate::parser::{C1Parser, ParseResult};
-//
-//     fn call_method<'a, F>(parse_method: F, text: &'static str) -> ParseResult
-//     where
-//         F: Fn(&mut C1Parser<'a>) -> ParseResult,
-//     {
-//         let mut parser = C1Parser::initialize_parser(text);
-//         if let Err(message) = parse_method(&mut parser) {
-//             eprintln!("Parse Error: {}", message);
-//             Err(message)
-//         } else {
-//             Ok(())
-//         }
-//     }
-//
-//     #[test]
-//     fn parse_empty_program() {
-//         let result = C1Parser::parse("");
-//         assert_eq!(result, Ok(()));
-//
-//         let result = C1Parser::parse("   ");
-//         assert_eq!(result, Ok(()));
-//
-//         let result = C1Parser::parse("// This is a valid comment!");
-//         assert_eq!(result, Ok(()));
-//
-//         let result = C1Parser::parse("/* This is a valid comment!\nIn two lines!*/\n");
-//         assert_eq!(result, Ok(()));
-//
-//         let result = C1Parser::parse("  \n ");
-//         assert_eq!(result, Ok(()));
-//     }
-//
-//     #[test]
-//     fn fail_invalid_program() {
-//         let result = C1Parser::parse("  bool  ");
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//
-//         let result = C1Parser::parse("x = 0;");
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//
-//         let result = C1Parser::parse("// A valid comment\nInvalid line.");
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//     }
-//
-//     #[test]
-//     fn valid_function() {
-//         let result = C1Parser::parse("  void foo() {}  ");
-//         assert!(result.is_ok());
-//
-//         let result = C1Parser::parse("int bar() {return 0;}");
-//         assert!(result.is_ok());
-//
-//         let result = C1Parser::parse(
-//             "float calc() {\n\
-//         x = 1.0;
-//         y = 2.2;
-//         return x + y;
-//         \n\
-//         }",
-//         );
-//         assert!(result.is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_function() {
-//         let result = C1Parser::parse("  void foo()) {}  ");
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//
-//         let result = C1Parser::parse("const bar() {return 0;}");
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//
-//         let result = C1Parser::parse(
-//             "int bar() {
-//                                                           return 0;
-//                                                      int foo() {}",
-//         );
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//
-//         let result = C1Parser::parse(
-//             "float calc(int invalid) {\n\
-//         x = 1.0;
-//         y = 2.2;
-//         return x + y;
-//         \n\
-//         }",
-//         );
-//         println!("{:?}", result);
-//         assert!(result.is_err());
-//     }
-//
-//     #[test]
-//     fn valid_function_call() {
-//         assert!(call_method(C1Parser::function_call, "foo()").is_ok());
-//         assert!(call_method(C1Parser::function_call, "foo( )").is_ok());
-//         assert!(call_method(C1Parser::function_call, "bar23( )").is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_function_call() {
-//         assert!(call_method(C1Parser::function_call, "foo)").is_err());
-//         assert!(call_method(C1Parser::function_call, "foo{ )").is_err());
-//         assert!(call_method(C1Parser::function_call, "bar _foo( )").is_err());
-//     }
-//
-//     #[test]
-//     fn valid_statement_list() {
-//         assert!(call_method(C1Parser::statement_list, "x = 4;").is_ok());
-//         assert!(call_method(
-//             C1Parser::statement_list,
-//             "x = 4;\n\
-//         y = 2.1;"
-//         )
-//         .is_ok());
-//         assert!(call_method(
-//             C1Parser::statement_list,
-//             "x = 4;\n\
-//         {\
-//         foo();\n\
-//         }"
-//         )
-//         .is_ok());
-//         assert!(call_method(C1Parser::statement_list, "{x = 4;}\ny = 1;\nfoo;\n{}").is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_statement_list() {
-//         assert!(call_method(
-//             C1Parser::statement_list,
-//             "x = 4\n\
-//         y = 2.1;"
-//         )
-//         .is_err());
-//         assert!(call_method(
-//             C1Parser::statement_list,
-//             "x = 4;\n\
-//         {\
-//         foo();"
-//         )
-//         .is_err());
-//         assert!(call_method(C1Parser::statement_list, "{x = 4;\ny = 1;\nfoo;\n{}").is_err());
-//     }
-//
-//     #[test]
-//     fn valid_if_statement() {
-//         assert!(call_method(C1Parser::if_statement, "if(x == 1) {}").is_ok());
-//         assert!(call_method(C1Parser::if_statement, "if(x == y) {}").is_ok());
-//         assert!(call_method(C1Parser::if_statement, "if(z) {}").is_ok());
-//         assert!(call_method(C1Parser::if_statement, "if(true) {}").is_ok());
-//         assert!(call_method(C1Parser::if_statement, "if(false) {}").is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_if_statement() {
-//         assert!(call_method(C1Parser::if_statement, "if(x == ) {}").is_err());
-//         assert!(call_method(C1Parser::if_statement, "if( == y) {}").is_err());
-//         assert!(call_method(C1Parser::if_statement, "if(> z) {}").is_err());
-//         assert!(call_method(C1Parser::if_statement, "if( {}").is_err());
-//         assert!(call_method(C1Parser::if_statement, "if(false) }").is_err());
-//     }
-//
-//     #[test]
-//     fn valid_return_statement() {
-//         assert!(call_method(C1Parser::return_statement, "return x").is_ok());
-//         assert!(call_method(C1Parser::return_statement, "return 1").is_ok());
-//         assert!(call_method(C1Parser::return_statement, "return").is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_return_statement() {
-//         assert!(call_method(C1Parser::return_statement, "1").is_err());
-//     }
-//
-//     #[test]
-//     fn valid_printf_statement() {
-//         assert!(call_method(C1Parser::printf, " printf(a+b)").is_ok());
-//         assert!(call_method(C1Parser::printf, "printf( 1)").is_ok());
-//         assert!(call_method(C1Parser::printf, "printf(a - c)").is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_printf_statement() {
-//         assert!(call_method(C1Parser::printf, "printf( ").is_err());
-//         assert!(call_method(C1Parser::printf, "printf(printf)").is_err());
-//         assert!(call_method(C1Parser::printf, "Printf()").is_err());
-//     }
-//
-//     #[test]
-//     fn valid_return_type() {
-//         assert!(call_method(C1Parser::return_type, "void").is_ok());
-//         assert!(call_method(C1Parser::return_type, "bool").is_ok());
-//         assert!(call_method(C1Parser::return_type, "int").is_ok());
-//         assert!(call_method(C1Parser::return_type, "float").is_ok());
-//     }
-//
-//     #[test]
-//     fn valid_assignment() {
-//         assert!(call_method(C1Parser::assignment, "x = y").is_ok());
-//         assert!(call_method(C1Parser::assignment, "x =y").is_ok());
-//         assert!(call_method(C1Parser::assignment, "1 + 2").is_ok());
-//     }
-//
-//     #[test]
-//     fn valid_stat_assignment() {
-//         assert!(call_method(C1Parser::stat_assignment, "x = y").is_ok());
-//         assert!(call_method(C1Parser::stat_assignment, "x =y").is_ok());
-//         assert!(call_method(C1Parser::stat_assignment, "x =y + t").is_ok());
-//     }
-//
-//     #[test]
-//     fn valid_factor() {
-//         assert!(call_method(C1Parser::factor, "4").is_ok());
-//         assert!(call_method(C1Parser::factor, "1.2").is_ok());
-//         assert!(call_method(C1Parser::factor, "true").is_ok());
-//         assert!(call_method(C1Parser::factor, "foo()").is_ok());
-//         assert!(call_method(C1Parser::factor, "x").is_ok());
-//         assert!(call_method(C1Parser::factor, "(x + y)").is_ok());
-//     }
-//
-//     #[test]
-//     fn fail_invalid_factor() {
-//         assert!(call_method(C1Parser::factor, "if").is_err());
-//         assert!(call_method(C1Parser::factor, "(4").is_err());
-//         assert!(call_method(C1Parser::factor, "bool").is_err());
-//     }
-//
-//     #[test]
-//     fn multiple_functions() {
-//         assert!(call_method(
-//             C1Parser::program,
-//             "void main() { hello();}\nfloat bar() {return 1.0;}"
-//         )
-//         .is_ok());
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use crate::parser::{C1Parser, ParseOptions, ParseResult};
+
+    fn call_method<'a, F, T>(parse_method: F, text: &'static str) -> ParseResult<()>
+    where
+        F: Fn(&mut C1Parser<'a>) -> ParseResult<T>,
+    {
+        let mut parser = C1Parser::initialize_parser(text);
+        match parse_method(&mut parser) {
+            Err(message) => {
+                eprintln!("Parse Error: {}", message);
+                Err(message)
+            }
+            // A method only succeeds if it consumed the whole input
+            Ok(_) if parser.current_token().is_some() => Err(parser.unexpected("end of input")),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    #[test]
+    fn parse_empty_program() {
+        let result = C1Parser::parse("");
+        assert_eq!(result, Ok(()));
+
+        let result = C1Parser::parse("   ");
+        assert_eq!(result, Ok(()));
+
+        let result = C1Parser::parse("// This is a valid comment!");
+        assert_eq!(result, Ok(()));
+
+        let result = C1Parser::parse("/* This is a valid comment!\nIn two lines!*/\n");
+        assert_eq!(result, Ok(()));
+
+        let result = C1Parser::parse("  \n ");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn fail_invalid_program() {
+        let result = C1Parser::parse("  bool  ");
+        println!("{:?}", result);
+        assert!(result.is_err());
+
+        let result = C1Parser::parse("x = 0;");
+        println!("{:?}", result);
+        assert!(result.is_err());
+
+        let result = C1Parser::parse("// A valid comment\nInvalid line.");
+        println!("{:?}", result);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn errors_contain_line_numbers() {
+        let result = C1Parser::parse("void main() {\n  x = 1;\n  y = ;\n}");
+        assert_eq!(
+            result,
+            Err("Line 3: expected factor, found Semicolon ';'".to_string())
+        );
+
+        let result = C1Parser::parse("void main() {\n  x = 1;\n");
+        assert_eq!(
+            result,
+            Err("Line 2: expected RightBrace, found end of input".to_string())
+        );
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        let options = ParseOptions {
+            max_depth: 3,
+            ..ParseOptions::default()
+        };
+        let text = "void main() { { { x = (1); } } }";
+        assert!(C1Parser::parse_program_with(text, &options).is_ok());
+        let text = "void main() { { if (x) { x = (1); } } }";
+        assert_eq!(
+            C1Parser::parse_program_with(text, &options),
+            Err("Line 1: nesting depth exceeds the limit of 3".to_string())
+        );
+    }
+
+    #[test]
+    fn else_is_not_part_of_the_grammar() {
+        let text = "void main() { if (x) y = 1; else { y = 2; } }";
+        assert_eq!(
+            C1Parser::parse(text),
+            Err("Line 1: expected statement, found KwElse 'else'".to_string())
+        );
+    }
+
+    #[test]
+    fn valid_function() {
+        let result = C1Parser::parse("  void foo() {}  ");
+        assert!(result.is_ok());
+
+        let result = C1Parser::parse("int bar() {return 0;}");
+        assert!(result.is_ok());
+
+        let result = C1Parser::parse(
+            "float calc() {\n\
+        x = 1.0;
+        y = 2.2;
+        return x + y;
+        \n\
+        }",
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_function() {
+        let result = C1Parser::parse("  void foo()) {}  ");
+        println!("{:?}", result);
+        assert!(result.is_err());
+
+        let result = C1Parser::parse("const bar() {return 0;}");
+        println!("{:?}", result);
+        assert!(result.is_err());
+
+        let result = C1Parser::parse(
+            "int bar() {
+                                                          return 0;
+                                                     int foo() {}",
+        );
+        println!("{:?}", result);
+        assert!(result.is_err());
+
+        let result = C1Parser::parse(
+            "float calc(int invalid) {\n\
+        x = 1.0;
+        y = 2.2;
+        return x + y;
+        \n\
+        }",
+        );
+        println!("{:?}", result);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn valid_function_call() {
+        assert!(call_method(C1Parser::function_call, "foo()").is_ok());
+        assert!(call_method(C1Parser::function_call, "foo( )").is_ok());
+        assert!(call_method(C1Parser::function_call, "bar23( )").is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_function_call() {
+        assert!(call_method(C1Parser::function_call, "foo)").is_err());
+        assert!(call_method(C1Parser::function_call, "foo{ )").is_err());
+        assert!(call_method(C1Parser::function_call, "bar _foo( )").is_err());
+    }
+
+    #[test]
+    fn valid_statement_list() {
+        assert!(call_method(C1Parser::statement_list, "x = 4;").is_ok());
+        assert!(call_method(
+            C1Parser::statement_list,
+            "x = 4;\n\
+        y = 2.1;"
+        )
+        .is_ok());
+        assert!(call_method(
+            C1Parser::statement_list,
+            "x = 4;\n\
+        {\
+        foo();\n\
+        }"
+        )
+        .is_ok());
+        assert!(call_method(C1Parser::statement_list, "{x = 4;}\ny = 1;\nfoo();\n{}").is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_statement_list() {
+        assert!(call_method(
+            C1Parser::statement_list,
+            "x = 4\n\
+        y = 2.1;"
+        )
+        .is_err());
+        assert!(call_method(
+            C1Parser::statement_list,
+            "x = 4;\n\
+        {\
+        foo();"
+        )
+        .is_err());
+        assert!(call_method(C1Parser::statement_list, "{x = 4;\ny = 1;\nfoo;\n{}").is_err());
+    }
+
+    #[test]
+    fn valid_if_statement() {
+        assert!(call_method(C1Parser::if_statement, "if(x == 1) {}").is_ok());
+        assert!(call_method(C1Parser::if_statement, "if(x == y) {}").is_ok());
+        assert!(call_method(C1Parser::if_statement, "if(z) {}").is_ok());
+        assert!(call_method(C1Parser::if_statement, "if(true) {}").is_ok());
+        assert!(call_method(C1Parser::if_statement, "if(false) {}").is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_if_statement() {
+        assert!(call_method(C1Parser::if_statement, "if(x == ) {}").is_err());
+        assert!(call_method(C1Parser::if_statement, "if( == y) {}").is_err());
+        assert!(call_method(C1Parser::if_statement, "if(> z) {}").is_err());
+        assert!(call_method(C1Parser::if_statement, "if( {}").is_err());
+        assert!(call_method(C1Parser::if_statement, "if(false) }").is_err());
+    }
+
+    #[test]
+    fn valid_return_statement() {
+        assert!(call_method(C1Parser::return_statement, "return x").is_ok());
+        assert!(call_method(C1Parser::return_statement, "return 1").is_ok());
+        assert!(call_method(C1Parser::return_statement, "return").is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_return_statement() {
+        assert!(call_method(C1Parser::return_statement, "1").is_err());
+    }
+
+    #[test]
+    fn valid_printf_statement() {
+        assert!(call_method(C1Parser::printf, " printf(a+b)").is_ok());
+        assert!(call_method(C1Parser::printf, "printf( 1)").is_ok());
+        assert!(call_method(C1Parser::printf, "printf(a - c)").is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_printf_statement() {
+        assert!(call_method(C1Parser::printf, "printf( ").is_err());
+        assert!(call_method(C1Parser::printf, "printf(printf)").is_err());
+        assert!(call_method(C1Parser::printf, "Printf()").is_err());
+    }
+
+    #[test]
+    fn valid_return_type() {
+        assert!(call_method(C1Parser::return_type, "void").is_ok());
+        assert!(call_method(C1Parser::return_type, "bool").is_ok());
+        assert!(call_method(C1Parser::return_type, "int").is_ok());
+        assert!(call_method(C1Parser::return_type, "float").is_ok());
+    }
+
+    #[test]
+    fn valid_assignment() {
+        assert!(call_method(C1Parser::assignment, "x = y").is_ok());
+        assert!(call_method(C1Parser::assignment, "x =y").is_ok());
+        assert!(call_method(C1Parser::assignment, "1 + 2").is_ok());
+    }
+
+    #[test]
+    fn valid_stat_assignment() {
+        assert!(call_method(C1Parser::stat_assignment, "x = y").is_ok());
+        assert!(call_method(C1Parser::stat_assignment, "x =y").is_ok());
+        assert!(call_method(C1Parser::stat_assignment, "x =y + t").is_ok());
+    }
+
+    #[test]
+    fn valid_factor() {
+        assert!(call_method(C1Parser::factor, "4").is_ok());
+        assert!(call_method(C1Parser::factor, "1.2").is_ok());
+        assert!(call_method(C1Parser::factor, "true").is_ok());
+        assert!(call_method(C1Parser::factor, "foo()").is_ok());
+        assert!(call_method(C1Parser::factor, "x").is_ok());
+        assert!(call_method(C1Parser::factor, "(x + y)").is_ok());
+    }
+
+    #[test]
+    fn fail_invalid_factor() {
+        assert!(call_method(C1Parser::factor, "if").is_err());
+        assert!(call_method(C1Parser::factor, "(4").is_err());
+        assert!(call_method(C1Parser::factor, "bool").is_err());
+    }
+
+    #[test]
+    fn multiple_functions() {
+        assert!(call_method(
+            C1Parser::program,
+            "void main() { hello();}\nfloat bar() {return 1.0;}"
+        )
+        .is_ok());
+    }
+}
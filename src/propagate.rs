@@ -0,0 +1,431 @@
+use crate::ast::{BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement};
+use crate::config::Storage;
+use crate::fold::{constant, literal, Folder};
+use crate::types::TypeEnvironment;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// What happened to an `if` statement whose condition always has the same value
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Elimination {
+    /// The condition is always true, the then branch replaces the statement
+    Unwrapped,
+    /// The condition is always false, the statement is dropped
+    Removed,
+}
+
+/// An `if` statement removed or replaced by the constant propagation
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct EliminatedBranch {
+    pub function: String,
+    pub line: usize,
+    /// The condition as written in the original program
+    pub condition: String,
+    pub elimination: Elimination,
+}
+
+impl fmt::Display for EliminatedBranch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, action) = match self.elimination {
+            Elimination::Unwrapped => (true, "the if statement was replaced by its body"),
+            Elimination::Removed => (false, "the if statement was removed"),
+        };
+        write!(
+            f,
+            "function `{}`, line {}: the condition `{}` is always {}, {}",
+            self.function, self.line, self.condition, value, action
+        )
+    }
+}
+
+impl Program {
+    /// Follow the values of variables through the body of every function, replace variables
+    /// whose value is known at a point by that value and fold the resulting expressions. `if`
+    /// statements whose condition becomes a constant are replaced by the branch that is taken,
+    /// each of them is reported.
+    ///
+    /// The analysis only looks at one function at a time. With global storage, a call can
+    /// assign any variable, so nothing is known about the variables after a call.
+    pub fn propagate_constants(&self, storage: Storage) -> (Program, Vec<EliminatedBranch>) {
+        let types = TypeEnvironment::infer(self, storage);
+        let mut eliminated = Vec::new();
+        let functions = self
+            .functions
+            .iter()
+            .map(|function| {
+                let mut propagation = Propagation {
+                    folder: Folder::new(&types, &function.name),
+                    function: &function.name,
+                    storage,
+                    eliminated: &mut eliminated,
+                };
+                let mut state = State::default();
+                FunctionDefinition {
+                    body: propagation.statements(&function.body, &mut state),
+                    ..function.clone()
+                }
+            })
+            .collect();
+        let program = Program {
+            functions,
+            comments: self.comments.clone(),
+        };
+        (program, eliminated)
+    }
+}
+
+/// The known values of variables at a point of a function
+#[derive(Debug, Default, Clone)]
+struct State {
+    values: HashMap<String, Value>,
+    /// No path reaches the point, e.g. after a `return`
+    unreachable: bool,
+}
+
+impl State {
+    /// Combine the states at the end of two paths that continue at the same point
+    fn merge(self, other: State) -> State {
+        if self.unreachable {
+            return other;
+        }
+        if other.unreachable {
+            return self;
+        }
+        let values = self
+            .values
+            .into_iter()
+            .filter(|(name, value)| other.values.get(name) == Some(value))
+            .collect();
+        State {
+            values,
+            unreachable: false,
+        }
+    }
+}
+
+struct Propagation<'a> {
+    folder: Folder<'a>,
+    function: &'a str,
+    storage: Storage,
+    eliminated: &'a mut Vec<EliminatedBranch>,
+}
+
+impl Propagation<'_> {
+    fn statements(&mut self, statements: &[Statement], state: &mut State) -> Vec<Statement> {
+        statements
+            .iter()
+            .flat_map(|statement| self.statement(statement, state))
+            .collect()
+    }
+
+    /// Rewrite a statement, an eliminated `if` is replaced by any number of statements
+    fn statement(&mut self, statement: &Statement, state: &mut State) -> Vec<Statement> {
+        let rewritten = match statement {
+            Statement::Block { statements, line } => Statement::Block {
+                statements: self.statements(statements, state),
+                line: *line,
+            },
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => {
+                let folded = self.expression(condition, state);
+                if let Some(value) = constant(&folded) {
+                    let taken = value.is_truthy();
+                    let elimination = match taken {
+                        true => Elimination::Unwrapped,
+                        false => Elimination::Removed,
+                    };
+                    self.eliminated.push(EliminatedBranch {
+                        function: self.function.to_string(),
+                        line: *line,
+                        condition: condition.to_string(),
+                        elimination,
+                    });
+                    return match (taken, then_branch.as_ref()) {
+                        (false, _) => Vec::new(),
+                        (true, Statement::Block { statements, .. }) => {
+                            self.statements(statements, state)
+                        }
+                        (true, branch) => self.statement(branch, state),
+                    };
+                }
+                let mut then_state = state.clone();
+                let then_branch = self.branch(then_branch, &mut then_state);
+                *state = then_state.merge(std::mem::take(state));
+                Statement::If {
+                    condition: folded,
+                    then_branch: Box::new(then_branch),
+                    line: *line,
+                }
+            }
+            Statement::Return { value, line } => {
+                let value = value.as_ref().map(|value| self.expression(value, state));
+                state.unreachable = true;
+                Statement::Return { value, line: *line }
+            }
+            Statement::Printf { value, line } => Statement::Printf {
+                value: self.expression(value, state),
+                line: *line,
+            },
+            Statement::Assign {
+                target,
+                value,
+                line,
+            } => {
+                let value = self.expression(value, state);
+                self.assign(target, &value, state);
+                Statement::Assign {
+                    target: target.clone(),
+                    value,
+                    line: *line,
+                }
+            }
+            Statement::Call { .. } => {
+                self.call(state);
+                statement.clone()
+            }
+        };
+        vec![rewritten]
+    }
+
+    /// Rewrite the branch of an `if` statement, which has to stay a single statement
+    fn branch(&mut self, branch: &Statement, state: &mut State) -> Statement {
+        let line = branch.line();
+        let mut statements = self.statement(branch, state);
+        if statements.len() == 1 {
+            statements.remove(0)
+        } else {
+            Statement::Block { statements, line }
+        }
+    }
+
+    /// Replace known variables in an expression and fold it. Sub-expressions are visited in
+    /// evaluation order, so assignments and calls inside the expression update the state.
+    fn expression(&mut self, expression: &Expression, state: &mut State) -> Expression {
+        let substituted = self.substitute(expression, state);
+        self.folder.fold(&substituted)
+    }
+
+    fn substitute(&mut self, expression: &Expression, state: &mut State) -> Expression {
+        let line = expression.line;
+        let kind = match &expression.kind {
+            ExpressionKind::Variable(name) => {
+                match state
+                    .values
+                    .get(name)
+                    .and_then(|value| literal(*value, line))
+                {
+                    Some(known) => return known,
+                    None => return expression.clone(),
+                }
+            }
+            ExpressionKind::Call(_) => {
+                self.call(state);
+                return expression.clone();
+            }
+            ExpressionKind::Assign { target, value } => {
+                let value = self.expression(value, state);
+                self.assign(target, &value, state);
+                ExpressionKind::Assign {
+                    target: target.clone(),
+                    value: Box::new(value),
+                }
+            }
+            ExpressionKind::Unary { op, operand } => ExpressionKind::Unary {
+                op: *op,
+                operand: Box::new(self.substitute(operand, state)),
+            },
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let lhs = self.substitute(lhs, state);
+                let rhs = if op.is_logical() {
+                    self.conditional(*op, &lhs, rhs, state)
+                } else {
+                    self.substitute(rhs, state)
+                };
+                ExpressionKind::Binary {
+                    op: *op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                }
+            }
+            _ => return expression.clone(),
+        };
+        Expression::new(kind, line)
+    }
+
+    /// Substitute the right operand of `&&` or `||`, which is only evaluated depending on the
+    /// value of the left one
+    fn conditional(
+        &mut self,
+        op: BinaryOp,
+        lhs: &Expression,
+        rhs: &Expression,
+        state: &mut State,
+    ) -> Expression {
+        let skipped = op == BinaryOp::Or;
+        match constant(&self.folder.fold(lhs)) {
+            // The operand is dropped by the folding anyway
+            Some(value) if value.is_truthy() == skipped => rhs.clone(),
+            Some(_) => self.substitute(rhs, state),
+            None => {
+                let mut evaluated = state.clone();
+                let rhs = self.substitute(rhs, &mut evaluated);
+                *state = evaluated.merge(std::mem::take(state));
+                rhs
+            }
+        }
+    }
+
+    fn assign(&self, target: &str, value: &Expression, state: &mut State) {
+        match value_of(value) {
+            Some(value) => state.values.insert(target.to_string(), value),
+            None => state.values.remove(target),
+        };
+    }
+
+    /// Forget what a called function may change. With local storage the callee has its own
+    /// variables, with global storage it may assign any of them.
+    fn call(&self, state: &mut State) {
+        if self.storage == Storage::Global {
+            state.values.clear();
+        }
+    }
+}
+
+/// Return the value of a folded expression whose only effects are assignments of known values,
+/// e.g. `1 + (a = 2)`. Such expressions are not folded because the assignment has to stay.
+fn value_of(expression: &Expression) -> Option<Value> {
+    match &expression.kind {
+        ExpressionKind::Assign { value, .. } => value_of(value),
+        ExpressionKind::Unary { operand, .. } => Some(value_of(operand)?.negate()),
+        ExpressionKind::Binary { op, lhs, rhs } => {
+            let lhs = value_of(lhs)?;
+            if op.is_logical() && lhs.is_truthy() == (*op == BinaryOp::Or) {
+                return Some(Value::Bool(lhs.is_truthy()));
+            }
+            Value::binary(*op, lhs, value_of(rhs)?).ok()
+        }
+        _ => constant(expression),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::propagate::Elimination;
+    use crate::{C1Parser, Interpreter};
+
+    fn propagate(text: &str, storage: Storage) -> (String, Vec<String>) {
+        let program = C1Parser::parse_program(text).unwrap();
+        let (propagated, eliminated) = program.propagate_constants(storage);
+        let mut original = Interpreter::new(&program);
+        original.storage = storage;
+        let mut optimized = Interpreter::new(&propagated);
+        optimized.storage = storage;
+        assert_eq!(optimized.run(), original.run());
+        let report = eliminated.iter().map(|branch| branch.to_string()).collect();
+        (propagated.to_string(), report)
+    }
+
+    #[test]
+    fn known_conditions_remove_branches() {
+        let (source, report) =
+            propagate(include_str!("../tests/data/beispiel.c-1"), Storage::Local);
+        assert!(source.contains("    printf(3);\n"), "{}", source);
+        assert_eq!(
+            report,
+            vec![
+                "function `blub`, line 6: the condition `blub1 < blub4` is always true, \
+                 the if statement was replaced by its body",
+                "function `main`, line 25: the condition `a <= b` is always true, \
+                 the if statement was replaced by its body",
+                "function `main`, line 26: the condition `a >= b` is always false, \
+                 the if statement was removed",
+            ]
+        );
+        assert!(source.contains("    return 17;\n"), "{}", source);
+
+        let (source, report) = propagate(
+            "void main() {
+                 a = 1;
+                 if (a > 1) printf(a);
+                 if (a) { b = 2; printf(b); }
+                 c = 1;
+                 if (x) c = 2;
+                 printf(c);
+                 if (x) { c = 2; return; }
+                 printf(c);
+             }",
+            Storage::Local,
+        );
+        assert_eq!(
+            source,
+            "void main() {
+    a = 1;
+    b = 2;
+    printf(2);
+    c = 1;
+    if (x)
+        c = 2;
+    printf(c);
+    if (x) {
+        c = 2;
+        return;
+    }
+    printf(c);
+}
+"
+        );
+        assert_eq!(report.len(), 2);
+    }
+
+    #[test]
+    fn paths_that_return_do_not_merge() {
+        let program = C1Parser::parse_program(
+            "void main() {
+                 c = 1;
+                 if (x) { c = 2; return; }
+                 if (c == 1) printf(c);
+             }",
+        )
+        .unwrap();
+        let (propagated, eliminated) = program.propagate_constants(Storage::Local);
+        assert_eq!(eliminated[0].elimination, Elimination::Unwrapped);
+        assert_eq!(eliminated[0].line, 4);
+        assert_eq!(propagated.functions[0].body[2].to_string(), "printf(1);");
+    }
+
+    #[test]
+    fn calls_overwrite_shared_variables() {
+        let text = "void reset() { a = 0; }
+                    void main() {
+                        a = 1;
+                        reset();
+                        if (a) printf(1);
+                        a = 1;
+                        b = a + (a = 2) + a;
+                        if (true || reset()) printf(b);
+                        if ((b == 5) && ((x = 0) == 0)) printf(x);
+                        printf(x);
+                    }";
+        let (_, report) = propagate(text, Storage::Global);
+        assert_eq!(
+            report,
+            vec![
+                "function `main`, line 8: the condition `true || reset()` is always true, \
+                 the if statement was replaced by its body",
+            ]
+        );
+        // The assignment in the condition has to stay, but its value is known afterwards
+        let (source, _) = propagate(text, Storage::Global);
+        assert!(source.contains("    if ((x = 0) == 0)\n"), "{}", source);
+        assert!(source.ends_with("    printf(0);\n}\n"), "{}", source);
+        // With local storage reset() has its own `a`
+        let (source, report) = propagate(text, Storage::Local);
+        assert!(source.contains("    printf(1);\n"), "{}", source);
+        assert_eq!(report.len(), 2);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::ast::{Expression, ExpressionKind, FunctionDefinition, Program, Statement};
+use crate::callgraph::CallGraph;
+use crate::config::Storage;
+use crate::fold::literal;
+use crate::interpreter::Interpreter;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How a function depends on and affects the state of the program. The order of the variants
+/// goes from least to most restrictive.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub enum Purity {
+    /// The result only depends on the function itself and a call has no effect besides it
+    Pure,
+    /// The result depends on variables that other functions can assign
+    ReadsGlobals,
+    /// A call prints, assigns variables that other functions can see or calls an undefined
+    /// function
+    SideEffecting,
+}
+
+impl Purity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Purity::Pure => "pure",
+            Purity::ReadsGlobals => "reads-globals",
+            Purity::SideEffecting => "side-effecting",
+        }
+    }
+}
+
+impl fmt::Display for Purity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Classification of all functions of a program by their purity.
+///
+/// Which variables are shared depends on the storage: with local storage every call has its
+/// own variables, so only `printf` and calls of impure functions make a function impure. With
+/// global storage reading a variable makes a function depend on its callers and assigning one
+/// is a side effect.
+#[derive(Debug, Clone)]
+pub struct PurityAnalysis {
+    storage: Storage,
+    functions: HashMap<String, Purity>,
+}
+
+impl PurityAnalysis {
+    /// Number of statements a single call may execute when it is evaluated at compile time
+    pub const DEFAULT_STEP_BUDGET: usize = 10_000;
+
+    pub fn analyze(program: &Program, storage: Storage) -> Self {
+        let graph = CallGraph::build(program);
+        // Like the interpreter, use the last definition of a name
+        let definitions: HashMap<&str, &FunctionDefinition> = program
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function))
+            .collect();
+        let mut functions: HashMap<String, Purity> = definitions
+            .iter()
+            .map(|(name, function)| (name.to_string(), direct_purity(function, storage)))
+            .collect();
+        // Impurity flows from callees to callers, iterate until nothing changes. Calls of
+        // undefined functions fail at runtime and count as side effects.
+        loop {
+            let mut changed = false;
+            for name in definitions.keys() {
+                let callees = graph.calls(name).iter().map(|call| {
+                    functions
+                        .get(&call.callee)
+                        .copied()
+                        .unwrap_or(Purity::SideEffecting)
+                });
+                let purity = callees.fold(functions[*name], Purity::max);
+                if purity != functions[*name] {
+                    functions.insert(name.to_string(), purity);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return PurityAnalysis { storage, functions };
+            }
+        }
+    }
+
+    /// Return the purity of a function, None if it is not defined
+    pub fn purity(&self, function: &str) -> Option<Purity> {
+        self.functions.get(function).copied()
+    }
+
+    /// Return the names of all functions with the given purity in alphabetical order
+    pub fn functions(&self, purity: Purity) -> Vec<&str> {
+        let mut functions: Vec<&str> = self
+            .functions
+            .iter()
+            .filter(|(_, p)| **p == purity)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        functions.sort_unstable();
+        functions
+    }
+}
+
+/// Purity of a function without looking at the functions it calls
+fn direct_purity(function: &FunctionDefinition, storage: Storage) -> Purity {
+    let mut purity = Purity::Pure;
+    function.walk_statements(&mut |statement| match statement {
+        Statement::Printf { .. } => purity = Purity::SideEffecting,
+        Statement::Assign { .. } if storage == Storage::Global => purity = Purity::SideEffecting,
+        _ => {}
+    });
+    if storage == Storage::Global {
+        function.walk_expressions(&mut |expression| match expression.kind {
+            ExpressionKind::Assign { .. } => purity = Purity::SideEffecting,
+            ExpressionKind::Variable(_) => purity = purity.max(Purity::ReadsGlobals),
+            _ => {}
+        });
+    }
+    purity
+}
+
+impl Program {
+    /// Return a copy of the program where calls of pure functions are replaced by their result,
+    /// which is computed by running the function. Calls used as statements are removed. A call
+    /// is kept if running the function fails, for example because it recurses forever, or
+    /// executes more than `step_budget` statements.
+    pub fn evaluate_pure_calls(&self, storage: Storage, step_budget: usize) -> Program {
+        let mut evaluator = Evaluator {
+            program: self,
+            analysis: PurityAnalysis::analyze(self, storage),
+            step_budget,
+            results: HashMap::new(),
+        };
+        Program {
+            functions: self
+                .functions
+                .iter()
+                .map(|function| FunctionDefinition {
+                    body: evaluator.statements(&function.body),
+                    ..function.clone()
+                })
+                .collect(),
+            comments: self.comments.clone(),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    program: &'a Program,
+    analysis: PurityAnalysis,
+    step_budget: usize,
+    /// Result of each evaluated function, Err if its evaluation failed
+    results: HashMap<String, Result<Option<Value>, ()>>,
+}
+
+impl Evaluator<'_> {
+    /// Run a pure function, Err if it is not pure or fails
+    fn evaluate(&mut self, name: &str) -> Result<Option<Value>, ()> {
+        if self.analysis.purity(name) != Some(Purity::Pure) {
+            return Err(());
+        }
+        if let Some(result) = self.results.get(name) {
+            return *result;
+        }
+        let mut interpreter = Interpreter::new(self.program);
+        interpreter.storage = self.analysis.storage;
+        interpreter.max_steps = Some(self.step_budget);
+        let result = interpreter.call_function(name).map_err(|_| ());
+        self.results.insert(name.to_string(), result);
+        result
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Vec<Statement> {
+        statements
+            .iter()
+            .filter_map(|statement| self.statement(statement))
+            .collect()
+    }
+
+    /// Rewrite a statement, None if it is a call without effect
+    fn statement(&mut self, statement: &Statement) -> Option<Statement> {
+        let rewritten = match statement {
+            Statement::Block { statements, line } => Statement::Block {
+                statements: self.statements(statements),
+                line: *line,
+            },
+            Statement::If {
+                condition,
+                then_branch,
+                line,
+            } => Statement::If {
+                condition: self.expression(condition),
+                then_branch: Box::new(self.branch(then_branch)),
+                line: *line,
+            },
+            Statement::Return { value, line } => Statement::Return {
+                value: value.as_ref().map(|value| self.expression(value)),
+                line: *line,
+            },
+            Statement::Printf { value, line } => Statement::Printf {
+                value: self.expression(value),
+                line: *line,
+            },
+            Statement::Assign {
+                target,
+                value,
+                line,
+            } => Statement::Assign {
+                target: target.clone(),
+                value: self.expression(value),
+                line: *line,
+            },
+            Statement::Call { name, .. } => {
+                if self.evaluate(name).is_ok() {
+                    return None;
+                }
+                statement.clone()
+            }
+        };
+        Some(rewritten)
+    }
+
+    /// Rewrite the branch of an `if` statement, a removed call becomes an empty block
+    fn branch(&mut self, branch: &Statement) -> Statement {
+        self.statement(branch).unwrap_or(Statement::Block {
+            statements: Vec::new(),
+            line: branch.line(),
+        })
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Expression {
+        let line = expression.line;
+        let kind = match &expression.kind {
+            ExpressionKind::Call(name) => {
+                return match self.evaluate(name) {
+                    Ok(Some(value)) => literal(value, line).unwrap_or_else(|| expression.clone()),
+                    _ => expression.clone(),
+                };
+            }
+            ExpressionKind::Assign { target, value } => ExpressionKind::Assign {
+                target: target.clone(),
+                value: Box::new(self.expression(value)),
+            },
+            ExpressionKind::Unary { op, operand } => ExpressionKind::Unary {
+                op: *op,
+                operand: Box::new(self.expression(operand)),
+            },
+            ExpressionKind::Binary { op, lhs, rhs } => ExpressionKind::Binary {
+                op: *op,
+                lhs: Box::new(self.expression(lhs)),
+                rhs: Box::new(self.expression(rhs)),
+            },
+            _ => return expression.clone(),
+        };
+        Expression::new(kind, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::purity::{Purity, PurityAnalysis};
+    use crate::{C1Parser, Interpreter};
+
+    #[test]
+    fn functions_are_classified_by_their_effects() {
+        let program = C1Parser::parse_program(include_str!("../tests/data/beispiel.c-1")).unwrap();
+        let local = PurityAnalysis::analyze(&program, Storage::Local);
+        assert_eq!(local.functions(Purity::Pure), vec!["blub"]);
+        assert_eq!(local.functions(Purity::SideEffecting), vec!["blah", "main"]);
+
+        let program = C1Parser::parse_program(
+            "int get() { return value; }
+             int twice() { return get() * 2; }
+             void set() { value = 2; }
+             int missing() { return nothing(); }
+             void main() { set(); printf(twice()); }",
+        )
+        .unwrap();
+        let global = PurityAnalysis::analyze(&program, Storage::Global);
+        assert_eq!(global.purity("twice"), Some(Purity::ReadsGlobals));
+        assert_eq!(global.purity("set"), Some(Purity::SideEffecting));
+        assert_eq!(global.purity("missing"), Some(Purity::SideEffecting));
+        assert_eq!(global.purity("undefined"), None);
+        let local = PurityAnalysis::analyze(&program, Storage::Local);
+        assert_eq!(local.functions(Purity::Pure), vec!["get", "set", "twice"]);
+    }
+
+    #[test]
+    fn pure_calls_are_replaced_by_their_results() {
+        let text = "int blub() { a = 6; return a * 7; }
+                    float half() { return 1 / 2.0; }
+                    int forever() { return forever(); }
+                    void nothing() { x = blub(); }
+                    void main() {
+                        nothing();
+                        if (blub() > 40) printf(half());
+                        printf(forever());
+                    }";
+        let program = C1Parser::parse_program(text).unwrap();
+        let evaluated =
+            program.evaluate_pure_calls(Storage::Local, PurityAnalysis::DEFAULT_STEP_BUDGET);
+        let main = evaluated.function("main").unwrap();
+        let statements: Vec<String> = main.body.iter().map(|s| s.to_string()).collect();
+        assert_eq!(statements, vec!["if (42 > 40)", "printf(forever());"]);
+        assert_eq!(
+            evaluated.function("nothing").unwrap().body[0].to_string(),
+            "x = 42;"
+        );
+        // With global storage the assignments make `blub` impure
+        let evaluated =
+            program.evaluate_pure_calls(Storage::Global, PurityAnalysis::DEFAULT_STEP_BUDGET);
+        assert_eq!(
+            evaluated.function("main").unwrap().body[1].to_string(),
+            "if (blub() > 40)"
+        );
+        let mut interpreter = Interpreter::new(&evaluated);
+        interpreter.storage = Storage::Global;
+        assert_eq!(
+            interpreter.run().unwrap_err().to_string(),
+            "Line 3: maximum call depth of 256 exceeded in call of `forever`"
+        );
+    }
+
+    #[test]
+    fn evaluation_is_bounded_by_the_step_budget() {
+        // f0() makes 2^16 calls
+        let mut text: String = (0..16)
+            .map(|i| format!("int f{}() {{ return f{}() + f{}(); }}\n", i, i + 1, i + 1))
+            .collect();
+        text.push_str("int f16() { return 1; }\nvoid main() { printf(f0()); }");
+        let program = C1Parser::parse_program(&text).unwrap();
+        let printed = |step_budget| {
+            let evaluated = program.evaluate_pure_calls(Storage::Local, step_budget);
+            evaluated.function("main").unwrap().body[0].to_string()
+        };
+        assert_eq!(
+            printed(PurityAnalysis::DEFAULT_STEP_BUDGET),
+            "printf(f0());"
+        );
+        assert_eq!(printed(1_000_000), "printf(65536);");
+    }
+}
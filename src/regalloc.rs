@@ -0,0 +1,377 @@
+use crate::ast::Type;
+use crate::cfg::BlockId;
+use crate::ir::{Instruction, InstructionKind, IrFunction, Operand, Place};
+use crate::ssa::FlowGraph;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Kind of machine register a value needs
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub enum RegisterClass {
+    /// Registers for `int` and `bool` values
+    General,
+    Float,
+}
+
+impl RegisterClass {
+    pub fn of(value_type: Type) -> Self {
+        match value_type {
+            Type::Float => RegisterClass::Float,
+            _ => RegisterClass::General,
+        }
+    }
+}
+
+/// Number of registers the allocator may use for each class. General registers keep their
+/// values across calls, float registers do not, like the callee-saved registers and the SSE
+/// registers of the System V ABI.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct RegisterSet {
+    pub general: usize,
+    pub float: usize,
+}
+
+impl RegisterSet {
+    pub fn count(&self, class: RegisterClass) -> usize {
+        match class {
+            RegisterClass::General => self.general,
+            RegisterClass::Float => self.float,
+        }
+    }
+}
+
+/// Places that are live at the start and at the end of every basic block, which means that
+/// their current value may still be read
+#[derive(Debug, PartialEq, Clone)]
+pub struct Liveness {
+    live_in: Vec<BTreeSet<Place>>,
+    live_out: Vec<BTreeSet<Place>>,
+}
+
+impl Liveness {
+    /// Solve the backward data-flow equations for a function in the block form of
+    /// `IrProgram::from_ssa`. Phis are not supported.
+    pub fn compute(function: &IrFunction, graph: &FlowGraph) -> Self {
+        let count = graph.blocks.len();
+        // Places read before they are assigned in the block, and places assigned in the block
+        let mut uses = vec![BTreeSet::new(); count];
+        let mut assigned = vec![BTreeSet::new(); count];
+        for (id, block) in graph.blocks.iter().enumerate() {
+            for instruction in &function.instructions[block.instructions.clone()] {
+                debug_assert!(!matches!(instruction.kind, InstructionKind::Phi { .. }));
+                for place in used_places(instruction) {
+                    if !assigned[id].contains(place) {
+                        uses[id].insert(place.clone());
+                    }
+                }
+                if let Some(target) = instruction.target() {
+                    assigned[id].insert(target.clone());
+                }
+            }
+        }
+
+        let mut live_in: Vec<BTreeSet<Place>> = uses.clone();
+        let mut live_out: Vec<BTreeSet<Place>> = vec![BTreeSet::new(); count];
+        let mut order = graph.reverse_postorder();
+        order.reverse();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order {
+                let out: BTreeSet<Place> = graph.blocks[block]
+                    .successors
+                    .iter()
+                    .flat_map(|successor| live_in[*successor].iter().cloned())
+                    .collect();
+                let mut inside = uses[block].clone();
+                inside.extend(
+                    out.iter()
+                        .filter(|place| !assigned[block].contains(*place))
+                        .cloned(),
+                );
+                if inside != live_in[block] || out != live_out[block] {
+                    live_in[block] = inside;
+                    live_out[block] = out;
+                    changed = true;
+                }
+            }
+        }
+        Liveness { live_in, live_out }
+    }
+
+    pub fn live_in(&self, block: BlockId) -> &BTreeSet<Place> {
+        &self.live_in[block]
+    }
+
+    pub fn live_out(&self, block: BlockId) -> &BTreeSet<Place> {
+        &self.live_out[block]
+    }
+}
+
+/// The positions of the instructions from the first to the last one at which a place may hold
+/// a value that is still needed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LiveInterval {
+    pub place: Place,
+    pub class: RegisterClass,
+    pub start: usize,
+    pub end: usize,
+    /// Whether a call happens while the value is live, which ends the life of float registers
+    pub crosses_call: bool,
+}
+
+/// Where the allocator put a place
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Location {
+    /// Index of a register within its class
+    Register(usize),
+    /// Index of a stack slot of the function
+    Stack(usize),
+}
+
+/// Result of the register allocation for a function
+#[derive(Debug, PartialEq, Clone)]
+pub struct Allocation {
+    pub locations: BTreeMap<Place, Location>,
+    pub stack_slots: usize,
+    pub intervals: Vec<LiveInterval>,
+    /// Places that are live at the start of the function, which are read before they are
+    /// assigned on some path
+    pub live_at_entry: BTreeSet<Place>,
+}
+
+impl Allocation {
+    /// Allocate registers for the temporaries and versions of a function in the block form of
+    /// `IrProgram::from_ssa` with the linear scan of Poletto and Sarkar. Intervals are handled
+    /// in order of their start. If no register is free, the interval that ends last is
+    /// spilled to a stack slot. Float values that live across a call always get a stack slot.
+    /// Variables are only left with global storage and stay in memory.
+    pub fn linear_scan(function: &IrFunction, registers: RegisterSet) -> Self {
+        let graph = FlowGraph::build(function);
+        let liveness = Liveness::compute(function, &graph);
+        let intervals = live_intervals(function, &graph, &liveness);
+
+        let mut locations = BTreeMap::new();
+        let mut stack_slots = 0;
+        let mut spill = |locations: &mut BTreeMap<Place, Location>, place: &Place| {
+            locations.insert(place.clone(), Location::Stack(stack_slots));
+            stack_slots += 1;
+        };
+        // Intervals that currently hold a register, and the free registers of every class
+        let mut active: Vec<&LiveInterval> = Vec::new();
+        let mut free: BTreeMap<RegisterClass, BTreeSet<usize>> =
+            [RegisterClass::General, RegisterClass::Float]
+                .into_iter()
+                .map(|class| (class, (0..registers.count(class)).collect()))
+                .collect();
+        for interval in &intervals {
+            active.retain(|other| {
+                let expired = other.end < interval.start;
+                if expired {
+                    if let Some(Location::Register(register)) = locations.get(&other.place) {
+                        free.get_mut(&other.class).unwrap().insert(*register);
+                    }
+                }
+                !expired
+            });
+            if interval.class == RegisterClass::Float && interval.crosses_call {
+                spill(&mut locations, &interval.place);
+                continue;
+            }
+            let free = free.get_mut(&interval.class).unwrap();
+            if let Some(register) = free.pop_first() {
+                locations.insert(interval.place.clone(), Location::Register(register));
+                active.push(interval);
+                continue;
+            }
+            let victim = active
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.class == interval.class)
+                .max_by_key(|(_, other)| other.end);
+            match victim {
+                Some((index, other)) if other.end > interval.end => {
+                    let register = locations[&other.place];
+                    spill(&mut locations, &other.place);
+                    locations.insert(interval.place.clone(), register);
+                    active[index] = interval;
+                }
+                _ => spill(&mut locations, &interval.place),
+            }
+        }
+
+        let live_at_entry = match graph.blocks.is_empty() {
+            true => BTreeSet::new(),
+            false => liveness
+                .live_in(FlowGraph::ENTRY)
+                .iter()
+                .filter(|place| locations.contains_key(*place))
+                .cloned()
+                .collect(),
+        };
+        Allocation {
+            locations,
+            stack_slots,
+            intervals,
+            live_at_entry,
+        }
+    }
+}
+
+/// Compute one interval for every temporary and version, sorted by start. Each interval covers
+/// the assignments and uses of its place and every block in which the place is live.
+fn live_intervals(
+    function: &IrFunction,
+    graph: &FlowGraph,
+    liveness: &Liveness,
+) -> Vec<LiveInterval> {
+    let mut ranges: BTreeMap<Place, (usize, usize)> = BTreeMap::new();
+    let mut cover = |place: &Place, position: usize| {
+        if matches!(place, Place::Variable(_)) {
+            return;
+        }
+        let range = ranges.entry(place.clone()).or_insert((position, position));
+        range.0 = range.0.min(position);
+        range.1 = range.1.max(position);
+    };
+    for (id, block) in graph.blocks.iter().enumerate() {
+        for place in liveness.live_in(id) {
+            cover(place, block.instructions.start);
+        }
+        for place in liveness.live_out(id) {
+            cover(place, block.instructions.end - 1);
+        }
+    }
+    let places = function
+        .instructions
+        .iter()
+        .enumerate()
+        .flat_map(|(position, instruction)| {
+            used_places(instruction)
+                .chain(instruction.target())
+                .map(move |place| (place, position))
+        });
+    for (place, position) in places {
+        // Version 0 is never assigned and read as zero
+        if !matches!(place, Place::Version(_, 0)) {
+            cover(place, position);
+        }
+    }
+
+    let calls: Vec<usize> = function
+        .instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| {
+            matches!(
+                instruction.kind,
+                InstructionKind::Call { .. } | InstructionKind::Print(_)
+            )
+        })
+        .map(|(position, _)| position)
+        .collect();
+    let mut intervals: Vec<LiveInterval> = ranges
+        .into_iter()
+        .map(|(place, (start, end))| LiveInterval {
+            class: RegisterClass::of(function.types[&place]),
+            place,
+            start,
+            end,
+            crosses_call: calls.iter().any(|call| start < *call && *call < end),
+        })
+        .collect();
+    intervals.sort_by_key(|interval| interval.start);
+    intervals
+}
+
+fn used_places(instruction: &Instruction) -> impl Iterator<Item = &Place> {
+    instruction
+        .operands()
+        .into_iter()
+        .filter_map(|operand| match operand {
+            Operand::Place(place) => Some(place),
+            Operand::Constant(_) => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::regalloc::{Allocation, Liveness, Location, RegisterClass, RegisterSet};
+    use crate::ssa::FlowGraph;
+    use crate::{C1Parser, Place};
+
+    #[test]
+    fn liveness_flows_backwards() {
+        let program =
+            C1Parser::parse_program("int main() { a = 1; b = 2; if (a < 3) printf(b); return a; }")
+                .unwrap();
+        let ir = program
+            .lower(Storage::Local)
+            .unwrap()
+            .to_ssa()
+            .from_ssa()
+            .unwrap();
+        let function = &ir.functions[0];
+        let graph = FlowGraph::build(function);
+        let liveness = Liveness::compute(function, &graph);
+        let version = |name: &str| Place::Version(Box::new(Place::Variable(name.into())), 1);
+        assert!(liveness.live_in(FlowGraph::ENTRY).is_empty());
+        let live_out: Vec<&Place> = liveness.live_out(FlowGraph::ENTRY).iter().collect();
+        assert_eq!(live_out, [&version("a"), &version("b")]);
+        // `b` is dead after it is printed
+        let then_block = graph.blocks[FlowGraph::ENTRY].successors[0];
+        let live_out: Vec<&Place> = liveness.live_out(then_block).iter().collect();
+        assert_eq!(live_out, [&version("a")]);
+    }
+
+    #[test]
+    fn values_are_spilled_when_registers_run_out() {
+        let program = C1Parser::parse_program(
+            "float main() { a = 1; b = 2; c = 3; x = 0.5; printf(a + b + c); return x; }",
+        )
+        .unwrap();
+        let ir = program
+            .lower(Storage::Local)
+            .unwrap()
+            .to_ssa()
+            .from_ssa()
+            .unwrap();
+        let function = &ir.functions[0];
+
+        let plenty = RegisterSet {
+            general: 8,
+            float: 8,
+        };
+        let allocation = Allocation::linear_scan(function, plenty);
+        assert_eq!(allocation.stack_slots, 1, "{:?}", allocation);
+        // The float lives across printf
+        let x = Place::Version(Box::new(Place::Variable("x".into())), 1);
+        assert_eq!(allocation.locations[&x], Location::Stack(0));
+
+        let scarce = RegisterSet {
+            general: 2,
+            float: 8,
+        };
+        let allocation = Allocation::linear_scan(function, scarce);
+        let general = |location: &Location| {
+            allocation.intervals.iter().any(|interval| {
+                interval.class == RegisterClass::General
+                    && allocation.locations[&interval.place] == *location
+            })
+        };
+        assert!(general(&Location::Register(1)) && !general(&Location::Register(2)));
+        assert_eq!(allocation.stack_slots, 3, "{:?}", allocation);
+        // No two overlapping intervals share a register
+        for a in &allocation.intervals {
+            for b in &allocation.intervals {
+                let overlap = a.start <= b.end && b.start <= a.end;
+                if a != b && a.class == b.class && overlap {
+                    assert_ne!(
+                        allocation.locations[&a.place],
+                        allocation.locations[&b.place]
+                    );
+                }
+            }
+        }
+    }
+}
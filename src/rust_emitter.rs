@@ -0,0 +1,992 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::backend::{always_returns, may_miss_result, EmitError, TypedProgram};
+use crate::config::Storage;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+/// Functions of the generated Rust code that implement the semantics of C(-1) where they differ
+/// from Rust. Each of them is only written if the program needs it, so that rustc does not warn
+/// about unused code.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+enum Helper {
+    Error,
+    Div,
+    Result,
+    Float,
+}
+
+impl Helper {
+    fn code(&self) -> &'static str {
+        match self {
+            Helper::Error => {
+                r#"fn c1_error(line: u32, message: &str) -> ! {
+    eprintln!("Line {}: {}", line, message);
+    std::process::exit(1);
+}
+"#
+            }
+            Helper::Div => {
+                r#"fn c1_div(lhs: i32, rhs: i32, line: u32) -> i32 {
+    if rhs == 0 {
+        c1_error(line, "division by zero");
+    }
+    lhs.wrapping_div(rhs)
+}
+"#
+            }
+            Helper::Result => {
+                r#"/// Fail if a function ended without a result that is used
+fn c1_result<T>(result: Option<T>, function: &str, line: u32) -> T {
+    match result {
+        Some(value) => value,
+        None => c1_error(line, &format!("function `{}` did not return a value", function)),
+    }
+}
+"#
+            }
+            Helper::Float => {
+                r#"/// Rust prints NaN as `NaN`, C(-1) as `nan`
+fn c1_float(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else {
+        format!("{:.6}", value)
+    }
+}
+"#
+            }
+        }
+    }
+}
+
+impl Program {
+    /// Translate the program into a standalone Rust source file that compiles without warnings.
+    ///
+    /// Every function becomes a `fn`, with a `f_` prefix, and variables get a `v_` prefix, so
+    /// that no name clashes with Rust keywords. With local storage variables are `let` bindings
+    /// with the type of the values assigned to them, which are only `mut` and start as zero
+    /// where this is needed. With global storage they are fields of a `Globals` struct that is
+    /// passed to the functions that use it. Stores whose value is never read are left out and
+    /// so is code after a `return`. int arithmetic uses the wrapping operations of `i32`,
+    /// `printf` becomes `println!` and functions that may end without a result return an
+    /// `Option`. The exit status of the Rust program is the value returned by `main`, runtime
+    /// errors are printed to stderr and exit with status 1.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn emit_rust(&self, storage: Storage, source_name: &str) -> Result<String, EmitError> {
+        let typed = TypedProgram::check(self, storage)?;
+        let mut emitter = RustEmitter::new(&typed);
+        let functions = emitter.functions();
+
+        let mut output = String::new();
+        writeln!(
+            output,
+            "// Generated from {}",
+            source_name.replace('\n', " ")
+        )
+        .unwrap();
+        for helper in &emitter.helpers {
+            writeln!(output, "\n{}", helper.code().trim_end()).unwrap();
+        }
+        if !emitter.globals.is_empty() {
+            output.push('\n');
+            if !emitter.globals.iter().all(|(name, _)| is_snake_case(name)) {
+                writeln!(output, "#[allow(non_snake_case)]").unwrap();
+            }
+            writeln!(output, "#[derive(Default)]\nstruct Globals {{").unwrap();
+            for (name, variable_type) in &emitter.globals {
+                writeln!(
+                    output,
+                    "    {}: {},",
+                    variable(name),
+                    rust_type(*variable_type)
+                )
+                .unwrap();
+            }
+            writeln!(output, "}}").unwrap();
+        }
+        output.push_str(&functions);
+        output.push_str(&emitter.main());
+        Ok(output)
+    }
+}
+
+/// Rust code of an expression together with its C(-1) type
+struct Code {
+    text: String,
+    value_type: Type,
+    /// Whether the code can be an operand without parentheses
+    atomic: bool,
+}
+
+impl Code {
+    fn new(text: String, value_type: Type, atomic: bool) -> Self {
+        Code {
+            text,
+            value_type,
+            atomic,
+        }
+    }
+
+    /// Return the code as the operand of an operator
+    fn operand(&self) -> String {
+        match self.atomic {
+            true => self.text.clone(),
+            false => format!("({})", self.text),
+        }
+    }
+}
+
+struct RustEmitter<'a> {
+    typed: &'a TypedProgram<'a>,
+    /// Global variables that are read somewhere, stores to the others are left out
+    globals: Vec<(String, Type)>,
+    /// Functions that access the globals, directly or through the functions they call
+    uses_globals: HashSet<&'a str>,
+    /// Functions that `main` calls, directly or indirectly, or `main` itself
+    called: HashSet<&'a str>,
+    helpers: BTreeSet<Helper>,
+    /// Variables of the function that is being translated and their analysis
+    variables: Vec<(String, Type)>,
+    bindings: Bindings,
+}
+
+impl<'a> RustEmitter<'a> {
+    fn new(typed: &'a TypedProgram<'a>) -> Self {
+        let program = typed.program;
+        let mut read = HashSet::new();
+        for function in &program.functions {
+            function.walk_expressions(&mut |expression| {
+                if let ExpressionKind::Variable(name) = &expression.kind {
+                    read.insert(name.as_str());
+                }
+            });
+        }
+        let globals: Vec<(String, Type)> = typed
+            .globals()
+            .iter()
+            .filter(|(name, _)| read.contains(name.as_str()))
+            .cloned()
+            .collect();
+
+        let mut uses_globals: HashSet<&str> = HashSet::new();
+        if !globals.is_empty() {
+            for function in &program.functions {
+                // Every variable that is read is a field of the globals
+                let field = |name: &str| globals.iter().any(|(global, _)| global == name);
+                let mut direct = false;
+                function.walk_statements(&mut |statement| {
+                    direct |=
+                        matches!(statement, Statement::Assign { target, .. } if field(target));
+                });
+                function.walk_expressions(&mut |expression| {
+                    direct |= match &expression.kind {
+                        ExpressionKind::Variable(_) => true,
+                        ExpressionKind::Assign { target, .. } => field(target),
+                        _ => false,
+                    };
+                });
+                if direct {
+                    uses_globals.insert(&function.name);
+                }
+            }
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for function in &program.functions {
+                    if !uses_globals.contains(function.name.as_str())
+                        && callees(function).any(|callee| uses_globals.contains(callee))
+                    {
+                        uses_globals.insert(&function.name);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut called = HashSet::from(["main"]);
+        let mut pending = vec!["main"];
+        while let Some(name) = pending.pop() {
+            for callee in callees(typed.function(name)) {
+                if called.insert(callee) {
+                    pending.push(callee);
+                }
+            }
+        }
+
+        RustEmitter {
+            typed,
+            globals,
+            uses_globals,
+            called,
+            helpers: BTreeSet::new(),
+            variables: Vec::new(),
+            bindings: Bindings::default(),
+        }
+    }
+
+    fn functions(&mut self) -> String {
+        let mut output = String::new();
+        for function in &self.typed.program.functions {
+            (self.variables, self.bindings) = match self.typed.storage {
+                Storage::Local => (
+                    self.typed.locals(&function.name).to_vec(),
+                    Bindings::local(function),
+                ),
+                Storage::Global => (
+                    self.globals.clone(),
+                    Bindings::global(function, &self.globals),
+                ),
+            };
+            output.push_str(&self.function(function));
+        }
+        output
+    }
+
+    fn function(&mut self, function: &FunctionDefinition) -> String {
+        let locals: Vec<&(String, Type)> = self
+            .typed
+            .locals(&function.name)
+            .iter()
+            .filter(|(name, _)| self.bindings.declared.contains(name))
+            .collect();
+
+        let mut output = String::from("\n");
+        let mut allowed = Vec::new();
+        if !self.called.contains(function.name.as_str()) {
+            allowed.push("dead_code");
+        }
+        if !is_snake_case(&function.name) || !locals.iter().all(|(name, _)| is_snake_case(name)) {
+            allowed.push("non_snake_case");
+        }
+        if !allowed.is_empty() {
+            writeln!(output, "#[allow({})]", allowed.join(", ")).unwrap();
+        }
+        let parameters = match self.uses_globals.contains(function.name.as_str()) {
+            true => "g: &mut Globals",
+            false => "",
+        };
+        let result = match function.return_type {
+            Type::Void => String::new(),
+            _ if may_miss_result(function) => {
+                format!(" -> Option<{}>", rust_type(function.return_type))
+            }
+            return_type => format!(" -> {}", rust_type(return_type)),
+        };
+        writeln!(
+            output,
+            "fn f_{}({}){} {{",
+            function.name, parameters, result
+        )
+        .unwrap();
+
+        for (name, variable_type) in locals {
+            let binding = match self.bindings.mutable.contains(name) {
+                true => "let mut",
+                false => "let",
+            };
+            match self.bindings.zeroed.contains(name) {
+                true => writeln!(
+                    output,
+                    "    {} {}: {} = {};",
+                    binding,
+                    variable(name),
+                    rust_type(*variable_type),
+                    zero(*variable_type)
+                ),
+                false => writeln!(
+                    output,
+                    "    {} {}: {};",
+                    binding,
+                    variable(name),
+                    rust_type(*variable_type)
+                ),
+            }
+            .unwrap();
+        }
+        self.statements(&mut output, function, &function.body, 1);
+        if function.return_type != Type::Void && !always_returns(&function.body) {
+            writeln!(output, "    None").unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+        output
+    }
+
+    /// Translate the statements up to the first one that always returns. Blocks are merged into
+    /// the enclosing statements, since all variables belong to the whole function.
+    fn statements(
+        &mut self,
+        output: &mut String,
+        function: &FunctionDefinition,
+        statements: &[Statement],
+        indent: usize,
+    ) {
+        for statement in reachable(statements) {
+            self.statement(output, function, statement, indent);
+        }
+    }
+
+    fn statement(
+        &mut self,
+        output: &mut String,
+        function: &FunctionDefinition,
+        statement: &Statement,
+        indent: usize,
+    ) {
+        let padding = "    ".repeat(indent);
+        match statement {
+            Statement::Block { statements, .. } => {
+                self.statements(output, function, statements, indent)
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                let condition = self.expression(condition);
+                writeln!(output, "{}if {} {{", padding, truth(condition).text).unwrap();
+                self.branch(output, function, then_branch, indent + 1);
+                writeln!(output, "{}}}", padding).unwrap();
+            }
+            Statement::Return { value: None, .. } if function.return_type != Type::Void => {
+                writeln!(output, "{}return None;", padding).unwrap();
+            }
+            Statement::Return { value: None, .. } => {
+                writeln!(output, "{}return;", padding).unwrap();
+            }
+            Statement::Return {
+                value: Some(value), ..
+            } => {
+                let value = convert(self.expression(value), function.return_type).text;
+                match may_miss_result(function) {
+                    true => writeln!(output, "{}return Some({});", padding, value),
+                    false => writeln!(output, "{}return {};", padding, value),
+                }
+                .unwrap();
+            }
+            Statement::Printf { value, .. } => {
+                let value = self.expression(value);
+                if value.value_type == Type::Float {
+                    self.helpers.insert(Helper::Float);
+                    writeln!(
+                        output,
+                        "{}println!(\"{{}}\", c1_float({}));",
+                        padding, value.text
+                    )
+                } else {
+                    writeln!(output, "{}println!(\"{{}}\", {});", padding, value.text)
+                }
+                .unwrap();
+            }
+            Statement::Assign { target, value, .. } => {
+                if !self.bindings.is_dead(value) {
+                    let value = self.expression(value).text;
+                    writeln!(output, "{}{} = {};", padding, self.place(target), value).unwrap();
+                } else if has_effects(value) {
+                    let value = self.expression(value).text;
+                    writeln!(output, "{}let _ = {};", padding, value).unwrap();
+                }
+            }
+            Statement::Call { name, .. } => {
+                writeln!(output, "{}{};", padding, self.call(name)).unwrap();
+            }
+        }
+    }
+
+    /// Translate the branch of an if statement, whose braces are already written
+    fn branch(
+        &mut self,
+        output: &mut String,
+        function: &FunctionDefinition,
+        branch: &Statement,
+        indent: usize,
+    ) {
+        self.statements(output, function, std::slice::from_ref(branch), indent);
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Code {
+        match &expression.kind {
+            ExpressionKind::Int(i32::MIN) => Code::new("i32::MIN".to_string(), Type::Int, true),
+            ExpressionKind::Int(value) => Code::new(value.to_string(), Type::Int, *value >= 0),
+            ExpressionKind::Float(value) => Code::new(
+                float_literal(*value),
+                Type::Float,
+                !value.is_sign_negative() || value.is_infinite(),
+            ),
+            ExpressionKind::Bool(value) => Code::new(value.to_string(), Type::Bool, true),
+            ExpressionKind::Variable(name) => {
+                let value_type = self.variable_type(name);
+                Code::new(self.place(name), value_type, true)
+            }
+            ExpressionKind::Call(name) => {
+                let callee = self.typed.function(name);
+                let call = self.call(name);
+                let text = match may_miss_result(callee) {
+                    true => {
+                        self.helpers.insert(Helper::Error);
+                        self.helpers.insert(Helper::Result);
+                        format!("c1_result({}, \"{}\", {})", call, name, expression.line)
+                    }
+                    false => call,
+                };
+                Code::new(text, callee.return_type, true)
+            }
+            ExpressionKind::Assign { target, value } if self.bindings.is_dead(value) => {
+                self.expression(value)
+            }
+            ExpressionKind::Assign { target, value } => {
+                let value = self.expression(value);
+                let place = self.place(target);
+                let text = format!("{{ {} = {}; {} }}", place, value.text, place);
+                Code::new(text, value.value_type, true)
+            }
+            ExpressionKind::Unary { operand, .. } => {
+                let operand = self.expression(operand);
+                match operand.value_type {
+                    Type::Float => Code::new(format!("-{}", operand.operand()), Type::Float, false),
+                    Type::Bool => Code::new(
+                        format!("-{}", promote(operand, Type::Int).text),
+                        Type::Int,
+                        false,
+                    ),
+                    _ => Code::new(
+                        format!("i32::wrapping_neg({})", operand.text),
+                        Type::Int,
+                        true,
+                    ),
+                }
+            }
+            ExpressionKind::Binary { op, lhs, rhs } if op.is_logical() => {
+                let lhs = truth(self.expression(lhs)).operand();
+                let rhs = truth(self.expression(rhs)).operand();
+                Code::new(
+                    format!("{} {} {}", lhs, op.symbol(), rhs),
+                    Type::Bool,
+                    false,
+                )
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expression(lhs);
+                let rhs = self.expression(rhs);
+                let target = match lhs.value_type == Type::Float || rhs.value_type == Type::Float {
+                    true => Type::Float,
+                    false => Type::Int,
+                };
+                let lhs = promote(lhs, target);
+                let rhs = promote(rhs, target);
+                let function = match op {
+                    BinaryOp::Add => "i32::wrapping_add",
+                    BinaryOp::Sub => "i32::wrapping_sub",
+                    BinaryOp::Mul => "i32::wrapping_mul",
+                    _ => "",
+                };
+                match op {
+                    _ if target == Type::Float || op.is_comparison() => {
+                        let value_type = match op.is_comparison() {
+                            true => Type::Bool,
+                            false => Type::Float,
+                        };
+                        let text = format!("{} {} {}", lhs.operand(), op.symbol(), rhs.operand());
+                        Code::new(text, value_type, false)
+                    }
+                    BinaryOp::Div => {
+                        self.helpers.insert(Helper::Error);
+                        self.helpers.insert(Helper::Div);
+                        let text =
+                            format!("c1_div({}, {}, {})", lhs.text, rhs.text, expression.line);
+                        Code::new(text, Type::Int, true)
+                    }
+                    _ => Code::new(
+                        format!("{}({}, {})", function, lhs.text, rhs.text),
+                        Type::Int,
+                        true,
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Return the call of a function without the check of its result
+    fn call(&self, name: &str) -> String {
+        match self.uses_globals.contains(name) {
+            true => format!("f_{}(g)", name),
+            false => format!("f_{}()", name),
+        }
+    }
+
+    /// Return where a variable is stored
+    fn place(&self, name: &str) -> String {
+        match self.typed.storage {
+            Storage::Local => variable(name),
+            Storage::Global => format!("g.{}", variable(name)),
+        }
+    }
+
+    fn variable_type(&self, name: &str) -> Type {
+        self.variables
+            .iter()
+            .find(|(known, _)| known == name)
+            .map(|(_, variable_type)| *variable_type)
+            .expect("variables that are read are checked")
+    }
+
+    /// Return the entry point, which calls `f_main` and exits with its result
+    fn main(&self) -> String {
+        let main = self.typed.function("main");
+        let mut output = String::from("\nfn main() {\n");
+        let mut call = String::from("f_main()");
+        if self.uses_globals.contains("main") {
+            writeln!(output, "    let mut globals = Globals::default();").unwrap();
+            call = String::from("f_main(&mut globals)");
+        }
+        if may_miss_result(main) {
+            // A missing result of main is not an error, the exit status is zero then
+            call = format!("{}.unwrap_or_default()", call);
+        }
+        let exit_code = match main.return_type {
+            Type::Void => None,
+            Type::Int => Some(call.clone()),
+            Type::Float => Some(format!("{} as i32", call)),
+            Type::Bool => Some(format!("i32::from({})", call)),
+        };
+        match exit_code {
+            Some(exit_code) => writeln!(output, "    std::process::exit({});", exit_code),
+            None => writeln!(output, "    {};", call),
+        }
+        .unwrap();
+        writeln!(output, "}}").unwrap();
+        output
+    }
+}
+
+/// Declarations of the local variables of a function and the stores that are left out, found by
+/// data-flow analyses of its body. They follow the rules of rustc, so that rustc neither rejects
+/// a read of a variable that may be uninitialized nor warns about unused values or `mut`.
+#[derive(Default)]
+struct Bindings {
+    /// Variables that need a binding
+    declared: HashSet<String>,
+    /// Variables that are read on some path before they are assigned, which start as zero
+    zeroed: HashSet<String>,
+    /// Variables that are assigned when they may already hold a value
+    mutable: HashSet<String>,
+    /// Assigned values that are never read, identified by their address in the program
+    dead: HashSet<*const Expression>,
+}
+
+impl Bindings {
+    fn local(function: &FunctionDefinition) -> Self {
+        let mut bindings = Bindings::default();
+        let mut live = HashSet::new();
+        bindings.live_statements(&function.body, &mut live);
+        let mut state = Assigned::default();
+        bindings.assign_statements(&function.body, &mut state);
+        bindings.declared = &bindings.zeroed | &state.stored;
+        for name in &bindings.zeroed {
+            if state.stored.contains(name) {
+                bindings.mutable.insert(name.clone());
+            }
+        }
+        bindings
+    }
+
+    /// Only stores to globals that are never read are left out
+    fn global(function: &FunctionDefinition, globals: &[(String, Type)]) -> Self {
+        let mut dead = HashSet::new();
+        let mut store = |target: &str, value: &Expression| {
+            if !globals.iter().any(|(name, _)| name == target) {
+                dead.insert(value as *const Expression);
+            }
+        };
+        function.walk_statements(&mut |statement| {
+            if let Statement::Assign { target, value, .. } = statement {
+                store(target, value);
+            }
+        });
+        function.walk_expressions(&mut |expression| {
+            if let ExpressionKind::Assign { target, value } = &expression.kind {
+                store(target, value);
+            }
+        });
+        Bindings {
+            dead,
+            ..Bindings::default()
+        }
+    }
+
+    fn is_dead(&self, value: &Expression) -> bool {
+        self.dead.contains(&(value as *const Expression))
+    }
+
+    /// Find the dead stores by going backwards through the statements, `live` holds the
+    /// variables whose value may still be read
+    fn live_statements(&mut self, statements: &[Statement], live: &mut HashSet<String>) {
+        for statement in reachable(statements).iter().rev() {
+            match statement {
+                Statement::Block { statements, .. } => self.live_statements(statements, live),
+                Statement::If {
+                    condition,
+                    then_branch,
+                    ..
+                } => {
+                    let skipped = live.clone();
+                    self.live_statements(std::slice::from_ref(then_branch), live);
+                    live.extend(skipped);
+                    self.live_expression(condition, live);
+                }
+                Statement::Return { value, .. } => {
+                    live.clear();
+                    if let Some(value) = value {
+                        self.live_expression(value, live);
+                    }
+                }
+                Statement::Printf { value, .. } => self.live_expression(value, live),
+                Statement::Assign { target, value, .. } => {
+                    if !live.remove(target) {
+                        self.dead.insert(value);
+                    }
+                    self.live_expression(value, live);
+                }
+                Statement::Call { .. } => {}
+            }
+        }
+    }
+
+    fn live_expression(&mut self, expression: &Expression, live: &mut HashSet<String>) {
+        match &expression.kind {
+            ExpressionKind::Variable(name) => {
+                live.insert(name.clone());
+            }
+            ExpressionKind::Assign { target, value } => {
+                if !live.remove(target) {
+                    self.dead.insert(&**value);
+                }
+                self.live_expression(value, live);
+            }
+            ExpressionKind::Unary { operand, .. } => self.live_expression(operand, live),
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                // The right operand of && and || may be skipped
+                let skipped = match op.is_logical() {
+                    true => live.clone(),
+                    false => HashSet::new(),
+                };
+                self.live_expression(rhs, live);
+                live.extend(skipped);
+                self.live_expression(lhs, live);
+            }
+            ExpressionKind::Int(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::Bool(_)
+            | ExpressionKind::Call(_) => {}
+        }
+    }
+
+    /// Find the variables that are read before they are assigned and those that are assigned
+    /// more than once by going forward through the statements
+    fn assign_statements(&mut self, statements: &[Statement], state: &mut Assigned) {
+        for statement in reachable(statements) {
+            match statement {
+                Statement::Block { statements, .. } => self.assign_statements(statements, state),
+                Statement::If {
+                    condition,
+                    then_branch,
+                    ..
+                } => {
+                    let (mut taken, skipped) = self.assign_condition(condition, state);
+                    let then_branch = std::slice::from_ref(&**then_branch);
+                    self.assign_statements(then_branch, &mut taken);
+                    // A branch that returns does not reach the following statements
+                    let stored = &taken.stored | &skipped.stored;
+                    if always_returns(then_branch) {
+                        *state = skipped;
+                    } else {
+                        taken.merge(skipped);
+                        *state = taken;
+                    }
+                    state.stored = stored;
+                }
+                Statement::Return {
+                    value: Some(value), ..
+                }
+                | Statement::Printf { value, .. } => self.assign_expression(value, state),
+                Statement::Return { value: None, .. } | Statement::Call { .. } => {}
+                Statement::Assign { target, value, .. } => {
+                    self.assign_expression(value, state);
+                    self.assign(target, value, state);
+                }
+            }
+        }
+    }
+
+    /// Return the states in which the condition of an if statement is true and false. Like
+    /// rustc, this follows the operands of && and || to the branch they select.
+    fn assign_condition(
+        &mut self,
+        condition: &Expression,
+        state: &Assigned,
+    ) -> (Assigned, Assigned) {
+        match &condition.kind {
+            ExpressionKind::Binary {
+                op: BinaryOp::And,
+                lhs,
+                rhs,
+            } => {
+                let (lhs_true, lhs_false) = self.assign_condition(lhs, state);
+                let (rhs_true, mut rhs_false) = self.assign_condition(rhs, &lhs_true);
+                rhs_false.merge(lhs_false);
+                (rhs_true, rhs_false)
+            }
+            ExpressionKind::Binary {
+                op: BinaryOp::Or,
+                lhs,
+                rhs,
+            } => {
+                let (lhs_true, lhs_false) = self.assign_condition(lhs, state);
+                let (mut rhs_true, rhs_false) = self.assign_condition(rhs, &lhs_false);
+                rhs_true.merge(lhs_true);
+                (rhs_true, rhs_false)
+            }
+            _ => {
+                let mut state = state.clone();
+                self.assign_expression(condition, &mut state);
+                (state.clone(), state)
+            }
+        }
+    }
+
+    fn assign_expression(&mut self, expression: &Expression, state: &mut Assigned) {
+        match &expression.kind {
+            ExpressionKind::Variable(name) => {
+                if !state.definitely.contains(name) {
+                    self.zeroed.insert(name.clone());
+                }
+            }
+            ExpressionKind::Assign { target, value } => {
+                self.assign_expression(value, state);
+                self.assign(target, value, state);
+            }
+            ExpressionKind::Unary { operand, .. } => self.assign_expression(operand, state),
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                self.assign_expression(lhs, state);
+                if op.is_logical() {
+                    let mut evaluated = state.clone();
+                    self.assign_expression(rhs, &mut evaluated);
+                    state.merge(evaluated);
+                } else {
+                    self.assign_expression(rhs, state);
+                }
+            }
+            ExpressionKind::Int(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::Bool(_)
+            | ExpressionKind::Call(_) => {}
+        }
+    }
+
+    fn assign(&mut self, target: &str, value: &Expression, state: &mut Assigned) {
+        if self.is_dead(value) {
+            return;
+        }
+        if state.maybe.contains(target) {
+            self.mutable.insert(target.to_string());
+        }
+        state.definitely.insert(target.to_string());
+        state.maybe.insert(target.to_string());
+        state.stored.insert(target.to_string());
+    }
+}
+
+/// Variables that hold a value at some point of a function
+#[derive(Default, Clone)]
+struct Assigned {
+    /// Assigned on every path
+    definitely: HashSet<String>,
+    /// Assigned on some path
+    maybe: HashSet<String>,
+    /// Assigned anywhere before
+    stored: HashSet<String>,
+}
+
+impl Assigned {
+    /// Join the state of another path to the same point
+    fn merge(&mut self, other: Assigned) {
+        self.definitely
+            .retain(|name| other.definitely.contains(name));
+        self.maybe.extend(other.maybe);
+        self.stored.extend(other.stored);
+    }
+}
+
+/// Return the statements up to the first one that always returns, the others are never run
+fn reachable(statements: &[Statement]) -> &[Statement] {
+    let end = statements
+        .iter()
+        .position(|statement| always_returns(std::slice::from_ref(statement)))
+        .map_or(statements.len(), |position| position + 1);
+    &statements[..end]
+}
+
+/// Return the names of the functions that a function calls
+fn callees(function: &FunctionDefinition) -> impl Iterator<Item = &str> {
+    let mut names = Vec::new();
+    function.walk_statements(&mut |statement| {
+        if let Statement::Call { name, .. } = statement {
+            names.push(name.as_str());
+        }
+    });
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Call(name) = &expression.kind {
+            names.push(name.as_str());
+        }
+    });
+    names.into_iter()
+}
+
+/// Whether evaluating the expression can do more than produce its value
+fn has_effects(expression: &Expression) -> bool {
+    let mut effects = false;
+    expression.walk(&mut |expression| {
+        effects |= matches!(
+            expression.kind,
+            ExpressionKind::Call(_)
+                | ExpressionKind::Assign { .. }
+                | ExpressionKind::Binary {
+                    op: BinaryOp::Div,
+                    ..
+                }
+        )
+    });
+    effects
+}
+
+/// Variables get a prefix so they cannot clash with Rust keywords or the functions
+fn variable(name: &str) -> String {
+    format!("v_{}", name)
+}
+
+/// C(-1) names have no underscores, so they are snake case unless they have capital letters
+fn is_snake_case(name: &str) -> bool {
+    !name.chars().any(char::is_uppercase)
+}
+
+fn rust_type(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Bool => "bool",
+        Type::Float => "f64",
+        Type::Int => "i32",
+        Type::Void => "()",
+    }
+}
+
+fn zero(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Bool => "false",
+        Type::Float => "0.0",
+        Type::Int | Type::Void => "0",
+    }
+}
+
+/// Interpret a value as a condition like C(-1) does
+fn truth(code: Code) -> Code {
+    match code.value_type {
+        Type::Int => Code::new(format!("{} != 0", code.operand()), Type::Bool, false),
+        Type::Float => Code::new(format!("{} != 0.0", code.operand()), Type::Bool, false),
+        _ => code,
+    }
+}
+
+/// Convert an operand for an arithmetic operation or a comparison, which are done on ints or
+/// floats. Both conversions are lossless.
+fn promote(code: Code, target: Type) -> Code {
+    match (code.value_type, target) {
+        (Type::Bool, Type::Int) => Code::new(format!("i32::from({})", code.text), target, true),
+        (Type::Bool, Type::Float) => {
+            Code::new(format!("f64::from(i32::from({}))", code.text), target, true)
+        }
+        (Type::Int, Type::Float) => Code::new(format!("f64::from({})", code.text), target, true),
+        _ => code,
+    }
+}
+
+/// Convert a value to the return type of its function like C(-1) does. `as` saturates and
+/// turns NaN into zero, like the conversion of C(-1).
+fn convert(code: Code, target: Type) -> Code {
+    match (code.value_type, target) {
+        (from, to) if from == to => code,
+        (_, Type::Bool) => truth(code),
+        (Type::Float, Type::Int) => Code::new(format!("{} as i32", code.operand()), target, false),
+        _ => promote(code, target),
+    }
+}
+
+/// Write a float so that Rust reads exactly the same value
+fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        "f64::NAN".to_string()
+    } else if value.is_infinite() {
+        let sign = if value < 0.0 { "NEG_" } else { "" };
+        format!("f64::{}INFINITY", sign)
+    } else {
+        // The shortest representation that reads back as the same value, e.g. 1.5 or 1e300
+        format!("{:?}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    #[test]
+    fn variables_become_typed_bindings() {
+        let program = C1Parser::parse_program(
+            "float blah() {
+                 a = blub() / 2;
+                 b = 1;
+                 if (a > 1.5) b = 2;
+                 c = 5;
+                 printf(b * c);
+                 c = -a;
+                 return a;
+             }
+             int blub() { return 3; }
+             void main() { d = 1 < 2; printf(d); printf(blah()); }",
+        )
+        .unwrap();
+        let rust = program.emit_rust(Storage::Local, "test.c-1").unwrap();
+        let expected = [
+            "fn f_blah() -> f64 {\n    let v_a: i32;\n    let mut v_b: i32;\n    let v_c: i32;\n",
+            "    v_a = c1_div(f_blub(), 2, 2);\n",
+            "    if f64::from(v_a) > 1.5 {\n        v_b = 2;\n    }\n",
+            "    println!(\"{}\", i32::wrapping_mul(v_b, v_c));\n",
+            // The last value of c is never read
+            "    println!(\"{}\", i32::wrapping_mul(v_b, v_c));\n    return f64::from(v_a);\n}\n",
+            "println!(\"{}\", c1_float(f_blah()));",
+            "fn main() {\n    f_main();\n}\n",
+        ];
+        for part in expected {
+            assert!(rust.contains(part), "{} not found in\n{}", part, rust);
+        }
+
+        let rust = program.emit_rust(Storage::Global, "test.c-1").unwrap();
+        assert!(rust.contains("struct Globals {\n    v_a: i32,\n    v_b: i32,\n"));
+        assert!(rust.contains("fn f_blub() -> i32 {"));
+        assert!(rust.contains("    g.v_a = c1_div(f_blub(), 2, 2);\n"));
+    }
+
+    #[test]
+    fn missing_results_are_options() {
+        let program = C1Parser::parse_program(
+            "int maybe() {\n if (false) return 1;\n}\nint main() { return maybe(); }",
+        )
+        .unwrap();
+        let rust = program.emit_rust(Storage::Local, "maybe.c-1").unwrap();
+        assert!(rust.contains(
+            "fn f_maybe() -> Option<i32> {\n    if false {\n        return Some(1);\n    }\n    None\n}"
+        ));
+        assert!(rust.contains("return c1_result(f_maybe(), \"maybe\", 4);"));
+        assert!(rust.ends_with("fn main() {\n    std::process::exit(f_main());\n}\n"));
+    }
+}
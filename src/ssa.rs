@@ -0,0 +1,1024 @@
+use crate::cfg::BlockId;
+use crate::config::Storage;
+use crate::ir::{Instruction, InstructionKind, IrFunction, IrProgram, Label, Operand, Place};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+
+/// A basic block of a function in three-address code
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub label: Label,
+    /// Positions of the instructions, from the label up to and including the terminator
+    pub instructions: Range<usize>,
+    pub successors: Vec<BlockId>,
+    pub predecessors: Vec<BlockId>,
+}
+
+/// Control flow between the basic blocks of a function in which every block starts with a label
+/// and ends with a terminator, like the functions of `IrProgram::to_ssa`. Block 0 is the entry
+/// block. Instructions before the first label are not part of any block.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FlowGraph {
+    pub blocks: Vec<Block>,
+    by_label: HashMap<Label, BlockId>,
+}
+
+impl FlowGraph {
+    pub const ENTRY: BlockId = 0;
+
+    pub fn build(function: &IrFunction) -> Self {
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut by_label = HashMap::new();
+        for (position, instruction) in function.instructions.iter().enumerate() {
+            if let InstructionKind::Label(label) = instruction.kind {
+                by_label.insert(label, blocks.len());
+                blocks.push(Block {
+                    label,
+                    instructions: position..position,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                });
+            }
+            if let Some(block) = blocks.last_mut() {
+                block.instructions.end = position + 1;
+            }
+        }
+        for id in 0..blocks.len() {
+            let last = &function.instructions[blocks[id].instructions.end - 1];
+            let targets = match last.kind {
+                InstructionKind::Jump(label) => vec![label],
+                InstructionKind::Branch {
+                    then_label,
+                    else_label,
+                    ..
+                } => vec![then_label, else_label],
+                _ => Vec::new(),
+            };
+            for label in targets {
+                let Some(&target) = by_label.get(&label) else {
+                    continue;
+                };
+                if !blocks[id].successors.contains(&target) {
+                    blocks[id].successors.push(target);
+                    blocks[target].predecessors.push(id);
+                }
+            }
+        }
+        FlowGraph { blocks, by_label }
+    }
+
+    /// Return the block that starts with the label
+    pub fn block(&self, label: Label) -> Option<BlockId> {
+        self.by_label.get(&label).copied()
+    }
+
+    /// Return the blocks reachable from the entry block in reverse postorder, so every block
+    /// comes after its dominators
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![(Self::ENTRY, 0)];
+        visited[Self::ENTRY] = true;
+        while let Some((block, next)) = stack.pop() {
+            match self.blocks[block].successors.get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => order.push(block),
+            }
+        }
+        order.reverse();
+        order
+    }
+}
+
+/// Dominator tree and dominance frontiers of a flow graph. Block `a` dominates block `b` if
+/// every path from the entry to `b` passes through `a`. Blocks that are not reachable from the
+/// entry are not part of the tree.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DominatorTree {
+    immediate_dominators: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+    frontiers: Vec<BTreeSet<BlockId>>,
+    reachable: Vec<bool>,
+}
+
+impl DominatorTree {
+    /// Compute the tree with the iterative algorithm of Cooper, Harvey and Kennedy
+    pub fn compute(graph: &FlowGraph) -> Self {
+        let count = graph.blocks.len();
+        let order = graph.reverse_postorder();
+        let mut rank = vec![usize::MAX; count];
+        for (index, block) in order.iter().enumerate() {
+            rank[*block] = index;
+        }
+        let mut dominators: Vec<Option<BlockId>> = vec![None; count];
+        if let Some(&entry) = order.first() {
+            dominators[entry] = Some(entry);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut processed = graph.blocks[block]
+                    .predecessors
+                    .iter()
+                    .copied()
+                    .filter(|predecessor| dominators[*predecessor].is_some());
+                let Some(first) = processed.next() else {
+                    continue;
+                };
+                let dominator = processed.fold(first, |mut a, mut b| {
+                    while a != b {
+                        while rank[a] > rank[b] {
+                            a = dominators[a].unwrap();
+                        }
+                        while rank[b] > rank[a] {
+                            b = dominators[b].unwrap();
+                        }
+                    }
+                    a
+                });
+                if dominators[block] != Some(dominator) {
+                    dominators[block] = Some(dominator);
+                    changed = true;
+                }
+            }
+        }
+
+        let reachable: Vec<bool> = rank.iter().map(|rank| *rank != usize::MAX).collect();
+        if let Some(&entry) = order.first() {
+            dominators[entry] = None;
+        }
+        let mut children = vec![Vec::new(); count];
+        for (block, dominator) in dominators.iter().enumerate() {
+            if let Some(dominator) = dominator {
+                children[*dominator].push(block);
+            }
+        }
+        let mut frontiers = vec![BTreeSet::new(); count];
+        for &block in &order {
+            let predecessors = &graph.blocks[block].predecessors;
+            if predecessors.len() < 2 {
+                continue;
+            }
+            for &predecessor in predecessors.iter().filter(|block| reachable[**block]) {
+                let mut runner = predecessor;
+                while Some(runner) != dominators[block] {
+                    frontiers[runner].insert(block);
+                    match dominators[runner] {
+                        Some(dominator) => runner = dominator,
+                        None => break,
+                    }
+                }
+            }
+        }
+        DominatorTree {
+            immediate_dominators: dominators,
+            children,
+            frontiers,
+            reachable,
+        }
+    }
+
+    /// Return the closest strict dominator of the block, `None` for the entry block
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.immediate_dominators[block]
+    }
+
+    /// Return the blocks whose immediate dominator is the given block
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block]
+    }
+
+    /// Return the blocks where the dominance of the given block ends: they are not strictly
+    /// dominated by it, but one of their predecessors is dominated by it
+    pub fn frontier(&self, block: BlockId) -> &BTreeSet<BlockId> {
+        &self.frontiers[block]
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.reachable[block]
+    }
+
+    /// Whether every path from the entry to `block` passes through `dominator`
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        if !self.reachable[block] {
+            return false;
+        }
+        let mut current = Some(block);
+        while let Some(block) = current {
+            if block == dominator {
+                return true;
+            }
+            current = self.immediate_dominators[block];
+        }
+        false
+    }
+}
+
+/// A violation of the invariants of SSA form
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SsaErrorKind {
+    /// Code that does not start with a label, so it is not a proper basic block
+    UnlabelledBlock,
+    /// Control continues from one block into the next without a terminator
+    FallsThrough,
+    UnknownLabel {
+        label: Label,
+    },
+    UnreachableBlock {
+        label: Label,
+    },
+    /// A phi instruction after other instructions of its block
+    MisplacedPhi,
+    /// The labels of the phi operands differ from the predecessors of the block
+    PhiPredecessors,
+    /// A variable that should have been renamed into versions
+    UnversionedPlace {
+        place: Place,
+    },
+    MultipleAssignments {
+        place: Place,
+    },
+    /// A place that is used but never assigned
+    Unassigned {
+        place: Place,
+    },
+    /// A use that is not dominated by the assignment of the place
+    NotDominated {
+        place: Place,
+    },
+}
+
+impl fmt::Display for SsaErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SsaErrorKind::UnlabelledBlock => write!(f, "the block does not start with a label"),
+            SsaErrorKind::FallsThrough => {
+                write!(f, "control falls through to the next block")
+            }
+            SsaErrorKind::UnknownLabel { label } => write!(f, "label {} does not exist", label),
+            SsaErrorKind::UnreachableBlock { label } => {
+                write!(f, "block {} is not reachable", label)
+            }
+            SsaErrorKind::MisplacedPhi => write!(f, "phi after other instructions of the block"),
+            SsaErrorKind::PhiPredecessors => write!(
+                f,
+                "the operands of the phi do not match the predecessors of the block"
+            ),
+            SsaErrorKind::UnversionedPlace { place } => {
+                write!(f, "variable `{}` has no version", place)
+            }
+            SsaErrorKind::MultipleAssignments { place } => {
+                write!(f, "`{}` is assigned more than once", place)
+            }
+            SsaErrorKind::Unassigned { place } => write!(f, "`{}` is never assigned", place),
+            SsaErrorKind::NotDominated { place } => {
+                write!(f, "use of `{}` is not dominated by its assignment", place)
+            }
+        }
+    }
+}
+
+/// A function rejected by the SSA verifier, pointing to the offending instruction
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SsaError {
+    pub function: String,
+    pub position: usize,
+    pub kind: SsaErrorKind,
+}
+
+impl fmt::Display for SsaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "function `{}`, instruction {}: {}",
+            self.function, self.position, self.kind
+        )
+    }
+}
+
+impl IrProgram {
+    /// Convert the program into static single assignment form. Every block starts with a label
+    /// and ends with a terminator, unreachable blocks are removed, and every place that is
+    /// assigned in more than one instruction is renamed into versions that are assigned exactly
+    /// once, with phi instructions where control flow merges. With global storage variables
+    /// are shared with the called functions and keep their names.
+    ///
+    /// Panics if the result does not pass `verify_ssa`, which would be a bug of the conversion.
+    pub fn to_ssa(&self) -> IrProgram {
+        let mut program = self.clone();
+        for function in &mut program.functions {
+            split_blocks(function);
+            let renamed = renamed_places(function, self.storage);
+            insert_phis(function, &renamed);
+            rename(function, &renamed);
+        }
+        if let Err(error) = program.verify_ssa() {
+            panic!("conversion into SSA form failed: {}\n{}", error, program);
+        }
+        program
+    }
+
+    /// Convert a program in SSA form back into ordinary three-address code by replacing every
+    /// phi with copies at the end of the predecessors. Edges from blocks with several
+    /// successors get a block of their own for the copies. Versions stay separate places.
+    ///
+    /// Fails if the program does not pass `verify_ssa` or if the blocks of the result are
+    /// broken.
+    pub fn from_ssa(&self) -> Result<IrProgram, SsaError> {
+        self.verify_ssa()?;
+        let mut program = self.clone();
+        for function in &mut program.functions {
+            remove_phis(function);
+            verify_blocks(function).map_err(|(position, kind)| SsaError {
+                function: function.name.clone(),
+                position,
+                kind,
+            })?;
+        }
+        Ok(program)
+    }
+
+    /// Check the invariants of SSA form: blocks start with a label and end with a terminator,
+    /// phis come first in their block and have one operand per predecessor, every place is
+    /// assigned at most once, and every use is dominated by the assignment. Uses of version 0,
+    /// the value before the first assignment, are allowed and fail when executed.
+    pub fn verify_ssa(&self) -> Result<(), SsaError> {
+        for function in &self.functions {
+            verify_blocks(function)
+                .and_then(|graph| verify_assignments(function, &graph, self.storage))
+                .map_err(|(position, kind)| SsaError {
+                    function: function.name.clone(),
+                    position,
+                    kind,
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Start every block with a label, end it with a terminator and drop unreachable blocks. The
+/// entry block gets a label of its own, so it has no predecessors.
+fn split_blocks(function: &mut IrFunction) {
+    let instructions = std::mem::take(&mut function.instructions);
+    let entry = function.new_label();
+    let mut blocks = vec![Instruction::new(
+        InstructionKind::Label(entry),
+        function.line,
+    )];
+    for instruction in instructions {
+        let terminated = blocks.last().is_some_and(Instruction::is_terminator);
+        match instruction.kind {
+            InstructionKind::Label(label) if !terminated => blocks.push(Instruction::new(
+                InstructionKind::Jump(label),
+                instruction.line,
+            )),
+            InstructionKind::Label(_) => {}
+            _ if terminated => {
+                let label = function.new_label();
+                blocks.push(Instruction::new(
+                    InstructionKind::Label(label),
+                    instruction.line,
+                ));
+            }
+            _ => {}
+        }
+        blocks.push(instruction);
+    }
+    function.instructions = blocks;
+
+    let graph = FlowGraph::build(function);
+    let tree = DominatorTree::compute(&graph);
+    function.instructions = graph
+        .blocks
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| tree.is_reachable(*id))
+        .flat_map(|(_, block)| function.instructions[block.instructions.clone()].to_vec())
+        .collect();
+}
+
+/// Return the places that have to be renamed: the temporaries assigned more than once and, with
+/// local storage, all variables
+fn renamed_places(function: &IrFunction, storage: Storage) -> BTreeSet<Place> {
+    let mut assignments: BTreeMap<&Place, usize> = BTreeMap::new();
+    for target in function.instructions.iter().filter_map(Instruction::target) {
+        *assignments.entry(target).or_default() += 1;
+    }
+    let mut renamed: BTreeSet<Place> = assignments
+        .into_iter()
+        .filter(|(place, count)| matches!(place, Place::Temp(_)) && *count > 1)
+        .map(|(place, _)| place.clone())
+        .collect();
+    if storage == Storage::Local {
+        for instruction in &function.instructions {
+            let used = instruction
+                .operands()
+                .into_iter()
+                .filter_map(|operand| match operand {
+                    Operand::Place(place) => Some(place),
+                    Operand::Constant(_) => None,
+                });
+            for place in used.chain(instruction.target()) {
+                if matches!(place, Place::Variable(_)) {
+                    renamed.insert(place.clone());
+                }
+            }
+        }
+    }
+    renamed
+}
+
+/// Insert a phi for a place at the start of every block in the iterated dominance frontier of
+/// the blocks that assign it
+fn insert_phis(function: &mut IrFunction, renamed: &BTreeSet<Place>) {
+    let graph = FlowGraph::build(function);
+    let tree = DominatorTree::compute(&graph);
+    let mut phis: BTreeMap<BlockId, Vec<&Place>> = BTreeMap::new();
+    for place in renamed {
+        let assigning: BTreeSet<BlockId> = graph
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| {
+                function.instructions[block.instructions.clone()]
+                    .iter()
+                    .any(|instruction| instruction.target() == Some(place))
+            })
+            .map(|(id, _)| id)
+            .collect();
+        let mut worklist: Vec<BlockId> = assigning.iter().copied().collect();
+        let mut with_phi = BTreeSet::new();
+        while let Some(block) = worklist.pop() {
+            for &frontier in tree.frontier(block) {
+                if with_phi.insert(frontier) {
+                    phis.entry(frontier).or_default().push(place);
+                    if !assigning.contains(&frontier) {
+                        worklist.push(frontier);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut instructions = Vec::with_capacity(function.instructions.len());
+    for (id, block) in graph.blocks.iter().enumerate() {
+        let label = &function.instructions[block.instructions.start];
+        instructions.push(label.clone());
+        for &place in phis.get(&id).into_iter().flatten() {
+            let sources = block
+                .predecessors
+                .iter()
+                .map(|predecessor| {
+                    let label = graph.blocks[*predecessor].label;
+                    (label, Operand::Place(place.clone()))
+                })
+                .collect();
+            let phi = InstructionKind::Phi {
+                target: place.clone(),
+                sources,
+            };
+            instructions.push(Instruction::new(phi, label.line));
+        }
+        instructions.extend_from_slice(
+            &function.instructions[block.instructions.start + 1..block.instructions.end],
+        );
+    }
+    function.instructions = instructions;
+}
+
+/// Replace the renamed places by versions, walking the dominator tree so that every use sees
+/// the version of the closest assignment above it
+fn rename(function: &mut IrFunction, renamed: &BTreeSet<Place>) {
+    let graph = FlowGraph::build(function);
+    let tree = DominatorTree::compute(&graph);
+    let mut renamer = Renamer {
+        graph: &graph,
+        tree: &tree,
+        renamed,
+        current: HashMap::new(),
+        versions: HashMap::new(),
+    };
+    if !graph.blocks.is_empty() {
+        renamer.block(function, FlowGraph::ENTRY);
+    }
+
+    let mut versions = BTreeMap::new();
+    for instruction in &function.instructions {
+        let used = instruction
+            .operands()
+            .into_iter()
+            .filter_map(|operand| match operand {
+                Operand::Place(place) => Some(place),
+                Operand::Constant(_) => None,
+            });
+        for place in used.chain(instruction.target()) {
+            if let Place::Version(..) = place {
+                versions.insert(place.clone(), function.types[place.base()]);
+            }
+        }
+    }
+    for place in renamed {
+        function.types.remove(place);
+    }
+    function.types.extend(versions);
+}
+
+struct Renamer<'a> {
+    graph: &'a FlowGraph,
+    tree: &'a DominatorTree,
+    renamed: &'a BTreeSet<Place>,
+    /// Stack of the versions visible in the current block for every renamed place
+    current: HashMap<Place, Vec<usize>>,
+    /// Last version created for every renamed place
+    versions: HashMap<Place, usize>,
+}
+
+impl Renamer<'_> {
+    fn block(&mut self, function: &mut IrFunction, block: BlockId) {
+        let mut assigned = Vec::new();
+        for position in self.graph.blocks[block].instructions.clone() {
+            let instruction = &mut function.instructions[position];
+            if !matches!(instruction.kind, InstructionKind::Phi { .. }) {
+                for operand in instruction.operands_mut() {
+                    self.rename_use(operand);
+                }
+            }
+            if let Some(target) = instruction.target_mut() {
+                if self.renamed.contains(target) {
+                    let version = self.versions.entry(target.clone()).or_default();
+                    *version += 1;
+                    self.current
+                        .entry(target.clone())
+                        .or_default()
+                        .push(*version);
+                    assigned.push(target.clone());
+                    *target = Place::Version(Box::new(target.clone()), *version);
+                }
+            }
+        }
+
+        let label = self.graph.blocks[block].label;
+        for &successor in &self.graph.blocks[block].successors {
+            for position in self.graph.blocks[successor].instructions.clone().skip(1) {
+                let InstructionKind::Phi { sources, .. } =
+                    &mut function.instructions[position].kind
+                else {
+                    break;
+                };
+                for (_, operand) in sources.iter_mut().filter(|(source, _)| *source == label) {
+                    self.rename_use(operand);
+                }
+            }
+        }
+
+        for &child in self.tree.children(block) {
+            self.block(function, child);
+        }
+        for place in assigned {
+            self.current.get_mut(&place).unwrap().pop();
+        }
+    }
+
+    fn rename_use(&self, operand: &mut Operand) {
+        if let Operand::Place(place) = operand {
+            if self.renamed.contains(place) {
+                let version = self
+                    .current
+                    .get(place)
+                    .and_then(|versions| versions.last())
+                    .copied()
+                    .unwrap_or(0);
+                *place = Place::Version(Box::new(place.clone()), version);
+            }
+        }
+    }
+}
+
+/// Target, source and line of a copy that replaces an operand of a phi
+type PhiCopy = (Place, Operand, usize);
+
+/// Replace the phis by copies on the incoming edges
+fn remove_phis(function: &mut IrFunction) {
+    let graph = FlowGraph::build(function);
+    let assigned: HashSet<Place> = function
+        .instructions
+        .iter()
+        .filter_map(Instruction::target)
+        .cloned()
+        .collect();
+
+    // Copies for every edge, a source that is never assigned leaves the target unassigned
+    let mut copies: BTreeMap<(BlockId, BlockId), Vec<PhiCopy>> = BTreeMap::new();
+    for (id, block) in graph.blocks.iter().enumerate() {
+        for instruction in &function.instructions[block.instructions.clone()] {
+            let InstructionKind::Phi { target, sources } = &instruction.kind else {
+                continue;
+            };
+            for (label, source) in sources {
+                if matches!(source, Operand::Place(place) if !assigned.contains(place)) {
+                    continue;
+                }
+                let predecessor = graph.block(*label).expect("phi operands name blocks");
+                copies.entry((predecessor, id)).or_default().push((
+                    target.clone(),
+                    source.clone(),
+                    instruction.line,
+                ));
+            }
+        }
+    }
+
+    let mut instructions = Vec::with_capacity(function.instructions.len());
+    let mut edge_blocks = Vec::new();
+    for (id, block) in graph.blocks.iter().enumerate() {
+        let (terminator, body) = function.instructions[block.instructions.clone()]
+            .split_last()
+            .expect("blocks end with a terminator");
+        instructions.extend(
+            body.iter()
+                .filter(|instruction| !matches!(instruction.kind, InstructionKind::Phi { .. }))
+                .cloned(),
+        );
+        let mut terminator = terminator.clone();
+        for &successor in &block.successors {
+            let Some(copies) = copies.get(&(id, successor)) else {
+                continue;
+            };
+            let copies = sequential_copies(function, copies);
+            if block.successors.len() == 1 {
+                instructions.extend(copies);
+                continue;
+            }
+            // The copies must only happen on this edge
+            let target = graph.blocks[successor].label;
+            let label = function.new_label();
+            if let InstructionKind::Branch {
+                then_label,
+                else_label,
+                ..
+            } = &mut terminator.kind
+            {
+                for branch_label in [then_label, else_label] {
+                    if *branch_label == target {
+                        *branch_label = label;
+                    }
+                }
+            }
+            edge_blocks.push(Instruction::new(
+                InstructionKind::Label(label),
+                terminator.line,
+            ));
+            edge_blocks.extend(copies);
+            edge_blocks.push(Instruction::new(
+                InstructionKind::Jump(target),
+                terminator.line,
+            ));
+        }
+        instructions.push(terminator);
+    }
+    instructions.extend(edge_blocks);
+    function.instructions = instructions;
+}
+
+/// Turn the copies of the phis of one edge into instructions. The phis assign their targets at
+/// the same time, so copies that read the target of another copy go through temporaries.
+fn sequential_copies(function: &mut IrFunction, copies: &[PhiCopy]) -> Vec<Instruction> {
+    let reads_target = copies.iter().any(|(_, source, _)| {
+        copies
+            .iter()
+            .any(|(target, _, _)| *source == Operand::Place(target.clone()))
+    });
+    let mut instructions = Vec::new();
+    let mut sources = Vec::new();
+    for (target, source, line) in copies {
+        let source = match reads_target {
+            true => {
+                let temp = function.new_temp(function.types[target]);
+                let copy = InstructionKind::Copy {
+                    target: temp.clone(),
+                    value: source.clone(),
+                };
+                instructions.push(Instruction::new(copy, *line));
+                Operand::Place(temp)
+            }
+            false => source.clone(),
+        };
+        sources.push(source);
+    }
+    for ((target, _, line), value) in copies.iter().zip(sources) {
+        let copy = InstructionKind::Copy {
+            target: target.clone(),
+            value,
+        };
+        instructions.push(Instruction::new(copy, *line));
+    }
+    instructions
+}
+
+/// Check that the function consists of reachable basic blocks with phis only at their start
+/// and return its flow graph
+fn verify_blocks(function: &IrFunction) -> Result<FlowGraph, (usize, SsaErrorKind)> {
+    let instructions = &function.instructions;
+    match instructions.first() {
+        Some(instruction) if matches!(instruction.kind, InstructionKind::Label(_)) => {}
+        _ => return Err((0, SsaErrorKind::UnlabelledBlock)),
+    }
+    if !instructions.last().is_some_and(Instruction::is_terminator) {
+        return Err((instructions.len() - 1, SsaErrorKind::FallsThrough));
+    }
+    for (position, pair) in instructions.windows(2).enumerate() {
+        let position = position + 1;
+        let (previous, instruction) = (&pair[0], &pair[1]);
+        match (&previous.kind, &instruction.kind) {
+            (_, InstructionKind::Label(_)) if !previous.is_terminator() => {
+                return Err((position, SsaErrorKind::FallsThrough))
+            }
+            (_, InstructionKind::Label(_)) => {}
+            _ if previous.is_terminator() => return Err((position, SsaErrorKind::UnlabelledBlock)),
+            (InstructionKind::Label(_) | InstructionKind::Phi { .. }, _) => {}
+            (_, InstructionKind::Phi { .. }) => return Err((position, SsaErrorKind::MisplacedPhi)),
+            _ => {}
+        }
+    }
+
+    let graph = FlowGraph::build(function);
+    for (position, instruction) in instructions.iter().enumerate() {
+        let labels = match &instruction.kind {
+            InstructionKind::Jump(label) => vec![*label],
+            InstructionKind::Branch {
+                then_label,
+                else_label,
+                ..
+            } => vec![*then_label, *else_label],
+            InstructionKind::Phi { sources, .. } => {
+                sources.iter().map(|(label, _)| *label).collect()
+            }
+            _ => Vec::new(),
+        };
+        if let Some(&label) = labels.iter().find(|label| graph.block(**label).is_none()) {
+            return Err((position, SsaErrorKind::UnknownLabel { label }));
+        }
+    }
+    let tree = DominatorTree::compute(&graph);
+    if let Some(block) = (0..graph.blocks.len()).find(|block| !tree.is_reachable(*block)) {
+        let block = &graph.blocks[block];
+        return Err((
+            block.instructions.start,
+            SsaErrorKind::UnreachableBlock { label: block.label },
+        ));
+    }
+    Ok(graph)
+}
+
+/// Check that every place is assigned once and that the assignment dominates its uses
+fn verify_assignments(
+    function: &IrFunction,
+    graph: &FlowGraph,
+    storage: Storage,
+) -> Result<(), (usize, SsaErrorKind)> {
+    let tree = DominatorTree::compute(graph);
+    let mut assignments: HashMap<&Place, (BlockId, usize)> = HashMap::new();
+    for (id, block) in graph.blocks.iter().enumerate() {
+        for position in block.instructions.clone() {
+            let instruction = &function.instructions[position];
+            if let InstructionKind::Phi { sources, .. } = &instruction.kind {
+                let mut labels: Vec<Label> = sources.iter().map(|(label, _)| *label).collect();
+                let mut predecessors: Vec<Label> = block
+                    .predecessors
+                    .iter()
+                    .map(|predecessor| graph.blocks[*predecessor].label)
+                    .collect();
+                labels.sort();
+                predecessors.sort();
+                if labels != predecessors {
+                    return Err((position, SsaErrorKind::PhiPredecessors));
+                }
+            }
+            let Some(target) = instruction.target() else {
+                continue;
+            };
+            if storage == Storage::Local && matches!(target, Place::Variable(_)) {
+                let place = target.clone();
+                return Err((position, SsaErrorKind::UnversionedPlace { place }));
+            }
+            if matches!(target, Place::Variable(_)) {
+                continue;
+            }
+            if assignments.insert(target, (id, position)).is_some() {
+                let place = target.clone();
+                return Err((position, SsaErrorKind::MultipleAssignments { place }));
+            }
+        }
+    }
+
+    for (id, block) in graph.blocks.iter().enumerate() {
+        for position in block.instructions.clone() {
+            let instruction = &function.instructions[position];
+            // A phi operand is used at the end of the predecessor it belongs to
+            let uses: Vec<(&Operand, BlockId, usize)> = match &instruction.kind {
+                InstructionKind::Phi { sources, .. } => sources
+                    .iter()
+                    .map(|(label, operand)| {
+                        let predecessor = graph.block(*label).unwrap();
+                        let end = graph.blocks[predecessor].instructions.end;
+                        (operand, predecessor, end)
+                    })
+                    .collect(),
+                _ => instruction
+                    .operands()
+                    .into_iter()
+                    .map(|operand| (operand, id, position))
+                    .collect(),
+            };
+            for (operand, block, used_at) in uses {
+                let Operand::Place(place) = operand else {
+                    continue;
+                };
+                match place {
+                    Place::Variable(_) if storage == Storage::Local => {
+                        let place = place.clone();
+                        return Err((position, SsaErrorKind::UnversionedPlace { place }));
+                    }
+                    Place::Variable(_) | Place::Version(_, 0) => continue,
+                    _ => {}
+                }
+                let place = place.clone();
+                match assignments.get(&place) {
+                    None => return Err((position, SsaErrorKind::Unassigned { place })),
+                    Some(&(assigned_in, assigned_at)) => {
+                        let dominated = match assigned_in == block {
+                            true => assigned_at < used_at,
+                            false => tree.dominates(assigned_in, block),
+                        };
+                        if !dominated {
+                            return Err((position, SsaErrorKind::NotDominated { place }));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::ir::{InstructionKind, Operand, Place};
+    use crate::ir_interpreter::IrInterpreter;
+    use crate::ssa::{DominatorTree, FlowGraph, SsaErrorKind};
+    use crate::C1Parser;
+    use std::collections::BTreeSet;
+
+    fn parse(text: &str) -> crate::Program {
+        C1Parser::parse_program(text).unwrap()
+    }
+
+    #[test]
+    fn dominators_of_nested_branches() {
+        let program = parse(
+            "int main() { a = 1; if (a) { if (a > 2) a = 3; printf(a); } printf(a); return a; }",
+        );
+        let ir = program.lower(Storage::Local).unwrap().to_ssa();
+        let graph = FlowGraph::build(&ir.functions[0]);
+        let tree = DominatorTree::compute(&graph);
+        let label = |block: usize| graph.blocks[block].label.to_string();
+        let blocks: Vec<String> = (0..graph.blocks.len()).map(label).collect();
+        assert_eq!(blocks, ["L5", "L1", "L3", "L4", "L2"]);
+        // The inner branch is dominated by the outer one, the end by the entry only
+        let dominators: Vec<Option<usize>> = (0..graph.blocks.len())
+            .map(|block| tree.immediate_dominator(block))
+            .collect();
+        assert_eq!(dominators, [None, Some(0), Some(1), Some(1), Some(0)]);
+        assert_eq!(tree.children(1), [2, 3]);
+        assert!(tree.dominates(1, 3) && !tree.dominates(2, 3) && tree.dominates(4, 4));
+        assert_eq!(*tree.frontier(2), BTreeSet::from([3]));
+        assert_eq!(*tree.frontier(3), BTreeSet::from([4]));
+        assert_eq!(*tree.frontier(1), BTreeSet::from([4]));
+        assert!(tree.frontier(0).is_empty());
+    }
+
+    #[test]
+    fn merges_get_phis() {
+        let program =
+            parse("int main() { a = 1; if (a > 0) { a = 2; b = 1.5; } printf(a); return a; }");
+        let ir = program.lower(Storage::Local).unwrap().to_ssa();
+        assert_eq!(
+            ir.to_string(),
+            "; local storage
+
+function int main()
+    ; variables a.1: int, a.2: int, a.3: int, b.0: float, b.1: float, b.2: float
+  L3:                                ; line 1
+    a.1 = 1                          ; line 1
+    %t1 = a.1 > 0                    ; line 1
+    if %t1 goto L1 else L2           ; line 1
+  L1:                                ; line 1
+    a.2 = 2                          ; line 1
+    b.1 = 1.5                        ; line 1
+    goto L2                          ; line 1
+  L2:                                ; line 1
+    a.3 = phi(L3: a.1, L1: a.2)      ; line 1
+    b.2 = phi(L3: b.0, L1: b.1)      ; line 1
+    print a.3                        ; line 1
+    return a.3                       ; line 1
+"
+        );
+
+        // Undefined operands of phis only fail when the value is used
+        let program =
+            parse("int main() { if (true) a = 1; printf(a); if (a > 2) b = 1; return b; }");
+        for storage in [Storage::Local, Storage::Global] {
+            let ir = program.lower(storage).unwrap().to_ssa();
+            let error = IrInterpreter::new(&ir).run().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Line 1: variable `b` is used before it is assigned"
+            );
+            let ir = ir.from_ssa().unwrap();
+            let mut interpreter = IrInterpreter::new(&ir);
+            let error = interpreter.run().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Line 1: variable `b` is used before it is assigned"
+            );
+            assert_eq!(interpreter.output(), "1\n");
+        }
+    }
+
+    #[test]
+    fn out_of_ssa_splits_critical_edges() {
+        let program = parse("bool main() { a = 1; b = (a > 0) || (a < -5); return b; }");
+        let ir = program.lower(Storage::Global).unwrap().to_ssa();
+        let phis = ir.functions[0]
+            .instructions
+            .iter()
+            .filter(|instruction| matches!(instruction.kind, InstructionKind::Phi { .. }))
+            .count();
+        // Only the temporary of `||` is assigned twice with global storage
+        assert_eq!(phis, 1);
+        let ir = ir.from_ssa().unwrap();
+        let text = ir.functions[0].to_string();
+        assert!(text.contains("if %t2 goto L4 else L1"), "{}", text);
+        assert!(text.contains("  L4:"), "{}", text);
+        let execution = IrInterpreter::new(&ir).run().unwrap();
+        assert_eq!(execution.exit_value, Some(crate::Value::Bool(true)));
+    }
+
+    #[test]
+    fn verifier_finds_broken_invariants() {
+        let program = parse("int main() { a = 1; if (a > 0) a = 2; return a; }");
+        let lowered = program.lower(Storage::Local).unwrap();
+        let error = lowered.from_ssa().unwrap_err();
+        // Lowered code does not start its blocks with labels yet
+        assert_eq!(error.kind, SsaErrorKind::UnlabelledBlock);
+        let ssa = lowered.to_ssa();
+        assert_eq!(ssa.verify_ssa(), Ok(()));
+
+        let mut broken = ssa.clone();
+        let instructions = &mut broken.functions[0].instructions;
+        let phi = instructions
+            .iter()
+            .position(|instruction| matches!(instruction.kind, InstructionKind::Phi { .. }))
+            .unwrap();
+        let assigned = instructions[phi].target().unwrap().clone();
+        *instructions[1].target_mut().unwrap() = assigned.clone();
+        let error = broken.verify_ssa().unwrap_err();
+        assert_eq!(error.position, phi);
+        assert_eq!(
+            error.kind,
+            SsaErrorKind::MultipleAssignments { place: assigned }
+        );
+
+        let mut broken = ssa.clone();
+        let instructions = &mut broken.functions[0].instructions;
+        let last = instructions.len() - 1;
+        *instructions[last].operands_mut()[0] = Operand::Place(Place::Variable("a".to_string()));
+        assert_eq!(
+            broken.verify_ssa().unwrap_err().to_string(),
+            format!(
+                "function `main`, instruction {}: variable `a` has no version",
+                last
+            )
+        );
+
+        let mut broken = ssa;
+        // Remove the jump to the merge block
+        broken.functions[0].instructions.remove(phi - 2);
+        assert_eq!(
+            broken.verify_ssa().unwrap_err().kind,
+            SsaErrorKind::FallsThrough
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::ast::{Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type};
+use crate::config::Storage;
+use std::collections::HashMap;
+
+/// What is known about the type of a variable while the inference runs
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Inferred {
+    /// No assignment has been seen yet
+    Unknown,
+    Known(Type),
+    /// The variable is assigned values of different types
+    Conflict,
+}
+
+impl Inferred {
+    fn join(self, other: Inferred) -> Inferred {
+        match (self, other) {
+            (Inferred::Unknown, other) | (other, Inferred::Unknown) => other,
+            (Inferred::Known(a), Inferred::Known(b)) if a == b => self,
+            _ => Inferred::Conflict,
+        }
+    }
+}
+
+/// Static types of the variables and expressions of a program.
+///
+/// C(-1) has no declarations, so the type of a variable is inferred from the values assigned to
+/// it. A variable has a type if all of its assignments produce values of that type. Which
+/// assignments count depends on the storage: with local storage only those of the same function,
+/// with global storage those of the whole program.
+#[derive(Debug, Clone)]
+pub struct TypeEnvironment {
+    storage: Storage,
+    /// Variable types per function, with global storage all variables are stored under ""
+    variables: HashMap<String, HashMap<String, Inferred>>,
+    functions: HashMap<String, Type>,
+}
+
+impl TypeEnvironment {
+    pub fn infer(program: &Program, storage: Storage) -> Self {
+        let mut environment = TypeEnvironment {
+            storage,
+            variables: HashMap::new(),
+            // Later definitions win, like in the interpreter
+            functions: program
+                .functions
+                .iter()
+                .map(|function| (function.name.clone(), function.return_type))
+                .collect(),
+        };
+        let mut assignments: Vec<(&str, &str, &Expression)> = Vec::new();
+        for function in &program.functions {
+            for (target, value) in assignments_of(function) {
+                assignments.push((environment.scope(&function.name), target, value));
+            }
+        }
+        // Types flow from variable to variable, so iterate until nothing changes. Each variable
+        // can only move from unknown to known to conflict, which bounds the number of rounds.
+        loop {
+            let mut changed = false;
+            for (scope, target, value) in &assignments {
+                let inferred = environment.infer_expression(scope, value);
+                let current = environment.lookup(scope, target);
+                let joined = current.join(inferred);
+                if joined != current {
+                    environment
+                        .variables
+                        .entry(scope.to_string())
+                        .or_default()
+                        .insert(target.to_string(), joined);
+                    changed = true;
+                }
+            }
+            if !changed {
+                return environment;
+            }
+        }
+    }
+
+    /// Return the type of a variable as seen from the given function, None if it is never
+    /// assigned or assigned values of different types
+    pub fn variable_type(&self, function: &str, name: &str) -> Option<Type> {
+        match self.lookup(self.scope(function), name) {
+            Inferred::Known(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Return the variables of a function, or of the program with global storage, whose
+    /// assignments produce values of different types
+    pub fn conflicts(&self, function: &str) -> Vec<&str> {
+        let mut conflicts: Vec<&str> = self
+            .variables
+            .get(self.scope(function))
+            .into_iter()
+            .flatten()
+            .filter(|(_, inferred)| **inferred == Inferred::Conflict)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        conflicts.sort_unstable();
+        conflicts
+    }
+
+    /// Return the static type of an expression inside the given function, None if it is not
+    /// known. Calls of void functions have no type.
+    pub fn expression_type(&self, function: &str, expression: &Expression) -> Option<Type> {
+        match self.infer_expression(self.scope(function), expression) {
+            Inferred::Known(result) => Some(result),
+            _ => None,
+        }
+    }
+
+    fn scope<'a>(&self, function: &'a str) -> &'a str {
+        match self.storage {
+            Storage::Local => function,
+            Storage::Global => "",
+        }
+    }
+
+    fn lookup(&self, scope: &str, name: &str) -> Inferred {
+        self.variables
+            .get(scope)
+            .and_then(|variables| variables.get(name))
+            .copied()
+            .unwrap_or(Inferred::Unknown)
+    }
+
+    fn infer_expression(&self, scope: &str, expression: &Expression) -> Inferred {
+        match &expression.kind {
+            ExpressionKind::Int(_) => Inferred::Known(Type::Int),
+            ExpressionKind::Float(_) => Inferred::Known(Type::Float),
+            ExpressionKind::Bool(_) => Inferred::Known(Type::Bool),
+            ExpressionKind::Variable(name) => self.lookup(scope, name),
+            ExpressionKind::Call(name) => match self.functions.get(name) {
+                Some(Type::Void) | None => Inferred::Conflict,
+                Some(result) => Inferred::Known(*result),
+            },
+            ExpressionKind::Assign { value, .. } => self.infer_expression(scope, value),
+            ExpressionKind::Unary { operand, .. } => match self.infer_expression(scope, operand) {
+                Inferred::Known(Type::Bool) => Inferred::Known(Type::Int),
+                inferred => inferred,
+            },
+            ExpressionKind::Binary { op, .. } if op.is_comparison() || op.is_logical() => {
+                Inferred::Known(Type::Bool)
+            }
+            ExpressionKind::Binary { lhs, rhs, .. } => {
+                let lhs = self.infer_expression(scope, lhs);
+                let rhs = self.infer_expression(scope, rhs);
+                match (lhs, rhs) {
+                    (Inferred::Conflict, _) | (_, Inferred::Conflict) => Inferred::Conflict,
+                    (Inferred::Unknown, _) | (_, Inferred::Unknown) => Inferred::Unknown,
+                    (Inferred::Known(Type::Float), _) | (_, Inferred::Known(Type::Float)) => {
+                        Inferred::Known(Type::Float)
+                    }
+                    _ => Inferred::Known(Type::Int),
+                }
+            }
+        }
+    }
+}
+
+/// Collect all assignments of a function as (target, value) pairs, including nested ones
+fn assignments_of(function: &FunctionDefinition) -> Vec<(&str, &Expression)> {
+    let mut assignments = Vec::new();
+    function.walk_statements(&mut |statement| {
+        if let Statement::Assign { target, value, .. } = statement {
+            assignments.push((target.as_str(), value));
+        }
+    });
+    function.walk_expressions(&mut |expression| {
+        if let ExpressionKind::Assign { target, value } = &expression.kind {
+            assignments.push((target.as_str(), value.as_ref()));
+        }
+    });
+    assignments
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Type;
+    use crate::config::Storage;
+    use crate::types::TypeEnvironment;
+    use crate::C1Parser;
+
+    #[test]
+    fn variable_types_follow_assignments() {
+        let program = C1Parser::parse_program(
+            "float f() { x = 1.5; return x; }
+             void main() { a = 1; a = a + 1; b = a * f(); c = a < b; d = 1; d = true; x = 2; }",
+        )
+        .unwrap();
+        let local = TypeEnvironment::infer(&program, Storage::Local);
+        assert_eq!(local.variable_type("main", "a"), Some(Type::Int));
+        assert_eq!(local.variable_type("main", "b"), Some(Type::Float));
+        assert_eq!(local.variable_type("main", "c"), Some(Type::Bool));
+        assert_eq!(local.variable_type("main", "d"), None);
+        assert_eq!(local.variable_type("main", "x"), Some(Type::Int));
+        assert_eq!(local.variable_type("f", "x"), Some(Type::Float));
+        assert_eq!(local.conflicts("main"), vec!["d"]);
+
+        // With global storage both functions assign the same x
+        let global = TypeEnvironment::infer(&program, Storage::Global);
+        assert_eq!(global.variable_type("main", "x"), None);
+        assert_eq!(global.conflicts("f"), vec!["d", "x"]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::ast::{BinaryOp, Type};
+use std::fmt;
+
+/// A runtime value of a C(-1) program.
+///
+/// The operations follow C: `bool` operands are promoted to `int`, mixed `int`/`float` operands
+/// are converted to `float`, comparisons and logical operators produce a `bool`. `int` is a 32 bit
+/// two's complement integer whose arithmetic wraps around on overflow, `float` is a double
+/// precision IEEE 754 number.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Value {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Value {
+    /// Return the type of the value
+    pub fn value_type(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Bool(_) => Type::Bool,
+        }
+    }
+
+    /// Interpret the value as a condition: everything except zero and false is true
+    pub fn is_truthy(&self) -> bool {
+        match *self {
+            Value::Int(value) => value != 0,
+            Value::Float(value) => value != 0.0,
+            Value::Bool(value) => value,
+        }
+    }
+
+    /// Convert the value to the given type like an assignment in C would. Floats are truncated
+    /// towards zero and saturate at the bounds of `int`. Returns None for `void`.
+    pub fn convert_to(&self, target: Type) -> Option<Value> {
+        match (target, *self) {
+            (Type::Void, _) => None,
+            (Type::Bool, value) => Some(Value::Bool(value.is_truthy())),
+            (Type::Int, Value::Float(value)) => Some(Value::Int(value as i32)),
+            (Type::Int, Value::Bool(value)) => Some(Value::Int(value as i32)),
+            (Type::Float, Value::Int(value)) => Some(Value::Float(value as f64)),
+            (Type::Float, Value::Bool(value)) => Some(Value::Float(value as i32 as f64)),
+            (_, value) => Some(value),
+        }
+    }
+
+    /// Negate the value, the only unary operation of C(-1)
+    pub fn negate(&self) -> Value {
+        match *self {
+            Value::Int(value) => Value::Int(value.wrapping_neg()),
+            Value::Float(value) => Value::Float(-value),
+            Value::Bool(value) => Value::Int(-(value as i32)),
+        }
+    }
+
+    /// Apply a binary operator. `&&` and `||` evaluate both operands here, callers that need
+    /// short-circuit evaluation have to check the left operand first. Fails on integer division by
+    /// zero.
+    pub fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, String> {
+        if op.is_logical() {
+            let result = match op {
+                BinaryOp::And => lhs.is_truthy() && rhs.is_truthy(),
+                _ => lhs.is_truthy() || rhs.is_truthy(),
+            };
+            return Ok(Value::Bool(result));
+        }
+        match Value::promote(lhs, rhs) {
+            (Value::Float(lhs), Value::Float(rhs)) => Ok(match op {
+                BinaryOp::Add => Value::Float(lhs + rhs),
+                BinaryOp::Sub => Value::Float(lhs - rhs),
+                BinaryOp::Mul => Value::Float(lhs * rhs),
+                BinaryOp::Div => Value::Float(lhs / rhs),
+                op => Value::Bool(compare(op, lhs, rhs)),
+            }),
+            (Value::Int(lhs), Value::Int(rhs)) => match op {
+                BinaryOp::Add => Ok(Value::Int(lhs.wrapping_add(rhs))),
+                BinaryOp::Sub => Ok(Value::Int(lhs.wrapping_sub(rhs))),
+                BinaryOp::Mul => Ok(Value::Int(lhs.wrapping_mul(rhs))),
+                BinaryOp::Div if rhs == 0 => Err("division by zero".to_string()),
+                BinaryOp::Div => Ok(Value::Int(lhs.wrapping_div(rhs))),
+                op => Ok(Value::Bool(compare(op, lhs, rhs))),
+            },
+            _ => unreachable!("operands are promoted to the same type"),
+        }
+    }
+
+    /// Apply the usual arithmetic conversions: both operands become `float` if one of them is a
+    /// `float`, otherwise both become `int`
+    fn promote(lhs: Value, rhs: Value) -> (Value, Value) {
+        let target = if matches!(lhs, Value::Float(_)) || matches!(rhs, Value::Float(_)) {
+            Type::Float
+        } else {
+            Type::Int
+        };
+        (
+            lhs.convert_to(target).unwrap(),
+            rhs.convert_to(target).unwrap(),
+        )
+    }
+}
+
+fn compare<T: PartialOrd>(op: BinaryOp, lhs: T, rhs: T) -> bool {
+    match op {
+        BinaryOp::Equal => lhs == rhs,
+        BinaryOp::NotEqual => lhs != rhs,
+        BinaryOp::Less => lhs < rhs,
+        BinaryOp::Greater => lhs > rhs,
+        BinaryOp::LessEqual => lhs <= rhs,
+        BinaryOp::GreaterEqual => lhs >= rhs,
+        _ => unreachable!("{} is not a comparison", op),
+    }
+}
+
+/// Prints the value the way `printf` prints it: integers like `%d`, floats like `%f` and booleans
+/// as `true` or `false`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) if value.is_nan() => write!(f, "nan"),
+            Value::Float(value) => write!(f, "{:.6}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{BinaryOp, Type};
+    use crate::value::Value;
+
+    #[test]
+    fn arithmetic_follows_c() {
+        assert_eq!(
+            Value::binary(BinaryOp::Div, Value::Int(-7), Value::Int(2)),
+            Ok(Value::Int(-3))
+        );
+        assert_eq!(
+            Value::binary(BinaryOp::Add, Value::Int(i32::MAX), Value::Int(1)),
+            Ok(Value::Int(i32::MIN))
+        );
+        assert_eq!(
+            Value::binary(BinaryOp::Mul, Value::Int(3), Value::Float(0.5)),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(
+            Value::binary(BinaryOp::Add, Value::Bool(true), Value::Bool(true)),
+            Ok(Value::Int(2))
+        );
+        assert_eq!(
+            Value::binary(BinaryOp::LessEqual, Value::Int(2), Value::Float(2.0)),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            Value::binary(BinaryOp::Or, Value::Int(0), Value::Float(0.5)),
+            Ok(Value::Bool(true))
+        );
+        assert!(Value::binary(BinaryOp::Div, Value::Int(1), Value::Int(0)).is_err());
+        assert_eq!(Value::Bool(true).negate(), Value::Int(-1));
+    }
+
+    #[test]
+    fn conversions_and_formatting() {
+        assert_eq!(
+            Value::Float(-3.9).convert_to(Type::Int),
+            Some(Value::Int(-3))
+        );
+        assert_eq!(
+            Value::Int(2).convert_to(Type::Bool),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(Value::Int(2).convert_to(Type::Void), None);
+        assert_eq!(Value::Float(2.5).to_string(), "2.500000");
+        assert_eq!(Value::Int(-4).to_string(), "-4");
+        assert_eq!(Value::Bool(false).to_string(), "false");
+    }
+}
@@ -0,0 +1,346 @@
+use crate::ast::Type;
+use crate::bytecode::{CompiledFunction, Instruction, Module};
+use std::fmt;
+
+/// A problem found by the verifier
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VerifyErrorKind {
+    /// The instruction needs more values than the stack holds
+    StackUnderflow {
+        required: usize,
+        depth: usize,
+    },
+    /// Two paths reach the instruction with different stack depths
+    StackMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// A return leaves values on the stack, or `return` finds no result
+    UnbalancedReturn {
+        depth: usize,
+    },
+    /// A jump leaves the code of its function
+    JumpOutOfRange {
+        target: u32,
+    },
+    /// Execution continues after the last instruction
+    FallsOffEnd,
+    UnknownFunction {
+        index: u32,
+    },
+    UnknownConstant {
+        index: u32,
+    },
+    UnknownVariable {
+        slot: u32,
+    },
+    /// `return` with a value in a function declared `void`
+    ValueReturnedFromVoid,
+    ConversionToVoid,
+    /// The line table does not have one entry per instruction
+    MissingLines {
+        lines: usize,
+    },
+}
+
+impl fmt::Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyErrorKind::StackUnderflow { required, depth } => write!(
+                f,
+                "stack underflow, {} values are needed but the stack holds {}",
+                required, depth
+            ),
+            VerifyErrorKind::StackMismatch { expected, found } => write!(
+                f,
+                "reached with stack depths {} and {} on different paths",
+                expected, found
+            ),
+            VerifyErrorKind::UnbalancedReturn { depth } => {
+                write!(f, "return with stack depth {}", depth)
+            }
+            VerifyErrorKind::JumpOutOfRange { target } => {
+                write!(f, "jump target {} is outside of the function", target)
+            }
+            VerifyErrorKind::FallsOffEnd => write!(f, "control reaches the end of the code"),
+            VerifyErrorKind::UnknownFunction { index } => {
+                write!(f, "function {} does not exist", index)
+            }
+            VerifyErrorKind::UnknownConstant { index } => {
+                write!(f, "constant {} does not exist", index)
+            }
+            VerifyErrorKind::UnknownVariable { slot } => {
+                write!(f, "variable {} does not exist", slot)
+            }
+            VerifyErrorKind::ValueReturnedFromVoid => {
+                write!(f, "value returned from a void function")
+            }
+            VerifyErrorKind::ConversionToVoid => write!(f, "conversion to void"),
+            VerifyErrorKind::MissingLines { lines } => {
+                write!(f, "the line table has {} entries", lines)
+            }
+        }
+    }
+}
+
+/// Bytecode rejected by the verifier, pointing to the offending instruction
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VerifyError {
+    pub function: String,
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "function `{}`, offset {}: {}",
+            self.function, self.offset, self.kind
+        )
+    }
+}
+
+impl Module {
+    /// Check that the module can be executed safely: every operand refers to something that
+    /// exists, every path through a function sees the same stack depth at each instruction,
+    /// never pops more than it pushed and returns with exactly its result on the stack, and no
+    /// path runs past the end of the code.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        for function in &self.functions {
+            self.verify_function(function)
+                .map_err(|(offset, kind)| VerifyError {
+                    function: function.name.clone(),
+                    offset,
+                    kind,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn verify_function(&self, function: &CompiledFunction) -> Result<(), (usize, VerifyErrorKind)> {
+        let code = &function.code;
+        if function.lines.len() != code.len() {
+            let lines = function.lines.len();
+            return Err((
+                lines.min(code.len()),
+                VerifyErrorKind::MissingLines { lines },
+            ));
+        }
+        // Stack depth before each instruction, None until a path to it is found
+        let mut depths: Vec<Option<usize>> = vec![None; code.len()];
+        let mut worklist = vec![(0, 0)];
+        while let Some((offset, depth)) = worklist.pop() {
+            if offset >= code.len() {
+                return Err((offset, VerifyErrorKind::FallsOffEnd));
+            }
+            match depths[offset] {
+                Some(expected) if expected != depth => {
+                    return Err((
+                        offset,
+                        VerifyErrorKind::StackMismatch {
+                            expected,
+                            found: depth,
+                        },
+                    ));
+                }
+                Some(_) => continue,
+                None => depths[offset] = Some(depth),
+            }
+            let instruction = code[offset];
+            self.check_operand(function, instruction)
+                .map_err(|kind| (offset, kind))?;
+            let (pops, pushes) = instruction.stack_effect();
+            if depth < pops {
+                return Err((
+                    offset,
+                    VerifyErrorKind::StackUnderflow {
+                        required: pops,
+                        depth,
+                    },
+                ));
+            }
+            let after = depth - pops + pushes;
+            match instruction {
+                Instruction::Return | Instruction::ReturnVoid => {
+                    if instruction == Instruction::Return && function.return_type == Type::Void {
+                        return Err((offset, VerifyErrorKind::ValueReturnedFromVoid));
+                    }
+                    if after != 0 {
+                        return Err((offset, VerifyErrorKind::UnbalancedReturn { depth }));
+                    }
+                }
+                Instruction::Jump(target) => worklist.push((target as usize, after)),
+                Instruction::JumpIfFalse(target) | Instruction::JumpIfTrue(target) => {
+                    worklist.push((target as usize, after));
+                    worklist.push((offset + 1, after));
+                }
+                _ => worklist.push((offset + 1, after)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the operand of an instruction refers to something that exists
+    fn check_operand(
+        &self,
+        function: &CompiledFunction,
+        instruction: Instruction,
+    ) -> Result<(), VerifyErrorKind> {
+        let exists = |index: u32, length: usize| (index as usize) < length;
+        match instruction {
+            Instruction::Constant(index) if !exists(index, self.constants.len()) => {
+                Err(VerifyErrorKind::UnknownConstant { index })
+            }
+            Instruction::Load(slot) | Instruction::Store(slot)
+                if !exists(slot, function.locals.len()) =>
+            {
+                Err(VerifyErrorKind::UnknownVariable { slot })
+            }
+            Instruction::LoadGlobal(slot) | Instruction::StoreGlobal(slot)
+                if !exists(slot, self.globals.len()) =>
+            {
+                Err(VerifyErrorKind::UnknownVariable { slot })
+            }
+            Instruction::Call(index) | Instruction::CallValue(index)
+                if !exists(index, self.functions.len()) =>
+            {
+                Err(VerifyErrorKind::UnknownFunction { index })
+            }
+            Instruction::Jump(target)
+            | Instruction::JumpIfFalse(target)
+            | Instruction::JumpIfTrue(target)
+                if !exists(target, function.code.len()) =>
+            {
+                Err(VerifyErrorKind::JumpOutOfRange { target })
+            }
+            Instruction::Convert(Type::Void) => Err(VerifyErrorKind::ConversionToVoid),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Type;
+    use crate::bytecode::{CompiledFunction, Instruction, Instruction::*, Module};
+    use crate::config::{LanguageOptions, Storage};
+    use crate::value::Value;
+    use crate::verifier::{VerifyError, VerifyErrorKind};
+    use crate::C1Parser;
+
+    /// A module with a single function `f` of the given type and code
+    fn module(return_type: Type, code: Vec<Instruction>) -> Module {
+        Module {
+            constants: vec![Value::Int(1)],
+            globals: Vec::new(),
+            functions: vec![CompiledFunction {
+                name: "f".to_string(),
+                return_type,
+                locals: vec!["a".to_string()],
+                lines: vec![1; code.len()],
+                code,
+            }],
+        }
+    }
+
+    fn error(return_type: Type, code: Vec<Instruction>) -> (usize, VerifyErrorKind) {
+        let VerifyError {
+            function,
+            offset,
+            kind,
+        } = module(return_type, code).verify().unwrap_err();
+        assert_eq!(function, "f");
+        (offset, kind)
+    }
+
+    #[test]
+    fn compiled_programs_are_valid() {
+        let program = C1Parser::parse_program(include_str!("../tests/data/beispiel.c-1")).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let language = LanguageOptions { storage };
+            assert_eq!(
+                Module::compile(&program, &language).unwrap().verify(),
+                Ok(())
+            );
+        }
+        let valid = module(
+            Type::Int,
+            vec![Constant(0), Dup, JumpIfTrue(4), Neg, Return],
+        );
+        assert_eq!(valid.verify(), Ok(()));
+    }
+
+    #[test]
+    fn stack_depth_is_checked_on_every_path() {
+        assert_eq!(
+            error(Type::Int, vec![Constant(0), Add, Return]),
+            (
+                1,
+                VerifyErrorKind::StackUnderflow {
+                    required: 2,
+                    depth: 1
+                }
+            )
+        );
+        // The jump skips the push, so both paths meet with different depths
+        assert_eq!(
+            error(
+                Type::Int,
+                vec![Constant(0), JumpIfFalse(3), Constant(0), Return]
+            ),
+            (
+                3,
+                VerifyErrorKind::StackMismatch {
+                    expected: 1,
+                    found: 0
+                }
+            )
+        );
+        assert_eq!(
+            error(Type::Void, vec![Constant(0), ReturnVoid]),
+            (1, VerifyErrorKind::UnbalancedReturn { depth: 1 })
+        );
+        assert_eq!(
+            error(Type::Int, vec![Constant(0), Store(0)]),
+            (2, VerifyErrorKind::FallsOffEnd)
+        );
+    }
+
+    #[test]
+    fn operands_are_checked() {
+        assert_eq!(
+            error(Type::Void, vec![Jump(5), ReturnVoid]),
+            (0, VerifyErrorKind::JumpOutOfRange { target: 5 })
+        );
+        assert_eq!(
+            error(Type::Void, vec![Call(1), ReturnVoid]),
+            (0, VerifyErrorKind::UnknownFunction { index: 1 })
+        );
+        assert_eq!(
+            error(Type::Void, vec![Constant(3), Print, ReturnVoid]),
+            (0, VerifyErrorKind::UnknownConstant { index: 3 })
+        );
+        assert_eq!(
+            error(Type::Void, vec![LoadGlobal(0), Print, ReturnVoid]),
+            (0, VerifyErrorKind::UnknownVariable { slot: 0 })
+        );
+    }
+
+    #[test]
+    fn returns_match_the_declared_type() {
+        assert_eq!(
+            error(Type::Void, vec![Constant(0), Return]),
+            (1, VerifyErrorKind::ValueReturnedFromVoid)
+        );
+        // Returning without a value is allowed, the caller fails if it uses the result
+        assert_eq!(module(Type::Int, vec![ReturnVoid]).verify(), Ok(()));
+        assert_eq!(
+            module(Type::Void, vec![Constant(0), Return])
+                .verify()
+                .unwrap_err()
+                .to_string(),
+            "function `f`, offset 1: value returned from a void function"
+        );
+    }
+}
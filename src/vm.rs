@@ -0,0 +1,324 @@
+use crate::ast::Type;
+use crate::bytecode::{Instruction, Module};
+use crate::interpreter::{Execution, RuntimeError};
+use crate::value::Value;
+
+/// Activation record of a function call
+struct Frame {
+    function: usize,
+    /// Offset of the next instruction
+    pc: usize,
+    locals: Vec<Option<Value>>,
+    /// Height of the stack when the call started
+    base: usize,
+    /// Whether the caller expects a result on the stack
+    wants_value: bool,
+    /// Line of the call, used to report a missing result
+    call_line: usize,
+}
+
+/// Runs a compiled module. The output and the runtime errors are the same as those of the tree
+/// interpreter, but calls do not use the native stack, so much deeper recursion is possible.
+pub struct Vm<'a> {
+    module: &'a Module,
+    output: String,
+    /// Maximum number of nested function calls before the execution is aborted
+    pub max_call_depth: usize,
+}
+
+impl<'a> Vm<'a> {
+    pub const DEFAULT_MAX_CALL_DEPTH: usize = 100_000;
+
+    pub fn new(module: &'a Module) -> Self {
+        Vm {
+            module,
+            output: String::new(),
+            max_call_depth: Self::DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    /// Return the output printed so far, which is useful after a runtime error
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Verify the module and run it by calling `main`
+    pub fn run(&mut self) -> Result<Execution, RuntimeError> {
+        let module = self.module;
+        module.verify().map_err(|error| {
+            let line = module
+                .function_index(&error.function)
+                .and_then(|index| module.functions[index].lines.get(error.offset).copied());
+            RuntimeError {
+                line: line.unwrap_or(1),
+                message: format!("invalid bytecode: {}", error),
+            }
+        })?;
+        let main = module.function_index("main").ok_or_else(|| RuntimeError {
+            line: 1,
+            message: "the program has no main function".to_string(),
+        })?;
+        let mut stack: Vec<Value> = Vec::new();
+        let mut globals: Vec<Option<Value>> = vec![None; module.globals.len()];
+        let mut frames = vec![Frame {
+            function: main,
+            pc: 0,
+            locals: vec![None; module.functions[main].locals.len()],
+            base: 0,
+            wants_value: false,
+            call_line: 1,
+        }];
+        loop {
+            let frame = frames.last_mut().unwrap();
+            let function = &module.functions[frame.function];
+            let (instruction, line) = match function.code.get(frame.pc) {
+                Some(instruction) => (*instruction, function.lines[frame.pc]),
+                None => {
+                    let line = function.lines.last().copied().unwrap_or(1);
+                    return Err(malformed(line, "control reaches the end of the code"));
+                }
+            };
+            frame.pc += 1;
+            match instruction {
+                Instruction::Constant(index) => {
+                    let value = module.constants.get(index as usize).ok_or_else(|| {
+                        malformed(line, &format!("constant {} does not exist", index))
+                    })?;
+                    stack.push(*value);
+                }
+                Instruction::Load(slot) => {
+                    let value = variable(&frame.locals, &function.locals, slot, line)?;
+                    stack.push(value);
+                }
+                Instruction::Store(slot) => {
+                    let value = pop(&mut stack, line)?;
+                    store(&mut frame.locals, slot, value, line)?;
+                }
+                Instruction::LoadGlobal(slot) => {
+                    let value = variable(&globals, &module.globals, slot, line)?;
+                    stack.push(value);
+                }
+                Instruction::StoreGlobal(slot) => {
+                    let value = pop(&mut stack, line)?;
+                    store(&mut globals, slot, value, line)?;
+                }
+                Instruction::Dup => {
+                    let value = pop(&mut stack, line)?;
+                    stack.push(value);
+                    stack.push(value);
+                }
+                Instruction::Neg => {
+                    let value = pop(&mut stack, line)?;
+                    stack.push(value.negate());
+                }
+                Instruction::Convert(target) => {
+                    let value = pop(&mut stack, line)?;
+                    let value = value
+                        .convert_to(target)
+                        .ok_or_else(|| malformed(line, "conversion to void"))?;
+                    stack.push(value);
+                }
+                Instruction::Jump(target) => frame.pc = target as usize,
+                Instruction::JumpIfFalse(target) => {
+                    if !pop(&mut stack, line)?.is_truthy() {
+                        frame.pc = target as usize;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if pop(&mut stack, line)?.is_truthy() {
+                        frame.pc = target as usize;
+                    }
+                }
+                Instruction::Call(index) | Instruction::CallValue(index) => {
+                    let callee = module.functions.get(index as usize).ok_or_else(|| {
+                        malformed(line, &format!("function {} does not exist", index))
+                    })?;
+                    if frames.len() >= self.max_call_depth {
+                        return Err(RuntimeError {
+                            line,
+                            message: format!(
+                                "maximum call depth of {} exceeded in call of `{}`",
+                                self.max_call_depth, callee.name
+                            ),
+                        });
+                    }
+                    frames.push(Frame {
+                        function: index as usize,
+                        pc: 0,
+                        locals: vec![None; callee.locals.len()],
+                        base: stack.len(),
+                        wants_value: matches!(instruction, Instruction::CallValue(_)),
+                        call_line: line,
+                    });
+                }
+                Instruction::Return | Instruction::ReturnVoid => {
+                    let result = match instruction {
+                        Instruction::Return => {
+                            let value = pop(&mut stack, line)?;
+                            Some(value.convert_to(function.return_type).ok_or_else(|| {
+                                RuntimeError {
+                                    line,
+                                    message: format!(
+                                        "void function `{}` returns a value",
+                                        function.name
+                                    ),
+                                }
+                            })?)
+                        }
+                        _ => None,
+                    };
+                    let frame = frames.pop().unwrap();
+                    stack.truncate(frame.base);
+                    if frames.is_empty() {
+                        return Ok(Execution {
+                            output: std::mem::take(&mut self.output),
+                            exit_value: result,
+                        });
+                    }
+                    if frame.wants_value {
+                        let value = result.ok_or_else(|| {
+                            let message = if function.return_type == Type::Void {
+                                format!("void function `{}` is called for its value", function.name)
+                            } else {
+                                format!("function `{}` did not return a value", function.name)
+                            };
+                            RuntimeError {
+                                line: frame.call_line,
+                                message,
+                            }
+                        })?;
+                        stack.push(value);
+                    }
+                }
+                Instruction::Print => {
+                    let value = pop(&mut stack, line)?;
+                    self.output.push_str(&format!("{}\n", value));
+                }
+                binary => {
+                    // All remaining instructions are binary operators
+                    let op = binary.binary_op().unwrap();
+                    let rhs = pop(&mut stack, line)?;
+                    let lhs = pop(&mut stack, line)?;
+                    let value = Value::binary(op, lhs, rhs)
+                        .map_err(|message| RuntimeError { line, message })?;
+                    stack.push(value);
+                }
+            }
+        }
+    }
+}
+
+/// Report bytecode that the compiler would never produce
+fn malformed(line: usize, message: &str) -> RuntimeError {
+    RuntimeError {
+        line,
+        message: format!("malformed bytecode: {}", message),
+    }
+}
+
+fn pop(stack: &mut Vec<Value>, line: usize) -> Result<Value, RuntimeError> {
+    stack
+        .pop()
+        .ok_or_else(|| malformed(line, "stack underflow"))
+}
+
+/// Read a variable slot, which fails if nothing was stored in it yet
+fn variable(
+    values: &[Option<Value>],
+    names: &[String],
+    slot: u32,
+    line: usize,
+) -> Result<Value, RuntimeError> {
+    match values.get(slot as usize) {
+        Some(Some(value)) => Ok(*value),
+        Some(None) => Err(RuntimeError {
+            line,
+            message: format!(
+                "variable `{}` is used before it is assigned",
+                names[slot as usize]
+            ),
+        }),
+        None => Err(malformed(
+            line,
+            &format!("variable {} does not exist", slot),
+        )),
+    }
+}
+
+fn store(
+    values: &mut [Option<Value>],
+    slot: u32,
+    value: Value,
+    line: usize,
+) -> Result<(), RuntimeError> {
+    match values.get_mut(slot as usize) {
+        Some(variable) => {
+            *variable = Some(value);
+            Ok(())
+        }
+        None => Err(malformed(
+            line,
+            &format!("variable {} does not exist", slot),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::Module;
+    use crate::config::{LanguageOptions, Storage};
+    use crate::value::Value;
+    use crate::vm::Vm;
+    use crate::C1Parser;
+
+    fn module(text: &str, storage: Storage) -> Module {
+        let program = C1Parser::parse_program(text).unwrap();
+        let language = LanguageOptions { storage };
+        Module::compile(&program, &language).unwrap()
+    }
+
+    #[test]
+    fn example_program() {
+        let module = module(include_str!("../tests/data/beispiel.c-1"), Storage::Local);
+        let execution = Vm::new(&module).run().unwrap();
+        assert_eq!(execution.output, "3\n17\n3.141590\n");
+        assert_eq!(execution.exit_value, None);
+    }
+
+    #[test]
+    fn deep_recursion_does_not_use_the_native_stack() {
+        let module = module(
+            "int count() {
+                 n = n + 1;
+                 if (n < 50000) return count();
+                 return n;
+             }
+             int main() { n = 0; return count() / 1000; }",
+            Storage::Global,
+        );
+        let execution = Vm::new(&module).run().unwrap();
+        assert_eq!(execution.exit_value, Some(Value::Int(50)));
+        assert_eq!(execution.exit_code(), 50);
+
+        let mut vm = Vm::new(&module);
+        vm.max_call_depth = 100;
+        assert_eq!(
+            vm.run().unwrap_err().to_string(),
+            "Line 3: maximum call depth of 100 exceeded in call of `count`"
+        );
+    }
+
+    #[test]
+    fn invalid_bytecode_is_not_executed() {
+        let mut module = module("void main() {\n printf(1);\n}", Storage::Local);
+        module.functions[0].code.remove(0);
+        module.functions[0].lines.remove(0);
+        let mut vm = Vm::new(&module);
+        assert_eq!(
+            vm.run().unwrap_err().to_string(),
+            "Line 2: invalid bytecode: function `main`, offset 0: stack underflow, 1 values are \
+             needed but the stack holds 0"
+        );
+        assert_eq!(vm.output(), "");
+    }
+}
@@ -0,0 +1,441 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::backend::{always_returns, may_miss_result, EmitError, TypedProgram};
+use crate::config::Storage;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Address of the first error message in the linear memory, address 0 means no message
+const FIRST_MESSAGE: usize = 8;
+
+/// Functions of the generated module that implement the semantics of C(-1) where they differ
+/// from WebAssembly: int division fails with an error instead of trapping and wraps around
+/// for `-2147483648 / -1`, and missing results are only an error if the result is used.
+const RUNTIME: &str = r#"  (func $c1_fail (param $line i32) (param $message i32)
+    (call $error (local.get $line) (local.get $message))
+    (unreachable))
+  (func $c1_div (param $lhs i32) (param $rhs i32) (param $line i32) (result i32)
+    (if (i32.eqz (local.get $rhs))
+      (then (call $c1_fail (local.get $line) (i32.const DIVISION_BY_ZERO))))
+    (if (i32.and
+          (i32.eq (local.get $lhs) (i32.const -2147483648))
+          (i32.eq (local.get $rhs) (i32.const -1)))
+      (then (return (local.get $lhs))))
+    (i32.div_s (local.get $lhs) (local.get $rhs)))
+  (func $c1_check_result (param $line i32)
+    (if (global.get $c1_missing_result)
+      (then (call $c1_fail (local.get $line) (global.get $c1_missing_result)))))
+"#;
+
+impl Program {
+    /// Translate the program into a WebAssembly module in the text format.
+    ///
+    /// Every function becomes a wasm function with the prefix `f_`, so that it cannot clash with
+    /// the imported functions. `int` and `bool` values are `i32` and `float`
+    /// values are `f64`. Variables are locals of their function with local storage and globals
+    /// with global storage, and start as zero. The module exports `main` and its memory, and
+    /// imports these functions from the host module `c1`:
+    ///
+    /// - `print_int (param i32)`, `print_float (param f64)` and `print_bool (param i32)` print
+    ///   the argument of `printf` like the interpreter does
+    /// - `error (param $line i32) (param $message i32)` reports a runtime error, the message
+    ///   is a NUL-terminated string at the given address of the memory. The module traps once
+    ///   the host function returns.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn emit_wat(&self, storage: Storage) -> Result<String, EmitError> {
+        let typed = TypedProgram::check(self, storage)?;
+        let mut emitter = WatEmitter {
+            typed: &typed,
+            messages: Vec::new(),
+            missing_results: HashMap::new(),
+            output: String::new(),
+        };
+        emitter.program();
+        Ok(emitter.output)
+    }
+}
+
+struct WatEmitter<'a> {
+    typed: &'a TypedProgram<'a>,
+    /// Error messages with their addresses in the linear memory
+    messages: Vec<(usize, String)>,
+    /// Address of the message for a function without result
+    missing_results: HashMap<String, usize>,
+    output: String,
+}
+
+impl WatEmitter<'_> {
+    fn program(&mut self) {
+        let program = self.typed.program;
+        let division_by_zero = self.message("division by zero".to_string());
+        for function in &program.functions {
+            if may_miss_result(function) {
+                let message = format!("function `{}` did not return a value", function.name);
+                let address = self.message(message);
+                self.missing_results.insert(function.name.clone(), address);
+            }
+        }
+
+        self.output.push_str("(module\n");
+        for value_type in [Type::Int, Type::Float, Type::Bool] {
+            writeln!(
+                self.output,
+                "  (import \"c1\" \"print_{}\" (func $print_{} (param {})))",
+                value_type,
+                value_type,
+                wasm_type(value_type)
+            )
+            .unwrap();
+        }
+        self.output
+            .push_str("  (import \"c1\" \"error\" (func $error (param i32 i32)))\n");
+        self.output.push_str("  (memory (export \"memory\") 1)\n");
+        for (address, message) in &self.messages {
+            writeln!(
+                self.output,
+                "  (data (i32.const {}) \"{}\\00\")",
+                address,
+                message.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+            .unwrap();
+        }
+        self.output
+            .push_str("  (global $c1_missing_result (mut i32) (i32.const 0))\n");
+        for (name, variable_type) in self.typed.globals() {
+            writeln!(
+                self.output,
+                "  (global ${} (mut {}) {})",
+                name,
+                wasm_type(*variable_type),
+                zero(*variable_type)
+            )
+            .unwrap();
+        }
+        self.output
+            .push_str(&RUNTIME.replace("DIVISION_BY_ZERO", &division_by_zero.to_string()));
+        for function in &program.functions {
+            self.function(function);
+        }
+        self.output.push_str(")\n");
+    }
+
+    /// Store an error message in the linear memory and return its address
+    fn message(&mut self, message: String) -> usize {
+        let address = match self.messages.last() {
+            Some((address, last)) => address + last.len() + 1,
+            None => FIRST_MESSAGE,
+        };
+        self.messages.push((address, message));
+        address
+    }
+
+    fn function(&mut self, function: &FunctionDefinition) {
+        write!(self.output, "  (func $f_{}", function.name).unwrap();
+        if function.name == "main" {
+            self.output.push_str(" (export \"main\")");
+        }
+        if function.return_type != Type::Void {
+            write!(self.output, " (result {})", wasm_type(function.return_type)).unwrap();
+        }
+        for (name, variable_type) in self.typed.locals(&function.name) {
+            write!(
+                self.output,
+                " (local ${} {})",
+                name,
+                wasm_type(*variable_type)
+            )
+            .unwrap();
+        }
+        self.output.push('\n');
+        for statement in &function.body {
+            self.statement(function, statement, 2);
+        }
+        if function.return_type != Type::Void {
+            if !always_returns(&function.body) {
+                self.missing_result(function, 2);
+            } else if !matches!(function.body.last(), Some(Statement::Return { .. })) {
+                // Wasm does not know that all branches of the last `if` return
+                self.output.push_str("    (unreachable)\n");
+            }
+        }
+        self.output.truncate(self.output.trim_end().len());
+        self.output.push_str(")\n");
+    }
+
+    /// Return from a function without a result, which is only an error if the caller uses it
+    fn missing_result(&mut self, function: &FunctionDefinition, indent: usize) {
+        let padding = "  ".repeat(indent);
+        writeln!(
+            self.output,
+            "{}(global.set $c1_missing_result (i32.const {}))\n{}(return {})",
+            padding,
+            self.missing_results[&function.name],
+            padding,
+            zero(function.return_type)
+        )
+        .unwrap();
+    }
+
+    fn statement(&mut self, function: &FunctionDefinition, statement: &Statement, indent: usize) {
+        let padding = "  ".repeat(indent);
+        match statement {
+            Statement::Block { statements, .. } => {
+                for statement in statements {
+                    self.statement(function, statement, indent);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                let condition = truth(self.expression(function, condition));
+                writeln!(self.output, "{}(if {}", padding, condition).unwrap();
+                writeln!(self.output, "{}  (then", padding).unwrap();
+                self.statement(function, then_branch, indent + 2);
+                writeln!(self.output, "{}  )", padding).unwrap();
+                writeln!(self.output, "{})", padding).unwrap();
+            }
+            Statement::Return { value: None, .. } if function.return_type != Type::Void => {
+                self.missing_result(function, indent);
+            }
+            Statement::Return { value: None, .. } => {
+                writeln!(self.output, "{}(return)", padding).unwrap();
+            }
+            Statement::Return {
+                value: Some(value), ..
+            } => {
+                let value = self.expression(function, value);
+                let value = convert(value, function.return_type);
+                writeln!(self.output, "{}(return {})", padding, value).unwrap();
+            }
+            Statement::Printf { value, .. } => {
+                let (value, value_type) = self.expression(function, value);
+                writeln!(
+                    self.output,
+                    "{}(call $print_{} {})",
+                    padding, value_type, value
+                )
+                .unwrap();
+            }
+            Statement::Assign { target, value, .. } => {
+                let value = self.expression(function, value).0;
+                writeln!(
+                    self.output,
+                    "{}({}.set ${} {})",
+                    padding,
+                    self.scope(),
+                    target,
+                    value
+                )
+                .unwrap();
+            }
+            Statement::Call { name, .. } => {
+                let callee = self.typed.function(name);
+                if callee.return_type == Type::Void {
+                    writeln!(self.output, "{}(call $f_{})", padding, name).unwrap();
+                } else {
+                    writeln!(self.output, "{}(drop (call $f_{}))", padding, name).unwrap();
+                }
+                // The result is not used, so it may be missing
+                if may_miss_result(callee) {
+                    writeln!(
+                        self.output,
+                        "{}(global.set $c1_missing_result (i32.const 0))",
+                        padding
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+
+    /// Translate an expression into a folded instruction, returning it with the C(-1) type of
+    /// its value
+    fn expression(&self, function: &FunctionDefinition, expression: &Expression) -> (String, Type) {
+        let value_type = self.typed.expression_type(&function.name, expression);
+        let code = match &expression.kind {
+            ExpressionKind::Int(value) => format!("(i32.const {})", value),
+            ExpressionKind::Float(value) => format!("(f64.const {})", float_literal(*value)),
+            ExpressionKind::Bool(value) => format!("(i32.const {})", *value as i32),
+            ExpressionKind::Variable(name) => format!("({}.get ${})", self.scope(), name),
+            ExpressionKind::Call(name) if may_miss_result(self.typed.function(name)) => format!(
+                "(block (result {}) (call $f_{}) (call $c1_check_result (i32.const {})))",
+                wasm_type(value_type),
+                name,
+                expression.line
+            ),
+            ExpressionKind::Call(name) => format!("(call $f_{})", name),
+            ExpressionKind::Assign { target, value } => {
+                let value = self.expression(function, value).0;
+                match self.typed.storage {
+                    Storage::Local => format!("(local.tee ${} {})", target, value),
+                    Storage::Global => format!(
+                        "(block (result {}) (global.set ${} {}) (global.get ${}))",
+                        wasm_type(value_type),
+                        target,
+                        value,
+                        target
+                    ),
+                }
+            }
+            ExpressionKind::Unary { operand, .. } => match self.expression(function, operand) {
+                (operand, Type::Float) => format!("(f64.neg {})", operand),
+                (operand, _) => format!("(i32.sub (i32.const 0) {})", operand),
+            },
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expression(function, lhs);
+                let rhs = self.expression(function, rhs);
+                match op {
+                    BinaryOp::And => format!(
+                        "(if (result i32) {} (then {}) (else (i32.const 0)))",
+                        truth(lhs),
+                        truth(rhs)
+                    ),
+                    BinaryOp::Or => format!(
+                        "(if (result i32) {} (then (i32.const 1)) (else {}))",
+                        truth(lhs),
+                        truth(rhs)
+                    ),
+                    op if lhs.1 == Type::Float || rhs.1 == Type::Float => format!(
+                        "(f64.{} {} {})",
+                        float_instruction(*op),
+                        convert(lhs, Type::Float),
+                        convert(rhs, Type::Float)
+                    ),
+                    BinaryOp::Div => format!(
+                        "(call $c1_div {} {} (i32.const {}))",
+                        lhs.0, rhs.0, expression.line
+                    ),
+                    // Bools are already ints
+                    op => format!("(i32.{} {} {})", int_instruction(*op), lhs.0, rhs.0),
+                }
+            }
+        };
+        (code, value_type)
+    }
+
+    /// Prefix of the instructions that access variables
+    fn scope(&self) -> &'static str {
+        match self.typed.storage {
+            Storage::Local => "local",
+            Storage::Global => "global",
+        }
+    }
+}
+
+fn wasm_type(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Float => "f64",
+        Type::Int | Type::Bool => "i32",
+        Type::Void => unreachable!("void values are rejected"),
+    }
+}
+
+fn zero(value_type: Type) -> &'static str {
+    match value_type {
+        Type::Float => "(f64.const 0)",
+        _ => "(i32.const 0)",
+    }
+}
+
+/// Convert a value like C(-1) does
+fn convert((value, value_type): (String, Type), target: Type) -> String {
+    match (value_type, target) {
+        (from, to) if from == to => value,
+        (Type::Float, Type::Int) => format!("(i32.trunc_sat_f64_s {})", value),
+        (_, Type::Float) => format!("(f64.convert_i32_s {})", value),
+        (_, Type::Bool) => truth((value, value_type)),
+        // Bools are already ints
+        _ => value,
+    }
+}
+
+/// Convert a value to a bool, like conditions and operands of `&&` and `||` are
+fn truth((value, value_type): (String, Type)) -> String {
+    match value_type {
+        Type::Bool => value,
+        Type::Float => format!("(f64.ne {} (f64.const 0))", value),
+        _ => format!("(i32.ne {} (i32.const 0))", value),
+    }
+}
+
+fn int_instruction(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div => "div_s",
+        BinaryOp::Equal => "eq",
+        BinaryOp::NotEqual => "ne",
+        BinaryOp::Less => "lt_s",
+        BinaryOp::Greater => "gt_s",
+        BinaryOp::LessEqual => "le_s",
+        BinaryOp::GreaterEqual => "ge_s",
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators short-circuit"),
+    }
+}
+
+fn float_instruction(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div => "div",
+        BinaryOp::Equal => "eq",
+        BinaryOp::NotEqual => "ne",
+        BinaryOp::Less => "lt",
+        BinaryOp::Greater => "gt",
+        BinaryOp::LessEqual => "le",
+        BinaryOp::GreaterEqual => "ge",
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators short-circuit"),
+    }
+}
+
+/// Write a float so that wasm reads exactly the same value
+fn float_literal(value: f64) -> String {
+    if value.is_infinite() {
+        "inf".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    #[test]
+    fn functions_become_wasm_functions() {
+        let program = C1Parser::parse_program(
+            "float half() { a = 1; return a / 2; }
+             int maybe() { if (false) return 1; }
+             void main() { b = 1 < 2.5; printf(b); printf(half()); maybe(); x = maybe(); }",
+        )
+        .unwrap();
+        let wat = program.emit_wat(Storage::Local).unwrap();
+        let expected = [
+            "  (import \"c1\" \"print_float\" (func $print_float (param f64)))\n",
+            "  (data (i32.const 8) \"division by zero\\00\")\n",
+            "  (data (i32.const 25) \"function `maybe` did not return a value\\00\")\n",
+            "  (func $f_half (result f64) (local $a i32)\n    (local.set $a (i32.const 1))\n    \
+             (return (f64.convert_i32_s (call $c1_div (local.get $a) (i32.const 2) (i32.const 1)))))\n",
+            "    (global.set $c1_missing_result (i32.const 25))\n    (return (i32.const 0)))\n",
+            "  (func $f_main (export \"main\") (local $b i32) (local $x i32)\n",
+            "(local.set $b (f64.lt (f64.convert_i32_s (i32.const 1)) (f64.const 2.5)))",
+            "    (call $print_bool (local.get $b))\n    (call $print_float (call $f_half))\n",
+            "    (drop (call $f_maybe))\n    (global.set $c1_missing_result (i32.const 0))\n",
+            "(local.set $x (block (result i32) (call $f_maybe) (call $c1_check_result (i32.const 3))))",
+        ];
+        for part in expected {
+            assert!(wat.contains(part), "{} not found in\n{}", part, wat);
+        }
+
+        let wat = program.emit_wat(Storage::Global).unwrap();
+        assert!(wat.contains("  (global $a (mut i32) (i32.const 0))\n"));
+        assert!(wat.contains("(global.set $b "));
+    }
+}
@@ -0,0 +1,621 @@
+use crate::ast::{
+    BinaryOp, Expression, ExpressionKind, FunctionDefinition, Program, Statement, Type,
+};
+use crate::backend::{always_returns, may_miss_result, EmitError, TypedProgram};
+use crate::config::Storage;
+use std::fmt::Write;
+
+/// Routines of the generated code that implement the semantics of C(-1) where they differ from
+/// the machine and print values like the interpreter. They are called with an aligned stack.
+const RUNTIME: &str = r#"# Report a runtime error at line %edi with the message at %rsi and exit
+c1_error:
+    pushq %rbp
+    movl %edi, %ebx
+    movq %rsi, %r12
+    xorl %edi, %edi
+    call fflush@PLT
+    movl $2, %edi
+    leaq .Lerror_format(%rip), %rsi
+    movl %ebx, %edx
+    movq %r12, %rcx
+    xorl %eax, %eax
+    call dprintf@PLT
+    movl $1, %edi
+    call exit@PLT
+
+# Divide %edi by %esi for line %edx, wrapping around for -2147483648 / -1
+c1_div:
+    testl %esi, %esi
+    je 1f
+    cmpl $-1, %esi
+    je 2f
+    movl %edi, %eax
+    cltd
+    idivl %esi
+    ret
+1:  movl %edx, %edi
+    leaq .Ldivision_by_zero(%rip), %rsi
+    subq $8, %rsp
+    call c1_error
+2:  movl %edi, %eax
+    negl %eax
+    ret
+
+# Convert %xmm0 to an int in %eax, saturating and with NaN as 0
+c1_to_int:
+    ucomisd %xmm0, %xmm0
+    jp 1f
+    movsd .Lint_max(%rip), %xmm1
+    ucomisd %xmm1, %xmm0
+    jae 2f
+    movsd .Lint_min(%rip), %xmm1
+    ucomisd %xmm0, %xmm1
+    jae 3f
+    cvttsd2si %xmm0, %eax
+    ret
+1:  xorl %eax, %eax
+    ret
+2:  movl $2147483647, %eax
+    ret
+3:  movl $-2147483648, %eax
+    ret
+
+c1_print_int:
+    subq $8, %rsp
+    movl %edi, %esi
+    leaq .Lint_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+
+# glibc prints NaN with a sign, C(-1) does not
+c1_print_float:
+    subq $8, %rsp
+    ucomisd %xmm0, %xmm0
+    jnp 1f
+    movq %xmm0, %rax
+    btrq $63, %rax
+    movq %rax, %xmm0
+1:  leaq .Lfloat_format(%rip), %rdi
+    movl $1, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+
+c1_print_bool:
+    subq $8, %rsp
+    leaq .Lfalse(%rip), %rsi
+    leaq .Ltrue(%rip), %rax
+    testl %edi, %edi
+    cmovne %rax, %rsi
+    leaq .Lstring_format(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addq $8, %rsp
+    ret
+"#;
+
+/// Constants used by the runtime routines
+const RUNTIME_DATA: &str = r#".Lerror_format:
+    .string "Line %d: %s\n"
+.Lint_format:
+    .string "%d\n"
+.Lfloat_format:
+    .string "%.6f\n"
+.Lstring_format:
+    .string "%s\n"
+.Ltrue:
+    .string "true"
+.Lfalse:
+    .string "false"
+.Ldivision_by_zero:
+    .string "division by zero"
+"#;
+
+impl Program {
+    /// Translate the program into x86-64 assembly for the GNU assembler, following the System V
+    /// ABI and linked against libc.
+    ///
+    /// Expressions are evaluated into `%eax` for `int` and `bool` values and into `%xmm0` for
+    /// `float` values, the left operand of a binary operator waits on the stack while the right
+    /// one is evaluated. Variables live in the stack frame of their function with local storage
+    /// and in `.bss` with global storage, and start as zero. `printf` calls the `printf` of libc
+    /// with the format of the value's type, and the exit status of the process is the value
+    /// returned by `main`. Runtime errors are printed to stderr and exit with status 1.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn emit_x86_64(&self, storage: Storage) -> Result<String, EmitError> {
+        let typed = TypedProgram::check(self, storage)?;
+        let mut emitter = X86Emitter {
+            typed: &typed,
+            output: String::new(),
+            data: String::new(),
+            function: String::new(),
+            labels: 0,
+            depth: 0,
+        };
+        emitter.program();
+        Ok(emitter.output)
+    }
+}
+
+struct X86Emitter<'a> {
+    typed: &'a TypedProgram<'a>,
+    output: String,
+    /// Contents of `.rodata` besides the constants of the runtime
+    data: String,
+    /// Name of the function whose code is emitted
+    function: String,
+    /// Number of local labels created so far
+    labels: usize,
+    /// Number of 8 byte values pushed onto the stack by the current function, to align the
+    /// stack for calls
+    depth: usize,
+}
+
+impl X86Emitter<'_> {
+    fn program(&mut self) {
+        let program = self.typed.program;
+        self.output.push_str("    .text\n");
+        for function in &program.functions {
+            self.function(function);
+        }
+        let main = program.function("main").unwrap();
+        let globals = self.typed.globals().iter().map(|(name, _)| name.as_str());
+        finish_program(&mut self.output, main.return_type, &self.data, globals);
+    }
+
+    fn function(&mut self, function: &FunctionDefinition) {
+        let locals = self.typed.locals(&function.name).len();
+        // Keep the stack aligned to 16 bytes
+        let frame = (locals + locals % 2) * 8;
+        writeln!(
+            self.output,
+            "\n# {} {}()\nf_{}:\n    pushq %rbp\n    movq %rsp, %rbp",
+            function.return_type, function.name, function.name
+        )
+        .unwrap();
+        if frame > 0 {
+            writeln!(self.output, "    subq ${}, %rsp", frame).unwrap();
+            for index in 0..locals {
+                writeln!(self.output, "    movq $0, -{}(%rbp)", (index + 1) * 8).unwrap();
+            }
+        }
+        self.function = function.name.clone();
+        self.depth = 0;
+        for statement in &function.body {
+            self.statement(function, statement);
+        }
+        if function.return_type != Type::Void && !always_returns(&function.body) {
+            self.missing_result(function);
+        }
+        writeln!(
+            self.output,
+            ".Lreturn_{}:\n    leave\n    ret",
+            function.name
+        )
+        .unwrap();
+    }
+
+    /// Return from a function without a result, which is only an error if the caller uses it
+    fn missing_result(&mut self, function: &FunctionDefinition) {
+        let message = self.string(&format!(
+            "function `{}` did not return a value",
+            function.name
+        ));
+        writeln!(
+            self.output,
+            "    leaq {}(%rip), %rax\n    movq %rax, c1_missing_result(%rip)",
+            message
+        )
+        .unwrap();
+        self.zero(function.return_type);
+        writeln!(self.output, "    jmp .Lreturn_{}", function.name).unwrap();
+    }
+
+    fn statement(&mut self, function: &FunctionDefinition, statement: &Statement) {
+        if !matches!(statement, Statement::Block { .. }) {
+            writeln!(self.output, "    # line {}", statement.line()).unwrap();
+        }
+        match statement {
+            Statement::Block { statements, .. } => {
+                for statement in statements {
+                    self.statement(function, statement);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                let end_label = self.label();
+                let condition_type = self.expression(function, condition);
+                self.truth(condition_type);
+                writeln!(self.output, "    testl %eax, %eax\n    je {}", end_label).unwrap();
+                self.statement(function, then_branch);
+                writeln!(self.output, "{}:", end_label).unwrap();
+            }
+            Statement::Return { value: None, .. } if function.return_type != Type::Void => {
+                self.missing_result(function);
+            }
+            Statement::Return { value, .. } => {
+                if let Some(value) = value {
+                    let value_type = self.expression(function, value);
+                    self.convert(value_type, function.return_type);
+                }
+                writeln!(self.output, "    jmp .Lreturn_{}", function.name).unwrap();
+            }
+            Statement::Printf { value, .. } => {
+                let routine = match self.expression(function, value) {
+                    Type::Float => "c1_print_float",
+                    Type::Bool => "c1_print_bool",
+                    _ => "c1_print_int",
+                };
+                if routine != "c1_print_float" {
+                    self.output.push_str("    movl %eax, %edi\n");
+                }
+                self.call(routine);
+            }
+            Statement::Assign { target, value, .. } => {
+                let value_type = self.expression(function, value);
+                self.store(target, value_type);
+            }
+            Statement::Call { name, .. } => {
+                self.call(&format!("f_{}", name));
+                // The result is not used, so it may be missing
+                if may_miss_result(self.typed.function(name)) {
+                    self.output
+                        .push_str("    movq $0, c1_missing_result(%rip)\n");
+                }
+            }
+        }
+    }
+
+    /// Evaluate an expression into `%eax` or `%xmm0` and return the C(-1) type of its value
+    fn expression(&mut self, function: &FunctionDefinition, expression: &Expression) -> Type {
+        let value_type = self.typed.expression_type(&function.name, expression);
+        match &expression.kind {
+            ExpressionKind::Int(value) => {
+                writeln!(self.output, "    movl ${}, %eax", value).unwrap()
+            }
+            ExpressionKind::Float(value) => {
+                let label = self.label();
+                writeln!(
+                    self.data,
+                    "    .balign 8\n{}:\n    .quad {:#x}  # {:?}",
+                    label,
+                    value.to_bits(),
+                    value
+                )
+                .unwrap();
+                writeln!(self.output, "    movsd {}(%rip), %xmm0", label).unwrap();
+            }
+            ExpressionKind::Bool(value) => {
+                writeln!(self.output, "    movl ${}, %eax", *value as i32).unwrap()
+            }
+            ExpressionKind::Variable(name) => {
+                let location = self.location(name);
+                match value_type {
+                    Type::Float => writeln!(self.output, "    movsd {}, %xmm0", location),
+                    _ => writeln!(self.output, "    movl {}, %eax", location),
+                }
+                .unwrap();
+            }
+            ExpressionKind::Call(name) => {
+                self.call(&format!("f_{}", name));
+                if may_miss_result(self.typed.function(name)) {
+                    let label = self.label();
+                    writeln!(
+                        self.output,
+                        "    cmpq $0, c1_missing_result(%rip)\n    je {}\n    \
+                         movl ${}, %edi\n    movq c1_missing_result(%rip), %rsi",
+                        label, expression.line
+                    )
+                    .unwrap();
+                    self.call("c1_error");
+                    writeln!(self.output, "{}:", label).unwrap();
+                }
+            }
+            ExpressionKind::Assign { target, value } => {
+                let value_type = self.expression(function, value);
+                self.store(target, value_type);
+            }
+            ExpressionKind::Unary { operand, .. } => match self.expression(function, operand) {
+                Type::Float => self
+                    .output
+                    .push_str("    movq %xmm0, %rax\n    btcq $63, %rax\n    movq %rax, %xmm0\n"),
+                _ => self.output.push_str("    negl %eax\n"),
+            },
+            ExpressionKind::Binary { op, lhs, rhs } if op.is_logical() => {
+                let end_label = self.label();
+                let lhs_type = self.expression(function, lhs);
+                self.truth(lhs_type);
+                let jump = if *op == BinaryOp::And { "je" } else { "jne" };
+                writeln!(
+                    self.output,
+                    "    testl %eax, %eax\n    {} {}",
+                    jump, end_label
+                )
+                .unwrap();
+                let rhs_type = self.expression(function, rhs);
+                self.truth(rhs_type);
+                writeln!(self.output, "{}:", end_label).unwrap();
+            }
+            ExpressionKind::Binary { op, lhs, rhs } => {
+                let lhs_type = self.typed.expression_type(&function.name, lhs);
+                let rhs_type = self.typed.expression_type(&function.name, rhs);
+                let operand_type = if lhs_type == Type::Float || rhs_type == Type::Float {
+                    Type::Float
+                } else {
+                    Type::Int
+                };
+                self.expression(function, lhs);
+                self.convert(lhs_type, operand_type);
+                self.push(operand_type);
+                self.expression(function, rhs);
+                self.convert(rhs_type, operand_type);
+                // The left operand goes to %ecx or %xmm1
+                self.pop(operand_type);
+                match operand_type {
+                    Type::Float => self.float_operation(*op),
+                    _ => self.int_operation(*op, expression.line),
+                }
+            }
+        }
+        value_type
+    }
+
+    /// Apply an operator to the ints in `%ecx` and `%eax`
+    fn int_operation(&mut self, op: BinaryOp, line: usize) {
+        let code = match op {
+            BinaryOp::Add => "    addl %ecx, %eax\n",
+            BinaryOp::Sub => "    subl %eax, %ecx\n    movl %ecx, %eax\n",
+            BinaryOp::Mul => "    imull %ecx, %eax\n",
+            BinaryOp::Div => {
+                writeln!(
+                    self.output,
+                    "    movl %ecx, %edi\n    movl %eax, %esi\n    movl ${}, %edx",
+                    line
+                )
+                .unwrap();
+                self.call("c1_div");
+                return;
+            }
+            op => {
+                writeln!(
+                    self.output,
+                    "    cmpl %eax, %ecx\n    set{} %al\n    movzbl %al, %eax",
+                    condition_code(op)
+                )
+                .unwrap();
+                return;
+            }
+        };
+        self.output.push_str(code);
+    }
+
+    /// Apply an operator to the floats in `%xmm1` and `%xmm0`
+    fn float_operation(&mut self, op: BinaryOp) {
+        self.output.push_str(float_code(op));
+        if op.is_comparison() {
+            self.output.push_str("    movzbl %al, %eax\n");
+        }
+    }
+
+    /// Convert the value in `%eax` or `%xmm0` like C(-1) does
+    fn convert(&mut self, from: Type, to: Type) {
+        match (from, to) {
+            (from, to) if from == to => {}
+            (Type::Float, Type::Int) => self.call("c1_to_int"),
+            (_, Type::Float) => self.output.push_str("    cvtsi2sdl %eax, %xmm0\n"),
+            (_, Type::Bool) => self.truth(from),
+            // Bools are already ints
+            _ => {}
+        }
+    }
+
+    /// Convert the value in `%eax` or `%xmm0` to a bool in `%eax`
+    fn truth(&mut self, value_type: Type) {
+        match value_type {
+            Type::Bool => {}
+            Type::Float => self.output.push_str(
+                "    xorpd %xmm1, %xmm1\n    ucomisd %xmm1, %xmm0\n    setne %al\n    \
+                 setp %cl\n    orb %cl, %al\n    movzbl %al, %eax\n",
+            ),
+            _ => self
+                .output
+                .push_str("    testl %eax, %eax\n    setne %al\n    movzbl %al, %eax\n"),
+        }
+    }
+
+    fn zero(&mut self, value_type: Type) {
+        match value_type {
+            Type::Float => self.output.push_str("    xorpd %xmm0, %xmm0\n"),
+            _ => self.output.push_str("    xorl %eax, %eax\n"),
+        }
+    }
+
+    /// Store the value in `%eax` or `%xmm0` in a variable
+    fn store(&mut self, name: &str, value_type: Type) {
+        let location = self.location(name);
+        match value_type {
+            Type::Float => writeln!(self.output, "    movsd %xmm0, {}", location),
+            _ => writeln!(self.output, "    movl %eax, {}", location),
+        }
+        .unwrap();
+    }
+
+    /// Return the operand that addresses a variable
+    fn location(&self, name: &str) -> String {
+        match self.typed.storage {
+            Storage::Local => {
+                let locals = self.typed.locals(&self.function);
+                let index = locals.iter().position(|(local, _)| local == name).unwrap();
+                format!("-{}(%rbp)", (index + 1) * 8)
+            }
+            Storage::Global => format!("{}(%rip)", variable(name)),
+        }
+    }
+
+    fn push(&mut self, value_type: Type) {
+        match value_type {
+            Type::Float => self
+                .output
+                .push_str("    subq $8, %rsp\n    movsd %xmm0, (%rsp)\n"),
+            _ => self.output.push_str("    pushq %rax\n"),
+        }
+        self.depth += 1;
+    }
+
+    /// Pop a value into `%ecx` or `%xmm1`
+    fn pop(&mut self, value_type: Type) {
+        match value_type {
+            Type::Float => self
+                .output
+                .push_str("    movsd (%rsp), %xmm1\n    addq $8, %rsp\n"),
+            _ => self.output.push_str("    popq %rcx\n"),
+        }
+        self.depth -= 1;
+    }
+
+    /// Call a function with the stack aligned to 16 bytes
+    fn call(&mut self, target: &str) {
+        if self.depth % 2 == 1 {
+            writeln!(
+                self.output,
+                "    subq $8, %rsp\n    call {}\n    addq $8, %rsp",
+                target
+            )
+            .unwrap();
+        } else {
+            writeln!(self.output, "    call {}", target).unwrap();
+        }
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    /// Add a string to `.rodata` and return its label
+    fn string(&mut self, text: &str) -> String {
+        let label = self.label();
+        writeln!(
+            self.data,
+            "{}:\n    .string \"{}\"",
+            label,
+            text.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+        .unwrap();
+        label
+    }
+}
+
+/// Return the suffix of `set` that tests the flags of an int comparison `cmpl rhs, lhs`
+pub(crate) fn condition_code(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Equal => "e",
+        BinaryOp::NotEqual => "ne",
+        BinaryOp::Less => "l",
+        BinaryOp::Greater => "g",
+        BinaryOp::LessEqual => "le",
+        _ => "ge",
+    }
+}
+
+/// Return the code that applies an operator to the floats in `%xmm1` and `%xmm0`. Arithmetic
+/// leaves the result in `%xmm0`, comparisons leave it in `%al`.
+pub(crate) fn float_code(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "    addsd %xmm0, %xmm1\n    movapd %xmm1, %xmm0\n",
+        BinaryOp::Sub => "    subsd %xmm0, %xmm1\n    movapd %xmm1, %xmm0\n",
+        BinaryOp::Mul => "    mulsd %xmm0, %xmm1\n    movapd %xmm1, %xmm0\n",
+        BinaryOp::Div => "    divsd %xmm0, %xmm1\n    movapd %xmm1, %xmm0\n",
+        // Unordered comparisons set ZF, PF and CF, which makes all of them false but !=
+        BinaryOp::Equal => {
+            "    ucomisd %xmm0, %xmm1\n    sete %al\n    setnp %cl\n    andb %cl, %al\n"
+        }
+        BinaryOp::NotEqual => {
+            "    ucomisd %xmm0, %xmm1\n    setne %al\n    setp %cl\n    orb %cl, %al\n"
+        }
+        BinaryOp::Less => "    ucomisd %xmm1, %xmm0\n    seta %al\n",
+        BinaryOp::Greater => "    ucomisd %xmm0, %xmm1\n    seta %al\n",
+        BinaryOp::LessEqual => "    ucomisd %xmm1, %xmm0\n    setae %al\n",
+        BinaryOp::GreaterEqual => "    ucomisd %xmm0, %xmm1\n    setae %al\n",
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators short-circuit"),
+    }
+}
+
+/// Append everything but the code of the C(-1) functions: the `main` of the process that calls
+/// `f_main`, the runtime, the constants in `data` and the variables with global storage
+pub(crate) fn finish_program<'a>(
+    output: &mut String,
+    main_type: Type,
+    data: &str,
+    globals: impl Iterator<Item = &'a str>,
+) {
+    output.push_str("\n    .globl main\nmain:\n    pushq %rbp\n    call f_main\n");
+    match main_type {
+        Type::Void => output.push_str("    xorl %eax, %eax\n"),
+        Type::Float => output.push_str("    call c1_to_int\n"),
+        Type::Int | Type::Bool => {}
+    }
+    output.push_str("    popq %rbp\n    ret\n\n");
+    output.push_str(RUNTIME);
+
+    output.push_str("\n    .section .rodata\n    .balign 8\n.Lint_max:\n    .double 2147483647\n");
+    output.push_str(".Lint_min:\n    .double -2147483648\n");
+    output.push_str(RUNTIME_DATA);
+    output.push_str(data);
+
+    output.push_str("\n    .bss\n    .balign 8\n");
+    output.push_str("c1_missing_result:\n    .zero 8\n");
+    for name in globals {
+        writeln!(output, "{}:\n    .zero 8", variable(name)).unwrap();
+    }
+    output.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+}
+
+/// Variables get a prefix so they cannot clash with functions or symbols of libc
+pub(crate) fn variable(name: &str) -> String {
+    format!("v_{}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::C1Parser;
+
+    #[test]
+    fn expressions_use_the_stack_for_operands() {
+        let program = C1Parser::parse_program(
+            "float half() { a = 3; return a / 2.0; }
+             void main() { b = half() > 1; printf(b); }",
+        )
+        .unwrap();
+        let asm = program.emit_x86_64(Storage::Local).unwrap();
+        let expected = [
+            "f_half:\n    pushq %rbp\n    movq %rsp, %rbp\n    subq $16, %rsp\n    \
+             movq $0, -8(%rbp)\n    # line 1\n    movl $3, %eax\n    movl %eax, -8(%rbp)\n",
+            "    movl -8(%rbp), %eax\n    cvtsi2sdl %eax, %xmm0\n    subq $8, %rsp\n    \
+             movsd %xmm0, (%rsp)\n    movsd .L1(%rip), %xmm0\n    movsd (%rsp), %xmm1\n    \
+             addq $8, %rsp\n    divsd %xmm0, %xmm1\n",
+            "    jmp .Lreturn_half\n.Lreturn_half:\n    leave\n    ret\n",
+            "    movl %eax, %edi\n    call c1_print_bool\n",
+            "    .globl main\nmain:\n    pushq %rbp\n    call f_main\n    xorl %eax, %eax\n",
+            ".L1:\n    .quad 0x4000000000000000  # 2.0\n",
+        ];
+        for part in expected {
+            assert!(asm.contains(part), "{} not found in\n{}", part, asm);
+        }
+        // The left operand is on the stack during the call of `half`
+        let program =
+            C1Parser::parse_program("int one() { return 1; } int main() { return 2 + one(); }")
+                .unwrap();
+        let asm = program.emit_x86_64(Storage::Global).unwrap();
+        assert!(
+            asm.contains("    pushq %rax\n    subq $8, %rsp\n    call f_one\n    addq $8, %rsp\n")
+        );
+    }
+}
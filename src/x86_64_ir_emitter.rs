@@ -0,0 +1,526 @@
+use crate::ast::{BinaryOp, Program, Type};
+use crate::backend::{check_assigned, EmitError};
+use crate::config::Storage;
+use crate::ir::{InstructionKind, IrFunction, IrProgram, Label, Operand, Place};
+use crate::regalloc::{Allocation, Location, RegisterClass, RegisterSet};
+use crate::value::Value;
+use crate::x86_64_emitter::{condition_code, finish_program, float_code, variable};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Callee-saved registers of the System V ABI available for int and bool values, with their
+/// 64 bit names for saving them
+const GENERAL_REGISTERS: [(&str, &str); 5] = [
+    ("%ebx", "%rbx"),
+    ("%r12d", "%r12"),
+    ("%r13d", "%r13"),
+    ("%r14d", "%r14"),
+    ("%r15d", "%r15"),
+];
+
+/// SSE registers not used by the runtime or for passing values
+const FLOAT_REGISTERS: [&str; 8] = [
+    "%xmm8", "%xmm9", "%xmm10", "%xmm11", "%xmm12", "%xmm13", "%xmm14", "%xmm15",
+];
+
+impl RegisterSet {
+    /// All registers the x86-64 backend can allocate
+    pub const X86_64: RegisterSet = RegisterSet {
+        general: GENERAL_REGISTERS.len(),
+        float: FLOAT_REGISTERS.len(),
+    };
+}
+
+impl Program {
+    /// Translate the program into x86-64 assembly like `emit_x86_64`, but through the
+    /// three-address code in SSA form and with values in registers chosen by
+    /// `Allocation::linear_scan` instead of on the stack. `registers` may not exceed
+    /// `RegisterSet::X86_64`.
+    ///
+    /// Fails if the program has no static types, see `EmitError`.
+    pub fn emit_x86_64_allocated(
+        &self,
+        storage: Storage,
+        registers: RegisterSet,
+    ) -> Result<String, EmitError> {
+        check_assigned(self, storage)?;
+        let ir = self
+            .lower(storage)?
+            .to_ssa()
+            .from_ssa()
+            .expect("to_ssa returns SSA form");
+        Ok(ir.emit_x86_64(registers))
+    }
+}
+
+impl IrProgram {
+    /// Translate a program in the block form of `from_ssa` into x86-64 assembly. Temporaries
+    /// and versions live in the registers or stack slots of their allocation, variables with
+    /// global storage in `.bss`. Places that are read before they are assigned start as zero.
+    pub fn emit_x86_64(&self, registers: RegisterSet) -> String {
+        assert!(
+            registers.general <= RegisterSet::X86_64.general
+                && registers.float <= RegisterSet::X86_64.float,
+            "x86-64 has not that many registers"
+        );
+        let may_miss_result = self
+            .functions
+            .iter()
+            .filter(|function| {
+                function.return_type != Type::Void
+                    && function
+                        .instructions
+                        .iter()
+                        .any(|instruction| instruction.kind == InstructionKind::Return(None))
+            })
+            .map(|function| function.name.as_str())
+            .collect();
+        let mut emitter = IrEmitter {
+            output: "    .text\n".to_string(),
+            data: String::new(),
+            may_miss_result,
+        };
+        for function in &self.functions {
+            let allocation = Allocation::linear_scan(function, registers);
+            emitter.function(function, &allocation);
+        }
+
+        let main = self
+            .function("main")
+            .expect("programs have a main function");
+        let mut globals: Vec<&str> = Vec::new();
+        if self.storage == Storage::Global {
+            let variables = self
+                .functions
+                .iter()
+                .flat_map(|function| function.types.keys());
+            for variable in variables {
+                if let Place::Variable(name) = variable {
+                    if !globals.contains(&name.as_str()) {
+                        globals.push(name);
+                    }
+                }
+            }
+        }
+        let mut output = emitter.output;
+        finish_program(
+            &mut output,
+            main.return_type,
+            &emitter.data,
+            globals.into_iter(),
+        );
+        output
+    }
+}
+
+struct IrEmitter<'a> {
+    output: String,
+    /// Contents of `.rodata` besides the constants of the runtime
+    data: String,
+    /// Functions that can return without a result
+    may_miss_result: HashSet<&'a str>,
+}
+
+/// The code of one function together with the locations of its places
+struct FunctionEmitter<'a, 'b> {
+    emitter: &'b mut IrEmitter<'a>,
+    function: &'b IrFunction,
+    allocation: &'b Allocation,
+    /// Number of saved callee-saved registers, the stack slots come after them
+    saved: usize,
+    /// Number of labels created for checks of missing results
+    checks: usize,
+}
+
+impl<'a> IrEmitter<'a> {
+    fn function(&mut self, function: &IrFunction, allocation: &Allocation) {
+        let used: Vec<usize> = (0..GENERAL_REGISTERS.len())
+            .filter(|register| {
+                allocation.intervals.iter().any(|interval| {
+                    interval.class == RegisterClass::General
+                        && allocation.locations[&interval.place] == Location::Register(*register)
+                })
+            })
+            .collect();
+        let slots = used.len() + allocation.stack_slots;
+        // Keep the stack aligned to 16 bytes
+        let frame = (slots + slots % 2) * 8;
+        writeln!(
+            self.output,
+            "\n# {} {}()\nf_{}:\n    pushq %rbp\n    movq %rsp, %rbp",
+            function.return_type, function.name, function.name
+        )
+        .unwrap();
+        if frame > 0 {
+            writeln!(self.output, "    subq ${}, %rsp", frame).unwrap();
+        }
+        for (index, register) in used.iter().enumerate() {
+            let (_, name) = GENERAL_REGISTERS[*register];
+            writeln!(self.output, "    movq {}, -{}(%rbp)", name, (index + 1) * 8).unwrap();
+        }
+
+        let mut emitter = FunctionEmitter {
+            emitter: self,
+            function,
+            allocation,
+            saved: used.len(),
+            checks: 0,
+        };
+        for place in &allocation.live_at_entry {
+            let value_type = function.types[place];
+            emitter.zero(value_type);
+            emitter.store(place, value_type);
+        }
+        for (position, instruction) in function.instructions.iter().enumerate() {
+            let next = function.instructions.get(position + 1);
+            let next_label = next.and_then(|next| match next.kind {
+                InstructionKind::Label(label) => Some(label),
+                _ => None,
+            });
+            emitter.instruction(&instruction.kind, instruction.line, next_label);
+        }
+
+        writeln!(self.output, ".Lf_{}_return:", function.name).unwrap();
+        for (index, register) in used.iter().enumerate() {
+            let (_, name) = GENERAL_REGISTERS[*register];
+            writeln!(self.output, "    movq -{}(%rbp), {}", (index + 1) * 8, name).unwrap();
+        }
+        self.output.push_str("    leave\n    ret\n");
+    }
+}
+
+impl FunctionEmitter<'_, '_> {
+    fn instruction(&mut self, kind: &InstructionKind, line: usize, next_label: Option<Label>) {
+        match kind {
+            InstructionKind::Copy { target, value } => {
+                let value_type = self.function.types[target];
+                let location = self.location(target);
+                let direct = match (value, value_type) {
+                    (
+                        Operand::Constant(Value::Float(_)) | Operand::Place(Place::Version(_, 0)),
+                        Type::Float,
+                    ) => None,
+                    (_, Type::Float) => Some(("movsd", self.operand(value))),
+                    _ => Some(("movl", self.operand(value))),
+                };
+                // x86-64 moves cannot have two memory operands
+                match direct {
+                    Some((mov, value)) if !(value.contains('(') && location.contains('(')) => {
+                        writeln!(self.emitter.output, "    {} {}, {}", mov, value, location)
+                            .unwrap()
+                    }
+                    _ => {
+                        self.load(value, value_type);
+                        self.store(target, value_type);
+                    }
+                }
+            }
+            InstructionKind::Unary {
+                target, operand, ..
+            } => {
+                let value_type = self.function.types[target];
+                self.load(operand, value_type);
+                match value_type {
+                    Type::Float => self
+                        .write("    movq %xmm0, %rax\n    btcq $63, %rax\n    movq %rax, %xmm0\n"),
+                    _ => self.write("    negl %eax\n"),
+                }
+                self.store(target, value_type);
+            }
+            InstructionKind::Binary {
+                target,
+                op,
+                lhs,
+                rhs,
+            } => {
+                match self.function.operand_type(lhs) {
+                    Type::Float => {
+                        self.load(lhs, Type::Float);
+                        self.write("    movapd %xmm0, %xmm1\n");
+                        self.load(rhs, Type::Float);
+                        self.write(float_code(*op));
+                        if op.is_comparison() {
+                            self.write("    movzbl %al, %eax\n");
+                        }
+                    }
+                    _ => self.int_operation(*op, lhs, rhs, line),
+                }
+                self.store(target, self.function.types[target]);
+            }
+            InstructionKind::Convert { target, to, value } => {
+                let from = self.function.operand_type(value);
+                self.load(value, from);
+                match (from, *to) {
+                    (from, to) if from == to => {}
+                    (Type::Float, Type::Int) => self.write("    call c1_to_int\n"),
+                    (_, Type::Float) => self.write("    cvtsi2sdl %eax, %xmm0\n"),
+                    (Type::Float, Type::Bool) => self.write(
+                        "    xorpd %xmm1, %xmm1\n    ucomisd %xmm1, %xmm0\n    setne %al\n    \
+                         setp %cl\n    orb %cl, %al\n    movzbl %al, %eax\n",
+                    ),
+                    (_, Type::Bool) => {
+                        self.write("    testl %eax, %eax\n    setne %al\n    movzbl %al, %eax\n")
+                    }
+                    // Bools are already ints
+                    _ => {}
+                }
+                self.store(target, *to);
+            }
+            InstructionKind::Call { target, function } => {
+                writeln!(self.emitter.output, "    call f_{}", function).unwrap();
+                let may_miss = self.emitter.may_miss_result.contains(function.as_str());
+                match target {
+                    Some(target) => {
+                        if may_miss {
+                            self.checks += 1;
+                            let label =
+                                format!(".Lf_{}_checked{}", self.function.name, self.checks);
+                            writeln!(
+                                self.emitter.output,
+                                "    cmpq $0, c1_missing_result(%rip)\n    je {}\n    \
+                                 movl ${}, %edi\n    movq c1_missing_result(%rip), %rsi\n    \
+                                 call c1_error\n{}:",
+                                label, line, label
+                            )
+                            .unwrap();
+                        }
+                        self.store(target, self.function.types[target]);
+                    }
+                    // The result is not used, so it may be missing
+                    None if may_miss => self.write("    movq $0, c1_missing_result(%rip)\n"),
+                    None => {}
+                }
+            }
+            InstructionKind::Print(value) => {
+                let value_type = self.function.operand_type(value);
+                self.load(value, value_type);
+                let routine = match value_type {
+                    Type::Float => "c1_print_float",
+                    Type::Bool => "c1_print_bool",
+                    _ => "c1_print_int",
+                };
+                if routine != "c1_print_float" {
+                    self.write("    movl %eax, %edi\n");
+                }
+                writeln!(self.emitter.output, "    call {}", routine).unwrap();
+            }
+            InstructionKind::Label(label) => {
+                writeln!(self.emitter.output, "{}:", self.label(*label)).unwrap()
+            }
+            InstructionKind::Jump(label) => {
+                if next_label != Some(*label) {
+                    writeln!(self.emitter.output, "    jmp {}", self.label(*label)).unwrap();
+                }
+            }
+            InstructionKind::Branch {
+                condition,
+                then_label,
+                else_label,
+            } => {
+                let known = match condition {
+                    Operand::Constant(value) => Some(value.is_truthy()),
+                    Operand::Place(Place::Version(_, 0)) => Some(false),
+                    Operand::Place(_) => None,
+                };
+                if let Some(known) = known {
+                    let label = self.label(if known { *then_label } else { *else_label });
+                    writeln!(self.emitter.output, "    jmp {}", label).unwrap();
+                    return;
+                }
+                let condition = self.operand(condition);
+                let then_label = self.label(*then_label);
+                writeln!(
+                    self.emitter.output,
+                    "    cmpl $0, {}\n    jne {}",
+                    condition, then_label
+                )
+                .unwrap();
+                if next_label != Some(*else_label) {
+                    let label = self.label(*else_label);
+                    writeln!(self.emitter.output, "    jmp {}", label).unwrap();
+                }
+            }
+            InstructionKind::Return(value) => {
+                let return_type = self.function.return_type;
+                match value {
+                    Some(value) => self.load(value, return_type),
+                    None if return_type != Type::Void => {
+                        let message = self.missing_result_message();
+                        writeln!(
+                            self.emitter.output,
+                            "    leaq {}(%rip), %rax\n    movq %rax, c1_missing_result(%rip)",
+                            message
+                        )
+                        .unwrap();
+                        self.zero(return_type);
+                    }
+                    None => {}
+                }
+                if next_label.is_some() {
+                    writeln!(
+                        self.emitter.output,
+                        "    jmp .Lf_{}_return",
+                        self.function.name
+                    )
+                    .unwrap();
+                }
+            }
+            InstructionKind::Phi { .. } => unreachable!("phis are removed by from_ssa"),
+        }
+    }
+
+    /// Apply an operator to two int operands, the result ends up in `%eax`
+    fn int_operation(&mut self, op: BinaryOp, lhs: &Operand, rhs: &Operand, line: usize) {
+        if op == BinaryOp::Div {
+            self.load(lhs, Type::Int);
+            self.write("    movl %eax, %edi\n");
+            self.load(rhs, Type::Int);
+            writeln!(
+                self.emitter.output,
+                "    movl %eax, %esi\n    movl ${}, %edx\n    call c1_div",
+                line
+            )
+            .unwrap();
+            return;
+        }
+        self.load(lhs, Type::Int);
+        let rhs = self.operand(rhs);
+        let code = match op {
+            BinaryOp::Add => format!("    addl {}, %eax\n", rhs),
+            BinaryOp::Sub => format!("    subl {}, %eax\n", rhs),
+            BinaryOp::Mul => format!("    imull {}, %eax\n", rhs),
+            op => format!(
+                "    cmpl {}, %eax\n    set{} %al\n    movzbl %al, %eax\n",
+                rhs,
+                condition_code(op)
+            ),
+        };
+        self.write(&code);
+    }
+
+    /// Return the assembly operand of an operand, which is not a float constant
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Constant(Value::Int(value)) => format!("${}", value),
+            Operand::Constant(Value::Bool(value)) => format!("${}", *value as i32),
+            Operand::Constant(Value::Float(_)) => unreachable!("float constants are loaded"),
+            Operand::Place(Place::Version(_, 0)) => "$0".to_string(),
+            Operand::Place(place) => self.location(place),
+        }
+    }
+
+    /// Load an operand into `%eax` or `%xmm0`
+    fn load(&mut self, operand: &Operand, value_type: Type) {
+        let code = match (operand, value_type) {
+            (Operand::Constant(Value::Float(value)), _) => format!(
+                "    movabsq ${:#x}, %rax\n    movq %rax, %xmm0\n",
+                value.to_bits()
+            ),
+            (Operand::Place(Place::Version(_, 0)), Type::Float) => {
+                "    xorpd %xmm0, %xmm0\n".to_string()
+            }
+            (Operand::Place(place), Type::Float) => {
+                format!("    movsd {}, %xmm0\n", self.location(place))
+            }
+            (operand, _) => format!("    movl {}, %eax\n", self.operand(operand)),
+        };
+        self.write(&code);
+    }
+
+    /// Store `%eax` or `%xmm0` into a place
+    fn store(&mut self, place: &Place, value_type: Type) {
+        let location = self.location(place);
+        match value_type {
+            Type::Float => writeln!(self.emitter.output, "    movsd %xmm0, {}", location),
+            _ => writeln!(self.emitter.output, "    movl %eax, {}", location),
+        }
+        .unwrap();
+    }
+
+    fn zero(&mut self, value_type: Type) {
+        match value_type {
+            Type::Float => self.write("    xorpd %xmm0, %xmm0\n"),
+            _ => self.write("    xorl %eax, %eax\n"),
+        }
+    }
+
+    /// Return the register or memory operand that holds a place
+    fn location(&self, place: &Place) -> String {
+        if let Place::Variable(name) = place {
+            return format!("{}(%rip)", variable(name));
+        }
+        match self.allocation.locations[place] {
+            Location::Register(register) => match self.function.types[place] {
+                Type::Float => FLOAT_REGISTERS[register].to_string(),
+                _ => GENERAL_REGISTERS[register].0.to_string(),
+            },
+            Location::Stack(slot) => format!("-{}(%rbp)", (self.saved + slot + 1) * 8),
+        }
+    }
+
+    fn label(&self, label: Label) -> String {
+        format!(".Lf_{}_{}", self.function.name, label.0)
+    }
+
+    /// Add the message for a missing result of the function to `.rodata` and return its label
+    fn missing_result_message(&mut self) -> String {
+        let label = format!(".Lf_{}_missing_result", self.function.name);
+        if !self.emitter.data.contains(&format!("{}:", label)) {
+            writeln!(
+                self.emitter.data,
+                "{}:\n    .string \"function `{}` did not return a value\"",
+                label, self.function.name
+            )
+            .unwrap();
+        }
+        label
+    }
+
+    fn write(&mut self, code: &str) {
+        self.emitter.output.push_str(code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Storage;
+    use crate::regalloc::RegisterSet;
+    use crate::C1Parser;
+
+    /// Count the instructions of the C(-1) functions that access memory: loads and stores of
+    /// stack slots and variables, pushes and pops
+    fn memory_operations(assembly: &str) -> usize {
+        let end = assembly.find("\n    .globl main").unwrap();
+        assembly[..end]
+            .lines()
+            .filter(|line| line.starts_with("    "))
+            .filter(|line| {
+                line.contains("(%") || line.starts_with("    push") || line.starts_with("    pop")
+            })
+            .count()
+    }
+
+    #[test]
+    fn registers_replace_memory_operations() {
+        let text = std::fs::read_to_string("tests/data/beispiel.c-1").unwrap();
+        let program = C1Parser::parse_program(&text).unwrap();
+        let stack = program.emit_x86_64(Storage::Local).unwrap();
+        let allocated = program
+            .emit_x86_64_allocated(Storage::Local, RegisterSet::X86_64)
+            .unwrap();
+        let spilled = program
+            .emit_x86_64_allocated(
+                Storage::Local,
+                RegisterSet {
+                    general: 1,
+                    float: 0,
+                },
+            )
+            .unwrap();
+        assert_eq!(memory_operations(&stack), 57);
+        // Only the frame pointers of the three functions and the 12 callee-saved registers
+        // they use are stored and loaded
+        assert_eq!(memory_operations(&allocated), 27);
+        assert_eq!(memory_operations(&spilled), 46);
+    }
+}
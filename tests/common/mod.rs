@@ -0,0 +1,32 @@
+//! Helpers shared by the integration tests that run the corpus through a backend
+
+// Every test binary compiles this module, but not every one uses all helpers
+#![allow(dead_code)]
+
+use cb_3::Storage;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// All programs of the corpus together with the example program
+pub fn corpus() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir("tests/data/corpus")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "c-1"))
+        .collect();
+    paths.sort();
+    paths.push(PathBuf::from("tests/data/beispiel.c-1"));
+    paths
+}
+
+/// Programs of the corpus with a function that reads a variable which only another function
+/// assigns, so the variable has no type with local storage
+const UNTYPED_WITH_LOCAL_STORAGE: &[&str] =
+    &["fibonacci.c-1", "shared_counter.c-1", "uninitialized.c-1"];
+
+/// Whether a program of the corpus has variables without a static type, which the backends
+/// reject. Every other program has to compile.
+pub fn is_untyped(path: &Path, storage: Storage) -> bool {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    storage == Storage::Local && UNTYPED_WITH_LOCAL_STORAGE.contains(&name)
+}
@@ -0,0 +1,26 @@
+use cb_3::Config;
+use std::fs;
+use std::path::Path;
+
+// Changing the working directory affects every test of the process, so this test has a binary of
+// its own
+#[test]
+fn configuration_is_discovered_from_a_relative_path() {
+    let root = std::env::temp_dir().join(format!("c1-relative-config-{}", std::process::id()));
+    let nested = root.join("exercises").join("week3");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(root.join(Config::FILE_NAME), "max-errors = 3\n").unwrap();
+    fs::write(nested.join("main.c-1"), "void main() {}").unwrap();
+
+    let previous = std::env::current_dir().unwrap();
+    std::env::set_current_dir(&nested).unwrap();
+    let discovered = Config::discover(Path::new("main.c-1"));
+    let (config, _) = Config::for_input(Path::new("main.c-1")).unwrap();
+    std::env::set_current_dir(previous).unwrap();
+
+    let discovered = discovered.map(|path| fs::canonicalize(path).unwrap());
+    let expected = fs::canonicalize(root.join(Config::FILE_NAME)).unwrap();
+    assert_eq!(discovered, Some(expected));
+    assert_eq!(config.max_errors, Some(3));
+    fs::remove_dir_all(&root).unwrap();
+}
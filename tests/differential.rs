@@ -0,0 +1,151 @@
+mod common;
+
+use cb_3::{
+    BraceStyle, C1Parser, Formatter, Inliner, Interpreter, IrInterpreter, LanguageOptions, Module,
+    ParseOptions, Program, PurityAnalysis, Storage, SyntaxTree, Vm,
+};
+use common::{corpus, is_untyped};
+use std::fs;
+
+#[test]
+fn virtual_machine_matches_interpreter() {
+    let paths = corpus();
+    assert!(paths.len() > 1, "the corpus is empty");
+    for path in paths {
+        let text = fs::read_to_string(&path).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let language = LanguageOptions { storage };
+            let options = ParseOptions {
+                language: language.clone(),
+                ..ParseOptions::default()
+            };
+            let program = C1Parser::parse_program_with(&text, &options).unwrap();
+            let module = Module::compile(&program, &language)
+                .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            // Run what a module file contains rather than the compiler output itself
+            let module = Module::from_bytes(&module.to_bytes()).unwrap();
+
+            let mut interpreter = Interpreter::new(&program);
+            interpreter.storage = storage;
+            let expected = interpreter.run();
+            let mut vm = Vm::new(&module);
+            vm.max_call_depth = interpreter.max_call_depth;
+            let actual = vm.run();
+
+            let context = format!("{} with {} storage", path.display(), storage.name());
+            assert_eq!(actual, expected, "{}", context);
+            assert_eq!(vm.output(), interpreter.output(), "{}", context);
+        }
+    }
+}
+
+#[test]
+fn optimizations_preserve_behaviour() {
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let program = C1Parser::parse_program(&text).unwrap();
+            let folded = program.fold_constants(storage);
+            let printed = folded.to_string();
+            let reparsed = C1Parser::parse_program(&printed).unwrap();
+            assert_eq!(reparsed.to_string(), printed, "{}", path.display());
+
+            let mut original = Interpreter::new(&program);
+            original.storage = storage;
+            let mut optimized = Interpreter::new(&folded);
+            optimized.storage = storage;
+            let context = format!("{} with {} storage", path.display(), storage.name());
+            let expected = original.run();
+            assert_eq!(optimized.run(), expected, "{}", context);
+
+            let (propagated, _) = program.propagate_constants(storage);
+            let mut optimized = Interpreter::new(&propagated);
+            optimized.storage = storage;
+            assert_eq!(optimized.run(), expected, "{}", context);
+
+            let evaluated =
+                program.evaluate_pure_calls(storage, PurityAnalysis::DEFAULT_STEP_BUDGET);
+            let mut optimized = Interpreter::new(&evaluated);
+            optimized.storage = storage;
+            assert_eq!(optimized.run(), expected, "{}", context);
+
+            let inlined = Inliner::new(storage).inline(&program);
+            let mut optimized = Interpreter::new(&inlined);
+            optimized.storage = storage;
+            assert_eq!(optimized.run(), expected, "{}", context);
+        }
+    }
+}
+
+#[test]
+fn lowered_programs_match_interpreter() {
+    let mut compared = 0;
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let program = C1Parser::parse_program(&text).unwrap();
+            let context = format!("{} with {} storage", path.display(), storage.name());
+            let untyped = is_untyped(&path, storage);
+            let ir = match program.lower(storage) {
+                Ok(ir) => ir,
+                Err(_) if untyped => continue,
+                Err(error) => panic!("{}: {}", context, error),
+            };
+            assert!(!untyped, "{} is expected to have no static types", context);
+            let mut interpreter = Interpreter::new(&program);
+            interpreter.storage = storage;
+            let expected = interpreter.run();
+            let mut lowered = IrInterpreter::new(&ir);
+            lowered.max_call_depth = interpreter.max_call_depth;
+            let actual = lowered.run();
+
+            assert_eq!(actual, expected, "{}\n{}", context, ir);
+            assert_eq!(lowered.output(), interpreter.output(), "{}", context);
+
+            // Both conversions verify their result
+            let ssa = ir.to_ssa();
+            let out_of_ssa = ssa.from_ssa().unwrap();
+            for ir in [ssa, out_of_ssa] {
+                let mut lowered = IrInterpreter::new(&ir);
+                lowered.max_call_depth = interpreter.max_call_depth;
+                assert_eq!(lowered.run(), expected, "{}\n{}", context, ir);
+                assert_eq!(lowered.output(), interpreter.output(), "{}", context);
+            }
+            compared += 1;
+        }
+    }
+    assert!(compared > 10, "only {} programs were compared", compared);
+}
+
+#[test]
+fn formatting_preserves_programs() {
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        for brace_style in [BraceStyle::SameLine, BraceStyle::NextLine] {
+            let mut formatter = Formatter::new();
+            formatter.brace_style = brace_style;
+            let formatted = formatter.format(&text).unwrap();
+
+            let context = format!("{} with {:?}", path.display(), brace_style);
+            let original = C1Parser::parse_program_with(&text, &formatter.parse_options).unwrap();
+            let reparsed =
+                C1Parser::parse_program_with(&formatted, &formatter.parse_options).unwrap();
+            assert_eq!(reparsed.to_string(), original.to_string(), "{}", context);
+            let comments = |program: &Program| -> Vec<String> {
+                program.comments.iter().map(|c| c.text.clone()).collect()
+            };
+            assert_eq!(comments(&reparsed), comments(&original), "{}", context);
+            assert_eq!(formatter.check(&formatted), Ok(None), "{}", context);
+        }
+    }
+}
+
+#[test]
+fn syntax_trees_reproduce_sources() {
+    let options = ParseOptions::default();
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        let tree = SyntaxTree::parse(&text, &options).unwrap();
+        assert_eq!(tree.to_string(), text, "{}", path.display());
+    }
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "jit")]
+
+mod common;
+
+use cb_3::{C1Parser, Interpreter, Jit, Storage};
+use common::{corpus, is_untyped};
+use std::fs;
+
+#[test]
+fn jit_matches_interpreter() {
+    let mut compared = 0;
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let program = C1Parser::parse_program(&text).unwrap();
+            let context = format!("{} with {} storage", path.display(), storage.name());
+            let untyped = is_untyped(&path, storage);
+            let jit = match Jit::compile(&program, storage) {
+                Ok(jit) => jit,
+                Err(_) if untyped => continue,
+                Err(error) => panic!("{}: {}", context, error),
+            };
+            assert!(!untyped, "{} is expected to have no static types", context);
+            let mut interpreter = Interpreter::new(&program);
+            interpreter.storage = storage;
+            let expected = interpreter.run();
+
+            let mut output = String::new();
+            let actual = jit.run(|value| output.push_str(&format!("{}\n", value)));
+            match expected {
+                Ok(execution) => {
+                    assert_eq!(actual, Ok(execution.exit_value), "{}", context);
+                    assert_eq!(output, execution.output, "{}", context);
+                }
+                Err(error) => {
+                    assert_eq!(actual, Err(error), "{}", context);
+                    assert_eq!(output, interpreter.output(), "{}", context);
+                }
+            }
+            // Every run starts with fresh globals
+            let mut again = String::new();
+            jit.run(|value| again.push_str(&format!("{}\n", value)))
+                .unwrap_or_default();
+            assert_eq!(again, output, "{}", context);
+            compared += 1;
+        }
+    }
+    assert!(compared > 10, "only {} programs were compared", compared);
+}
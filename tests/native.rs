@@ -0,0 +1,245 @@
+mod common;
+
+use cb_3::{C1Parser, EmitError, Interpreter, RegisterSet, Storage, Type, Value};
+use common::{corpus, is_untyped};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory for the generated files of one test
+fn scratch_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("cb3-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Environment variable that skips the tests whose toolchain is not installed
+const SKIP_MISSING_TOOLS: &str = "C1_SKIP_TOOLCHAIN_TESTS";
+
+/// Check that the external tools of a test are installed. A missing tool fails the test, unless
+/// `C1_SKIP_TOOLCHAIN_TESTS` is set, in which case the test is skipped and this returns false.
+fn have_tools(names: &[&str]) -> bool {
+    let missing: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|name| Command::new(name).arg("--version").output().is_err())
+        .collect();
+    if missing.is_empty() {
+        return true;
+    }
+    assert!(
+        std::env::var_os(SKIP_MISSING_TOOLS).is_some(),
+        "{} not found, set {} to skip this test",
+        missing.join(" and "),
+        SKIP_MISSING_TOOLS
+    );
+    eprintln!("skipped, {} not found", missing.join(" and "));
+    false
+}
+
+/// Exit status the native program must have for the result of the interpreter
+fn exit_status(exit_value: Option<Value>) -> i32 {
+    match exit_value.and_then(|value| value.convert_to(Type::Int)) {
+        Some(Value::Int(value)) => value & 0xff,
+        _ => 0,
+    }
+}
+
+/// Compile every program of the corpus with `build`, run it and compare the output and exit
+/// status with the interpreter. `build` returns the command that runs the program or the error
+/// of the backend, which only programs without static types may cause.
+fn compare_with_interpreter(
+    name: &str,
+    build: impl Fn(&cb_3::Program, Storage, &Path, &Path) -> Result<Command, EmitError>,
+) {
+    let directory = scratch_directory(name);
+    let mut compared = 0;
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let program = C1Parser::parse_program(&text).unwrap();
+            let mut interpreter = Interpreter::new(&program);
+            interpreter.storage = storage;
+            let expected = interpreter.run();
+            if matches!(&expected, Err(error) if error.message.contains("call depth")) {
+                // Native code has a much larger stack
+                continue;
+            }
+            let context = format!("{} with {} storage", path.display(), storage.name());
+            let untyped = is_untyped(&path, storage);
+            let mut command = match build(&program, storage, &path, &directory) {
+                Ok(command) => command,
+                Err(_) if untyped => continue,
+                Err(error) => panic!("{}: {}", context, error),
+            };
+            assert!(!untyped, "{} is expected to have no static types", context);
+            let run = command.output().unwrap();
+            compared += 1;
+            let stdout = String::from_utf8(run.stdout).unwrap();
+            match expected {
+                Ok(execution) => {
+                    assert_eq!(stdout, execution.output, "{}", context);
+                    assert_eq!(
+                        run.status.code(),
+                        Some(exit_status(execution.exit_value)),
+                        "{}",
+                        context
+                    );
+                }
+                Err(error) => {
+                    assert_eq!(stdout, interpreter.output(), "{}", context);
+                    assert_eq!(run.status.code(), Some(1), "{}", context);
+                    let stderr = String::from_utf8(run.stderr).unwrap();
+                    assert_eq!(stderr.trim_end(), error.to_string(), "{}", context);
+                }
+            }
+        }
+    }
+    assert!(compared > 10, "only {} programs were compared", compared);
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn c_programs_match_interpreter() {
+    if !have_tools(&["cc"]) {
+        return;
+    }
+    compare_with_interpreter("c", |program, storage, path, directory| {
+        let source_name = path.file_name().unwrap().to_str().unwrap();
+        let code = program.emit_c(storage, source_name)?;
+        let stem = format!("{}-{}", source_name, storage.name());
+        let source = directory.join(format!("{}.c", stem));
+        let executable = directory.join(stem);
+        fs::write(&source, code).unwrap();
+        let compiled = Command::new("cc")
+            .args([
+                "-std=c99",
+                "-O2",
+                "-pedantic-errors",
+                "-Wall",
+                "-Wno-unused-but-set-variable",
+            ])
+            .arg("-o")
+            .arg(&executable)
+            .arg(&source)
+            .arg("-lm")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&compiled.stderr);
+        assert!(compiled.status.success() && stderr.is_empty(), "{}", stderr);
+        Ok(Command::new(executable))
+    });
+}
+
+/// Assemble and link the assembly generated for a corpus program with the C compiler and return
+/// the command that runs it
+fn link_assembly(assembly: &str, path: &Path, storage: Storage, directory: &Path) -> Command {
+    let stem = format!(
+        "{}-{}",
+        path.file_name().unwrap().to_str().unwrap(),
+        storage.name()
+    );
+    let source = directory.join(format!("{}.s", stem));
+    let executable = directory.join(stem);
+    fs::write(&source, assembly).unwrap();
+    let linked = Command::new("cc")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&source)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&linked.stderr);
+    assert!(linked.status.success() && stderr.is_empty(), "{}", stderr);
+    Command::new(executable)
+}
+
+#[test]
+fn assembly_programs_match_interpreter() {
+    if !have_tools(&["cc"]) {
+        return;
+    }
+    compare_with_interpreter("asm", |program, storage, path, directory| {
+        let assembly = program.emit_x86_64(storage)?;
+        Ok(link_assembly(&assembly, path, storage, directory))
+    });
+}
+
+#[test]
+fn allocated_assembly_programs_match_interpreter() {
+    if !have_tools(&["cc"]) {
+        return;
+    }
+    // With a single register of each class most values are spilled
+    let scarce = RegisterSet {
+        general: 1,
+        float: 1,
+    };
+    for (name, registers) in [("regalloc", RegisterSet::X86_64), ("spill", scarce)] {
+        compare_with_interpreter(name, |program, storage, path, directory| {
+            let assembly = program.emit_x86_64_allocated(storage, registers)?;
+            Ok(link_assembly(&assembly, path, storage, directory))
+        });
+    }
+}
+
+#[test]
+fn llvm_programs_match_interpreter() {
+    if !have_tools(&["llvm-as", "lli"]) {
+        return;
+    }
+    compare_with_interpreter("llvm", |program, storage, path, directory| {
+        let source_name = path.file_name().unwrap().to_str().unwrap();
+        let code = program.emit_llvm(storage, source_name)?;
+        let stem = format!("{}-{}", source_name, storage.name());
+        let source = directory.join(format!("{}.ll", stem));
+        let bitcode = directory.join(format!("{}.bc", stem));
+        fs::write(&source, code).unwrap();
+        let assembled = Command::new("llvm-as")
+            .arg("-o")
+            .arg(&bitcode)
+            .arg(&source)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&assembled.stderr);
+        assert!(
+            assembled.status.success() && stderr.is_empty(),
+            "{}",
+            stderr
+        );
+        let mut command = Command::new("lli");
+        command.arg(bitcode);
+        Ok(command)
+    });
+}
+
+#[test]
+fn rust_programs_match_interpreter() {
+    if !have_tools(&["rustc"]) {
+        return;
+    }
+    compare_with_interpreter("rust", |program, storage, path, directory| {
+        let source_name = path.file_name().unwrap().to_str().unwrap();
+        let code = program.emit_rust(storage, source_name)?;
+        let stem = format!("{}-{}", source_name, storage.name());
+        let source = directory.join(format!("{}.rs", stem));
+        let executable = directory.join(stem);
+        fs::write(&source, code).unwrap();
+        let compiled = Command::new("rustc")
+            .args([
+                "--edition",
+                "2021",
+                "--crate-name",
+                "c1",
+                "-D",
+                "warnings",
+                "-o",
+            ])
+            .arg(&executable)
+            .arg(&source)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&compiled.stderr);
+        assert!(compiled.status.success() && stderr.is_empty(), "{}", stderr);
+        Ok(Command::new(executable))
+    });
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use cb_3::{C1Parser, Interpreter, Storage, Value};
+use common::{corpus, is_untyped};
+use std::fs;
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store, Val};
+
+/// What the host functions imported by the module have seen
+#[derive(Default)]
+struct Host {
+    output: String,
+    error: Option<String>,
+}
+
+/// Run `main` of a module with a host that prints like the interpreter. Returns the output,
+/// the result of `main` and the runtime error reported by the module, if any.
+fn run(wat: &str) -> (String, Option<Val>, Option<String>) {
+    let wasm = wat::parse_str(wat).unwrap_or_else(|error| panic!("{}\n{}", error, wat));
+    let engine = Engine::default();
+    let module = Module::new(&engine, &wasm).unwrap_or_else(|error| panic!("{}\n{}", error, wat));
+    let mut store = Store::new(&engine, Host::default());
+    let mut linker = <Linker<Host>>::new(&engine);
+    linker
+        .func_wrap("c1", "print_int", |mut caller: Caller<Host>, value: i32| {
+            let output = &mut caller.data_mut().output;
+            output.push_str(&format!("{}\n", Value::Int(value)));
+        })
+        .unwrap();
+    linker
+        .func_wrap(
+            "c1",
+            "print_float",
+            |mut caller: Caller<Host>, value: f64| {
+                let output = &mut caller.data_mut().output;
+                output.push_str(&format!("{}\n", Value::Float(value)));
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "c1",
+            "print_bool",
+            |mut caller: Caller<Host>, value: i32| {
+                let output = &mut caller.data_mut().output;
+                output.push_str(&format!("{}\n", Value::Bool(value != 0)));
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "c1",
+            "error",
+            |mut caller: Caller<Host>, line: i32, message: i32| {
+                let memory = caller
+                    .get_export("memory")
+                    .and_then(Extern::into_memory)
+                    .unwrap();
+                let bytes = &memory.data(&caller)[message as usize..];
+                let end = bytes.iter().position(|byte| *byte == 0).unwrap();
+                let message = String::from_utf8(bytes[..end].to_vec()).unwrap();
+                caller.data_mut().error = Some(format!("Line {}: {}", line, message));
+            },
+        )
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let main = instance.get_func(&store, "main").unwrap();
+    let mut results: Vec<Val> = main
+        .ty(&store)
+        .results()
+        .iter()
+        .map(|value_type| Val::default(*value_type))
+        .collect();
+    let trapped = main.call(&mut store, &[], &mut results).is_err();
+    let host = store.into_data();
+    assert_eq!(trapped, host.error.is_some(), "only runtime errors trap");
+    (host.output, results.pop(), host.error)
+}
+
+#[test]
+fn functions_may_be_named_like_imports() {
+    let program =
+        C1Parser::parse_program("void error() { printf(1); } void main() { error(); }").unwrap();
+    let (output, _, error) = run(&program.emit_wat(Storage::Local).unwrap());
+    assert_eq!(output, "1\n");
+    assert_eq!(error, None);
+}
+
+#[test]
+fn wasm_modules_match_interpreter() {
+    let mut compared = 0;
+    for path in corpus() {
+        let text = fs::read_to_string(&path).unwrap();
+        for storage in [Storage::Local, Storage::Global] {
+            let program = C1Parser::parse_program(&text).unwrap();
+            let mut interpreter = Interpreter::new(&program);
+            interpreter.storage = storage;
+            let expected = interpreter.run();
+            if matches!(&expected, Err(error) if error.message.contains("call depth")) {
+                // The wasm stack is much larger
+                continue;
+            }
+            let context = format!("{} with {} storage", path.display(), storage.name());
+            let untyped = is_untyped(&path, storage);
+            let wat = match program.emit_wat(storage) {
+                Ok(wat) => wat,
+                Err(_) if untyped => continue,
+                Err(error) => panic!("{}: {}", context, error),
+            };
+            assert!(!untyped, "{} is expected to have no static types", context);
+            let (output, result, error) = run(&wat);
+            compared += 1;
+            match expected {
+                Ok(execution) => {
+                    assert_eq!(output, execution.output, "{}", context);
+                    let result = result.map(|result| match (result, execution.exit_value) {
+                        (Val::I32(value), Some(Value::Bool(_))) => Value::Bool(value != 0),
+                        (Val::I32(value), _) => Value::Int(value),
+                        (Val::F64(value), _) => Value::Float(value.into()),
+                        (result, _) => panic!("unexpected result {:?}", result),
+                    });
+                    // A missing result of main is zero in wasm
+                    if execution.exit_value.is_some() {
+                        assert_eq!(result, execution.exit_value, "{}", context);
+                    }
+                    assert_eq!(error, None, "{}", context);
+                }
+                Err(expected) => {
+                    assert_eq!(output, interpreter.output(), "{}", context);
+                    assert_eq!(error, Some(expected.to_string()), "{}", context);
+                }
+            }
+        }
+    }
+    assert!(compared > 10, "only {} programs were compared", compared);
+}